@@ -0,0 +1,362 @@
+// `lok release.tar.gz`: the files of `.zip`, `.tar` and `.tar.gz` archives,
+// read in memory and counted like files of a directory
+use crate::format::compact_size;
+use crate::inflate::{self, crc32, inflate};
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+// All of an archive is held in memory, this much decompressed at most
+const LIMIT: usize = 1 << 30;
+
+pub fn is_archive(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .is_some_and(|name| EXTENSIONS.iter().any(|ext| name.ends_with(ext)))
+}
+
+// A regular file of the archive, or why it couldn't be read
+pub struct Entry {
+    pub name: PathBuf,
+    pub data: Result<Vec<u8>, String>,
+}
+
+// A broken archive fails as a whole, a broken entry only on its own
+pub fn entries(path: &Path, bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    let name = path.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        zip(bytes, LIMIT)
+    } else if name.ends_with(".tar") {
+        tar(bytes)
+    } else {
+        tar(&gunzip(bytes, LIMIT)?)
+    }
+}
+
+fn too_large(limit: usize) -> String {
+    format!(
+        "decompresses past the {} an archive is limited to",
+        compact_size(limit as u64)
+    )
+}
+
+fn inflate_error(err: inflate::Error, limit: usize) -> String {
+    match err {
+        inflate::Error::TooLarge(_) => too_large(limit),
+        err => err.to_string(),
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+// RFC 1952, a header, the deflate stream, then its CRC-32 and length
+fn gunzip(bytes: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if bytes.len() < 18 || bytes[..3] != [0x1f, 0x8b, 8] {
+        return Err("is not gzip compressed".to_string());
+    }
+    let flags = bytes[3];
+    let mut at = 10;
+    // FEXTRA
+    if flags & 4 != 0 {
+        at += 2 + u16_at(bytes, at).ok_or("has a broken gzip header")? as usize;
+    }
+    // FNAME and FCOMMENT end with a NUL
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            let end = bytes
+                .get(at..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+                .ok_or("has a broken gzip header")?;
+            at += end + 1;
+        }
+    }
+    // FHCRC
+    if flags & 2 != 0 {
+        at += 2;
+    }
+    let stream = bytes.get(at..).ok_or("has a broken gzip header")?;
+    let (data, used) = inflate(stream, limit).map_err(|err| inflate_error(err, limit))?;
+    match u32_at(stream, used) {
+        Some(crc) if crc == crc32(&data) => Ok(data),
+        Some(_) => Err("fails its CRC check".to_string()),
+        None => Err("is truncated".to_string()),
+    }
+}
+
+// POSIX ustar with the GNU long names and the pax `path`
+fn tar(bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut rst = vec![];
+    let mut at = 0;
+    // A name for the next header, from a `L` or `x` entry
+    let mut long_name = None;
+    while let Some(header) = bytes.get(at..at + 512) {
+        // Two zero blocks end the archive
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = octal(&header[124..136]).ok_or("has a broken tar header")? as usize;
+        let data = bytes.get(at + 512..at + 512 + size).ok_or("is truncated")?;
+        at += 512 + size.div_ceil(512) * 512;
+
+        let mut name = text(&header[..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = text(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        match header[156] {
+            b'L' => long_name = Some(text(data)),
+            b'x' => long_name = pax_path(data).or(long_name),
+            b'0' | 0 => rst.push(Entry {
+                name: PathBuf::from(long_name.take().unwrap_or(name)),
+                data: Ok(data.to_vec()),
+            }),
+            // Directories, links, devices
+            _ => long_name = None,
+        }
+    }
+    Ok(rst)
+}
+
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn octal(bytes: &[u8]) -> Option<u64> {
+    let digits = text(bytes);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    match digits.is_empty() {
+        true => Some(0),
+        false => u64::from_str_radix(digits, 8).ok(),
+    }
+}
+
+// `<length> path=<name>\n` records
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data).lines().find_map(|record| {
+        record
+            .split_once(" path=")
+            .map(|(_, path)| path.to_string())
+    })
+}
+
+// The central directory at the end lists the entries, stored or deflated.
+// Entries past `limit` in all are skipped, the ones before are kept
+fn zip(bytes: &[u8], limit: usize) -> Result<Vec<Entry>, String> {
+    // End of central directory record, after it only a comment of at most 64 KiB
+    let min = bytes.len().saturating_sub(22 + 0xffff);
+    let end = (min..=bytes.len().saturating_sub(22))
+        .rev()
+        .find(|at| u32_at(bytes, *at) == Some(0x0605_4b50))
+        .ok_or("is not a zip archive")?;
+    let count = u16_at(bytes, end + 10).unwrap() as usize;
+    let mut at = u32_at(bytes, end + 16).unwrap() as usize;
+    if count == 0xffff || at == 0xffff_ffff {
+        return Err("is a zip64 archive, which isn't supported".to_string());
+    }
+
+    let broken = || "has a broken central directory".to_string();
+    let mut rst = vec![];
+    let mut left = limit;
+    for _ in 0..count {
+        if u32_at(bytes, at) != Some(0x0201_4b50) {
+            return Err(broken());
+        }
+        let field = |offset| u16_at(bytes, at + offset).ok_or_else(broken);
+        let method = field(10)?;
+        let crc = u32_at(bytes, at + 16).ok_or_else(broken)?;
+        let compressed = u32_at(bytes, at + 20).ok_or_else(broken)? as usize;
+        let (name_len, extra_len, comment_len) = (field(28)?, field(30)?, field(32)?);
+        let local = u32_at(bytes, at + 42).ok_or_else(broken)? as usize;
+        let name = bytes
+            .get(at + 46..at + 46 + name_len as usize)
+            .ok_or_else(broken)?;
+        let name = String::from_utf8_lossy(name).to_string();
+        at += 46 + name_len as usize + extra_len as usize + comment_len as usize;
+        if name.ends_with('/') {
+            continue;
+        }
+
+        let data = (|| {
+            if u32_at(bytes, local) != Some(0x0403_4b50) {
+                return Err("has no local header".to_string());
+            }
+            let skip = 30
+                + u16_at(bytes, local + 26).unwrap_or(0) as usize
+                + u16_at(bytes, local + 28).unwrap_or(0) as usize;
+            let raw = bytes
+                .get(local + skip..local + skip + compressed)
+                .ok_or("is truncated")?;
+            let data = match method {
+                0 if raw.len() > left => return Err(too_large(limit)),
+                0 => raw.to_vec(),
+                8 => {
+                    inflate(raw, left)
+                        .map_err(|err| inflate_error(err, limit))?
+                        .0
+                }
+                _ => return Err(format!("uses compression method {}", method)),
+            };
+            match crc32(&data) == crc {
+                true => {
+                    left -= data.len();
+                    Ok(data)
+                }
+                false => Err("fails its CRC check".to_string()),
+            }
+        })();
+        rst.push(Entry {
+            name: PathBuf::from(name),
+            data,
+        });
+    }
+    Ok(rst)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(name: &str, kind: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}\0", size);
+        header[124..136].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    fn block(data: &[u8]) -> Vec<u8> {
+        let mut block = data.to_vec();
+        block.resize(data.len().div_ceil(512) * 512, 0);
+        block
+    }
+
+    #[test]
+    fn test_is_archive() {
+        assert!(is_archive(Path::new("release-1.4.tar.gz")));
+        assert!(is_archive(Path::new("a/b.TGZ")));
+        assert!(is_archive(Path::new("src.zip")));
+        assert!(!is_archive(Path::new("main.rs")));
+        assert!(!is_archive(Path::new("notes.gz")));
+    }
+
+    #[test]
+    fn test_tar() {
+        let long = format!("{}/main.rs", "a".repeat(120));
+        let mut bytes = header("src/", b'5', 0);
+        bytes.extend(header("src/main.rs", b'0', 13));
+        bytes.extend(block(b"fn main() {}\n"));
+        bytes.extend(header("././@LongLink", b'L', long.len()));
+        bytes.extend(block(long.as_bytes()));
+        bytes.extend(header("short", b'0', 2));
+        bytes.extend(block(b"a\n"));
+        bytes.extend(vec![0; 1024]);
+
+        let entries = tar(&bytes).unwrap();
+        let names = entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, [PathBuf::from("src/main.rs"), PathBuf::from(&long)]);
+        assert_eq!(entries[0].data, Ok(b"fn main() {}\n".to_vec()));
+
+        assert!(tar(&header("cut.rs", b'0', 600)).is_err());
+    }
+
+    #[test]
+    fn test_gunzip() {
+        // "hello hello hello\n" gzipped with a file name
+        let mut bytes = vec![0x1f, 0x8b, 8, 8, 0, 0, 0, 0, 0, 3];
+        bytes.extend(b"a.txt\0");
+        bytes.extend([
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00,
+        ]);
+        bytes.extend(0xdf8a7c3bu32.to_le_bytes());
+        bytes.extend(18u32.to_le_bytes());
+        assert_eq!(gunzip(&bytes, LIMIT), Ok(b"hello hello hello\n".to_vec()));
+        assert_eq!(
+            gunzip(&bytes, 17),
+            Err("decompresses past the 17B an archive is limited to".to_string())
+        );
+
+        let at = bytes.len() - 8;
+        bytes[at] ^= 1;
+        assert_eq!(
+            gunzip(&bytes, LIMIT),
+            Err("fails its CRC check".to_string())
+        );
+        assert!(gunzip(b"plain text, not gzip", LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_zip() {
+        // A stored entry, one with an unknown method and a deflated one
+        let hello = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00,
+        ];
+        let mut bytes = vec![];
+        let mut directory = vec![];
+        for (name, method, data, crc) in [
+            ("a.rs", 0u16, &b"fn a() {}\n"[..], crc32(b"fn a() {}\n")),
+            ("b.rs", 99, b"??", 0),
+            ("c.txt", 8, &hello, 0xdf8a7c3b),
+        ] {
+            let local = bytes.len() as u32;
+            bytes.extend(0x0403_4b50u32.to_le_bytes());
+            bytes.extend([0; 22]);
+            bytes.extend((name.len() as u16).to_le_bytes());
+            bytes.extend(0u16.to_le_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.extend(data);
+
+            directory.extend(0x0201_4b50u32.to_le_bytes());
+            directory.extend([0; 6]);
+            directory.extend(method.to_le_bytes());
+            directory.extend([0; 4]);
+            directory.extend(crc.to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0; 12]);
+            directory.extend(local.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let offset = bytes.len() as u32;
+        bytes.extend(&directory);
+        bytes.extend(0x0605_4b50u32.to_le_bytes());
+        bytes.extend([0; 6]);
+        bytes.extend(3u16.to_le_bytes());
+        bytes.extend((directory.len() as u32).to_le_bytes());
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+
+        let entries = zip(&bytes, LIMIT).unwrap();
+        assert_eq!(entries[0].name, PathBuf::from("a.rs"));
+        assert_eq!(entries[0].data, Ok(b"fn a() {}\n".to_vec()));
+        assert_eq!(
+            entries[1].data,
+            Err("uses compression method 99".to_string())
+        );
+        assert_eq!(entries[2].data, Ok(b"hello hello hello\n".to_vec()));
+
+        // What is left of the limit after `a.rs` is too little for `c.txt`
+        let entries = zip(&bytes, 20).unwrap();
+        assert!(entries[0].data.is_ok());
+        assert_eq!(
+            entries[2].data,
+            Err("decompresses past the 20B an archive is limited to".to_string())
+        );
+        let entries = zip(&bytes, 9).unwrap();
+        assert!(entries[0].data.is_err());
+        assert!(entries[2].data.is_err());
+        assert!(zip(b"not a zip", LIMIT).is_err());
+    }
+}
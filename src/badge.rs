@@ -0,0 +1,112 @@
+use crate::format::{compact_number, compact_size};
+use crate::output::{xml_escape, Output};
+
+#[derive(Debug)]
+pub struct Badge {
+    pub label: String,
+    pub metric: Metric,
+}
+
+impl Default for Badge {
+    fn default() -> Self {
+        Self {
+            label: "lines of code".to_string(),
+            metric: Metric::Code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Metric {
+    Code,
+    Comment,
+    Files,
+    Size,
+}
+
+impl std::str::FromStr for Metric {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "code" => Ok(Metric::Code),
+            "comment" => Ok(Metric::Comment),
+            "files" => Ok(Metric::Files),
+            "size" => Ok(Metric::Size),
+            _ => Err(()),
+        }
+    }
+}
+
+// Average width of a Verdana 11px character, close enough for short labels
+const CHAR_WIDTH: usize = 7;
+const PADDING: usize = 10;
+
+impl Badge {
+    pub fn value(&self, output: &Output) -> String {
+        match self.metric {
+            Metric::Code => compact_number(output.total_code),
+            Metric::Comment => compact_number(output.total_comment),
+            Metric::Files => compact_number(output.total_file),
+            Metric::Size => compact_size(output.total_size),
+        }
+    }
+
+    pub fn svg(&self, value: &str) -> String {
+        let label_width = self.label.chars().count() * CHAR_WIDTH + PADDING;
+        let value_width = value.chars().count() * CHAR_WIDTH + PADDING;
+        let label = xml_escape(&self.label);
+        let value = xml_escape(value);
+        let width = label_width + value_width;
+        let label_x = label_width as f64 / 2.0;
+        let value_x = label_width as f64 + value_width as f64 / 2.0;
+
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}">
+  <title>{label}: {value}</title>
+  <linearGradient id="s" x2="0" y2="100%">
+    <stop offset="0" stop-color="#bbb" stop-opacity=".1"/>
+    <stop offset="1" stop-opacity=".1"/>
+  </linearGradient>
+  <clipPath id="r">
+    <rect width="{width}" height="20" rx="3" fill="#fff"/>
+  </clipPath>
+  <g clip-path="url(#r)">
+    <rect width="{label_width}" height="20" fill="#555"/>
+    <rect x="{label_width}" width="{value_width}" height="20" fill="#007ec6"/>
+    <rect width="{width}" height="20" fill="url(#s)"/>
+  </g>
+  <g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+    <text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text>
+    <text x="{label_x}" y="14">{label}</text>
+    <text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text>
+    <text x="{value_x}" y="14">{value}</text>
+  </g>
+</svg>"##,
+            width = width,
+            label = label,
+            value = value,
+            label_width = label_width,
+            value_width = value_width,
+            label_x = label_x,
+            value_x = value_x,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_svg() {
+        let badge = Badge {
+            label: "code & <docs>".to_string(),
+            metric: Metric::Code,
+        };
+        let svg = badge.svg("12.3k");
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"146\""));
+        assert!(svg.contains("<text x=\"50.5\" y=\"14\">code &amp; &lt;docs&gt;</text>"));
+        assert!(svg.contains(">12.3k</text>"));
+        assert!(svg.ends_with("</svg>"));
+    }
+}
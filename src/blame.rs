@@ -0,0 +1,231 @@
+// `--by-author`: the code lines of each file by who last changed them, from `git blame`
+use crate::config::Language;
+use crate::format::group_digits;
+use crate::output::box_table;
+use crate::parse::{classify, Kind};
+use glob::Pattern;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+// Lines git doesn't know about, of an untracked file or changed since the last commit
+pub const UNCOMMITTED: &str = "(uncommitted)";
+
+// Shared by the workers, each adds the files it counted
+pub struct Authors {
+    // `--author`
+    pattern: Option<Pattern>,
+    // Code lines by author and language
+    counts: Mutex<BTreeMap<(String, &'static str), i64>>,
+}
+
+impl Authors {
+    pub fn new(pattern: Option<Pattern>) -> Self {
+        Self {
+            pattern,
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn add(&self, path: &Path, config: &Language) {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+        let authors = blame(path);
+        let mut counts = BTreeMap::new();
+        let mut line = 0;
+        classify(&content, config, |kind| {
+            if kind == Kind::Code {
+                let author = authors
+                    .as_ref()
+                    .and_then(|authors| authors.get(line))
+                    .map(String::as_str)
+                    .unwrap_or(UNCOMMITTED);
+                *counts.entry(author).or_insert(0) += 1;
+            }
+            line += 1;
+        });
+
+        let mut total = self.counts.lock().unwrap();
+        for (author, code) in counts {
+            if self.pattern.as_ref().is_none_or(|p| p.matches(author)) {
+                *total.entry((author.to_string(), config.name)).or_insert(0) += code;
+            }
+        }
+    }
+
+    // Most code first
+    pub fn rows(self) -> Vec<(String, &'static str, i64)> {
+        let mut rows = self
+            .counts
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|((author, language), code)| (author, language, code))
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (&a.0, a.1).cmp(&(&b.0, b.1))));
+        rows
+    }
+}
+
+// The email of each line, `None` outside a repository or for an untracked file
+fn blame(path: &Path) -> Option<Vec<String>> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["blame", "--line-porcelain", "--"])
+        .arg(path.file_name()?)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_porcelain(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Every line is a header, `author-mail <a@b.c>` among others, then the line after a tab
+fn parse_porcelain(porcelain: &str) -> Vec<String> {
+    let mut rst = vec![];
+    let mut author = UNCOMMITTED;
+    for line in porcelain.lines() {
+        if line.starts_with('\t') {
+            rst.push(author.to_string());
+        } else if let Some(mail) = line.strip_prefix("author-mail ") {
+            author = match mail.trim_start_matches('<').trim_end_matches('>') {
+                "not.committed.yet" => UNCOMMITTED,
+                mail => mail,
+            };
+        }
+    }
+    rst
+}
+
+// Long author names are cut to fit `width`
+pub fn table(rows: &[(String, &'static str, i64)], separator: &str, width: usize) -> Vec<String> {
+    let total = rows.iter().map(|row| row.2).sum::<i64>();
+    let header = ["Author", "Language", "Code"].map(String::from);
+    let cells = rows
+        .iter()
+        .map(|(author, language, code)| {
+            vec![
+                author.clone(),
+                language.to_string(),
+                group_digits(code, separator),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+    let total = [
+        "Total".to_string(),
+        String::new(),
+        group_digits(total, separator),
+    ];
+    box_table(&header, &cells, Some(&total), 2, 0, width)
+}
+
+// `-o json`, the authors with the most code first
+#[derive(Serialize)]
+struct JsonAuthors<'a> {
+    authors: Vec<JsonAuthor<'a>>,
+    total: JsonTotal,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    author: &'a str,
+    language: &'a str,
+    code: i64,
+}
+
+#[derive(Serialize)]
+struct JsonTotal {
+    code: i64,
+}
+
+pub fn json(rows: &[(String, &'static str, i64)]) -> Vec<String> {
+    let report = JsonAuthors {
+        authors: rows
+            .iter()
+            .map(|(author, language, code)| JsonAuthor {
+                author,
+                language,
+                code: *code,
+            })
+            .collect(),
+        total: JsonTotal {
+            code: rows.iter().map(|row| row.2).sum(),
+        },
+    };
+    vec![serde_json::to_string_pretty(&report).unwrap()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::text::display_width;
+
+    #[test]
+    fn test_parse_porcelain() {
+        let porcelain = "\
+1f2e3d 1 1 2
+author Ann
+author-mail <ann@example.com>
+summary one
+filename a.rs
+\tfn main() {
+1f2e3d 2 2
+author Ann
+author-mail <ann@example.com>
+filename a.rs
+\t}
+0000000 3 3 1
+author Not Committed Yet
+author-mail <not.committed.yet>
+filename a.rs
+\t// new
+";
+        assert_eq!(
+            parse_porcelain(porcelain),
+            ["ann@example.com", "ann@example.com", UNCOMMITTED]
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let rows = vec![
+            ("ann@example.com".to_string(), "Rust", 1200),
+            (UNCOMMITTED.to_string(), "Go", 3),
+        ];
+        assert_eq!(
+            table(&rows, ",", 80),
+            [
+                "╭──────────────────────────────────╮",
+                "│ Author           Language   Code │",
+                "├──────────────────────────────────┤",
+                "│ ann@example.com  Rust      1,200 │",
+                "│ (uncommitted)    Go            3 │",
+                "├──────────────────────────────────┤",
+                "│ Total                      1,203 │",
+                "╰──────────────────────────────────╯",
+            ]
+        );
+
+        // Wide names, cut on a narrow terminal
+        let wide = vec![("李小龙 <li@example.com>".to_string(), "Rust", 1)];
+        let data = table(&wide, ",", 80);
+        assert_eq!(data[3], "│ 李小龙 <li@example.com>  Rust         1 │");
+        assert!(data.iter().all(|line| display_width(line) == 43));
+        let data = table(&wide, ",", 30);
+        assert_eq!(data[3], "│ 李小龙 <l…  Rust         1 │");
+        assert!(data.iter().all(|line| display_width(line) == 30));
+        let value: serde_json::Value = serde_json::from_str(&json(&rows).join("\n")).unwrap();
+        assert_eq!(value["authors"][1]["author"], UNCOMMITTED);
+        assert_eq!(value["total"]["code"], 1203);
+    }
+}
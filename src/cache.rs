@@ -0,0 +1,328 @@
+// `--cache`: the counts of the last run, a file with the same mtime and size
+// isn't read again
+use crate::config::{Language, CONFIG};
+use crate::output::AtomicFile;
+use crate::parse::Data;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Bumped when the file changes shape, an older cache is thrown away
+const VERSION: u32 = 1;
+
+// A file changed again within this long of its mtime may keep the same one
+const RACY: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    version: u32,
+    // Changes with the languages and the counting rules
+    fingerprint: String,
+    files: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    // Seconds and nanoseconds since the epoch
+    mtime: (u64, u32),
+    size: u64,
+    // The language the walk picked and the one it was counted as, they
+    // differ when the heuristics turn a `.h` file into C++
+    config: String,
+    language: String,
+    blank: u64,
+    comment: u64,
+    code: u64,
+}
+
+// What a file looked like before it was read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    mtime: (u64, u32),
+    size: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let meta = fs::metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            mtime: (mtime.as_secs(), mtime.subsec_nanos()),
+            size: meta.len(),
+        })
+    }
+}
+
+pub struct Cache {
+    path: PathBuf,
+    fingerprint: String,
+    // Keys are absolute so the cache works from any directory
+    cwd: PathBuf,
+    started: SystemTime,
+    old: HashMap<PathBuf, Entry>,
+    new: Mutex<HashMap<PathBuf, Entry>>,
+    pub hits: AtomicUsize,
+    pub misses: AtomicUsize,
+}
+
+impl Cache {
+    // An unreadable or outdated cache is the same as none
+    pub fn load(path: PathBuf, heuristics: bool) -> Cache {
+        let fingerprint = fingerprint(CONFIG.all_language(), heuristics);
+        let old = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Stored>(&bytes).ok())
+            .filter(|stored| stored.version == VERSION && stored.fingerprint == fingerprint)
+            .map(|stored| stored.files)
+            .unwrap_or_default();
+        Cache {
+            path,
+            fingerprint,
+            cwd: env::current_dir().unwrap_or_default(),
+            started: SystemTime::now(),
+            old,
+            new: Mutex::new(HashMap::new()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    // The counts of the last run, or the stamp to `insert` the new ones with
+    pub fn get(&self, path: &Path, config: &Language) -> Result<Data, Option<Stamp>> {
+        let key = self.cwd.join(path);
+        let stamp = Stamp::of(path);
+        let entry = match (self.old.get(&key), stamp) {
+            (Some(entry), Some(stamp))
+                if entry.mtime == stamp.mtime
+                    && entry.size == stamp.size
+                    && entry.config == config.name =>
+            {
+                entry
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return Err(stamp);
+            }
+        };
+        let language = match entry.language == config.name {
+            true => Some(config.name),
+            false => CONFIG.find(&entry.language).map(|language| language.name),
+        };
+        let language = match language {
+            Some(language) => language,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return Err(stamp);
+            }
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.new.lock().unwrap().insert(key, entry.clone());
+        Ok(Data {
+            path: path.to_path_buf(),
+            language,
+            blank: entry.blank,
+            comment: entry.comment,
+            code: entry.code,
+            size: entry.size,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn insert(&self, stamp: Stamp, config: &Language, data: &Data) {
+        // Written in the same tick as the cache was made, a later write
+        // could keep this mtime
+        let mtime = UNIX_EPOCH + Duration::new(stamp.mtime.0, stamp.mtime.1);
+        if mtime + RACY > self.started {
+            return;
+        }
+        let entry = Entry {
+            mtime: stamp.mtime,
+            size: stamp.size,
+            config: config.name.to_string(),
+            language: data.language.to_string(),
+            blank: data.blank,
+            comment: data.comment,
+            code: data.code,
+        };
+        self.new
+            .lock()
+            .unwrap()
+            .insert(self.cwd.join(&data.path), entry);
+    }
+
+    // Files of the last run this one didn't reach are kept while they exist,
+    // a run with `--language` shouldn't forget the others
+    pub fn save(self) -> io::Result<()> {
+        let mut files = self.new.into_inner().unwrap();
+        for (path, entry) in self.old {
+            if !files.contains_key(&path) && path.is_file() {
+                files.insert(path, entry);
+            }
+        }
+        let stored = Stored {
+            version: VERSION,
+            fingerprint: self.fingerprint,
+            files,
+        };
+        let mut file = AtomicFile::create(&self.path)?;
+        let mut writer = BufWriter::new(&mut file);
+        serde_json::to_writer(&mut writer, &stored)?;
+        writer.flush()?;
+        drop(writer);
+        file.commit()
+    }
+}
+
+// `$XDG_CACHE_HOME/rots/`, else `~/.cache/rots/`, a file for each set of paths
+pub fn default_path(work_dirs: &[PathBuf], files: &[PathBuf]) -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    let mut hasher = DefaultHasher::new();
+    for path in work_dirs.iter().chain(files) {
+        path.canonicalize()
+            .unwrap_or_else(|_| path.clone())
+            .hash(&mut hasher);
+    }
+    Some(
+        dir.join("rots")
+            .join(format!("cache-{:016x}.json", hasher.finish())),
+    )
+}
+
+// Another version of lok may count differently, so may other languages or
+// parsing rules
+fn fingerprint(languages: &[&Language], heuristics: bool) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    heuristics.hash(&mut hasher);
+    for language in languages {
+        language.name.hash(&mut hasher);
+        language.extension.hash(&mut hasher);
+        language.filename.hash(&mut hasher);
+        language.single.hash(&mut hasher);
+        language.multi.hash(&mut hasher);
+        language.nested.hash(&mut hasher);
+        language.line_start.hash(&mut hasher);
+        language.syntax.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lok-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn data(path: &Path, code: u64) -> Data {
+        Data {
+            path: path.to_path_buf(),
+            language: "Rust",
+            blank: 1,
+            comment: 2,
+            code,
+            size: 7,
+        }
+    }
+
+    // An mtime old enough to be cached
+    fn old_file(path: &Path) -> Stamp {
+        fs::write(path, "fn a() {}").unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        Stamp::of(path).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("round-trip");
+        let rust = CONFIG.find("Rust").unwrap();
+        let source = dir.join("a.rs");
+        let path = dir.join("cache.json");
+
+        let cache = Cache::load(path.clone(), true);
+        let stamp = cache.get(&source, rust).unwrap_err();
+        assert_eq!(stamp, None);
+        let stamp = old_file(&source);
+        cache.insert(stamp, rust, &data(&source, 5));
+        cache.save().unwrap();
+
+        let cache = Cache::load(path.clone(), true);
+        let hit = cache.get(&source, rust).unwrap();
+        assert_eq!(hit.code, 5);
+        assert_eq!(hit.language, "Rust");
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 1);
+
+        // Another language for the file, then other flags
+        let c = CONFIG.find("C").unwrap();
+        assert!(cache.get(&source, c).is_err());
+        assert!(Cache::load(path.clone(), false).old.is_empty());
+
+        // Same size, a newer mtime
+        fs::write(&source, "fn b() {}").unwrap();
+        assert!(cache.get(&source, rust).is_err());
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recent_and_outdated() {
+        let dir = temp_dir("recent");
+        let rust = CONFIG.find("Rust").unwrap();
+        let source = dir.join("a.rs");
+        let path = dir.join("cache.json");
+
+        // Just written, may change again within the same mtime
+        fs::write(&source, "fn a() {}").unwrap();
+        let cache = Cache::load(path.clone(), true);
+        let stamp = cache.get(&source, rust).unwrap_err().unwrap();
+        cache.insert(stamp, rust, &data(&source, 5));
+        assert!(cache.new.lock().unwrap().is_empty());
+
+        // Another format
+        let stamp = old_file(&source);
+        let cache = Cache::load(path.clone(), true);
+        cache.insert(stamp, rust, &data(&source, 5));
+        cache.save().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace("\"version\":1", "\"version\":0")).unwrap();
+        assert!(Cache::load(path.clone(), true).old.is_empty());
+        fs::write(&path, "not json").unwrap();
+        assert!(Cache::load(path, true).old.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let rust = *CONFIG.find("Rust").unwrap();
+        let plain = Language {
+            nested: &[],
+            line_start: false,
+            syntax: None,
+            ..rust
+        };
+        let tokens_only = fingerprint(&[&plain], true);
+        assert_ne!(fingerprint(&[&rust], true), tokens_only);
+        assert_ne!(fingerprint(&[&plain], false), tokens_only);
+        assert_eq!(fingerprint(&[&plain], true), tokens_only);
+    }
+}
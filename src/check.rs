@@ -0,0 +1,335 @@
+// `lok check`: fail when the count goes over the limits of the config or the flags
+use crate::output::{json_string, Format, Output};
+use crate::Detail;
+use serde::Deserialize;
+
+// What `--fail-over` and `--fail-under` compare, the totals of the report
+pub const METRICS: [&str; 5] = ["code", "comment", "blank", "file", "size"];
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub max_code: Option<u64>,
+    pub max_files: Option<u64>,
+    // Comment lines per code line
+    pub max_comment_ratio: Option<f64>,
+}
+
+impl Limits {
+    // The flags win over the config, one limit at a time
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            max_code: self.max_code.or(other.max_code),
+            max_files: self.max_files.or(other.max_files),
+            max_comment_ratio: self.max_comment_ratio.or(other.max_comment_ratio),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.max_code.is_some() as usize
+            + self.max_files.is_some() as usize
+            + self.max_comment_ratio.is_some() as usize
+    }
+}
+
+#[derive(Debug)]
+pub struct Check {
+    // Limits of the whole count
+    pub total: Limits,
+    // `[limits.NAME]`, names as they are in the config
+    pub languages: Vec<(&'static str, Limits)>,
+    pub format: Format,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    // `None` for the whole count
+    pub language: Option<&'static str>,
+    pub limit: &'static str,
+    pub value: String,
+    pub max: String,
+}
+
+// Prints the violations, the exit code is 1 if there are any
+pub fn run(check: &Check, details: &[Detail]) -> i32 {
+    let violations = violations(check, details);
+    let data = match check.format {
+        Format::Json => json(&violations),
+        _ => table(check, &violations),
+    };
+    println!("{}", data.join("\n"));
+    !violations.is_empty() as i32
+}
+
+pub fn violations(check: &Check, details: &[Detail]) -> Vec<Violation> {
+    let mut total = Detail::new("Total");
+    for detail in details {
+        total.comment += detail.comment;
+        total.code += detail.code;
+        total.file += detail.file;
+    }
+    let mut rst = exceeded(None, &check.total, &total);
+    for (name, limits) in &check.languages {
+        // A language without files has nothing over any limit
+        if let Some(detail) = details.iter().find(|detail| detail.name == *name) {
+            rst.extend(exceeded(Some(name), limits, detail));
+        }
+    }
+    rst
+}
+
+fn exceeded(language: Option<&'static str>, limits: &Limits, detail: &Detail) -> Vec<Violation> {
+    let violation = |limit, value: String, max: String| Violation {
+        language,
+        limit,
+        value,
+        max,
+    };
+    let mut rst = vec![];
+    if let Some(max) = limits.max_code.filter(|max| detail.code > *max) {
+        rst.push(violation("code", detail.code.to_string(), max.to_string()));
+    }
+    if let Some(max) = limits.max_files.filter(|max| detail.file > *max) {
+        rst.push(violation("files", detail.file.to_string(), max.to_string()));
+    }
+    // No ratio without code, e.g. Markdown is all comments
+    if let (Some(max), true) = (limits.max_comment_ratio, detail.code > 0) {
+        let ratio = detail.comment as f64 / detail.code as f64;
+        if ratio > max {
+            rst.push(violation(
+                "comment_ratio",
+                format!("{:.2}", ratio),
+                max.to_string(),
+            ));
+        }
+    }
+    rst
+}
+
+fn table(check: &Check, violations: &[Violation]) -> Vec<String> {
+    let count = check.total.len()
+        + check
+            .languages
+            .iter()
+            .map(|(_, limits)| limits.len())
+            .sum::<usize>();
+    if violations.is_empty() {
+        return vec![format!("No limit exceeded, {} checked", count)];
+    }
+
+    let name = |v: &Violation| v.language.unwrap_or("Total");
+    let n = violations.iter().map(|v| name(v).len()).max().unwrap();
+    let l = violations.iter().map(|v| v.limit.len()).max().unwrap();
+    let mut data = violations
+        .iter()
+        .map(|v| {
+            format!(
+                "{:n$}    {:l$}    {} > {}",
+                name(v),
+                v.limit,
+                v.value,
+                v.max,
+                n = n,
+                l = l
+            )
+        })
+        .collect::<Vec<String>>();
+    data.push(format!("{} of {} limits exceeded", violations.len(), count));
+    data
+}
+
+fn json(violations: &[Violation]) -> Vec<String> {
+    let items = violations
+        .iter()
+        .map(|v| {
+            format!(
+                "    {{ \"language\": {}, \"limit\": {}, \"value\": {}, \"max\": {} }}",
+                v.language
+                    .map(json_string)
+                    .unwrap_or_else(|| "null".to_string()),
+                json_string(v.limit),
+                v.value,
+                v.max
+            )
+        })
+        .collect::<Vec<String>>();
+
+    if items.is_empty() {
+        return vec!["{ \"violations\": [] }".to_string()];
+    }
+    vec![
+        "{".to_string(),
+        "  \"violations\": [".to_string(),
+        items.join(",\n"),
+        "  ]".to_string(),
+        "}".to_string(),
+    ]
+}
+
+// `--fail-over code=50000` or `--fail-under comment=100`
+#[derive(Debug, PartialEq)]
+pub struct Threshold {
+    // Fails above the value, otherwise below it
+    pub over: bool,
+    // One of `METRICS`
+    pub metric: &'static str,
+    pub value: u64,
+}
+
+// A line for each threshold the totals are on the wrong side of
+pub fn failures(thresholds: &[Threshold], output: &Output) -> Vec<String> {
+    thresholds
+        .iter()
+        .filter_map(|threshold| {
+            let actual = match threshold.metric {
+                "code" => output.total_code,
+                "comment" => output.total_comment,
+                "blank" => output.total_blank,
+                "file" => output.total_file,
+                _ => output.total_size,
+            };
+            let (flag, side) = match threshold.over {
+                true if actual > threshold.value => ("--fail-over", "over"),
+                false if actual < threshold.value => ("--fail-under", "under"),
+                _ => return None,
+            };
+            Some(format!(
+                "{} is {}, {} {} {}={}",
+                threshold.metric, actual, side, flag, threshold.metric, threshold.value
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn detail(name: &str, code: u64, comment: u64, file: u64) -> Detail {
+        Detail {
+            code,
+            comment,
+            file,
+            ..Detail::new(name)
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let details = [detail("Rust", 80, 10, 4), detail("Markdown", 0, 50, 3)];
+        let check = Check {
+            total: Limits {
+                max_code: Some(100),
+                max_files: Some(5),
+                ..Limits::default()
+            },
+            languages: vec![
+                (
+                    "Rust",
+                    Limits {
+                        max_code: Some(50),
+                        max_comment_ratio: Some(0.1),
+                        ..Limits::default()
+                    },
+                ),
+                (
+                    "Markdown",
+                    Limits {
+                        max_comment_ratio: Some(0.1),
+                        ..Limits::default()
+                    },
+                ),
+                (
+                    "Go",
+                    Limits {
+                        max_code: Some(0),
+                        ..Limits::default()
+                    },
+                ),
+            ],
+            format: Format::Table,
+        };
+        let violations = violations(&check, &details);
+        let found = violations
+            .iter()
+            .map(|v| (v.language, v.limit, v.value.as_str(), v.max.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (None, "files", "7", "5"),
+                (Some("Rust"), "code", "80", "50"),
+                (Some("Rust"), "comment_ratio", "0.12", "0.1"),
+            ]
+        );
+        assert_eq!(
+            table(&check, &violations),
+            [
+                "Total    files            7 > 5",
+                "Rust     code             80 > 50",
+                "Rust     comment_ratio    0.12 > 0.1",
+                "3 of 6 limits exceeded",
+            ]
+        );
+        assert_eq!(table(&check, &[]), ["No limit exceeded, 6 checked"]);
+    }
+
+    #[test]
+    fn test_failures() {
+        let output = Output::new(vec![detail("Rust", 80, 10, 4), detail("Go", 20, 0, 1)]);
+        let threshold = |over, metric, value| Threshold {
+            over,
+            metric,
+            value,
+        };
+        let thresholds = [
+            threshold(true, "code", 100),
+            threshold(true, "code", 99),
+            threshold(false, "comment", 11),
+            threshold(false, "file", 5),
+            threshold(true, "size", 0),
+        ];
+        assert_eq!(
+            failures(&thresholds, &output),
+            [
+                "code is 100, over --fail-over code=99",
+                "comment is 10, under --fail-under comment=11",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits_or() {
+        let flags = Limits {
+            max_code: Some(1),
+            ..Limits::default()
+        };
+        let config = Limits {
+            max_code: Some(2),
+            max_files: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(
+            flags.or(config),
+            Limits {
+                max_code: Some(1),
+                max_files: Some(3),
+                max_comment_ratio: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let violations = [Violation {
+            language: Some("Rust"),
+            limit: "code",
+            value: "80".to_string(),
+            max: "50".to_string(),
+        }];
+        let value: serde_json::Value = serde_json::from_str(&json(&violations).join("\n")).unwrap();
+        assert_eq!(value["violations"][0]["language"], "Rust");
+        assert_eq!(value["violations"][0]["value"], 80);
+        assert_eq!(json(&[]), ["{ \"violations\": [] }"]);
+    }
+}
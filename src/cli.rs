@@ -1,16 +1,179 @@
+use crate::archive;
+use crate::badge::{Badge, Metric};
+use crate::cache;
+use crate::check::{self, Check, Limits, Threshold};
+use crate::config::{self, Language, Mapping, Problem, CONFIG};
+use crate::diff::{self, DiffOptions};
+use crate::format::{self, NumberFormat, SizeStyle, SizeUnit};
+use crate::git::{self, GitDiff};
+use crate::man;
 use crate::output::Format;
-use crate::{exit, print_language_list, Sort};
-use clap::{crate_name, crate_version, value_t_or_exit, App, AppSettings, Arg, SubCommand};
+use crate::settings::{self, Settings};
+use crate::text;
+use crate::{exit, print_language_json, print_language_list, Color, Group, Order, Sort};
+use clap::{
+    crate_name, crate_version, value_t_or_exit, App, AppSettings, Arg, ArgMatches, Shell,
+    SubCommand,
+};
 use glob::Pattern;
+use lok::{CountOptions, CountOptionsBuilder};
+use std::fs;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const FORMATS: [&str; 10] = [
+    "table",
+    "html",
+    "html-report",
+    "markdown",
+    "csv",
+    "json",
+    "yaml",
+    "xml",
+    "jsonl",
+    "svg-badge",
+];
+
+pub const SORTS: [&str; 7] = [
+    "language", "code", "comment", "blank", "file", "size", "lines",
+];
+
+// Environment variables read as defaults, see `env_list`
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
 
 pub fn parse() -> Options {
-    let app = App::new(crate_name!())
+    parse_from(std::env::args_os(), &|name| std::env::var(name).ok())
+}
+
+// The whole command line, also the source of the shell completions
+pub fn app() -> App<'static, 'static> {
+    App::new(crate_name!())
         .version(crate_version!())
         .global_setting(AppSettings::ColoredHelp)
         .setting(AppSettings::VersionlessSubcommands)
-        .subcommand(SubCommand::with_name("ls").about("Print a list of supported languages"))
-        .arg(Arg::with_name("directory").help("Calculate the specified directory"))
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("Print a list of supported languages")
+                .arg(
+                    Arg::with_name("aliases")
+                        .long("aliases")
+                        .help("Also print the other names '--language' accepts"),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .long("verbose")
+                        .short("v")
+                        .help("Also print the single and multi-line comment tokens"),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .value_name("QUERY")
+                        .help("Only print languages whose name or extension contains this"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FORMAT")
+                        .possible_values(&["table", "json"])
+                        .default_value("table")
+                        .help("Print the languages as a table or as json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print the completion script of a shell")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&Shell::variants())
+                        .help("The shell to complete for"),
+                )
+                .after_help(
+                    "Install:\n    \
+                     bash:        lok completions bash > ~/.local/share/bash-completion/completions/lok\n    \
+                     zsh:         lok completions zsh > \"${fpath[1]}/_lok\"\n    \
+                     fish:        lok completions fish > ~/.config/fish/completions/lok.fish\n    \
+                     powershell:  lok completions powershell >> $PROFILE",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gen-man")
+                .about("Print the man page as roff")
+                .setting(AppSettings::Hidden),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the languages, built-in and from config files, for mistakes"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Count and exit with 1 if the count goes over the limits, for CI")
+                .arg(
+                    Arg::with_name("max-code")
+                        .long("max-code")
+                        .value_name("N")
+                        .help("Most code lines of the whole count, over 'max_code' of the config"),
+                )
+                .arg(
+                    Arg::with_name("max-files")
+                        .long("max-files")
+                        .value_name("N")
+                        .help("Most files of the whole count, over 'max_files' of the config"),
+                )
+                .arg(
+                    Arg::with_name("max-comment-ratio")
+                        .long("max-comment-ratio")
+                        .value_name("X")
+                        .help("Most comment lines per code line, over 'max_comment_ratio' of the config"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .possible_values(&["table", "json"])
+                        .default_value("table")
+                        .hide_default_value(true)
+                        .help("Print the violated limits as a table or as json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare two json reports")
+                .arg(
+                    Arg::with_name("files")
+                        .long("files")
+                        .required(true)
+                        .help("Compare per-file reports, renamed files show as removed and added"),
+                )
+                .arg(Arg::with_name("old").required(true).help("Previous report"))
+                .arg(Arg::with_name("new").required(true).help("Current report"))
+                .arg(
+                    Arg::with_name("threshold")
+                        .long("threshold")
+                        .value_name("LINES")
+                        .default_value("0")
+                        .hide_default_value(true)
+                        .help("Hide files whose code lines changed by less than this"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .possible_values(&["table", "markdown", "json"])
+                        .default_value("table")
+                        .hide_default_value(true)
+                        .help("Specify output format"),
+                ),
+        )
+        .arg(
+            Arg::with_name("directory")
+                .multiple(true)
+                .help("Calculate the specified directories or files, counted into one report"),
+        )
         .arg(
             Arg::with_name("error")
                 .long("error")
@@ -24,6 +187,32 @@ pub fn parse() -> Options {
                 .multiple(true)
                 .help("Exclude files using 'glob' matching"),
         )
+        .arg(
+            Arg::with_name("exclude-from")
+                .long("exclude-from")
+                .value_name("FILE")
+                .help("Read exclude globs from FILE, one per line; .rotsignore is read by default"),
+        )
+        .arg(
+            Arg::with_name("files-from")
+                .long("files-from")
+                .value_name("FILE")
+                .help("Count the files listed in FILE, one per line, instead of walking; '-' reads stdin"),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .value_name("EXT")
+                .conflicts_with("files-from")
+                .help("Count stdin as a single file with the extension EXT"),
+        )
+        .arg(
+            Arg::with_name("null")
+                .short("0")
+                .long("null")
+                .requires("files-from")
+                .help("Paths in '--files-from' are separated by NUL, as 'find -print0' writes them"),
+        )
         .arg(
             Arg::with_name("include")
                 .short("i")
@@ -37,23 +226,390 @@ pub fn parse() -> Options {
                 .short("o")
                 .long("output")
                 .value_name("OUTPUT")
-                .possible_values(&["table", "html", "markdown"])
                 .default_value("table")
-                .max_values(1)
+                .multiple(true)
+                .number_of_values(1)
+                .hide_default_value(true)
+                .validator(
+                    |value| match value.split('=').next().unwrap().parse::<Format>() {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err(format!("Expected one of: {}", FORMATS.join(", "))),
+                    },
+                )
+                .help(
+                    "Specify output format, repeatable, FORMAT=FILE writes to a file\n\
+                     [possible values: table, html, html-report, markdown, csv, json, yaml, xml, jsonl, svg-badge]",
+                ),
+        )
+        .arg(
+            Arg::with_name("output-path")
+                .long("output-path")
+                .value_name("FILE")
+                .help("Write the output to a file instead of stdout"),
+        )
+        .arg(
+            Arg::with_name("delimiter")
+                .long("delimiter")
+                .value_name("CHAR")
+                .default_value(",")
+                .hide_default_value(true)
+                .help("Field delimiter for csv output, e.g. '\\t'"),
+        )
+        .arg(
+            Arg::with_name("badge-label")
+                .long("badge-label")
+                .value_name("TEXT")
+                .default_value("lines of code")
+                .help("Label of the svg badge"),
+        )
+        .arg(
+            Arg::with_name("badge-metric")
+                .long("badge-metric")
+                .value_name("METRIC")
+                .possible_values(&["code", "comment", "files", "size"])
+                .default_value("code")
                 .hide_default_value(true)
-                .help("Specify output format"),
+                .help("Number shown on the svg badge"),
         )
         .arg(
             Arg::with_name("sort")
                 .short("s")
                 .long("sort")
                 .value_name("SORT")
-                .possible_values(&["language", "code", "comment", "blank", "file", "size"])
+                .possible_values(&SORTS)
                 .default_value("language")
                 .max_values(1)
                 .hide_default_value(true)
                 .help("Specify the column sort by"),
         )
+        .arg(
+            Arg::with_name("order")
+                .long("order")
+                .value_name("ORDER")
+                .possible_values(&["asc", "desc"])
+                .help("Sort direction, defaults to asc for language and desc otherwise"),
+        )
+        .arg(
+            Arg::with_name("group-by")
+                .long("group-by")
+                .value_name("KEY")
+                .possible_values(&["language", "dir", "extension"])
+                .default_value("language")
+                .hide_default_value(true)
+                .help("Specify what each row counts"),
+        )
+        .arg(
+            Arg::with_name("group-depth")
+                .long("group-depth")
+                .value_name("N")
+                .default_value("1")
+                .hide_default_value(true)
+                .help("Number of leading directories used by '--group-by dir'"),
+        )
+        .arg(
+            Arg::with_name("avg")
+                .long("avg")
+                .help("Show the average code lines per file"),
+        )
+        .arg(
+            Arg::with_name("size-unit")
+                .long("size-unit")
+                .value_name("UNIT")
+                .possible_values(&["binary", "si", "bytes"])
+                .default_value("binary")
+                .hide_default_value(true)
+                .help("Show sizes in KiB, KB or plain bytes"),
+        )
+        .arg(
+            Arg::with_name("number-format")
+                .long("number-format")
+                .value_name("FORMAT")
+                .possible_values(&["comma", "dot", "space", "none"])
+                .default_value("comma")
+                .hide_default_value(true)
+                .help("Thousands separator of numbers in table, html and markdown"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .value_name("WHEN")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .hide_default_value(true)
+                .help("Color the table, auto only on a terminal without NO_COLOR"),
+        )
+        .arg(
+            Arg::with_name("warn-code")
+                .long("warn-code")
+                .value_name("N")
+                .help("Flag rows with more than N code lines"),
+        )
+        .arg(
+            Arg::with_name("warn-comment-ratio")
+                .long("warn-comment-ratio")
+                .value_name("X")
+                .help("Flag rows with more than X comment lines per code line"),
+        )
+        .arg(
+            Arg::with_name("save-snapshot")
+                .long("save-snapshot")
+                .value_name("FILE")
+                .help("Save the totals of every row as json, for a later '--diff'"),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .value_name("FILE")
+                .help("Print what changed since a '--save-snapshot', as a table or with '-o json'"),
+        )
+        .arg(
+            Arg::with_name("by-author")
+                .long("by-author")
+                .help("Count code lines by the author of their last change, from git blame, slow"),
+        )
+        .arg(
+            Arg::with_name("author")
+                .long("author")
+                .value_name("PATTERN")
+                .requires("by-author")
+                .help("Only count the authors whose email matches PATTERN, e.g. '*@example.com'"),
+        )
+        .arg(
+            Arg::with_name("git-diff")
+                .long("git-diff")
+                .value_name("REV1..REV2")
+                .help("Print what changed per language between two git revisions, counted from the blobs"),
+        )
+        .arg(
+            Arg::with_name("fail-over")
+                .long("fail-over")
+                .value_name("METRIC=N")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Exit with 1 after the report if a total is over N, e.g. 'code=50000'"),
+        )
+        .arg(
+            Arg::with_name("fail-under")
+                .long("fail-under")
+                .value_name("METRIC=N")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Exit with 1 after the report if a total is under N, e.g. 'comment=100'"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .value_name("N")
+                .help("Maximum width of the table, defaults to the terminal width"),
+        )
+        .arg(
+            Arg::with_name("allow-empty")
+                .long("allow-empty")
+                .help("Exit with 0 when no file was counted, instead of 2"),
+        )
+        .arg(
+            Arg::with_name("cache")
+                .long("cache")
+                .value_name("PATH")
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .help("Reuse the counts of files whose mtime and size didn't change, kept in '--cache=PATH' or the cache dir"),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Read every file, even with '--cache' or 'cache = true' in the config"),
+        )
+        .arg(
+            Arg::with_name("cache-clear")
+                .long("cache-clear")
+                .help("Delete the cache before counting"),
+        )
+        .arg(
+            Arg::with_name("no-total")
+                .long("no-total")
+                .help("Leave out the totals row"),
+        )
+        .arg(
+            Arg::with_name("hidden")
+                .long("hidden")
+                .help("Count hidden files and directories, by default paths starting with '.' are skipped"),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .help("Follow symbolic links, files reachable through several links are counted once"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Count hardlinked files once, costs an extra stat per file"),
+        )
+        .arg(
+            Arg::with_name("mmap")
+                .long("mmap")
+                .help(
+                    "Map files over 1 MiB into memory instead of reading them. \
+                     A file truncated while it's mapped crashes the run",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-size")
+                .long("no-size")
+                .conflicts_with_all(&["max-size", "mmap", "cache"])
+                .help("Don't stat the files, the Size column shows '-' and sizes are 0"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .help("Walk and count with N threads, one per CPU by default"),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .long("max-size")
+                .value_name("SIZE")
+                .help("Skip files larger than SIZE, e.g. '512K' or '10M'"),
+        )
+        .arg(
+            Arg::with_name("min-lines")
+                .long("min-lines")
+                .value_name("N")
+                .help("Leave out files with fewer than N lines"),
+        )
+        .arg(
+            Arg::with_name("max-lines")
+                .long("max-lines")
+                .value_name("N")
+                .help("Leave out files with more than N lines"),
+        )
+        .arg(
+            Arg::with_name("modified-since")
+                .long("modified-since")
+                .value_name("WHEN")
+                .help("Only count files modified after WHEN by mtime, e.g. '2026-10-14', '2026-10-14T08:30:00Z' or '30d'"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .help("Only count files at most N levels below the directory, 1 is the directory itself"),
+        )
+        .arg(
+            Arg::with_name("no-default-excludes")
+                .long("no-default-excludes")
+                .help("Also count node_modules, target, vendor, build, dist and __pycache__"),
+        )
+        .arg(
+            Arg::with_name("no-ignore")
+                .long("no-ignore")
+                .help("Also count files listed in .gitignore"),
+        )
+        .arg(
+            Arg::with_name("by-family")
+                .long("by-family")
+                .help("Summarize related languages by family"),
+        )
+        .arg(
+            Arg::with_name("vendored-report")
+                .long("vendored-report")
+                .help("Count vendored directories separately by package"),
+        )
+        .arg(
+            Arg::with_name("files")
+                .long("files")
+                .help("List every counted file under its language"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Print the time taken and the files left out by reason to stderr"),
+        )
+        .arg(
+            Arg::with_name("no-progress")
+                .long("no-progress")
+                .help("Don't show the progress on stderr while counting"),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .help("Browse the languages and their files in the terminal, the table without one"),
+        )
+        .arg(
+            Arg::with_name("language")
+                .long("language")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .display_order(1000)
+                .help("Only count the language called NAME, case-insensitive, e.g. 'rust' or 'typescript jsx'"),
+        )
+        .arg(
+            Arg::with_name("exclude-language")
+                .long("exclude-language")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .display_order(1000)
+                .help("Leave out the language called NAME, case-insensitive"),
+        )
+        .arg(
+            Arg::with_name("disable-language")
+                .long("disable-language")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .display_order(1000)
+                .help("Forget the built-in language called NAME, its extensions are no longer known"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .display_order(1000)
+                .help("Read settings from the TOML file instead of '~/.config/rots/config.toml'"),
+        )
+        .arg(
+            Arg::with_name("no-config")
+                .long("no-config")
+                .display_order(1000)
+                .help("Don't look for '~/.config/rots/config.toml' and '.rots.toml'"),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("EXT=LANGUAGE")
+                .display_order(1000)
+                .help("Count files with the extension EXT as LANGUAGE, a new name makes a new language"),
+        )
+        .arg(
+            Arg::with_name("map-comment")
+                .long("map-comment")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("LANGUAGE=TOKENS")
+                .display_order(1000)
+                .help("Line comment tokens of a language made by '--map', comma separated, '#' by default"),
+        )
+        .arg(
+            Arg::with_name("detect")
+                .long("detect")
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("METHOD")
+                .possible_values(&["modeline"])
+                .display_order(1000)
+                .help("Read files no extension or name matches, 'modeline' looks for vim and Emacs modelines"),
+        )
+        .arg(
+            Arg::with_name("no-heuristics")
+                .long("no-heuristics")
+                .display_order(1000)
+                .help("Count every .h file as C instead of guessing C++ or Objective-C from its start"),
+        )
         .arg(
             Arg::with_name("extension")
                 .long("extension")
@@ -62,75 +618,1087 @@ pub fn parse() -> Options {
                 .display_order(1000)
                 .help("Parse file with specified extension"),
         )
-        .get_matches();
+}
+
+fn parse_from<I, T>(args: I, env: Env) -> Options
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let app = app().get_matches_from(args);
 
-    if app.is_present("ls") {
-        print_language_list();
+    // Nothing to count, and a broken config shouldn't break the shell
+    if let Some(matches) = app.subcommand_matches("completions") {
+        let shell = value_t_or_exit!(matches, "shell", Shell);
+        self::app().gen_completions_to(crate_name!(), shell, &mut io::stdout());
         std::process::exit(0)
     }
+    if app.subcommand_matches("gen-man").is_some() {
+        print!("{}", man::render(&self::app()));
+        std::process::exit(0)
+    }
+
+    // Before anything looks up a language by extension, `ls` shows the result
+    let builtin = CONFIG.builtin().iter().collect::<Vec<&Language>>();
+    let disabled: Vec<&str> = app
+        .values_of("disable-language")
+        .map(|values| values.map(|name| language_name(&builtin, name)).collect())
+        .unwrap_or_default();
+    // Command line > project config > user config > built-in defaults
+    let discover = !app.is_present("no-config");
+    let user = match app.value_of("config") {
+        Some(file) => Some(PathBuf::from(file)),
+        None if discover => settings::user_path().filter(|path| path.is_file()),
+        None => None,
+    };
+    let project = match discover {
+        true => settings::project_path(&project_dir(app.value_of("directory"))),
+        false => None,
+    };
+    let read = |path: Option<PathBuf>| match path {
+        Some(path) => settings::read(&path).unwrap_or_else(|err| exit!("{}", err)),
+        None => Settings::default(),
+    };
+    let mut settings = read(user).merge(read(project));
+    let mapping = app
+        .values_of("map")
+        .map(|values| values.map(parse_mapping).collect())
+        .unwrap_or_default();
+    let comments = app
+        .values_of("map-comment")
+        .map(|values| values.map(parse_map_comment).collect())
+        .unwrap_or_default();
+    config::setup(
+        &disabled,
+        std::mem::take(&mut settings.language),
+        mapping,
+        comments,
+    )
+    .unwrap_or_else(|err| exit!("{}", err));
+    let limits = settings.total_limits();
+    let language_limits = std::mem::take(&mut settings.limits);
+
+    // A broken language would miscount silently, `doctor` shows every problem
+    let problems = config::validate(CONFIG.all_language());
+    if app.subcommand_matches("doctor").is_some() {
+        print_problems(&problems);
+        std::process::exit(problems.iter().any(|p| p.fatal) as i32)
+    }
+    if let Some(problem) = problems.iter().find(|p| p.fatal) {
+        exit!(
+            "Language '{}': {}, see 'lok doctor'",
+            problem.language,
+            problem.message
+        );
+    }
 
-    let dir = app.value_of("directory").unwrap_or(".");
-    let work_dir = PathBuf::from(dir);
+    if let Some(matches) = app.subcommand_matches("ls") {
+        let languages = CONFIG
+            .all_language()
+            .iter()
+            .copied()
+            .filter(|language| {
+                matches
+                    .value_of("query")
+                    .is_none_or(|q| language.matches(q))
+            })
+            .collect::<Vec<&Language>>();
+        if matches.value_of("output") == Some("json") {
+            print_language_json(&languages);
+        } else if languages.is_empty() {
+            exit!(
+                "No language matches '{}', see 'lok ls'",
+                matches.value_of("query").unwrap()
+            );
+        } else {
+            print_language_list(
+                &languages,
+                matches.is_present("aliases"),
+                matches.is_present("verbose"),
+            );
+        }
+        std::process::exit(0)
+    }
+
+    if let Some(matches) = app.subcommand_matches("diff") {
+        diff::run(DiffOptions {
+            old: matches.value_of("old").unwrap().to_string(),
+            new: matches.value_of("new").unwrap().to_string(),
+            threshold: value_t_or_exit!(matches, "threshold", u64),
+            format: value_t_or_exit!(matches, "output", Format),
+            width: terminal_width(),
+        });
+        std::process::exit(0)
+    }
 
     // Whether the output is wrong
-    let print_error = app.is_present("error");
+    let print_error = app.is_present("error") || env_flag(env, "ROTS_ERROR");
+
+    // Directories are walked, files are counted as they are and quoted globs
+    // are expanded. A missing path is a typo, a glob may match nothing
+    let args = app
+        .values_of("directory")
+        .map(|values| values.collect())
+        .unwrap_or_else(|| vec!["."]);
+    let mut roots = Vec::new();
+    for arg in &args {
+        let expanded = !Path::new(arg).exists() && arg.contains(['*', '?', '[']);
+        let paths = if expanded {
+            glob::glob(arg)
+                .unwrap_or_else(|err| exit!("Cannot parse '{}' to glob matcher\n{:#?}", arg, err))
+                .filter_map(Result::ok)
+                .collect::<Vec<PathBuf>>()
+        } else {
+            if !Path::new(arg).exists() {
+                exit!("'{}' does not exist", arg);
+            }
+            vec![PathBuf::from(arg)]
+        };
+        if paths.is_empty() && print_error {
+            use bright::Colorful;
+            eprintln!("{} '{}' matches nothing", "error:".yellow(), arg);
+        }
+        for path in paths {
+            // A glob picks up every file, only the named ones must be countable
+            if expanded
+                && path.is_file()
+                && !archive::is_archive(&path)
+                && CONFIG.get_by_path(&path).is_none()
+            {
+                continue;
+            }
+            if path.is_dir() || path.is_file() {
+                roots.push(path);
+            } else if print_error {
+                use bright::Colorful;
+                eprintln!("{} '{}' does not exist", "error:".yellow(), path.display());
+            }
+        }
+    }
+    if roots.is_empty() {
+        exit!("'{}' matches nothing", args.join("', '"));
+    }
+
+    // Command line > `ROTS_` variables > config files
+    let exclude = match app.values_of("exclude") {
+        Some(values) => Some(values.map(String::from).collect()),
+        None => env_list(env, "ROTS_EXCLUDE").or(settings.exclude),
+    };
+    let include = match app.values_of("include") {
+        Some(values) => Some(values.map(String::from).collect()),
+        None => env_list(env, "ROTS_INCLUDE").or(settings.include),
+    };
+
+    let stdin = app.value_of("stdin").map(String::from);
+
+    let files_from = app.value_of("files-from").map(|file| {
+        let content = read_file_list(file);
+        let paths = match app.is_present("null") {
+            true => content.split('\0').collect::<Vec<&str>>(),
+            false => content.lines().collect(),
+        };
+        paths
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>()
+    });
 
-    let exclude = app
-        .values_of("exclude")
-        .map(|values| force_to_glob(&work_dir, values.collect()));
+    let gitignore = !app.is_present("no-ignore");
 
-    let include = app
-        .values_of("include")
-        .map(|values| force_to_glob(&work_dir, values.collect()));
+    let hidden = app.is_present("hidden");
 
-    let format = value_t_or_exit!(app, "output", Format);
+    let default_excludes = !app.is_present("no-default-excludes");
 
-    let sort = value_t_or_exit!(app, "sort", Sort);
+    let follow = app.is_present("follow");
+
+    let dedup = app.is_present("dedup");
+
+    // Falls back to reading where a file can't be mapped
+    let mmap = app.is_present("mmap");
+
+    // Saves a stat per file on a slow network filesystem
+    let no_size = app.is_present("no-size");
+
+    // Fewer on a shared CI runner, more than the CPUs when the disk is the bottleneck
+    let threads = match (app.value_of("threads"), env("ROTS_THREADS")) {
+        (Some(_), _) => match value_t_or_exit!(app, "threads", usize) {
+            0 => exit!("'--threads' must be at least 1"),
+            threads => Some(threads),
+        },
+        (None, Some(value)) => match value.parse::<usize>() {
+            Ok(0) => exit!("'ROTS_THREADS' must be at least 1"),
+            Ok(threads) => Some(threads),
+            Err(_) => exit!("'{}' isn't a valid value for 'ROTS_THREADS'", value),
+        },
+        (None, None) => None,
+    };
+
+    let max_size = app
+        .value_of("max-size")
+        .map(|value| format::parse_size(value).unwrap_or_else(|err| exit!("{}", err)));
+
+    let min_lines = app
+        .value_of("min-lines")
+        .map(|_| value_t_or_exit!(app, "min-lines", u64));
+    let max_lines = app
+        .value_of("max-lines")
+        .map(|_| value_t_or_exit!(app, "max-lines", u64));
+    if let (Some(min), Some(max)) = (min_lines, max_lines) {
+        if min > max {
+            exit!(
+                "'--min-lines' {} is greater than '--max-lines' {}",
+                min,
+                max
+            );
+        }
+    }
+
+    // Compared with the mtime, not the commit history
+    let modified_since = app.value_of("modified-since").map(|value| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let secs = format::parse_since(value, now).unwrap_or_else(|err| exit!("{}", err));
+        UNIX_EPOCH + Duration::from_secs(secs)
+    });
+
+    // `--include` is matched after the walk, a glob deeper than this finds nothing
+    let max_depth =
+        app.value_of("max-depth")
+            .map(|_| match value_t_or_exit!(app, "max-depth", usize) {
+                0 => exit!("'--max-depth' must be at least 1"),
+                depth => depth,
+            });
+
+    let delimiter = parse_delimiter(app.value_of("delimiter").unwrap());
+    let badge = Badge {
+        label: app.value_of("badge-label").unwrap().to_string(),
+        metric: value_t_or_exit!(app, "badge-metric", Metric),
+    };
+    let output_env = env("ROTS_OUTPUT");
+    let outputs = match (app.occurrences_of("output"), &output_env, &settings.output) {
+        (0, Some(value), _) => {
+            let format = value.split('=').next().unwrap();
+            if format.parse::<Format>().is_err() {
+                exit!(
+                    "Invalid value for 'ROTS_OUTPUT': Expected one of: {}",
+                    FORMATS.join(", ")
+                );
+            }
+            vec![value.as_str()]
+        }
+        (0, None, Some(values)) => values
+            .iter()
+            .map(|value| value.get_ref().as_str())
+            .collect(),
+        _ => app.values_of("output").unwrap().collect(),
+    };
+    let outputs = parse_outputs(outputs, app.value_of("output-path"), delimiter, &badge);
+
+    let sort = match (app.occurrences_of("sort"), env("ROTS_SORT"), settings.sort) {
+        (0, Some(value), _) => value.parse::<Sort>().unwrap_or_else(|_| {
+            let mut values = SORTS.to_vec();
+            values.sort_unstable();
+            exit!(
+                "'{}' isn't a valid value for 'ROTS_SORT'\n\t[possible values: {}]",
+                value,
+                values.join(", ")
+            )
+        }),
+        // Checked when the file was read
+        (0, None, Some(value)) => value.into_inner().parse::<Sort>().unwrap(),
+        _ => value_t_or_exit!(app, "sort", Sort),
+    };
+    if no_size && matches!(sort, Sort::Size) {
+        exit!("'--sort size' doesn't work with '--no-size', no size is measured");
+    }
+    let order = match app.value_of("order") {
+        Some(_) => value_t_or_exit!(app, "order", Order),
+        None => sort.default_order(),
+    };
+
+    let group = match app.value_of("group-by").unwrap() {
+        "dir" => match value_t_or_exit!(app, "group-depth", usize) {
+            0 => exit!("'--group-depth' must be at least 1"),
+            depth => Group::Dir(depth),
+        },
+        "extension" => Group::Extension,
+        _ => Group::Language,
+    };
+
+    let heuristics = !app.is_present("no-heuristics");
+
+    // Always on for files an `--include` glob names
+    let detect_modeline = app
+        .values_of("detect")
+        .is_some_and(|mut values| values.any(|value| value == "modeline"));
 
     let extension = app
         .values_of("extension")
-        .map(|values| values.map(|s| s.to_string()).collect::<Vec<String>>());
+        .map(|values| values.map(|s| s.to_string()).collect::<Vec<String>>())
+        .or(settings.extension);
+
+    let languages = app.values_of("language").map(|values| {
+        values
+            .map(|name| language_name(CONFIG.all_language(), name))
+            .collect::<Vec<&'static str>>()
+    });
+
+    let exclude_languages = app.values_of("exclude-language").map(|values| {
+        values
+            .map(|name| language_name(CONFIG.all_language(), name))
+            .collect::<Vec<&'static str>>()
+    });
+    if let (Some(languages), Some(excluded)) = (&languages, &exclude_languages) {
+        if let Some(name) = languages.iter().find(|name| excluded.contains(name)) {
+            exit!(
+                "'{}' is given to both '--language' and '--exclude-language'",
+                name
+            );
+        }
+    }
+
+    let by_family = app.is_present("by-family");
+    if by_family && !matches!(group, Group::Language) {
+        exit!("'--by-family' only works with '--group-by language'");
+    }
+
+    let vendored_report = app.is_present("vendored-report");
+
+    // Falls back to the outputs when stdin or stdout isn't a terminal
+    let tui = app.is_present("tui") && stdout_is_tty() && stdin_is_tty();
+
+    // `--error` lines would be drawn over
+    let progress = !app.is_present("no-progress") && !print_error && stderr_is_tty();
+
+    // The TUI opens a language to its files
+    let per_file = app.is_present("files") || tui;
+
+    let avg = app.is_present("avg");
+
+    let total = !app.is_present("no-total");
+
+    let allow_empty = app.is_present("allow-empty");
+
+    let number = value_t_or_exit!(app, "number-format", NumberFormat);
+
+    let size = SizeStyle {
+        unit: value_t_or_exit!(app, "size-unit", SizeUnit),
+        separator: number.separator(),
+        disabled: no_size,
+        ..SizeStyle::default()
+    };
+
+    let width = match app.value_of("width") {
+        Some(_) => match value_t_or_exit!(app, "width", usize) {
+            0 => exit!("'--width' must be at least 1"),
+            width => width,
+        },
+        None => terminal_width(),
+    };
+
+    let warn_code = app
+        .value_of("warn-code")
+        .map(|_| value_t_or_exit!(app, "warn-code", u64));
+
+    let warn_ratio = app.value_of("warn-comment-ratio").map(|_| {
+        match value_t_or_exit!(app, "warn-comment-ratio", f64) {
+            ratio if ratio.is_finite() && ratio >= 0.0 => ratio,
+            _ => exit!("'--warn-comment-ratio' must be a positive number"),
+        }
+    });
+
+    // `Auto` is only kept for a terminal, it colors stdout and no files
+    let color = match app.value_of("color").unwrap() {
+        "always" => Color::Always,
+        "never" => Color::Never,
+        _ if stdout_is_tty() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) => {
+            Color::Auto
+        }
+        _ => Color::Never,
+    };
+
+    let over = app
+        .values_of("fail-over")
+        .into_iter()
+        .flatten()
+        .map(|value| parse_threshold(true, value));
+    let under = app
+        .values_of("fail-under")
+        .into_iter()
+        .flatten()
+        .map(|value| parse_threshold(false, value));
+    let thresholds = over.chain(under).collect::<Vec<Threshold>>();
+    // Nothing is totaled when every file is printed as it's counted
+    if !thresholds.is_empty() && outputs.iter().any(|(f, _)| matches!(f, Format::Jsonl)) {
+        exit!("'--fail-over' and '--fail-under' don't work with '--output jsonl'");
+    }
+
+    let save_snapshot = app.value_of("save-snapshot").map(PathBuf::from);
+    let diff_snapshot = app.value_of("diff").map(PathBuf::from);
+    if (save_snapshot.is_some() || diff_snapshot.is_some())
+        && outputs.iter().any(|(f, _)| matches!(f, Format::Jsonl))
+    {
+        exit!("'--save-snapshot' and '--diff' don't work with '--output jsonl'");
+    }
+    let snapshot_flags = counting_flags(&app);
+
+    let by_author = app.is_present("by-author").then(|| {
+        if outputs.iter().any(|(f, _)| matches!(f, Format::Jsonl)) || tui {
+            exit!("'--by-author' doesn't work with '--output jsonl' or '--tui'");
+        }
+        app.value_of("author").map(|pattern| {
+            Pattern::new(pattern).unwrap_or_else(|err| {
+                exit!("Cannot parse '{}' to glob matcher\n{:#?}", pattern, err)
+            })
+        })
+    });
+
+    if let Some(range) = app.value_of("git-diff") {
+        git::run(GitDiff {
+            range: range.to_string(),
+            dir: project_dir(app.value_of("directory")),
+            heuristics,
+            languages,
+            exclude_languages,
+            json: matches!(outputs.first(), Some((Format::Json, _))),
+            number,
+            size,
+            width,
+        });
+        std::process::exit(0)
+    }
+
+    let check = app.subcommand_matches("check").map(|matches| {
+        if !matches!(group, Group::Language) || by_family {
+            exit!(
+                "'lok check' counts by language, it doesn't go with '--group-by' or '--by-family'"
+            );
+        }
+        let flags = Limits {
+            max_code: matches
+                .value_of("max-code")
+                .map(|_| value_t_or_exit!(matches, "max-code", u64)),
+            max_files: matches
+                .value_of("max-files")
+                .map(|_| value_t_or_exit!(matches, "max-files", u64)),
+            max_comment_ratio: matches.value_of("max-comment-ratio").map(|_| {
+                match value_t_or_exit!(matches, "max-comment-ratio", f64) {
+                    ratio if ratio.is_finite() && ratio >= 0.0 => ratio,
+                    _ => exit!("'--max-comment-ratio' must be a positive number"),
+                }
+            }),
+        };
+        let languages = language_limits
+            .into_iter()
+            .map(|(name, limits)| (language_name(CONFIG.all_language(), &name), limits))
+            .collect();
+        Check {
+            total: flags.or(limits),
+            languages,
+            format: value_t_or_exit!(matches, "output", Format),
+        }
+    });
+
+    let mut builder = CountOptionsBuilder::new()
+        .print_error(print_error)
+        .gitignore(gitignore)
+        .hidden(hidden)
+        .default_excludes(default_excludes)
+        .vendored(vendored_report)
+        .max_depth(max_depth)
+        .follow_symlinks(follow)
+        .dedup(dedup)
+        .mmap(mmap)
+        .size(!no_size)
+        .modified_since(modified_since)
+        .max_size(max_size)
+        .min_lines(min_lines)
+        .max_lines(max_lines)
+        .detect_modeline(detect_modeline)
+        .heuristics(heuristics);
+    if let Some(threads) = threads {
+        builder = builder.threads(threads);
+    }
+    for root in roots {
+        builder = builder.root(root);
+    }
+    for glob in exclude.iter().flatten() {
+        builder = builder.exclude_glob(glob);
+    }
+    for glob in include.iter().flatten() {
+        builder = builder.include_glob(glob);
+    }
+    if let Some(extension) = extension {
+        builder = builder.extensions(extension);
+    }
+    if let Some(languages) = languages {
+        builder = builder.languages(languages);
+    }
+    if let Some(exclude_languages) = exclude_languages {
+        builder = builder.exclude_languages(exclude_languages);
+    }
+    let mut count = builder.build().unwrap_or_else(|err| exit!("{}", err));
+
+    // `.rotsignore` in each work dir and `--exclude-from` add to `-e`
+    for work_dir in &count.work_dirs {
+        let rotsignore = work_dir.join(".rotsignore");
+        if rotsignore.is_file() {
+            count
+                .exclude
+                .get_or_insert_with(Vec::new)
+                .extend(read_exclude_file(
+                    std::slice::from_ref(work_dir),
+                    &rotsignore,
+                ));
+        }
+    }
+    if let Some(file) = app.value_of("exclude-from") {
+        let roots = count
+            .work_dirs
+            .iter()
+            .chain(&count.archives)
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        count
+            .exclude
+            .get_or_insert_with(Vec::new)
+            .extend(read_exclude_file(&roots, Path::new(file)));
+    }
+
+    // Listed paths are relative to the work dir
+    let base = count
+        .work_dirs
+        .first()
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("."));
+    count.files_from = files_from.map(|paths| paths.iter().map(|line| base.join(line)).collect());
+
+    // `--cache` without a path and `cache = true` use the cache dir
+    let cache_path = || match app.value_of("cache") {
+        Some(path) => PathBuf::from(path),
+        None => cache::default_path(&count.work_dirs, &count.files)
+            .unwrap_or_else(|| exit!("Cannot find the cache dir, pass '--cache PATH'")),
+    };
+    if app.is_present("cache-clear") {
+        let path = cache_path();
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                exit!("Cannot delete '{}'\n{:#?}", path.display(), err)
+            }
+            _ => {}
+        }
+    }
+    let use_cache = app.is_present("cache") || settings.cache == Some(true);
+    let cache = (use_cache && !app.is_present("no-cache") && !no_size).then(cache_path);
 
     Options {
-        work_dir,
-        print_error,
-        exclude,
-        include,
-        format,
+        count,
+        stdin,
+        outputs,
         sort,
-        extension,
+        order,
+        group,
+        by_family,
+        per_file,
+        avg,
+        total,
+        size,
+        number,
+        width,
+        color,
+        warn_code,
+        warn_ratio,
+        thresholds,
+        allow_empty,
+        cache,
+        save_snapshot,
+        diff_snapshot,
+        snapshot_flags,
+        by_author,
+        tui,
+        check,
+        progress,
+        stats: app.is_present("stats"),
     }
 }
 
 pub struct Options {
-    pub work_dir: PathBuf,
-    pub print_error: bool,
-    pub exclude: Option<Vec<Pattern>>,
-    pub include: Option<Vec<Pattern>>,
-    pub format: Format,
+    // What is counted and the filters, the rest is how it's reported
+    pub count: CountOptions,
+    // `--stdin`, the extension the piped content is counted as
+    pub stdin: Option<String>,
+    pub outputs: Vec<(Format, Option<PathBuf>)>,
     pub sort: Sort,
-    pub extension: Option<Vec<String>>,
+    pub order: Order,
+    pub group: Group,
+    pub by_family: bool,
+    pub per_file: bool,
+    pub avg: bool,
+    pub total: bool,
+    pub size: SizeStyle,
+    pub number: NumberFormat,
+    pub width: usize,
+    pub color: Color,
+    pub warn_code: Option<u64>,
+    pub warn_ratio: Option<f64>,
+    // `--fail-over` and `--fail-under`
+    pub thresholds: Vec<Threshold>,
+    // Exit with 0 even if no file was counted, `--allow-empty`
+    pub allow_empty: bool,
+    // `--cache`, where the counts of the last run are kept
+    pub cache: Option<PathBuf>,
+    // `--save-snapshot` and `--diff`
+    pub save_snapshot: Option<PathBuf>,
+    pub diff_snapshot: Option<PathBuf>,
+    // Recorded in the snapshot, a diff warns if they aren't the same
+    pub snapshot_flags: Vec<String>,
+    // `--by-author`, with the `--author` pattern
+    pub by_author: Option<Option<Pattern>>,
+    // `--tui` on a terminal, replaces the outputs
+    pub tui: bool,
+    // `lok check`, the limits replace the outputs
+    pub check: Option<Check>,
+    // A spinner and bar on stderr while counting
+    pub progress: bool,
+    // `--stats`, after the report
+    pub stats: bool,
+}
+
+// Columns of the terminal on stdout, 80 when it isn't one
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    80
+}
+
+fn stdout_is_tty() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::isatty(libc::STDOUT_FILENO) == 1
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+fn stderr_is_tty() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::isatty(libc::STDERR_FILENO) == 1
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+fn stdin_is_tty() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+// Resolve a `--language` value, suggest close names for a typo
+fn language_name(all: &[&'static Language], name: &str) -> &'static str {
+    if let Some(language) = all.iter().find(|l| l.is_called(name)) {
+        return language.name;
+    }
+
+    let lower = name.to_lowercase();
+    let mut close = all
+        .iter()
+        .map(|l| {
+            let other = l.name.to_lowercase();
+            // `type` is close to `TypeScript` and `TypeScript JSX`
+            match other.starts_with(&lower) {
+                true => (0, l.name),
+                false => (text::edit_distance(&lower, &other), l.name),
+            }
+        })
+        .filter(|(distance, _)| *distance <= 2.max(lower.len() / 3))
+        .collect::<Vec<(usize, &str)>>();
+    close.sort();
+    match close.is_empty() {
+        true => exit!("Unknown language '{}', see 'lok ls'", name),
+        false => {
+            let names = close.iter().take(3).map(|(_, name)| format!("'{}'", name));
+            exit!(
+                "Unknown language '{}', did you mean {}?",
+                name,
+                names.collect::<Vec<String>>().join(", ")
+            )
+        }
+    }
+}
+
+// `ROTS_EXCLUDE=a/**:b/**`, colon separated like `PATH`
+fn env_list(env: Env, name: &str) -> Option<Vec<String>> {
+    env(name).map(|value| {
+        value
+            .split(':')
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    })
+}
+
+// `ROTS_ERROR=1`, empty is the same as unset
+fn env_flag(env: Env, name: &str) -> bool {
+    let value = env(name).unwrap_or_default();
+    match value.to_ascii_lowercase().as_str() {
+        "" | "0" | "false" | "no" => false,
+        "1" | "true" | "yes" => true,
+        _ => exit!(
+            "'{}' isn't a valid value for '{}'\n\t[possible values: 1, 0, true, false, yes, no]",
+            value,
+            name
+        ),
+    }
+}
+
+// Where `.rots.toml` is looked for, the first path argument or its directory
+fn project_dir(first: Option<&str>) -> PathBuf {
+    let path = Path::new(first.unwrap_or("."));
+    match path.is_file() {
+        true => path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+        false if path.is_dir() => path.to_path_buf(),
+        false => PathBuf::from("."),
+    }
+}
+
+fn print_problems(problems: &[Problem]) {
+    use bright::Colorful;
+    for problem in problems {
+        let level = match problem.fatal {
+            true => "error:".red().bold(),
+            false => "warning:".yellow().bold(),
+        };
+        println!(
+            "{} Language '{}': {}",
+            level, problem.language, problem.message
+        );
+    }
+    if problems.is_empty() {
+        println!(
+            "No problems found in {} languages",
+            CONFIG.all_language().len()
+        );
+    }
+}
+
+// `--map` value, "m=MATLAB" or ".m=MATLAB"
+fn parse_mapping(value: &str) -> Mapping {
+    let (extension, language) = value
+        .split_once('=')
+        .unwrap_or_else(|| exit!("Cannot parse '--map {}', use EXT=LANGUAGE", value));
+    let extension = extension.trim().trim_start_matches('.');
+    let language = language.trim();
+    if extension.is_empty() {
+        exit!("'--map {}' has no extension before '='", value);
+    }
+    if language.is_empty() {
+        exit!("'--map {}' has no language after '='", value);
+    }
+    Mapping {
+        extension: extension.to_string(),
+        language: language.to_string(),
+    }
+}
+
+// Flags that change what is counted, not how it's printed
+const COUNTING_FLAGS: [&str; 24] = [
+    "exclude",
+    "exclude-from",
+    "include",
+    "extension",
+    "language",
+    "exclude-language",
+    "disable-language",
+    "map",
+    "map-comment",
+    "detect",
+    "no-heuristics",
+    "hidden",
+    "follow",
+    "dedup",
+    "no-ignore",
+    "no-default-excludes",
+    "max-depth",
+    "max-size",
+    "min-lines",
+    "max-lines",
+    "modified-since",
+    "group-by",
+    "group-depth",
+    "by-family",
+];
+
+// `--exclude=**/gen/**` for every value, in the order above
+fn counting_flags(app: &ArgMatches) -> Vec<String> {
+    let mut rst = vec![];
+    // A default value isn't a flag that was given
+    for name in COUNTING_FLAGS
+        .iter()
+        .filter(|name| app.occurrences_of(name) > 0)
+    {
+        // A flag has no values
+        let values = app
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&str>>();
+        match values.is_empty() {
+            true => rst.push(format!("--{}", name)),
+            false => rst.extend(values.iter().map(|value| format!("--{}={}", name, value))),
+        }
+    }
+    rst
+}
+
+// `--fail-over` and `--fail-under` value, "code=50000" or "size=10M"
+fn parse_threshold(over: bool, value: &str) -> Threshold {
+    let flag = if over { "--fail-over" } else { "--fail-under" };
+    let (metric, number) = value
+        .split_once('=')
+        .unwrap_or_else(|| exit!("Cannot parse '{} {}', use METRIC=N", flag, value));
+    let metric = check::METRICS
+        .iter()
+        .find(|m| **m == metric.trim())
+        .unwrap_or_else(|| {
+            exit!(
+                "'{} {}' has an unknown metric, use one of: {}",
+                flag,
+                value,
+                check::METRICS.join(", ")
+            )
+        });
+    let value = match *metric {
+        "size" => format::parse_size(number).unwrap_or_else(|err| exit!("{}", err)),
+        _ => number
+            .trim()
+            .parse::<u64>()
+            .unwrap_or_else(|_| exit!("'{} {}' needs a whole number after '='", flag, value)),
+    };
+    Threshold {
+        over,
+        metric,
+        value,
+    }
+}
+
+// `--map-comment` value, "Octave=%" or "Lisp=;;,;"
+fn parse_map_comment(value: &str) -> (String, Vec<String>) {
+    let (language, tokens) = value.split_once('=').unwrap_or_else(|| {
+        exit!(
+            "Cannot parse '--map-comment {}', use LANGUAGE=TOKENS",
+            value
+        )
+    });
+    let language = language.trim();
+    let tokens = tokens
+        .split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .collect::<Vec<String>>();
+    if language.is_empty() {
+        exit!("'--map-comment {}' has no language before '='", value);
+    }
+    if tokens.is_empty() {
+        exit!("'--map-comment {}' has no tokens after '='", value);
+    }
+    (language.to_string(), tokens)
 }
 
-// Translate to the same path
+// Translate to the same path, once for every form the roots take
 // ./src src => ./src ./src
 // /src  src => /src   /src
 // src   src => src    src
-fn force_to_glob(path: &Path, values: Vec<&str>) -> Vec<Pattern> {
-    values
-        .iter()
-        .map(|s| {
-            if path.starts_with(".") && !s.starts_with("./") {
-                format!("./{}", s)
-            } else if path.starts_with("/") && !s.starts_with('/') {
-                format!("/{}", s)
-            } else {
-                (*s).to_string()
-            }
+fn read_file_list(file: &str) -> String {
+    let result = if file == "-" {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content).map(|_| content)
+    } else {
+        fs::read_to_string(file)
+    };
+    result.unwrap_or_else(|err| exit!("Cannot read '{}'\n{:#?}", file, err))
+}
+
+// One glob per line, blank lines and `#` comments are skipped
+fn read_exclude_file(paths: &[PathBuf], file: &Path) -> Vec<Pattern> {
+    let content = fs::read_to_string(file)
+        .unwrap_or_else(|err| exit!("Cannot read '{}'\n{:#?}", file.display(), err));
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(|(n, line)| {
+            lok::normalize_globs(paths, line)
+                .into_iter()
+                .map(move |glob| (n, line, glob))
         })
-        .map(|s| {
-            Pattern::new(s.as_str())
-                .unwrap_or_else(|err| exit!("Cannot parse '{}' to glob matcher\n{:#?}", s, err))
+        .map(|(n, line, glob)| {
+            Pattern::new(&glob).unwrap_or_else(|err| {
+                exit!(
+                    "{}:{}: Cannot parse '{}' to glob matcher\n{:#?}",
+                    file.display(),
+                    n,
+                    line,
+                    err
+                )
+            })
         })
-        .collect::<Vec<Pattern>>()
+        .collect()
+}
+
+// json             => (Json, None)
+// json=report.json => (Json, Some("report.json"))
+// `--output-path` takes the place of stdout
+fn parse_outputs(
+    values: Vec<&str>,
+    output_path: Option<&str>,
+    delimiter: char,
+    badge: &Badge,
+) -> Vec<(Format, Option<PathBuf>)> {
+    let mut outputs: Vec<(Format, Option<PathBuf>)> = Vec::new();
+
+    for value in values {
+        let (format, path) = match value.find('=') {
+            Some(i) => (&value[..i], Some(&value[i + 1..])),
+            None => (value, None),
+        };
+        let format = match format.parse::<Format>() {
+            Ok(Format::Csv(_)) => Format::Csv(delimiter),
+            Ok(Format::Badge(_)) => Format::Badge(Badge {
+                label: badge.label.clone(),
+                metric: badge.metric,
+            }),
+            Ok(format) => format,
+            Err(_) => exit!("Invalid output format '{}'", format),
+        };
+        let path = match path {
+            Some("") => exit!("Missing file name in '{}'", value),
+            Some(path) => Some(PathBuf::from(path)),
+            None => match output_path {
+                Some("") => exit!("Missing file name in '--output-path'"),
+                path => path.map(PathBuf::from),
+            },
+        };
+
+        if outputs.iter().any(|(_, p)| *p == path) {
+            match &path {
+                Some(path) => exit!("'{}' is used by more than one output", path.display()),
+                None => exit!("Only one output can be written to stdout, use FORMAT=FILE"),
+            }
+        }
+        outputs.push((format, path));
+    }
+
+    let jsonl = outputs.iter().any(|(f, _)| matches!(f, Format::Jsonl));
+    if jsonl && outputs.len() > 1 {
+        exit!("jsonl output cannot be combined with other outputs");
+    }
+
+    outputs
+}
+
+// Accept a single character, or an escape like '\t' as typed in the shell
+fn parse_delimiter(value: &str) -> char {
+    let delimiter = match value {
+        "\\t" => Some('\t'),
+        _ => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch),
+                _ => None,
+            }
+        }
+    };
+    match delimiter {
+        Some('"') | Some('\n') | Some('\r') | None => {
+            exit!("Invalid delimiter '{}', expected a single character", value)
+        }
+        Some(ch) => ch,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(args: &[&str], vars: &[(&str, &str)]) -> Options {
+        let env = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        parse_from([&["lok", "src", "--no-config"], args].concat(), &env)
+    }
+
+    fn globs(patterns: &Option<Vec<Pattern>>) -> Vec<&str> {
+        patterns.iter().flatten().map(Pattern::as_str).collect()
+    }
+
+    #[test]
+    fn test_env() {
+        let vars = [
+            ("ROTS_EXCLUDE", "**/gen.rs:tests/**"),
+            ("ROTS_INCLUDE", "**/*.rs"),
+            ("ROTS_OUTPUT", "json"),
+            ("ROTS_SORT", "code"),
+            ("ROTS_ERROR", "1"),
+        ];
+        let parsed = options(&[], &vars);
+        assert_eq!(globs(&parsed.count.exclude), ["**/gen.rs", "tests/**"]);
+        assert_eq!(globs(&parsed.count.include), ["**/*.rs"]);
+        assert!(matches!(parsed.outputs[..], [(Format::Json, None)]));
+        assert!(matches!(parsed.sort, Sort::Code));
+        assert!(parsed.count.print_error);
+
+        assert_eq!(
+            options(&[], &[("ROTS_THREADS", "3")]).count.threads,
+            Some(3)
+        );
+        assert!(!options(&[], &[("ROTS_ERROR", "")]).count.print_error);
+        assert!(!options(&[], &[("ROTS_ERROR", "false")]).count.print_error);
+        assert!(options(&[], &[]).count.exclude.is_none());
+    }
+
+    #[test]
+    fn test_env_overridden() {
+        let vars = [
+            ("ROTS_EXCLUDE", "**/gen.rs"),
+            ("ROTS_OUTPUT", "json"),
+            ("ROTS_SORT", "code"),
+        ];
+        let parsed = options(&["-e", "**/lib.rs", "-o", "csv", "-s", "size"], &vars);
+        assert_eq!(globs(&parsed.count.exclude), ["**/lib.rs"]);
+        assert!(matches!(parsed.outputs[..], [(Format::Csv(_), None)]));
+        assert!(matches!(parsed.sort, Sort::Size));
+        let parsed = options(&["--threads", "1"], &[("ROTS_THREADS", "8")]);
+        assert_eq!(parsed.count.threads, Some(1));
+    }
+
+    #[test]
+    fn test_snapshot_flags() {
+        let parsed = options(&["--hidden", "-e", "a/**", "-e", "b/**", "-s", "code"], &[]);
+        assert_eq!(
+            parsed.snapshot_flags,
+            ["--exclude=a/**", "--exclude=b/**", "--hidden"]
+        );
+        assert!(options(&[], &[]).snapshot_flags.is_empty());
+    }
 }
@@ -1,12 +1,95 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
 #[derive(Debug)]
 pub struct Config(&'static [Language]);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Language {
     pub name: &'static str,
     pub extension: &'static [&'static str],
     pub single: &'static [&'static str],
     pub multi: &'static [(&'static str, &'static str)],
+    pub family: Option<&'static str>,
+    // Other names for `--language`, lowercase and unique across languages
+    pub alias: &'static [&'static str],
+    // Whole file names, for files like `Makefile` that go without an extension
+    pub filename: &'static [&'static str],
+    // The pairs of `multi` that nest, `/* a /* b */ c */` ends at the second `*/`
+    pub nested: &'static [(&'static str, &'static str)],
+    // Block comment tokens only count as the first word of a line, unindented
+    pub line_start: bool,
+    // Literals the tokens alone get wrong, see `Syntax`
+    pub syntax: Option<Syntax>,
+}
+
+// Languages whose strings can hide comment tokens and run over several lines
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub enum Syntax {
+    // `"..."`, and raw strings `r#"..."#` with any number of `#` and no escapes
+    Rust,
+    // `'''` and `"""` strings, a comment only as the docstring of a module,
+    // class or function
+    Python,
+    // POD, from an unindented `=pod`, `=head1` or other command to `=cut`, and
+    // heredocs
+    Perl,
+    // Long brackets of any level, `--[==[ comment ]==]` and `[[ string ]]`
+    Lua,
+    // Heredocs, `<<EOF` up to a line `EOF` is code whatever it looks like. So in
+    // Ruby, Perl and PHP
+    Shell,
+    Ruby,
+    Php,
+}
+
+impl Language {
+    const fn alias(self, alias: &'static [&'static str]) -> Self {
+        Self { alias, ..self }
+    }
+
+    const fn filename(self, filename: &'static [&'static str]) -> Self {
+        Self { filename, ..self }
+    }
+
+    const fn nested(self, nested: &'static [(&'static str, &'static str)]) -> Self {
+        Self { nested, ..self }
+    }
+
+    const fn line_start(self) -> Self {
+        Self {
+            line_start: true,
+            ..self
+        }
+    }
+
+    const fn syntax(self, syntax: Syntax) -> Self {
+        Self {
+            syntax: Some(syntax),
+            ..self
+        }
+    }
+
+    // The name or one of the aliases, in any case
+    pub fn is_called(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .alias
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    // For `lok ls QUERY`, part of the name or an extension in any case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim_start_matches('.').to_lowercase();
+        self.name.to_lowercase().contains(&query)
+            || self
+                .extension
+                .iter()
+                .any(|ext| ext.to_lowercase().contains(&query))
+    }
 }
 
 macro_rules! language {
@@ -16,83 +99,884 @@ macro_rules! language {
             extension: $ext,
             single: $single,
             multi: $multi,
+            family: None,
+            alias: &[],
+            filename: &[],
+            nested: &[],
+            line_start: false,
+            syntax: None,
+        }
+    };
+    ($name: expr, $ext: expr, $single: expr, $multi: expr, $family: expr) => {
+        Language {
+            name: $name,
+            extension: $ext,
+            single: $single,
+            multi: $multi,
+            family: Some($family),
+            alias: &[],
+            filename: &[],
+            nested: &[],
+            line_start: false,
+            syntax: None,
+        }
+    };
+}
+
+// A `[[language]]` table, a built-in name extends that language
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserLanguage {
+    pub name: String,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    // Whole file names like `Justfile`
+    #[serde(default)]
+    pub filenames: Vec<String>,
+    // Left out keeps the built-in tokens, or none for a new language
+    pub single: Option<Vec<String>>,
+    pub multi: Option<Vec<(String, String)>>,
+    // The `--by-family` row, left out keeps the built-in family
+    pub family: Option<String>,
+}
+
+// The languages of this run: the built-in table without `--disable-language`,
+// with config file languages and `--map` extensions added. Set once at startup,
+// before the first lookup
+static LANGUAGES: OnceLock<Vec<&'static Language>> = OnceLock::new();
+
+// The languages of each extension and file name, built from `LANGUAGES` on the
+// first lookup instead of a scan over every language for every file
+static LOOKUP: OnceLock<Lookup> = OnceLock::new();
+
+struct Lookup {
+    // In the order of `SHARED`, else of the config
+    extensions: HashMap<&'static str, Vec<&'static Language>>,
+    // The first language with the name
+    filenames: HashMap<&'static str, &'static Language>,
+}
+
+// `--map EXT=LANGUAGE`, a name that isn't built in makes a new language
+pub struct Mapping {
+    pub extension: String,
+    pub language: String,
+}
+
+// Languages made by `--map` use `#` comments unless `--map-comment` says otherwise
+const NEW_SINGLE: &[&str] = &["#"];
+
+fn leak<T>(items: Vec<T>) -> &'static [T] {
+    Box::leak(items.into_boxed_slice())
+}
+
+fn leak_str(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+// Drop `extension` from every language, the newest definition wins
+fn take_extension(languages: &mut [Language], extension: &str) {
+    for item in languages.iter_mut() {
+        if item.extension.contains(&extension) {
+            let rest = item.extension.iter().filter(|ext| **ext != extension);
+            item.extension = leak(rest.copied().collect());
+        }
+    }
+}
+
+// Same as `take_extension` for whole file names
+fn take_filename(languages: &mut [Language], filename: &str) {
+    for item in languages.iter_mut() {
+        if item.filename.contains(&filename) {
+            let rest = item.filename.iter().filter(|name| **name != filename);
+            item.filename = leak(rest.copied().collect());
+        }
+    }
+}
+
+pub fn setup(
+    disabled: &[&str],
+    user: Vec<UserLanguage>,
+    mapping: Vec<Mapping>,
+    comments: Vec<(String, Vec<String>)>,
+) -> Result<(), String> {
+    let _ = LANGUAGES.set(build(disabled, user, mapping, comments)?);
+    Ok(())
+}
+
+fn build(
+    disabled: &[&str],
+    user: Vec<UserLanguage>,
+    mapping: Vec<Mapping>,
+    comments: Vec<(String, Vec<String>)>,
+) -> Result<Vec<&'static Language>, String> {
+    let mut languages = CONFIG
+        .0
+        .iter()
+        .filter(|language| !disabled.contains(&language.name))
+        .copied()
+        .collect::<Vec<Language>>();
+    let builtin = languages.len();
+
+    for UserLanguage {
+        name,
+        extensions,
+        filenames,
+        single,
+        multi,
+        family,
+    } in user
+    {
+        // Like `--map`, the user's extensions win over the built-in ones
+        let extensions = extensions
+            .into_iter()
+            .map(|ext| ext.trim_start_matches('.').to_string())
+            .collect::<Vec<String>>();
+        for ext in &extensions {
+            take_extension(&mut languages, ext);
+        }
+        let extensions = extensions.into_iter().map(leak_str);
+        for filename in &filenames {
+            take_filename(&mut languages, filename);
         }
+        let filenames = filenames.into_iter().map(leak_str);
+        let single = single.map(|tokens| leak(tokens.into_iter().map(leak_str).collect()));
+        let multi = multi.map(|pairs| {
+            leak(
+                pairs
+                    .into_iter()
+                    .map(|(start, end)| (leak_str(start), leak_str(end)))
+                    .collect(),
+            )
+        });
+        let family = family.map(leak_str);
+        match languages.iter_mut().find(|item| item.is_called(&name)) {
+            Some(item) => {
+                let mut exts = item.extension.to_vec();
+                exts.extend(extensions);
+                item.extension = leak(exts);
+                let mut names = item.filename.to_vec();
+                names.extend(filenames);
+                item.filename = leak(names);
+                item.single = single.unwrap_or(item.single);
+                item.multi = multi.unwrap_or(item.multi);
+                item.family = family.or(item.family);
+            }
+            None => languages.push(Language {
+                name: leak_str(name),
+                extension: leak(extensions.collect()),
+                single: single.unwrap_or(&[]),
+                multi: multi.unwrap_or(&[]),
+                family,
+                alias: &[],
+                filename: leak(filenames.collect()),
+                nested: &[],
+                line_start: false,
+                syntax: None,
+            }),
+        }
+    }
+
+    for Mapping {
+        extension,
+        language,
+    } in mapping
+    {
+        // The mapping wins over whatever had the extension before
+        take_extension(&mut languages, &extension);
+        let extension = leak_str(extension);
+        match languages.iter_mut().find(|item| item.is_called(&language)) {
+            Some(item) => {
+                let mut exts = item.extension.to_vec();
+                exts.push(extension);
+                item.extension = leak(exts);
+            }
+            None => languages.push(Language {
+                name: leak_str(language),
+                extension: leak(vec![extension]),
+                single: NEW_SINGLE,
+                multi: &[],
+                family: None,
+                alias: &[],
+                filename: &[],
+                nested: &[],
+                line_start: false,
+                syntax: None,
+            }),
+        }
+    }
+
+    for (name, tokens) in comments {
+        let position = languages
+            .iter()
+            .position(|item| item.name.eq_ignore_ascii_case(&name));
+        match position {
+            Some(i) if i >= builtin => {
+                let tokens = tokens.into_iter().map(leak_str).collect();
+                languages[i].single = leak(tokens);
+            }
+            _ => {
+                return Err(format!(
+                    "'--map-comment' only applies to a new language from '--map', '{}' is not one",
+                    name
+                ))
+            }
+        }
+    }
+
+    Ok(languages
+        .into_iter()
+        .map(|language| &*Box::leak(Box::new(language)))
+        .collect())
+}
+
+// Something wrong in a language, fatal ones can't be counted with
+#[derive(Debug)]
+pub struct Problem {
+    pub language: &'static str,
+    pub message: String,
+    pub fatal: bool,
+}
+
+// Check the languages of this run, built-in and user-defined alike
+pub fn validate(languages: &[&'static Language]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut problem = |language: &Language, fatal, message: String| {
+        problems.push(Problem {
+            language: language.name,
+            message,
+            fatal,
+        })
     };
+
+    for (i, language) in languages.iter().enumerate() {
+        if language.name.trim().is_empty() {
+            problem(language, true, "The name is empty".to_string());
+        }
+        let tokens = language
+            .single
+            .iter()
+            .chain(language.multi.iter().flat_map(|(start, end)| [start, end]));
+        for token in tokens.clone() {
+            if token.is_empty() {
+                problem(language, true, "A comment token is empty".to_string());
+            } else if !token.is_ascii() {
+                problem(
+                    language,
+                    false,
+                    format!("The comment token '{}' isn't ASCII", token),
+                );
+            }
+        }
+        for (start, end) in language.multi {
+            if start == end && start.len() < 3 {
+                problem(
+                    language,
+                    false,
+                    format!(
+                        "The block comment '{}' opens and closes with the same short token",
+                        start
+                    ),
+                );
+            }
+        }
+        for ext in language.extension {
+            if ext.is_empty() {
+                problem(language, true, "An extension is empty".to_string());
+                continue;
+            }
+            // Shared extensions are expected, the rest mean one language is never used
+            let shared = SHARED.iter().any(|(shared, _)| shared == ext);
+            let other = languages[..i].iter().find(|l| l.extension.contains(ext));
+            if let (false, Some(other)) = (shared, other) {
+                problem(
+                    language,
+                    false,
+                    format!(
+                        "'.{}' is an extension of '{}' too, which comes first",
+                        ext, other.name
+                    ),
+                );
+            }
+        }
+        for name in language.filename {
+            if name.is_empty() {
+                problem(language, true, "A file name is empty".to_string());
+            } else if let Some(other) = languages[..i].iter().find(|l| l.filename.contains(name)) {
+                problem(
+                    language,
+                    false,
+                    format!(
+                        "'{}' is a file name of '{}' too, which comes first",
+                        name, other.name
+                    ),
+                );
+            }
+        }
+    }
+    problems
 }
 
 impl Config {
-    pub fn all_language(&self) -> &'static [Language] {
-        &self.0
+    // Languages as they are compiled in, before `setup`
+    pub fn builtin(&self) -> &'static [Language] {
+        self.0
+    }
+
+    pub fn all_language(&self) -> &'static [&'static Language] {
+        LANGUAGES.get_or_init(|| self.0.iter().collect())
     }
 
-    // Get language configuration by extension
-    pub fn get(&self, extension: &str) -> Option<&Language> {
-        for item in self.0 {
-            for ext in item.extension {
-                if *ext == extension {
-                    return Some(&item);
+    fn lookup(&self) -> &'static Lookup {
+        LOOKUP.get_or_init(|| {
+            let mut extensions = HashMap::<&str, Vec<&Language>>::new();
+            let mut filenames = HashMap::new();
+            for &language in self.all_language() {
+                for ext in language.extension {
+                    extensions.entry(ext).or_default().push(language);
                 }
+                for name in language.filename {
+                    filenames.entry(*name).or_insert(language);
+                }
+            }
+            for (ext, candidates) in extensions.iter_mut() {
+                let order = SHARED
+                    .iter()
+                    .find(|(shared, _)| shared == ext)
+                    .map(|(_, names)| *names)
+                    .unwrap_or(&[]);
+                // Stable, languages `SHARED` doesn't name go last
+                candidates.sort_by_key(|item| {
+                    order
+                        .iter()
+                        .position(|name| *name == item.name)
+                        .unwrap_or(order.len())
+                });
+            }
+            Lookup {
+                extensions,
+                filenames,
+            }
+        })
+    }
+
+    // Get language configuration of a file, a known file name comes before the extension
+    pub fn get_by_path(&self, path: &Path) -> Option<&'static Language> {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| self.get_by_filename(name))
+            .or_else(|| self.extension_of(path).and_then(|ext| self.get(ext)))
+    }
+
+    // `d.ts` of `index.d.ts` when a language has it, else the last part, `js` of `jquery.min.js`
+    pub fn extension_of<'p>(&self, path: &'p Path) -> Option<&'p str> {
+        let name = path.file_name()?.to_str()?;
+        let mut parts = name.rsplitn(3, '.');
+        let (last, second) = (parts.next()?, parts.next());
+        if let (Some(second), Some(stem)) = (second, parts.next()) {
+            let compound = &name[name.len() - last.len() - second.len() - 1..];
+            if !stem.is_empty() && !second.is_empty() && self.get(compound).is_some() {
+                return Some(compound);
             }
         }
-        None
+        path.extension()?.to_str()
+    }
+
+    // Get language configuration by the whole file name
+    pub fn get_by_filename(&self, filename: &str) -> Option<&'static Language> {
+        self.lookup().filenames.get(filename).copied()
+    }
+
+    // Get language configuration by extension, the first of the candidates
+    pub fn get(&self, extension: &str) -> Option<&'static Language> {
+        self.lookup()
+            .extensions
+            .get(extension)
+            .and_then(|candidates| candidates.first())
+            .copied()
+    }
+
+    // Every language with the extension, in the order of `SHARED`
+    pub fn candidates(&self, extension: &str) -> Vec<&'static Language> {
+        self.lookup()
+            .extensions
+            .get(extension)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Get language configuration by name
+    pub fn find(&self, name: &str) -> Option<&'static Language> {
+        self.all_language()
+            .iter()
+            .find(|item| item.name == name)
+            .copied()
     }
 }
 
+// Extensions of more than one language, the first one counts unless
+// `detect::guess` tells otherwise from the content
+const SHARED: &[(&str, &[&str])] = &[
+    ("h", &["C", "C++", "ObjectiveC"]),
+    ("m", &["ObjectiveC", "MATLAB"]),
+    ("pl", &["Perl", "Prolog"]),
+    ("sc", &["Scala", "SuperCollider"]),
+    ("r", &["R", "Rebol"]),
+    ("json", &["JSON", "JSON with Comments"]),
+];
+
+const C_FAMILY: &str = "C family";
+const JS_FAMILY: &str = "JavaScript family";
+const SHELL_FAMILY: &str = "Shell family";
+const MARKUP_FAMILY: &str = "Markup family";
+const DATA_FAMILY: &str = "Data family";
+
 pub const CONFIG: Config = Config(&[
     language!(
         "ASP.NET",
         &["asax", "ascx", "asmx", "aspx", "master", "sitemap", "webinfo"],
         &[],
         &[("<!--", "-->"), ("<%--", "-->")]
-    ),
-    language!("C", &["c"], &["//"], &[("/*", "*/")]),
+    )
+    .alias(&["asp", "aspnet"]),
+    language!("C", &["c", "h"], &["//"], &[("/*", "*/")], C_FAMILY),
+    language!("CMake", &["cmake"], &["#"], &[("#[[", "]]")]).filename(&["CMakeLists.txt"]),
     language!(
         "CSS",
         &["css", "scss", "sass", "less"],
         &["//"],
         &[("/*", "*/")]
     ),
-    language!("C++", &["cpp"], &["//"], &[("/*", "*/")]),
-    language!("CoffeeScript", &["coffee"], &["#"], &[("###", "###")]),
-    language!("C#", &["cs"], &["//", "///"], &[("/*", "*/")]),
-    language!("D", &["d"], &["//", "///"], &[("/*", "*/")]),
+    language!(
+        "C++",
+        &["cpp", "h", "hpp", "hh", "hxx"],
+        &["//"],
+        &[("/*", "*/")],
+        C_FAMILY
+    )
+    .alias(&["cpp", "cplusplus"]),
+    language!("CoffeeScript", &["coffee"], &["#"], &[("###", "###")]).alias(&["coffee"]),
+    language!("C#", &["cs"], &["//", "///"], &[("/*", "*/")]).alias(&["cs", "csharp", "c-sharp"]),
+    language!("D", &["d"], &["//", "///"], &[("/*", "*/"), ("/+", "+/")]).nested(&[("/+", "+/")]),
     language!("Dart", &["dart"], &["//", "///"], &[("/*", "*/")]),
-    language!("Go", &["go"], &["//"], &[("/*", "*/")]),
-    language!("HTML", &["htm", "html"], &[], &[("<!--", "-->")]),
-    language!("Haskell", &["hs"], &["--"], &[("{-", "-}")]),
-    language!("JavaScript", &["js", "mjs"], &["//"], &[("/*", "*/")]),
-    language!("JavaScript JSX", &["jsx"], &["//"], &[("/*", "*/")]),
-    language!("JSON", &["json"], &[], &[]),
-    language!("Julia", &["jl"], &["#"], &[("#=", "=#")]),
+    language!("Dockerfile", &["dockerfile"], &["#"], &[])
+        .alias(&["docker"])
+        .filename(&["Dockerfile", "Containerfile"]),
+    language!("Go", &["go"], &["//"], &[("/*", "*/")]).alias(&["golang"]),
+    language!("Groovy", &["groovy", "gradle"], &["//"], &[("/*", "*/")]).filename(&["Jenkinsfile"]),
+    language!(
+        "HTML",
+        &["htm", "html"],
+        &[],
+        &[("<!--", "-->")],
+        MARKUP_FAMILY
+    ),
+    language!("Haskell", &["hs"], &["--"], &[("{-", "-}")])
+        .alias(&["hs"])
+        .nested(&[("{-", "-}")]),
+    language!(
+        "JavaScript",
+        &["js", "mjs"],
+        &["//"],
+        &[("/*", "*/")],
+        JS_FAMILY
+    )
+    .alias(&["js"]),
+    language!(
+        "JavaScript JSX",
+        &["jsx"],
+        &["//"],
+        &[("/*", "*/")],
+        JS_FAMILY
+    )
+    .alias(&["jsx"]),
+    language!("JSON", &["json"], &[], &[], DATA_FAMILY),
+    // VS Code settings and most tool configs, a `.json` starting with a comment too
+    language!(
+        "JSON with Comments",
+        &["jsonc", "json"],
+        &["//"],
+        &[("/*", "*/")],
+        DATA_FAMILY
+    )
+    .alias(&["jsonc"])
+    .filename(&[
+        "tsconfig.json",
+        "jsconfig.json",
+        ".eslintrc.json",
+        "devcontainer.json",
+    ]),
+    language!("Julia", &["jl"], &["#"], &[("#=", "=#")]).alias(&["jl"]),
     language!("Java", &["java"], &["//"], &[("/*", "*/")]),
     language!("LLVM", &["ll"], &[","], &[]),
-    language!("Lua", &["lua"], &["--"], &[("--[[", "]]")]),
-    language!("Markdown", &["md", "markdown"], &[], &[]),
-    language!("Nim", &["nim"], &["#"], &[("＃[", "]#")]),
-    language!("ObjectiveC", &["m"], &["//", "///"], &[("/*", "*/")]),
-    language!("Objective-C++", &["mm"], &["//"], &[("/*", "*/")]),
-    language!("PHP", &["php"], &["//", "#"], &[("/*", "*/")]),
+    language!("Lua", &["lua"], &["--"], &[]).syntax(Syntax::Lua),
+    language!("MATLAB", &["m"], &["%"], &[("%{", "%}")]),
+    language!("Makefile", &["mk", "mak"], &["#"], &[])
+        .alias(&["make"])
+        .filename(&["Makefile", "makefile", "GNUmakefile"]),
+    language!("Markdown", &["md", "markdown"], &[], &[], MARKUP_FAMILY).alias(&["md"]),
+    language!("Nim", &["nim"], &["#"], &[("#[", "]#")]).nested(&[("#[", "]#")]),
     language!(
-        "Python",
-        &["py"],
-        &["#"],
-        &[("'''", "'''"), (r#"""""#, r#"""""#)]
-    ),
-    language!("Perl", &["pl", "pm"], &["#"], &[("=", "=")]),
+        "ObjectiveC",
+        &["m", "h"],
+        &["//", "///"],
+        &[("/*", "*/")],
+        C_FAMILY
+    )
+    .alias(&["objc", "objective-c"]),
+    language!("Objective-C++", &["mm"], &["//"], &[("/*", "*/")], C_FAMILY).alias(&[
+        "objcpp",
+        "objc++",
+        "objective-cpp",
+    ]),
+    language!("PHP", &["php"], &["//", "#"], &[("/*", "*/")]).syntax(Syntax::Php),
+    language!(
+        "PHP Blade",
+        &["blade.php"],
+        &[],
+        &[("{{--", "--}}"), ("<!--", "-->")]
+    )
+    .alias(&["blade"]),
+    language!("Python", &["py"], &["#"], &[])
+        .alias(&["py"])
+        .syntax(Syntax::Python),
+    language!("Perl", &["pl", "pm"], &["#"], &[]).syntax(Syntax::Perl),
+    language!("Prolog", &["pl", "pro"], &["%"], &[("/*", "*/")]),
     language!("R", &["r"], &["#"], &[]),
-    language!("Rust", &["rs"], &["//", "///"], &[("/*", "*/")]),
-    language!("Ruby", &["rb"], &["#"], &[("=", "=")]),
+    language!("Rebol", &["r", "reb", "r3"], &[";"], &[]),
+    language!("Rust", &["rs", "rs.in"], &["//", "///"], &[("/*", "*/")])
+        .alias(&["rs"])
+        .nested(&[("/*", "*/")])
+        .syntax(Syntax::Rust),
+    language!("Ruby", &["rb"], &["#"], &[("=begin", "=end")])
+        .alias(&["rb"])
+        .line_start()
+        .syntax(Syntax::Ruby)
+        .filename(&["Rakefile", "Gemfile"]),
     language!("Swift", &["swift"], &["//"], &[("/*", "*/")]),
     language!("Scala", &["sc"], &["//"], &[("/*", "*/")]),
-    language!("Shell", &["sh", "bash", "zsh", "fish"], &["#"], &[]),
+    language!("SuperCollider", &["sc", "scd"], &["//"], &[("/*", "*/")]),
+    language!(
+        "Shell",
+        &["sh", "bash", "zsh", "fish"],
+        &["#"],
+        &[],
+        SHELL_FAMILY
+    )
+    .alias(&["sh", "bash"])
+    .syntax(Syntax::Shell),
     language!("SQL", &["sql"], &["--"], &[("/*", "*/")]),
-    language!("TypeScript", &["ts"], &["//"], &[("/*", "*/")]),
-    language!("TypeScript JSX", &["tsx"], &["//"], &[("/*", "*/")]),
-    language!("TOML", &["toml"], &["#"], &[]),
+    language!("TypeScript", &["ts"], &["//"], &[("/*", "*/")], JS_FAMILY).alias(&["ts"]),
+    language!(
+        "TypeScript Declarations",
+        &["d.ts", "d.mts", "d.cts"],
+        &["//"],
+        &[("/*", "*/")],
+        JS_FAMILY
+    )
+    .alias(&["dts"]),
+    language!(
+        "TypeScript JSX",
+        &["tsx"],
+        &["//"],
+        &[("/*", "*/")],
+        JS_FAMILY
+    )
+    .alias(&["tsx"]),
+    language!("TOML", &["toml"], &["#"], &[], DATA_FAMILY),
     // This file may contain multiple languages. html.. js ts .. css scss sass..
     // Not processed here
-    language!("Vue", &["vue"], &["//"], &[("<!--", "-->"), ("/*", "*/")]),
-    language!("VimScript", &["vim"], &[], &[]),
-    language!("XML", &["xml"], &[], &[("<!--", "-->")]),
-    language!("YAML", &["yml", "yaml"], &["#"], &[]),
+    language!(
+        "Vue",
+        &["vue"],
+        &["//"],
+        &[("<!--", "-->"), ("/*", "*/")],
+        JS_FAMILY
+    ),
+    language!(
+        "Svelte",
+        &["svelte"],
+        &["//"],
+        &[("<!--", "-->"), ("/*", "*/")],
+        JS_FAMILY
+    ),
+    language!("VimScript", &["vim"], &[], &[]).alias(&["vim", "viml"]),
+    language!("XML", &["xml"], &[], &[("<!--", "-->")], MARKUP_FAMILY),
+    language!("YAML", &["yml", "yaml"], &["#"], &[], DATA_FAMILY).alias(&["yml"]),
 ]);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let all = CONFIG.all_language();
+        for language in all {
+            for ext in language.extension {
+                // The scan over every language the map replaces
+                let first = all
+                    .iter()
+                    .find(|item| item.extension.contains(ext))
+                    .unwrap();
+                let expected = match SHARED.iter().find(|(shared, _)| shared == ext) {
+                    Some((_, names)) => names[0],
+                    None => first.name,
+                };
+                assert_eq!(CONFIG.get(ext).unwrap().name, expected, "{}", ext);
+                assert!(CONFIG
+                    .candidates(ext)
+                    .iter()
+                    .any(|item| item.name == language.name));
+            }
+            for name in language.filename {
+                let first = all.iter().find(|item| item.filename.contains(name));
+                assert_eq!(
+                    CONFIG.get_by_filename(name).map(|l| l.name),
+                    first.map(|l| l.name)
+                );
+            }
+        }
+        assert!(CONFIG.get("no-such-extension").is_none());
+        let names = CONFIG
+            .candidates("h")
+            .iter()
+            .map(|l| l.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["C", "C++", "ObjectiveC"]);
+    }
+
+    #[test]
+    fn test_unique_aliases() {
+        let all = CONFIG.all_language();
+        for language in all {
+            for alias in language.alias {
+                assert_eq!(alias.to_lowercase(), *alias);
+                let owners = all
+                    .iter()
+                    .filter(|other| other.is_called(alias))
+                    .map(|other| other.name)
+                    .collect::<Vec<&str>>();
+                assert_eq!(owners, vec![language.name], "'{}' is ambiguous", alias);
+            }
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mapping = |extension: &str, language: &str| Mapping {
+            extension: extension.to_string(),
+            language: language.to_string(),
+        };
+        let all = build(
+            &["Perl"],
+            vec![],
+            vec![mapping("m", "Octave"), mapping("inc", "php")],
+            vec![("octave".to_string(), vec!["%".to_string()])],
+        )
+        .unwrap();
+        let find = |name: &str| all.iter().find(|l| l.name == name);
+
+        assert!(find("Perl").is_none());
+        assert_eq!(find("ObjectiveC").unwrap().extension, &["h"]);
+        assert_eq!(find("PHP").unwrap().extension, &["php", "inc"]);
+        let octave = find("Octave").unwrap();
+        assert_eq!(octave.extension, &["m"]);
+        assert_eq!(octave.single, &["%"]);
+        assert!(find("MATLAB").unwrap().extension.is_empty());
+
+        let lisp = build(&[], vec![], vec![mapping("lisp", "Lisp")], vec![]).unwrap();
+        assert_eq!(lisp.last().unwrap().single, &["#"]);
+
+        let err = build(&[], vec![], vec![], vec![("Rust".to_string(), vec![])]);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_build_user() {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        let user = vec![
+            UserLanguage {
+                name: "Zig".to_string(),
+                extensions: strings(&["zig", ".zon"]),
+                filenames: strings(&["build.zig.zon", "Makefile"]),
+                single: Some(strings(&["//"])),
+                multi: None,
+                family: Some("Systems".to_string()),
+            },
+            UserLanguage {
+                name: "rs".to_string(),
+                extensions: strings(&["c"]),
+                filenames: vec![],
+                single: None,
+                multi: Some(vec![("(*".to_string(), "*)".to_string())]),
+                family: Some("Systems".to_string()),
+            },
+            UserLanguage {
+                name: "C++".to_string(),
+                extensions: vec![],
+                filenames: vec![],
+                single: None,
+                multi: None,
+                family: None,
+            },
+        ];
+        let all = build(&[], user, vec![mapping_zon()], vec![]).unwrap();
+        let find = |name: &str| all.iter().find(|l| l.name == name).unwrap();
+
+        let zig = find("Zig");
+        assert_eq!(zig.extension, &["zig"]);
+        assert_eq!(zig.single, &["//"]);
+        assert!(zig.multi.is_empty());
+        // The user's extension wins, left out tokens stay as built in
+        let rust = find("Rust");
+        assert_eq!(rust.extension, &["rs", "rs.in", "c"]);
+        assert_eq!(rust.single, CONFIG.find("Rust").unwrap().single);
+        assert_eq!(rust.multi, &[("(*", "*)")]);
+        assert_eq!(find("C").extension, &["h"]);
+        assert_eq!(zig.filename, &["build.zig.zon", "Makefile"]);
+        assert_eq!(find("Makefile").filename, &["makefile", "GNUmakefile"]);
+        // `--map` comes last
+        assert_eq!(find("ZON").extension, &["zon"]);
+        // Left out keeps the built-in family
+        assert_eq!(
+            (zig.family, rust.family),
+            (Some("Systems"), Some("Systems"))
+        );
+        assert_eq!(find("C++").family, CONFIG.find("C++").unwrap().family);
+    }
+
+    fn mapping_zon() -> Mapping {
+        Mapping {
+            extension: "zon".to_string(),
+            language: "ZON".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_get_by_path() {
+        let name = |path: &str| CONFIG.get_by_path(Path::new(path)).map(|l| l.name);
+        assert_eq!(name("src/main.rs"), Some("Rust"));
+        assert_eq!(name("Makefile"), Some("Makefile"));
+        assert_eq!(name("lib/rules.mk"), Some("Makefile"));
+        assert_eq!(name("docker/Dockerfile"), Some("Dockerfile"));
+        // The file name wins over the `.txt` extension
+        assert_eq!(name("CMakeLists.txt"), Some("CMake"));
+        assert_eq!(name("notes.txt"), None);
+        assert_eq!(name("MAKEFILE"), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let builtin = CONFIG.builtin().iter().collect::<Vec<&Language>>();
+        let problems = validate(&builtin);
+        assert!(problems.is_empty(), "{:#?}", problems);
+
+        let broken = [
+            &Language {
+                name: "A",
+                extension: &["a", ""],
+                single: &["#"],
+                multi: &[("<<", "")],
+                family: None,
+                alias: &[],
+                filename: &[],
+                nested: &[],
+                line_start: false,
+                syntax: None,
+            },
+            &Language {
+                name: "B",
+                extension: &["a", "m"],
+                single: &["＃"],
+                multi: &[("=", "=")],
+                family: None,
+                alias: &[],
+                filename: &[],
+                nested: &[],
+                line_start: false,
+                syntax: None,
+            },
+        ];
+        let problems = validate(&broken)
+            .into_iter()
+            .map(|p| (p.language, p.fatal, p.message))
+            .collect::<Vec<(&str, bool, String)>>();
+        assert_eq!(
+            problems,
+            [
+                ("A", true, "A comment token is empty".to_string()),
+                ("A", true, "An extension is empty".to_string()),
+                ("B", false, "The comment token '＃' isn't ASCII".to_string()),
+                (
+                    "B",
+                    false,
+                    "The block comment '=' opens and closes with the same short token".to_string()
+                ),
+                (
+                    "B",
+                    false,
+                    "'.a' is an extension of 'A' too, which comes first".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_candidates() {
+        let names = |ext: &str| {
+            CONFIG
+                .candidates(ext)
+                .iter()
+                .map(|l| l.name)
+                .collect::<Vec<&str>>()
+        };
+        assert_eq!(names("h"), ["C", "C++", "ObjectiveC"]);
+        assert_eq!(names("m"), ["ObjectiveC", "MATLAB"]);
+        assert_eq!(names("rs"), ["Rust"]);
+        assert!(names("unknown").is_empty());
+        // The first candidate is what an extension means without a guess
+        assert_eq!(CONFIG.get("m").unwrap().name, "ObjectiveC");
+        assert_eq!(CONFIG.get("pl").unwrap().name, "Perl");
+        assert_eq!(CONFIG.get("r").unwrap().name, "R");
+    }
+
+    #[test]
+    fn test_compound_extension() {
+        fn ext(path: &str) -> Option<&str> {
+            CONFIG.extension_of(Path::new(path))
+        }
+        let name = |path: &str| CONFIG.get_by_path(Path::new(path)).map(|l| l.name);
+        assert_eq!(ext("src/index.d.ts"), Some("d.ts"));
+        assert_eq!(name("src/index.d.ts"), Some("TypeScript Declarations"));
+        assert_eq!(name("src/index.ts"), Some("TypeScript"));
+        assert_eq!(name("build/gen.rs.in"), Some("Rust"));
+        assert_eq!(name("views/home.blade.php"), Some("PHP Blade"));
+        // Dots that aren't a known compound extension
+        assert_eq!(ext("jquery.min.js"), Some("js"));
+        assert_eq!(name("jquery.min.js"), Some("JavaScript"));
+        assert_eq!(name("app.spec.ts"), Some("TypeScript"));
+        assert_eq!(ext("v1.2.rs"), Some("rs"));
+        // A compound extension needs a name in front
+        assert_eq!(ext("d.ts"), Some("ts"));
+        assert_eq!(ext(".d.ts"), Some("ts"));
+        assert_eq!(ext("a..ts"), Some("ts"));
+        assert_eq!(ext("Makefile"), None);
+    }
+
+    #[test]
+    fn test_is_called() {
+        let rust = CONFIG.find("Rust").unwrap();
+        assert!(rust.is_called("rust"));
+        assert!(rust.is_called("RS"));
+        assert!(!rust.is_called("ruby"));
+    }
+
+    #[test]
+    fn test_matches() {
+        let rust = CONFIG.find("Rust").unwrap();
+        assert!(rust.matches("RUS"));
+        assert!(rust.matches(".rs"));
+        assert!(rust.matches("in"));
+        assert!(!rust.matches("ruby"));
+    }
+}
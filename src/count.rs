@@ -0,0 +1,1775 @@
+// The walk and the workers: which files are counted and their lines, the
+// report around them is up to the caller
+use crate::archive;
+use crate::cache::Cache;
+use crate::config::{Language, CONFIG};
+use crate::detect;
+use crate::ignore::Gitignore;
+use crate::parse::{parse_bytes, parse_str, parser, parser_mmap, parser_no_size, Data, Value};
+use crate::{fold, is_wanted, Detail};
+use glob::{MatchOptions, Pattern, PatternError};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::ops::ControlFlow;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+macro_rules! err {
+    ($kind: expr, $path: expr) => {{
+        use bright::Colorful;
+        eprintln!("{} {:?} {:?}", "error:".yellow(), $kind, $path);
+    }};
+}
+
+/// What to count and which files to leave out. The default counts a walk
+/// like `lok` without flags does, `.gitignore` and build directories are skipped
+///
+/// ```
+/// use lok::CountOptions;
+///
+/// let options = CountOptions {
+///     hidden: true,
+///     languages: Some(vec!["Rust", "TOML"]),
+///     ..CountOptions::default()
+/// };
+/// assert!(options.gitignore);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CountOptions {
+    // Walked for files
+    pub work_dirs: Vec<PathBuf>,
+    // Counted without a walk and without the filters, they were asked for
+    pub files: Vec<PathBuf>,
+    // `.zip`, `.tar` and `.tar.gz` files, their entries are filtered like a walk
+    pub archives: Vec<PathBuf>,
+    // Counted instead of walking the work dirs
+    pub files_from: Option<Vec<PathBuf>>,
+    // Content counted as one file called `<stdin>`, replaces the walk
+    pub stdin: Option<(String, &'static Language)>,
+    // Print the files that couldn't be counted to stderr
+    pub print_error: bool,
+    pub exclude: Option<Vec<Pattern>>,
+    pub include: Option<Vec<Pattern>>,
+    // Skip what .gitignore files list
+    pub gitignore: bool,
+    // Count paths starting with `.`
+    pub hidden: bool,
+    // Prune build and dependency directories like `node_modules` and `target`
+    pub default_excludes: bool,
+    // Still walk `node_modules` and `vendor`, for a report of the packages in them
+    pub vendored: bool,
+    // Files directly in the work dir are at depth 1
+    pub max_depth: Option<usize>,
+    // Walk into symlinked directories
+    pub follow: bool,
+    // Count a file reachable through several hardlinks once
+    pub dedup: bool,
+    // Older files are skipped
+    pub modified_since: Option<SystemTime>,
+    // In bytes, larger files are skipped
+    pub max_size: Option<u64>,
+    // All lines of a file count
+    pub min_lines: Option<u64>,
+    pub max_lines: Option<u64>,
+    // Only files with these extensions
+    pub extension: Option<Vec<String>>,
+    // Language names as they are in the config
+    pub languages: Option<Vec<&'static str>>,
+    pub exclude_languages: Option<Vec<&'static str>>,
+    // The language of unknown files from `vim: ft=` or `-*- mode: -*-`
+    pub detect_modeline: bool,
+    // Look at the start of `.h` files for C++ and Objective-C
+    pub heuristics: bool,
+    // Workers counting files, one per CPU by default
+    pub threads: Option<usize>,
+    // Map large files instead of reading them
+    pub mmap: bool,
+    // Off with `--no-size`, files aren't stat'ed and every size is 0
+    pub size: bool,
+}
+
+impl Default for CountOptions {
+    fn default() -> Self {
+        Self {
+            work_dirs: Vec::new(),
+            files: Vec::new(),
+            archives: Vec::new(),
+            files_from: None,
+            stdin: None,
+            print_error: false,
+            exclude: None,
+            include: None,
+            gitignore: true,
+            hidden: false,
+            default_excludes: true,
+            vendored: false,
+            max_depth: None,
+            follow: false,
+            dedup: false,
+            modified_since: None,
+            max_size: None,
+            min_lines: None,
+            max_lines: None,
+            extension: None,
+            languages: None,
+            exclude_languages: None,
+            detect_modeline: false,
+            heuristics: true,
+            threads: None,
+            mmap: false,
+            size: true,
+        }
+    }
+}
+
+/// [`CountOptions`] a setting at a time. The roots, globs and threads are checked
+/// by `build`, which returns what's wrong instead of exiting
+///
+/// ```
+/// use lok::CountOptionsBuilder;
+///
+/// let options = CountOptionsBuilder::new()
+///     .root("src")
+///     .exclude_glob("**/tests/**")
+///     .extensions(["rs", "toml"])
+///     .follow_symlinks(true)
+///     .threads(2)
+///     .build()
+///     .unwrap();
+/// assert_eq!(options.work_dirs.len(), 1);
+/// assert_eq!(options.threads, Some(2));
+///
+/// let err = CountOptionsBuilder::new().include_glob("[").build().unwrap_err();
+/// assert!(err.to_string().starts_with("Cannot parse '[' to glob matcher"));
+/// ```
+#[derive(Debug, Default)]
+pub struct CountOptionsBuilder {
+    options: CountOptions,
+    // Directories, archives and files, told apart by `build`
+    roots: Vec<PathBuf>,
+    exclude: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+}
+
+impl CountOptionsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Walked if it's a directory, counted like one if it's an archive
+    pub fn root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.roots.push(path.into());
+        self
+    }
+
+    // Relative to the roots, `src/**` also matches `./src/main.rs` of the root `.`
+    pub fn exclude_glob(mut self, glob: &str) -> Self {
+        self.exclude
+            .get_or_insert_with(Vec::new)
+            .push(glob.to_string());
+        self
+    }
+
+    pub fn include_glob(mut self, glob: &str) -> Self {
+        self.include
+            .get_or_insert_with(Vec::new)
+            .push(glob.to_string());
+        self
+    }
+
+    // Without the dot, `rs`
+    pub fn extensions<I: IntoIterator<Item = S>, S: Into<String>>(mut self, extensions: I) -> Self {
+        self.options.extension = Some(extensions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.options.follow = follow;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.options.mmap = mmap;
+        self
+    }
+
+    pub fn size(mut self, size: bool) -> Self {
+        self.options.size = size;
+        self
+    }
+
+    pub fn files_from(mut self, paths: Vec<PathBuf>) -> Self {
+        self.options.files_from = Some(paths);
+        self
+    }
+
+    pub fn print_error(mut self, print_error: bool) -> Self {
+        self.options.print_error = print_error;
+        self
+    }
+
+    pub fn gitignore(mut self, gitignore: bool) -> Self {
+        self.options.gitignore = gitignore;
+        self
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.options.hidden = hidden;
+        self
+    }
+
+    pub fn default_excludes(mut self, default_excludes: bool) -> Self {
+        self.options.default_excludes = default_excludes;
+        self
+    }
+
+    pub fn vendored(mut self, vendored: bool) -> Self {
+        self.options.vendored = vendored;
+        self
+    }
+
+    pub fn max_depth(mut self, depth: impl Into<Option<usize>>) -> Self {
+        self.options.max_depth = depth.into();
+        self
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = dedup;
+        self
+    }
+
+    pub fn modified_since(mut self, since: impl Into<Option<SystemTime>>) -> Self {
+        self.options.modified_since = since.into();
+        self
+    }
+
+    pub fn max_size(mut self, bytes: impl Into<Option<u64>>) -> Self {
+        self.options.max_size = bytes.into();
+        self
+    }
+
+    pub fn min_lines(mut self, lines: impl Into<Option<u64>>) -> Self {
+        self.options.min_lines = lines.into();
+        self
+    }
+
+    pub fn max_lines(mut self, lines: impl Into<Option<u64>>) -> Self {
+        self.options.max_lines = lines.into();
+        self
+    }
+
+    // Names as they are in the config, see [`Config::find`](crate::Config::find)
+    pub fn languages(mut self, names: Vec<&'static str>) -> Self {
+        self.options.languages = Some(names);
+        self
+    }
+
+    pub fn exclude_languages(mut self, names: Vec<&'static str>) -> Self {
+        self.options.exclude_languages = Some(names);
+        self
+    }
+
+    pub fn detect_modeline(mut self, detect: bool) -> Self {
+        self.options.detect_modeline = detect;
+        self
+    }
+
+    pub fn heuristics(mut self, heuristics: bool) -> Self {
+        self.options.heuristics = heuristics;
+        self
+    }
+
+    pub fn build(self) -> Result<CountOptions, Error> {
+        let mut options = self.options;
+        for root in self.roots {
+            if root.is_dir() {
+                options.work_dirs.push(root);
+            } else if root.is_file() && archive::is_archive(&root) {
+                options.archives.push(root);
+            } else if root.is_file() {
+                if CONFIG.get_by_path(&root).is_none() {
+                    return Err(Error::Unknown(root));
+                }
+                options.files.push(root);
+            } else {
+                return Err(Error::NotFound(root));
+            }
+        }
+        // A directory inside another root is already walked, `src/**` names both
+        let dirs = options.work_dirs.clone();
+        options
+            .work_dirs
+            .retain(|dir| !dirs.iter().any(|root| root != dir && dir.starts_with(root)));
+        options.work_dirs.dedup();
+
+        if options.threads == Some(0) {
+            return Err(Error::Invalid("threads must be at least 1".to_string()));
+        }
+
+        // Entries of an archive are matched as paths below it, `release.tar.gz/src/main.rs`
+        let roots = options
+            .work_dirs
+            .iter()
+            .chain(&options.archives)
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        let compile = |globs: Option<Vec<String>>| {
+            globs
+                .map(|globs| {
+                    globs
+                        .iter()
+                        .flat_map(|glob| match roots.is_empty() {
+                            true => vec![glob.clone()],
+                            false => normalize_globs(&roots, glob),
+                        })
+                        .map(|glob| Pattern::new(&glob).map_err(|err| Error::Glob(glob, err)))
+                        .collect::<Result<Vec<Pattern>, Error>>()
+                })
+                .transpose()
+        };
+        options.exclude = compile(self.exclude)?;
+        options.include = compile(self.include)?;
+        Ok(options)
+    }
+}
+
+/// `glob` for each of the roots, `src/**` is `./src/**` below `.` and `/src/**`
+/// below an absolute path
+pub fn normalize_globs(roots: &[PathBuf], glob: &str) -> Vec<String> {
+    let mut globs = roots
+        .iter()
+        .map(|root| normalize_glob(root, glob))
+        .collect::<Vec<String>>();
+    globs.sort();
+    globs.dedup();
+    globs
+}
+
+fn normalize_glob(root: &Path, glob: &str) -> String {
+    if root.starts_with(".") && !glob.starts_with("./") {
+        format!("./{}", glob)
+    } else if root.starts_with("/") && !glob.starts_with('/') {
+        format!("/{}", glob)
+    } else {
+        glob.to_string()
+    }
+}
+
+/// Why nothing could be counted. A file that can't be read is only skipped
+#[derive(Debug)]
+pub enum Error {
+    // A root that isn't there
+    NotFound(PathBuf),
+    // A file root of no known language
+    Unknown(PathBuf),
+    Glob(String, PatternError),
+    // A setting out of range
+    Invalid(String),
+    // A work dir removed while it was walked
+    Vanished(PathBuf),
+    Read(PathBuf, io::Error),
+    // Not an archive after all, or a broken one
+    Archive(PathBuf, String),
+    // A worker panicked
+    Thread,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(path) => write!(f, "'{}' does not exist", path.display()),
+            Error::Unknown(path) => write!(f, "'{}' is not a known language", path.display()),
+            Error::Glob(glob, err) => {
+                write!(f, "Cannot parse '{}' to glob matcher\n{:#?}", glob, err)
+            }
+            Error::Invalid(reason) => write!(f, "{}", reason),
+            Error::Vanished(path) => write!(f, "'{}' no longer exists", path.display()),
+            Error::Read(path, err) => write!(f, "Cannot read '{}'\n{:#?}", path.display(), err),
+            Error::Archive(path, reason) => write!(f, "'{}' {}", path.display(), reason),
+            Error::Thread => write!(f, "Thread exits abnormally"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Counts the files below `root`, or `root` itself, into a row per language
+/// sorted by name
+///
+/// ```
+/// use lok::{count, CountOptions};
+/// use std::path::Path;
+///
+/// let details = count(Path::new("src/lib.rs"), &CountOptions::default()).unwrap();
+/// assert_eq!(details[0].name, "Rust");
+/// assert_eq!(details[0].file, 1);
+/// ```
+pub fn count(root: &Path, options: &CountOptions) -> Result<Vec<Detail>, Error> {
+    let mut options = options.clone();
+    if root.is_dir() {
+        options.work_dirs = vec![root.to_path_buf()];
+    } else if archive::is_archive(root) {
+        options.archives = vec![root.to_path_buf()];
+    } else if root.is_file() {
+        options.files = vec![root.to_path_buf()];
+    } else {
+        return Err(Error::NotFound(root.to_path_buf()));
+    }
+    let summary = Counter::new(options).run()?;
+    let mut total = BTreeMap::new();
+    for data in summary.files {
+        fold(&mut total, data.language, data, false);
+    }
+    Ok(total.into_values().collect())
+}
+
+/// What a run found besides the files
+#[derive(Debug, Default)]
+pub struct Summary {
+    // The counted files, in no particular order. Empty after `run_with`
+    pub files: Vec<Data>,
+    // Deleted by someone else while counting
+    pub vanished: usize,
+    // Directories skipped by the default excludes
+    pub pruned: usize,
+    // Files handed to the workers
+    pub discovered: usize,
+    pub skipped: Skipped,
+    // Size of the counted files
+    pub bytes: u64,
+    // Until the last directory was read, the workers go on a little longer
+    pub walk_time: Duration,
+    // Workers counting at the same time
+    pub threads: usize,
+}
+
+/// The files left out and why, by the walk or after a worker read them
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Skipped {
+    // No language for the name or extension
+    pub unknown: usize,
+    // Matched by `exclude`, or not by `include`
+    pub excluded: usize,
+    // Not one of `extension`
+    pub extension: usize,
+    // `languages` and `exclude_languages`
+    pub language: usize,
+    // Older than `modified_since`
+    pub modified: usize,
+    // Over `max_size`
+    pub oversized: usize,
+    // A file already counted through another path, `dedup` and `follow`
+    pub duplicate: usize,
+    // `min_lines` and `max_lines`
+    pub lines: usize,
+    pub binary: usize,
+    // Couldn't be read
+    pub error: usize,
+}
+
+// Why a file isn't counted, the index into `Tally::skipped`
+#[derive(Debug, Clone, Copy)]
+enum Skip {
+    Unknown,
+    Excluded,
+    Extension,
+    Language,
+    Modified,
+    Oversized,
+    Duplicate,
+    Lines,
+    Binary,
+    Error,
+}
+
+// Shared by the walk and the workers, the counts of `Summary`
+#[derive(Default)]
+struct Tally {
+    skipped: [AtomicUsize; 10],
+    bytes: AtomicU64,
+}
+
+impl Tally {
+    fn skip(&self, skip: Skip) {
+        self.skipped[skip as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn skipped(&self) -> Skipped {
+        let get = |skip: Skip| self.skipped[skip as usize].load(Ordering::Relaxed);
+        Skipped {
+            unknown: get(Skip::Unknown),
+            excluded: get(Skip::Excluded),
+            extension: get(Skip::Extension),
+            language: get(Skip::Language),
+            modified: get(Skip::Modified),
+            oversized: get(Skip::Oversized),
+            duplicate: get(Skip::Duplicate),
+            lines: get(Skip::Lines),
+            binary: get(Skip::Binary),
+            error: get(Skip::Error),
+        }
+    }
+}
+
+/// How far a run got, read from another thread while it goes
+///
+/// ```
+/// use lok::{CountOptions, Counter, Progress};
+/// use std::sync::Arc;
+///
+/// let options = CountOptions {
+///     work_dirs: vec!["src".into()],
+///     ..CountOptions::default()
+/// };
+/// let progress = Arc::new(Progress::default());
+/// Counter::new(options).progress(progress.clone()).run().unwrap();
+/// assert!(progress.walked());
+/// assert_eq!(progress.done(), progress.discovered());
+/// ```
+#[derive(Debug, Default)]
+pub struct Progress {
+    discovered: AtomicUsize,
+    done: AtomicUsize,
+    walked: AtomicBool,
+}
+
+impl Progress {
+    // Files the walk queued so far
+    pub fn discovered(&self) -> usize {
+        self.discovered.load(Ordering::Relaxed)
+    }
+
+    // Files the workers are done with, counted or skipped
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    // The walk is over, `discovered` is the total from now on
+    pub fn walked(&self) -> bool {
+        self.walked.load(Ordering::Relaxed)
+    }
+}
+
+type Inspect = Arc<dyn Fn(&Data) + Send + Sync>;
+
+/// The walk feeding a worker per CPU, [`count`] with the files themselves
+///
+/// ```
+/// use lok::{CountOptions, Counter};
+///
+/// let options = CountOptions {
+///     work_dirs: vec!["src".into()],
+///     ..CountOptions::default()
+/// };
+/// let summary = Counter::new(options).run().unwrap();
+/// assert!(summary.files.iter().any(|data| data.path.ends_with("lib.rs")));
+/// ```
+pub struct Counter {
+    options: CountOptions,
+    cache: Option<Arc<Cache>>,
+    inspect: Option<Inspect>,
+    progress: Option<Arc<Progress>>,
+}
+
+impl Counter {
+    pub fn new(options: CountOptions) -> Self {
+        Self {
+            options,
+            cache: None,
+            inspect: None,
+            progress: None,
+        }
+    }
+
+    // Files with the same mtime and size aren't read again
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    // Called by the workers for every counted file
+    pub fn inspect(mut self, f: impl Fn(&Data) + Send + Sync + 'static) -> Self {
+        self.inspect = Some(Arc::new(f));
+        self
+    }
+
+    // Counts the files found and done while it runs, see [`Progress`]
+    pub fn progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn run(self) -> Result<Summary, Error> {
+        let mut files = vec![];
+        let mut summary = self.run_with(|data| {
+            files.push(data);
+            ControlFlow::Continue(())
+        })?;
+        summary.files = files;
+        Ok(summary)
+    }
+
+    /// Hands every file to `f` as soon as a worker counted it, in no particular
+    /// order. A `Break` ends the walk, the files still queued are dropped
+    ///
+    /// ```
+    /// use lok::{CountOptions, Counter};
+    /// use std::ops::ControlFlow;
+    ///
+    /// let options = CountOptions {
+    ///     work_dirs: vec!["src".into()],
+    ///     ..CountOptions::default()
+    /// };
+    /// let mut first = None;
+    /// Counter::new(options)
+    ///     .run_with(|data| {
+    ///         first = Some(data);
+    ///         ControlFlow::Break(())
+    ///     })
+    ///     .unwrap();
+    /// assert!(first.is_some());
+    /// ```
+    pub fn run_with(self, mut f: impl FnMut(Data) -> ControlFlow<()>) -> Result<Summary, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || self.start(sender, &stop))
+        };
+        for data in &receiver {
+            if f(data).is_break() {
+                stop.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+        // What the workers still send goes nowhere
+        drop(receiver);
+        thread.join().unwrap_or(Err(Error::Thread))
+    }
+
+    fn start(self, sender: Sender<Data>, stop: &Arc<AtomicBool>) -> Result<Summary, Error> {
+        // Idle workers sleep in `recv`, they stop once the walk dropped its sender
+        let (work_sender, work) = mpsc::channel();
+        let work = Arc::new(Mutex::new(work));
+        let queue = Queue {
+            sender: work_sender,
+            discovered: AtomicUsize::new(0),
+            progress: self.progress.clone(),
+        };
+        let cpus = self.options.threads.unwrap_or_else(num_cpus::get);
+        let mut threads = Vec::with_capacity(cpus);
+        let vanished = Arc::new(AtomicUsize::new(0));
+        let tally = Arc::new(Tally::default());
+
+        for _ in 0..cpus {
+            let task = Task {
+                work: work.clone(),
+                print_error: self.options.print_error,
+                sender: sender.clone(),
+                stop: stop.clone(),
+                vanished: vanished.clone(),
+                tally: tally.clone(),
+                // Its stamps are a stat of every file
+                cache: self.cache.clone().filter(|_| self.options.size),
+                inspect: self.inspect.clone(),
+                min_lines: self.options.min_lines,
+                max_lines: self.options.max_lines,
+                heuristics: self.options.heuristics,
+                mmap: self.options.mmap,
+                size: self.options.size,
+                progress: self.progress.clone(),
+                languages: self.options.languages.clone(),
+                exclude_languages: self.options.exclude_languages.clone(),
+            };
+            threads.push(std::thread::spawn(move || task.start()));
+        }
+        // The receiver sees the end once the workers are done
+        drop(sender);
+
+        let mut summary = Summary::default();
+        // The workers stop either way, an error only ends the walk early
+        // The walkers are joined before it returns, nothing is sent after the drop
+        let walking = Instant::now();
+        let walked = self.walk(&queue, &vanished, &tally, stop, &mut summary);
+        summary.walk_time = walking.elapsed();
+        summary.discovered = queue.discovered.load(Ordering::Relaxed);
+        drop(queue);
+        if let Some(progress) = &self.progress {
+            progress.walked.store(true, Ordering::Relaxed);
+        }
+        let mut panicked = false;
+        for thread in threads {
+            panicked |= thread.join().is_err();
+        }
+        walked?;
+        if panicked {
+            return Err(Error::Thread);
+        }
+        summary.vanished = vanished.load(Ordering::Relaxed);
+        summary.skipped = tally.skipped();
+        summary.bytes = tally.bytes.load(Ordering::Relaxed);
+        summary.threads = cpus;
+        Ok(summary)
+    }
+
+    fn walk(
+        &self,
+        queue: &Queue,
+        vanished: &AtomicUsize,
+        tally: &Tally,
+        stop: &AtomicBool,
+        summary: &mut Summary,
+    ) -> Result<(), Error> {
+        let options = &self.options;
+        let print_error = options.print_error;
+        let pruned = AtomicUsize::new(0);
+        let pruned_dirs = &pruned;
+        // Files seen so far, `--follow`, hardlinks and overlapping roots reach a file twice
+        let seen = Mutex::new(HashSet::new());
+
+        // Named files skip the filters, they were asked for
+        let named = options
+            .files
+            .iter()
+            .map(PathBuf::as_path)
+            .collect::<HashSet<&Path>>();
+        for path in &options.files {
+            if options.dedup {
+                if let Some(id) = file_id(path) {
+                    if !seen.lock().unwrap().insert(id) {
+                        continue;
+                    }
+                }
+            }
+            if let Some(config) = CONFIG.get_by_path(path) {
+                queue.push(Work::Parse(path.clone(), config));
+            }
+        }
+
+        // Filters on the name for files found by the walk, listed by `--files-from`
+        // or inside an archive
+        let matches = |work_dir: &Path, path: &Path| -> Result<&'static Language, Skip> {
+            // Include files
+            if let Some(include) = &options.include {
+                // A hidden path has to be spelled out, `**` doesn't reach into `.git`
+                let relative = path.strip_prefix(work_dir).unwrap_or(path);
+                let match_options = MatchOptions {
+                    require_literal_leading_dot: !options.hidden && relative.iter().any(is_hidden),
+                    ..MatchOptions::new()
+                };
+                // So does a default-excluded directory, `**/*.js` skips node_modules
+                let excluded = relative
+                    .parent()
+                    .into_iter()
+                    .flat_map(Path::iter)
+                    .filter(|name| {
+                        options.default_excludes && is_default_excluded(name, options.vendored)
+                    })
+                    .collect::<Vec<&OsStr>>();
+                let any = include.iter().any(|m| {
+                    excluded
+                        .iter()
+                        .all(|name| m.as_str().contains(&*name.to_string_lossy()))
+                        && m.matches_path_with(path, match_options)
+                });
+                if !any {
+                    return Err(Skip::Excluded);
+                }
+            }
+
+            // Exclude files
+            if let Some(exclude) = &options.exclude {
+                for matcher in exclude {
+                    if matcher.matches_path(path) {
+                        return Err(Skip::Excluded);
+                    }
+                }
+            }
+
+            // File with the specified extension
+            let ext = CONFIG.extension_of(path);
+
+            // This extension is not included in config
+            if let Some(extension) = &options.extension {
+                if !extension.iter().any(|s| Some(s.as_str()) == ext) {
+                    return Err(Skip::Extension);
+                }
+            }
+
+            // A file name like `Makefile` comes before the extension, a modeline is
+            // the last resort and only opens unknown files when asked to
+            let config = CONFIG
+                .get_by_path(path)
+                .or_else(
+                    || match options.detect_modeline || options.include.is_some() {
+                        true => detect::modeline(path),
+                        false => None,
+                    },
+                )
+                .ok_or(Skip::Unknown)?;
+            // Not one of the `--language` names. A file with a shared extension like
+            // `.h` may turn out to be another language, the workers check again
+            let candidates = match (options.heuristics, ext) {
+                (true, Some(ext)) => CONFIG.candidates(ext),
+                _ => Vec::new(),
+            };
+            let names = match candidates.first() {
+                Some(first) if first.name == config.name => {
+                    candidates.iter().map(|l| l.name).collect::<Vec<&str>>()
+                }
+                _ => vec![config.name],
+            };
+            if !names
+                .iter()
+                .any(|name| is_wanted(name, &options.languages, &options.exclude_languages))
+            {
+                return Err(Skip::Language);
+            }
+            Ok(config)
+        };
+
+        let check = |work_dir: &Path, path: &Path| -> Result<&'static Language, Skip> {
+            let config = matches(work_dir, path)?;
+            if options.modified_since.is_some() || options.max_size.is_some() {
+                let meta = match fs::metadata(path) {
+                    Ok(meta) => meta,
+                    Err(err) => {
+                        if print_error {
+                            err!(err.kind(), path);
+                        }
+                        return Err(Skip::Error);
+                    }
+                };
+                if let Some(since) = options.modified_since {
+                    match meta.modified() {
+                        Ok(mtime) if mtime >= since => {}
+                        Ok(_) => return Err(Skip::Modified),
+                        Err(err) => {
+                            if print_error {
+                                err!(err.kind(), path);
+                            }
+                            return Err(Skip::Error);
+                        }
+                    }
+                }
+                if options.max_size.is_some_and(|max| meta.len() > max) {
+                    if print_error {
+                        use bright::Colorful;
+                        eprintln!("{} {:?} is larger than --max-size", "skipped:".cyan(), path);
+                    }
+                    return Err(Skip::Oversized);
+                }
+            }
+
+            if options.follow || options.dedup {
+                if let Some(id) = file_id(path) {
+                    if !seen.lock().unwrap().insert(id) {
+                        return Err(Skip::Duplicate);
+                    }
+                }
+            }
+
+            Ok(config)
+        };
+        let accept = |work_dir: &Path, path: &Path| match check(work_dir, path) {
+            Ok(config) => Some(config),
+            Err(skip) => {
+                tally.skip(skip);
+                None
+            }
+        };
+
+        // Piped content is counted as one file
+        if let Some((content, config)) = &options.stdin {
+            queue.push(Work::Text(content.clone(), config));
+        }
+
+        // A file list or stdin replaces the walk
+        let walked = match (&options.files_from, &options.stdin) {
+            (None, None) => &options.work_dirs[..],
+            _ => &[][..],
+        };
+        // Until the caller had enough
+        let stopped = || stop.load(Ordering::Relaxed);
+        for path in options.files_from.iter().flatten() {
+            if stopped() {
+                break;
+            }
+            if !path.is_file() {
+                tally.skip(Skip::Error);
+                if print_error {
+                    use bright::Colorful;
+                    eprintln!("{} '{}' does not exist", "error:".yellow(), path.display());
+                }
+                continue;
+            }
+            let work_dir = root_of(&options.work_dirs, path);
+            if let Some(config) = accept(work_dir, path) {
+                queue.push(Work::Parse(path.clone(), config));
+            }
+        }
+
+        // Entries have no mtime worth going by, `--modified-since` doesn't apply
+        for path in &options.archives {
+            let bytes = fs::read(path).map_err(|err| Error::Read(path.clone(), err))?;
+            let entries =
+                archive::entries(path, &bytes).map_err(|err| Error::Archive(path.clone(), err))?;
+            for entry in entries {
+                if stopped() {
+                    break;
+                }
+                // `./src/main.rs` and `/src/main.rs` are both `src/main.rs`
+                let relative = entry
+                    .name
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect::<PathBuf>();
+                // As if the archive was a directory being walked
+                let prune = options.include.is_none();
+                if prune && !options.hidden && relative.iter().any(is_hidden) {
+                    continue;
+                }
+                if prune
+                    && options.default_excludes
+                    && relative
+                        .parent()
+                        .into_iter()
+                        .flat_map(Path::iter)
+                        .any(|name| is_default_excluded(name, options.vendored))
+                {
+                    continue;
+                }
+                let name = path.join(&relative);
+                let config = match matches(path, &name) {
+                    Ok(config) => config,
+                    Err(skip) => {
+                        tally.skip(skip);
+                        continue;
+                    }
+                };
+                let data = match entry.data {
+                    Ok(data) => data,
+                    Err(err) => {
+                        tally.skip(Skip::Error);
+                        if print_error {
+                            use bright::Colorful;
+                            eprintln!("{} {:?} {}", "error:".yellow(), name, err);
+                        }
+                        continue;
+                    }
+                };
+                if options.max_size.is_some_and(|max| data.len() as u64 > max) {
+                    tally.skip(Skip::Oversized);
+                    if print_error {
+                        use bright::Colorful;
+                        eprintln!("{} {:?} is larger than --max-size", "skipped:".cyan(), name);
+                    }
+                    continue;
+                }
+                queue.push(Work::Bytes(name, data, config));
+            }
+        }
+
+        let walkers = options.threads.unwrap_or_else(num_cpus::get);
+        for work_dir in walked {
+            if stopped() {
+                break;
+            }
+            // Which of two paths to the same file gets counted shouldn't depend on
+            // which walker got there first, `--follow` and `--dedup` walk on one thread.
+            // `--threads 1` still takes the walkers, so their bugs show up there too
+            if !options.follow && !options.dedup {
+                if options.max_depth == Some(0) {
+                    continue;
+                }
+                let walk = Walk {
+                    options,
+                    work_dir,
+                    queue,
+                    accept: &accept,
+                    named: &named,
+                    vanished,
+                    pruned: pruned_dirs,
+                    stop,
+                    dirs: Mutex::new(Dirs::default()),
+                    ready: Condvar::new(),
+                    error: Mutex::new(None),
+                };
+                let gitignore = (options.gitignore && options.include.is_none())
+                    .then(|| Arc::new(Gitignore::default()));
+                walk.push(work_dir.clone(), 0, &gitignore);
+                walk.run(walkers)?;
+                continue;
+            }
+            // Every counted file has to match `--include`, so it wins over .gitignore
+            // and hidden paths, nothing is pruned then
+            let prune = options.include.is_none();
+            let hidden = options.hidden;
+            let default_excludes = options.default_excludes;
+            let vendored = options.vendored;
+            let mut gitignore = (options.gitignore && prune).then(Gitignore::default);
+            let mut walk = WalkDir::new(work_dir)
+                .max_depth(options.max_depth.unwrap_or(usize::MAX))
+                .follow_links(options.follow);
+            // Which of two links to the same file gets counted shouldn't change between runs
+            if options.follow {
+                walk = walk.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+            }
+            let walk = walk.into_iter().filter_entry(move |entry| {
+                if prune && !hidden && entry.depth() > 0 && is_hidden(entry.file_name()) {
+                    return false;
+                }
+                if prune
+                    && default_excludes
+                    && entry.depth() > 0
+                    && entry.file_type().is_dir()
+                    && is_default_excluded(entry.file_name(), vendored)
+                {
+                    pruned_dirs.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                let gitignore = match &mut gitignore {
+                    Some(gitignore) => gitignore,
+                    None => return true,
+                };
+                let is_dir = entry.file_type().is_dir();
+                if entry.depth() > 0 && gitignore.is_ignored(entry.path(), is_dir) {
+                    return false;
+                }
+                if is_dir {
+                    gitignore.load(entry.path());
+                }
+                true
+            });
+
+            for item in walk {
+                if stopped() {
+                    break;
+                }
+                let entry = match item {
+                    Ok(entry) => entry,
+                    Err(error) => {
+                        // WalkDir stops at a link back to one of its ancestors
+                        if let (Some(ancestor), Some(path)) = (error.loop_ancestor(), error.path())
+                        {
+                            if print_error {
+                                use bright::Colorful;
+                                eprintln!(
+                                    "{} {:?} links back to {:?}",
+                                    "error:".yellow(),
+                                    path,
+                                    ancestor
+                                );
+                            }
+                        } else if let (Some(err), Some(path)) = (error.io_error(), error.path()) {
+                            if err.kind() == ErrorKind::NotFound {
+                                if error.depth() == 0 || !work_dir.exists() {
+                                    return Err(Error::Vanished(work_dir.clone()));
+                                }
+                                vanished.fetch_add(1, Ordering::Relaxed);
+                            } else if print_error {
+                                err!(err.kind(), path);
+                            }
+                        }
+                        continue;
+                    }
+                };
+
+                let path = entry.path();
+
+                // Already counted as a named file
+                if named.contains(path) {
+                    continue;
+                }
+
+                if let Some(config) = accept(work_dir, path) {
+                    queue.push(Work::Parse(path.to_path_buf(), config));
+                }
+            }
+        }
+
+        summary.pruned = pruned.into_inner();
+        Ok(())
+    }
+}
+
+// A directory waiting for a walker, with the .gitignore rules of it and its parents
+struct Dir {
+    path: PathBuf,
+    depth: usize,
+    gitignore: Option<Arc<Gitignore>>,
+}
+
+#[derive(Default)]
+struct Dirs {
+    queue: Vec<Dir>,
+    // Queued or being read, the walk is over at 0
+    pending: usize,
+}
+
+// A root read by several threads, each lists one directory at a time and queues
+// the directories it finds for whichever walker is free
+struct Walk<'a, F> {
+    options: &'a CountOptions,
+    work_dir: &'a Path,
+    queue: &'a Queue,
+    accept: &'a F,
+    named: &'a HashSet<&'a Path>,
+    vanished: &'a AtomicUsize,
+    pruned: &'a AtomicUsize,
+    stop: &'a AtomicBool,
+    dirs: Mutex<Dirs>,
+    // Signalled for a new directory and once the walk is over
+    ready: Condvar,
+    // The first one ends the walk
+    error: Mutex<Option<Error>>,
+}
+
+impl<'a, F> Walk<'a, F>
+where
+    F: Fn(&Path, &Path) -> Option<&'static Language> + Sync,
+{
+    // Returns once every directory was read, only then may the workers be told to quit
+    fn run(self, walkers: usize) -> Result<(), Error> {
+        std::thread::scope(|scope| {
+            for _ in 0..walkers {
+                scope.spawn(|| self.walker());
+            }
+        });
+        match self.error.into_inner().unwrap() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn walker(&self) {
+        loop {
+            let dir = {
+                let mut dirs = self.dirs.lock().unwrap();
+                loop {
+                    if let Some(dir) = dirs.queue.pop() {
+                        break dir;
+                    }
+                    if dirs.pending == 0 {
+                        return;
+                    }
+                    // Another walker is still reading and may queue more
+                    dirs = self.ready.wait(dirs).unwrap();
+                }
+            };
+            let failed = self.error.lock().unwrap().is_some();
+            if !failed && !self.stop.load(Ordering::Relaxed) {
+                if let Err(err) = self.read(&dir) {
+                    self.error.lock().unwrap().get_or_insert(err);
+                }
+            }
+            // After the subdirectories were queued, so it can't reach 0 too early
+            let mut dirs = self.dirs.lock().unwrap();
+            dirs.pending -= 1;
+            if dirs.pending == 0 {
+                self.ready.notify_all();
+            }
+        }
+    }
+
+    fn push(&self, path: PathBuf, depth: usize, gitignore: &Option<Arc<Gitignore>>) {
+        // The parent's rules are shared until a directory has its own .gitignore
+        let gitignore = gitignore
+            .as_ref()
+            .map(|parent| match path.join(".gitignore").is_file() {
+                true => {
+                    let mut own = Gitignore::clone(parent);
+                    own.load(&path);
+                    Arc::new(own)
+                }
+                false => parent.clone(),
+            });
+        let mut dirs = self.dirs.lock().unwrap();
+        dirs.pending += 1;
+        dirs.queue.push(Dir {
+            path,
+            depth,
+            gitignore,
+        });
+        self.ready.notify_one();
+    }
+
+    // The same filters as the single walk, in the same order
+    fn read(&self, dir: &Dir) -> Result<(), Error> {
+        let options = self.options;
+        let prune = options.include.is_none();
+        let entries = match fs::read_dir(&dir.path) {
+            Ok(entries) => entries,
+            Err(err) => return self.failed(&dir.path, dir.depth, err),
+        };
+        for entry in entries {
+            let depth = dir.depth + 1;
+            let (entry, file_type) = match entry.and_then(|e| e.file_type().map(|t| (e, t))) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    self.failed(&dir.path, depth, err)?;
+                    continue;
+                }
+            };
+            let name = entry.file_name();
+            let path = entry.path();
+            let is_dir = file_type.is_dir();
+            if prune && !options.hidden && is_hidden(&name) {
+                continue;
+            }
+            if prune
+                && options.default_excludes
+                && is_dir
+                && is_default_excluded(&name, options.vendored)
+            {
+                self.pruned.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if let Some(gitignore) = &dir.gitignore {
+                if gitignore.is_ignored(&path, is_dir) {
+                    continue;
+                }
+            }
+            if is_dir {
+                if options.max_depth.is_none_or(|max| depth < max) {
+                    self.push(path, depth, &dir.gitignore);
+                }
+                continue;
+            }
+            // Already counted as a named file
+            if self.named.contains(path.as_path()) {
+                continue;
+            }
+            if let Some(config) = (self.accept)(self.work_dir, &path) {
+                self.queue.push(Work::Parse(path, config));
+            }
+        }
+        Ok(())
+    }
+
+    fn failed(&self, path: &Path, depth: usize, err: io::Error) -> Result<(), Error> {
+        if err.kind() == ErrorKind::NotFound {
+            if depth == 0 || !self.work_dir.exists() {
+                return Err(Error::Vanished(self.work_dir.to_path_buf()));
+            }
+            self.vanished.fetch_add(1, Ordering::Relaxed);
+        } else if self.options.print_error {
+            err!(err.kind(), path);
+        }
+        Ok(())
+    }
+}
+
+// `.git`, `.idea`, `.env.js`
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+// Device and inode, hardlinks share them
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|meta| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path).ok()
+}
+
+// Build output and dependencies, pruned from the walk unless `--no-default-excludes`
+const DEFAULT_EXCLUDES: [&str; 6] = [
+    "node_modules",
+    "target",
+    "vendor",
+    "build",
+    "dist",
+    "__pycache__",
+];
+
+// Directories holding third-party code
+pub const VENDORED_DIRS: [&str; 2] = ["node_modules", "vendor"];
+
+// `--vendored-report` still has to walk the vendored directories
+fn is_default_excluded(name: &OsStr, vendored: bool) -> bool {
+    name.to_str().is_some_and(|name| {
+        DEFAULT_EXCLUDES.contains(&name) && !(vendored && VENDORED_DIRS.contains(&name))
+    })
+}
+
+// The root a file was found under, the deepest one when roots overlap
+pub fn root_of<'a>(work_dirs: &'a [PathBuf], path: &Path) -> &'a Path {
+    work_dirs
+        .iter()
+        .filter(|dir| path.starts_with(dir))
+        .max_by_key(|dir| dir.components().count())
+        .map(PathBuf::as_path)
+        .unwrap_or_else(|| Path::new("."))
+}
+
+// The walk's end of the work queue
+struct Queue {
+    sender: Sender<Work<'static>>,
+    discovered: AtomicUsize,
+    progress: Option<Arc<Progress>>,
+}
+
+impl Queue {
+    // Sending only fails once every worker panicked, `start` reports that
+    fn push(&self, work: Work<'static>) {
+        self.discovered.fetch_add(1, Ordering::Relaxed);
+        if let Some(progress) = &self.progress {
+            progress.discovered.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = self.sender.send(work);
+    }
+}
+
+enum Work<'a> {
+    Parse(PathBuf, &'a Language),
+    // Content read from stdin
+    Text(String, &'a Language),
+    // An archive entry, named `<archive>/<entry>`
+    Bytes(PathBuf, Vec<u8>, &'a Language),
+}
+
+struct Task<'a> {
+    work: Arc<Mutex<Receiver<Work<'a>>>>,
+    print_error: bool,
+    sender: Sender<Data>,
+    // Set once the caller had enough, the rest of the work is dropped
+    stop: Arc<AtomicBool>,
+    vanished: Arc<AtomicUsize>,
+    tally: Arc<Tally>,
+    // `--cache`, shared by the workers
+    cache: Option<Arc<Cache>>,
+    // Called with every counted file
+    inspect: Option<Inspect>,
+    // `--min-lines` and `--max-lines`, only known after parsing
+    min_lines: Option<u64>,
+    max_lines: Option<u64>,
+    // Off with `--no-heuristics`, `.h` is then always C
+    heuristics: bool,
+    // `--mmap`
+    mmap: bool,
+    // `--no-size`
+    size: bool,
+    progress: Option<Arc<Progress>>,
+    // `--language` and `--exclude-language` for what `heuristics` decided
+    languages: Option<Vec<&'static str>>,
+    exclude_languages: Option<Vec<&'static str>>,
+}
+
+impl<'a> Task<'a> {
+    fn skip(&self, data: &Data) -> Option<Skip> {
+        let lines = data.blank + data.comment + data.code;
+        if !(self.min_lines.is_none_or(|min| lines >= min)
+            && self.max_lines.is_none_or(|max| lines <= max))
+        {
+            return Some(Skip::Lines);
+        }
+        match is_wanted(data.language, &self.languages, &self.exclude_languages) {
+            true => None,
+            false => Some(Skip::Language),
+        }
+    }
+
+    // Only the files that are counted, `--by-author` blames them and that is slow
+    fn inspect(&self, data: &Data) {
+        if let (Some(inspect), None) = (&self.inspect, self.skip(data)) {
+            inspect(data);
+        }
+    }
+
+    fn emit(&self, data: Data) {
+        match self.skip(&data) {
+            Some(skip) => self.tally.skip(skip),
+            None => {
+                self.tally.bytes.fetch_add(data.size, Ordering::Relaxed);
+                let _ = self.sender.send(data);
+            }
+        }
+    }
+
+    fn report(&self, value: Value) {
+        match value {
+            Value::Ok(data) => {
+                self.inspect(&data);
+                self.emit(data)
+            }
+            Value::Err(kind, p) => {
+                self.tally.skip(Skip::Error);
+                if self.print_error {
+                    err!(kind, p)
+                }
+            }
+            Value::Vanished => {
+                self.vanished.fetch_add(1, Ordering::Relaxed);
+            }
+            Value::Binary(p) => {
+                self.tally.skip(Skip::Binary);
+                if self.print_error {
+                    use bright::Colorful;
+                    eprintln!("{} {:?} is binary", "skipped:".cyan(), p);
+                }
+            }
+            Value::Invalid => {}
+        }
+    }
+
+    fn start(self) {
+        loop {
+            // Ends once the walk is over and the queue drained
+            let work = match self.work.lock().unwrap().recv() {
+                Ok(work) => work,
+                Err(_) => break,
+            };
+            if self.stop.load(Ordering::Relaxed) {
+                continue;
+            }
+            self.count(work);
+            if let Some(progress) = &self.progress {
+                progress.done.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn count(&self, work: Work) {
+        match work {
+            Work::Parse(path, config) => {
+                let stamp = match &self.cache {
+                    Some(cache) => match cache.get(&path, config) {
+                        Ok(data) => {
+                            self.inspect(&data);
+                            self.emit(data);
+                            return;
+                        }
+                        Err(stamp) => stamp,
+                    },
+                    None => None,
+                };
+                let value = match (self.size, self.mmap) {
+                    (false, _) => parser_no_size(path, config, self.heuristics),
+                    (true, true) => parser_mmap(path, config, self.heuristics),
+                    (true, false) => parser(path, config, self.heuristics),
+                };
+                if let (Value::Ok(data), Some(cache), Some(stamp)) = (&value, &self.cache, stamp) {
+                    cache.insert(stamp, config, data);
+                }
+                self.report(value);
+            }
+            Work::Bytes(path, bytes, config) => {
+                let size = match self.size {
+                    true => bytes.len() as u64,
+                    false => 0,
+                };
+                let value = parse_bytes(path, bytes, size, config, self.heuristics);
+                self.report(value);
+            }
+            Work::Text(content, config) => {
+                let size = content.len() as u64;
+                let data = parse_str(PathBuf::from("<stdin>"), &content, size, config);
+                self.emit(data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_with_break() {
+        let options = CountOptions {
+            work_dirs: vec!["src".into()],
+            ..CountOptions::default()
+        };
+        let mut calls = 0;
+        Counter::new(options)
+            .run_with(|_| {
+                calls += 1;
+                ControlFlow::Break(())
+            })
+            .unwrap();
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_skipped() {
+        let root = std::env::temp_dir().join("lok-skipped");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("main.rs"), "fn main() {\n}\n").unwrap();
+        fs::write(root.join("notes.unknown"), "?\n").unwrap();
+        fs::write(root.join("gen.rs"), "fn gen() {\n}\n").unwrap();
+        fs::write(root.join("big.rs"), "// big\n".repeat(100)).unwrap();
+        fs::write(root.join("one.rs"), "fn one() {}\n").unwrap();
+        fs::write(root.join("blob.rs"), b"\0\x01\x02\n").unwrap();
+
+        let options = CountOptionsBuilder::new()
+            .root(&root)
+            .exclude_glob(&root.join("gen.rs").to_string_lossy())
+            .max_size(200)
+            .min_lines(2)
+            .threads(2)
+            .build()
+            .unwrap();
+        let summary = Counter::new(options).run().unwrap();
+        assert_eq!(summary.files.len(), 1);
+        assert_eq!(
+            summary.skipped,
+            Skipped {
+                unknown: 1,
+                excluded: 1,
+                oversized: 1,
+                lines: 1,
+                binary: 1,
+                ..Skipped::default()
+            }
+        );
+        // Handed to the workers, the binary and the one-line file with `main.rs`
+        assert_eq!(summary.discovered, 3);
+        assert_eq!(summary.bytes, 14);
+        assert_eq!(summary.threads, 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_builder() {
+        let options = CountOptionsBuilder::new()
+            .root(".")
+            .root("./src")
+            .root("Cargo.toml")
+            .exclude_glob("target/**")
+            .include_glob("./src/*.rs")
+            .build()
+            .unwrap();
+        // `./src` is walked with `.`
+        assert_eq!(options.work_dirs, [PathBuf::from(".")]);
+        assert_eq!(options.files, [PathBuf::from("Cargo.toml")]);
+        let globs = |patterns: Option<Vec<Pattern>>| {
+            patterns
+                .unwrap()
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(globs(options.exclude), ["./target/**"]);
+        assert_eq!(globs(options.include), ["./src/*.rs"]);
+
+        let build = |builder: CountOptionsBuilder| builder.build().unwrap_err().to_string();
+        assert_eq!(
+            build(CountOptionsBuilder::new().root("missing")),
+            "'missing' does not exist"
+        );
+        assert_eq!(
+            build(CountOptionsBuilder::new().root("LICENSE")),
+            "'LICENSE' is not a known language"
+        );
+        assert_eq!(
+            build(CountOptionsBuilder::new().threads(0)),
+            "threads must be at least 1"
+        );
+        assert!(build(CountOptionsBuilder::new().root(".").exclude_glob("a[")).contains("'./a['"));
+    }
+
+    fn walk_tree(name: &str, dirs: usize, files: usize) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        for d in 0..dirs {
+            let dir = root.join(format!("d{}", d % 10)).join(format!("d{}", d));
+            fs::create_dir_all(&dir).unwrap();
+            for f in 0..files {
+                fs::write(dir.join(format!("f{}.rs", f)), "fn a() {}\n").unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn test_parallel_walk() {
+        let root = walk_tree("lok-parallel-walk", 20, 3);
+        fs::write(root.join(".gitignore"), "d3/\n").unwrap();
+        fs::write(root.join("d1/.gitignore"), "f0.rs\n").unwrap();
+        fs::create_dir_all(root.join("d2/node_modules")).unwrap();
+        fs::write(root.join("d2/node_modules/m.rs"), "fn m() {}\n").unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::write(root.join(".hidden/h.rs"), "fn h() {}\n").unwrap();
+
+        let run = |threads, max_depth| {
+            let options = CountOptionsBuilder::new()
+                .root(&root)
+                .threads(threads)
+                .max_depth(max_depth)
+                .build()
+                .unwrap();
+            let summary = Counter::new(options).run().unwrap();
+            let mut paths = summary
+                .files
+                .into_iter()
+                .map(|d| d.path)
+                .collect::<Vec<_>>();
+            paths.sort();
+            (paths, summary.pruned)
+        };
+        let (paths, pruned) = run(4, None);
+        assert_eq!((paths.len(), pruned), (20 * 3 - 2 * 3 - 2, 1));
+        assert_eq!(run(1, None), (paths, pruned));
+        assert_eq!(run(4, Some(2)), run(1, Some(2)));
+        assert_eq!(run(4, Some(3)), run(1, Some(3)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // 10 directories a level, `files` in each of the deepest
+    fn deep_tree(name: &str, depth: usize, files: usize) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+        let mut dirs = vec![root.clone()];
+        for _ in 0..depth {
+            dirs = dirs
+                .iter()
+                .flat_map(|dir| (0..10).map(move |d| dir.join(format!("d{}", d))))
+                .collect();
+        }
+        for dir in &dirs {
+            fs::create_dir_all(dir).unwrap();
+            for f in 0..files {
+                fs::write(dir.join(format!("f{}.rs", f)), "fn a() {}\n").unwrap();
+            }
+        }
+        root
+    }
+
+    // Only the walk, no file has the extension. As root on Linux the page cache
+    // is dropped before each run, the directories then come from the disk
+    // cargo test --release bench_walk -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_walk() {
+        let trees = [
+            ("wide", walk_tree("lok-bench-walk", 1000, 100)),
+            ("deep", deep_tree("lok-bench-deep", 4, 10)),
+        ];
+        for (shape, root) in &trees {
+            for threads in [1, 2, 8, 32] {
+                let mut cold = false;
+                let mut times = (0..5)
+                    .map(|_| {
+                        let options = CountOptionsBuilder::new()
+                            .root(root)
+                            .extensions(["none"])
+                            .threads(threads)
+                            .build()
+                            .unwrap();
+                        cold = drop_caches();
+                        let start = std::time::Instant::now();
+                        Counter::new(options).run().unwrap();
+                        start.elapsed()
+                    })
+                    .collect::<Vec<std::time::Duration>>();
+                times.sort();
+                eprintln!(
+                    "{} tree of 100k files, {} walkers{}: median {:?} of 5",
+                    shape,
+                    threads,
+                    if cold { ", cold cache" } else { "" },
+                    times[2]
+                );
+            }
+            fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    fn drop_caches() -> bool {
+        let _ = std::process::Command::new("sync").status();
+        fs::write("/proc/sys/vm/drop_caches", "3").is_ok()
+    }
+
+    // CPU time of the calling thread
+    #[cfg(target_os = "linux")]
+    fn thread_cpu() -> Option<std::time::Duration> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) };
+        let time =
+            |t: libc::timeval| std::time::Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+        Some(time(usage.ru_utime) + time(usage.ru_stime))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn thread_cpu() -> Option<std::time::Duration> {
+        None
+    }
+
+    #[test]
+    fn test_slow_producer() {
+        let (queue, work) = mpsc::channel();
+        let (sender, receiver) = mpsc::channel();
+        let task = Task {
+            work: Arc::new(Mutex::new(work)),
+            print_error: false,
+            sender,
+            stop: Arc::new(AtomicBool::new(false)),
+            vanished: Arc::new(AtomicUsize::new(0)),
+            tally: Arc::new(Tally::default()),
+            cache: None,
+            inspect: None,
+            min_lines: None,
+            max_lines: None,
+            heuristics: true,
+            mmap: false,
+            size: true,
+            progress: None,
+            languages: None,
+            exclude_languages: None,
+        };
+        let worker = std::thread::spawn(move || {
+            task.start();
+            thread_cpu()
+        });
+
+        // A walk on a slow disk, the worker waits for each file
+        let paths = [
+            "src/lib.rs",
+            "src/count.rs",
+            "src/parse.rs",
+            "src/ignore.rs",
+        ];
+        for path in paths.iter().cycle().take(20) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            let config = CONFIG.get_by_path(Path::new(path)).unwrap();
+            queue
+                .send(Work::Parse(PathBuf::from(path), config))
+                .unwrap();
+        }
+        drop(queue);
+        let cpu = worker.join().unwrap();
+
+        let files = receiver.iter().collect::<Vec<Data>>();
+        assert_eq!(files.len(), 20);
+        for data in &files {
+            let config = CONFIG.get_by_path(&data.path).unwrap();
+            match parser(data.path.clone(), config, true) {
+                Value::Ok(expected) => assert_eq!(data, &expected),
+                _ => panic!("{:?} wasn't counted", data.path),
+            }
+        }
+        // 200ms of waiting, spinning would take about as much CPU
+        if let Some(cpu) = cpu {
+            assert!(cpu < std::time::Duration::from_millis(100), "{:?}", cpu);
+        }
+    }
+}
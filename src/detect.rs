@@ -0,0 +1,434 @@
+// Languages of files the extension says nothing about, `--detect`, or
+// says too little about, like `.h`
+use crate::config::{Language, CONFIG};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Vim looks at 5 lines at either end, a few KiB covers them
+const MODELINE_BYTES: u64 = 4096;
+const MODELINE_LINES: usize = 5;
+
+// Vim filetypes and Emacs modes that aren't a language name or alias
+const FILETYPES: &[(&str, &str)] = &[
+    ("shell-script", "Shell"),
+    ("zsh", "Shell"),
+    ("fish", "Shell"),
+    ("cperl", "Perl"),
+    ("js2", "JavaScript"),
+    ("javascriptreact", "JavaScript JSX"),
+    ("typescriptreact", "TypeScript JSX"),
+    ("objcpp", "Objective-C++"),
+    ("dockerfile", "Dockerfile"),
+];
+
+// Read the ends of `path` and look for a modeline there
+pub fn modeline(path: &Path) -> Option<&'static Language> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let mut head = Vec::new();
+    file.by_ref()
+        .take(MODELINE_BYTES)
+        .read_to_end(&mut head)
+        .ok()?;
+    // The end of a long file, without reading the head twice
+    let mut tail = Vec::new();
+    if len > MODELINE_BYTES {
+        let start = (len - MODELINE_BYTES).max(MODELINE_BYTES);
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_to_end(&mut tail).ok()?;
+    }
+    let head = String::from_utf8_lossy(&head);
+    let tail = String::from_utf8_lossy(&tail);
+    let last = match tail.is_empty() {
+        true => &head,
+        false => &tail,
+    };
+
+    head.lines()
+        .take(MODELINE_LINES)
+        .chain(last.lines().rev().take(MODELINE_LINES))
+        .find_map(|line| filetype(line).and_then(language))
+}
+
+// `vim: ft=yaml`, `vim: set filetype=yaml:` or `-*- mode: ruby -*-`
+fn filetype(line: &str) -> Option<String> {
+    vim(line)
+        .or_else(|| emacs(line))
+        .map(|name| name.to_lowercase())
+}
+
+fn vim(line: &str) -> Option<&str> {
+    // `vim:`, `vi:` or `ex:` at the start or after a blank
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(i, _)| line[..*i].chars().last().is_none_or(char::is_whitespace))
+            .map(|(i, _)| i + marker.len())
+    })?;
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            option
+                .strip_prefix("ft=")
+                .or_else(|| option.strip_prefix("filetype="))
+        })
+        .filter(|name| !name.is_empty())
+}
+
+fn emacs(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (vars, _) = rest.split_once("-*-")?;
+    let mode = match vars.contains(':') {
+        true => vars.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            match key.trim().eq_ignore_ascii_case("mode") {
+                true => Some(value.trim()),
+                false => None,
+            }
+        })?,
+        false => vars.trim(),
+    };
+    let mode = mode.strip_suffix("-mode").unwrap_or(mode);
+    Some(mode).filter(|mode| !mode.is_empty())
+}
+
+fn language(filetype: String) -> Option<&'static Language> {
+    let name = FILETYPES
+        .iter()
+        .find(|(filetype_name, _)| *filetype_name == filetype)
+        .map(|(_, name)| *name)
+        .unwrap_or(&filetype);
+    CONFIG
+        .all_language()
+        .iter()
+        .find(|language| language.is_called(name))
+        .copied()
+}
+
+// Bytes at the start of a file the guesses below look at
+pub const GUESS_BYTES: usize = 8192;
+
+// Up to `GUESS_BYTES`, cut on a char boundary
+fn prefix(content: &str) -> &str {
+    let mut end = content.len().min(GUESS_BYTES);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    &content[..end]
+}
+
+// Lines of `content` that aren't blank, trimmed
+fn lines(content: &str) -> impl Iterator<Item = &str> {
+    prefix(content)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+// The language of a file whose extension more than one language has,
+// `None` leaves it to the first of them
+pub fn guess(extension: &str, content: &str) -> Option<&'static str> {
+    match extension {
+        "h" => Some(header(content)),
+        "m" => objc_or_matlab(content),
+        "pl" => perl_or_prolog(content),
+        "sc" => scala_or_supercollider(content),
+        "r" => r_or_rebol(content),
+        "json" => json_or_jsonc(content),
+        _ => None,
+    }
+}
+
+// The language of a bare `.h` file: `@interface` or `#import` is Objective-C,
+// `class`, `template` or `namespace` is C++, anything else stays C
+pub fn header(content: &str) -> &'static str {
+    let mut cpp = false;
+    let mut in_comment = false;
+    for line in lines(content) {
+        // Words in comments say nothing
+        if in_comment || line.starts_with("/*") {
+            in_comment = !line.contains("*/");
+            continue;
+        }
+        if line.starts_with("//") {
+            continue;
+        }
+        if ["@interface", "@protocol", "@implementation", "#import"]
+            .iter()
+            .any(|token| line.starts_with(token))
+        {
+            return "ObjectiveC";
+        }
+        let mut words = line.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'));
+        cpp = cpp || words.any(|word| ["class", "template", "namespace"].contains(&word));
+    }
+    match cpp {
+        true => "C++",
+        false => "C",
+    }
+}
+
+// `@interface` and `#import` against `%` comments and `function` ... `end`
+fn objc_or_matlab(content: &str) -> Option<&'static str> {
+    lines(content).find_map(|line| {
+        if [
+            "@interface",
+            "@implementation",
+            "@protocol",
+            "#import",
+            "#include",
+        ]
+        .iter()
+        .any(|token| line.starts_with(token))
+        {
+            Some("ObjectiveC")
+        } else if line.starts_with('%') || line.starts_with("function ") || line == "end" {
+            Some("MATLAB")
+        } else {
+            None
+        }
+    })
+}
+
+// `:-` directives and rules against `use strict`, `my $a` and `sub`
+fn perl_or_prolog(content: &str) -> Option<&'static str> {
+    lines(content).find_map(|line| {
+        if line.starts_with(":-") || line.ends_with(":-") || line.contains(") :-") {
+            Some("Prolog")
+        } else if line.starts_with("#!") && line.contains("perl")
+            || [
+                "use strict",
+                "use warnings",
+                "my $",
+                "my @",
+                "my %",
+                "sub ",
+                "package ",
+            ]
+            .iter()
+            .any(|token| line.starts_with(token))
+        {
+            Some("Perl")
+        } else {
+            None
+        }
+    })
+}
+
+// `SynthDef` and `s.boot` against Scala definitions
+fn scala_or_supercollider(content: &str) -> Option<&'static str> {
+    lines(content).find_map(|line| {
+        if line.contains("SynthDef") || line.starts_with("s.boot") || line.starts_with('~') {
+            Some("SuperCollider")
+        } else if [
+            "object ",
+            "import ",
+            "def ",
+            "val ",
+            "case class ",
+            "package ",
+        ]
+        .iter()
+        .any(|token| line.starts_with(token))
+        {
+            Some("Scala")
+        } else {
+            None
+        }
+    })
+}
+
+// A Rebol script starts with its `REBOL [...]` header
+fn r_or_rebol(content: &str) -> Option<&'static str> {
+    let first = lines(content).next()?;
+    match first
+        .get(..5)
+        .is_some_and(|word| word.eq_ignore_ascii_case("rebol"))
+    {
+        true => Some("Rebol"),
+        false => None,
+    }
+}
+
+// JSON can't start with a comment
+fn json_or_jsonc(content: &str) -> Option<&'static str> {
+    let first = lines(content).next()?;
+    match first.starts_with("//") || first.starts_with("/*") {
+        true => Some("JSON with Comments"),
+        false => None,
+    }
+}
+
+// Move a file from the first language of a shared extension to the one
+// `guess` finds, as long as that language still has the extension
+pub fn refine<'a>(path: &Path, content: &str, config: &'a Language) -> &'a Language {
+    let extension = match CONFIG.extension_of(path) {
+        Some(extension) => extension,
+        None => return config,
+    };
+    let candidates = CONFIG.candidates(extension);
+    if candidates.len() < 2 || candidates[0].name != config.name {
+        return config;
+    }
+    // VS Code reads its `.vscode/settings.json` and the others with comments
+    let vscode = path.parent().and_then(Path::file_name) == Some(".vscode".as_ref());
+    let guessed = match extension == "json" && vscode {
+        true => Some("JSON with Comments"),
+        false => guess(extension, content),
+    };
+    guessed
+        .and_then(|name| candidates.into_iter().find(|l| l.name == name))
+        .unwrap_or(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_filetype() {
+        let ft = filetype;
+        assert_eq!(ft("# vim: ft=yaml").as_deref(), Some("yaml"));
+        assert_eq!(
+            ft("// vim: set ts=4 filetype=javascript:").as_deref(),
+            Some("javascript")
+        );
+        assert_eq!(ft("vi:noai:sw=3 ft=Ruby").as_deref(), Some("ruby"));
+        assert_eq!(ft("# -*- mode: ruby -*-").as_deref(), Some("ruby"));
+        assert_eq!(
+            ft("# -*- coding: utf-8; mode: python -*-").as_deref(),
+            Some("python")
+        );
+        assert_eq!(
+            ft(";; -*- shell-script -*-").as_deref(),
+            Some("shell-script")
+        );
+        assert_eq!(ft("-*- Mode: perl-mode -*-").as_deref(), Some("perl"));
+
+        // Not modelines
+        assert_eq!(ft("# vim: set ts=4 sw=4:"), None);
+        assert_eq!(ft("myvim: ft=yaml"), None);
+        assert_eq!(ft("-*- coding: utf-8 -*-"), None);
+        assert_eq!(ft("a -*- b"), None);
+        assert_eq!(ft("vim: ft="), None);
+    }
+
+    #[test]
+    fn test_language() {
+        let name = |ft: &str| language(ft.to_string()).map(|l| l.name);
+        assert_eq!(name("yaml"), Some("YAML"));
+        assert_eq!(name("c++"), Some("C++"));
+        assert_eq!(name("sh"), Some("Shell"));
+        assert_eq!(name("shell-script"), Some("Shell"));
+        assert_eq!(name("javascriptreact"), Some("JavaScript JSX"));
+        assert_eq!(name("notalanguage"), None);
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(
+            header("#ifndef A_H\n#define A_H\nint a(void);\n#endif\n"),
+            "C"
+        );
+        assert_eq!(header("#import <Foundation/Foundation.h>\n"), "ObjectiveC");
+        assert_eq!(header("@interface A : NSObject\n@end\n"), "ObjectiveC");
+        assert_eq!(header("#pragma once\nclass A {};\n"), "C++");
+        assert_eq!(header("template <typename T>\nT max(T a, T b);\n"), "C++");
+        assert_eq!(header("namespace a {\n}\n"), "C++");
+        // Objective-C wins over C++, comments and longer words don't count
+        assert_eq!(header("class A;\n#import \"b.h\"\n"), "ObjectiveC");
+        assert_eq!(header("// a class of its own\nint subclass;\n"), "C");
+        assert_eq!(header("/*\n * class\n */\nstruct classes;\n"), "C");
+        // Only the start of a file is read
+        let late = format!("{}class A {{}};\n", "int a;\n".repeat(GUESS_BYTES / 7));
+        assert_eq!(header(&late), "C");
+        assert_eq!(header(&"中".repeat(GUESS_BYTES)), "C");
+    }
+
+    #[test]
+    fn test_guess() {
+        let m = |content| guess("m", content);
+        assert_eq!(
+            m("#import \"A.h\"\n@implementation A\n@end\n"),
+            Some("ObjectiveC")
+        );
+        assert_eq!(m("% plot\nx = 1:10;\n"), Some("MATLAB"));
+        assert_eq!(m("function y = f(x)\n  y = x;\nend\n"), Some("MATLAB"));
+        assert_eq!(m("x = 1;\n"), None);
+
+        let pl = |content| guess("pl", content);
+        assert_eq!(pl("#!/usr/bin/perl\nprint 1;\n"), Some("Perl"));
+        assert_eq!(pl("use strict;\nmy $a = 1;\n"), Some("Perl"));
+        assert_eq!(pl(":- module(a, []).\n"), Some("Prolog"));
+        assert_eq!(
+            pl("parent(tom, bob).\nancestor(X, Y) :- parent(X, Y).\n"),
+            Some("Prolog")
+        );
+        assert_eq!(pl("print 1;\n"), None);
+
+        let sc = |content| guess("sc", content);
+        assert_eq!(sc("object Main extends App\n"), Some("Scala"));
+        assert_eq!(
+            sc("SynthDef(\\beep, { Out.ar(0, SinOsc.ar) }).add;\n"),
+            Some("SuperCollider")
+        );
+        assert_eq!(sc("s.boot;\n"), Some("SuperCollider"));
+        assert_eq!(sc("1 + 1\n"), None);
+
+        let r = |content| guess("r", content);
+        assert_eq!(r("\nREBOL [Title: \"a\"]\nprint 1\n"), Some("Rebol"));
+        assert_eq!(r("x <- c(1, 2)\n# REBOL\n"), None);
+        assert_eq!(r(""), None);
+
+        let json = |content| guess("json", content);
+        assert_eq!(json("// settings\n{}\n"), Some("JSON with Comments"));
+        assert_eq!(json("\n  /* a */ {}"), Some("JSON with Comments"));
+        assert_eq!(json("{\"a\": \"// b\"}\n"), None);
+
+        assert_eq!(guess("rs", "fn main() {}\n"), None);
+    }
+
+    #[test]
+    fn test_refine() {
+        let c = CONFIG.find("C").unwrap();
+        let refined = |path: &str, content, config| refine(Path::new(path), content, config).name;
+        assert_eq!(refined("a.h", "class A;\n", c), "C++");
+        assert_eq!(refined("a.c", "class A;\n", c), "C");
+        let rust = CONFIG.find("Rust").unwrap();
+        assert_eq!(refined("a.h", "class A;\n", rust), "Rust");
+        // Inconclusive keeps the built-in choice
+        let objc = CONFIG.find("ObjectiveC").unwrap();
+        assert_eq!(refined("a.m", "% a\n", objc), "MATLAB");
+        assert_eq!(refined("a.m", "x = 1;\n", objc), "ObjectiveC");
+        let json = CONFIG.find("JSON").unwrap();
+        assert_eq!(
+            refined(".vscode/launch.json", "{}", json),
+            "JSON with Comments"
+        );
+        assert_eq!(refined("launch.json", "{}", json), "JSON");
+    }
+
+    #[test]
+    fn test_modeline() {
+        let dir = std::env::temp_dir().join(format!("lok-detect-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let detect = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            modeline(&path).map(|l| l.name)
+        };
+        assert_eq!(detect("a.conf", "key: 1\n# vim: ft=yaml\n"), Some("YAML"));
+        assert_eq!(
+            detect("b.conf", "# -*- mode: ruby -*-\nputs 1\n"),
+            Some("Ruby")
+        );
+        assert_eq!(detect("c.conf", "# vim: ft=bogus\n"), None);
+        // Only the ends of a file are looked at
+        let middle = format!("{}# vim: ft=yaml\n{}", "x\n".repeat(10), "x\n".repeat(10));
+        assert_eq!(detect("d.conf", &middle), None);
+        let long = format!("{}# vim: ft=yaml\n", "x\n".repeat(10000));
+        assert_eq!(detect("e.conf", &long), Some("YAML"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
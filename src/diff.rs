@@ -0,0 +1,391 @@
+use crate::exit;
+use crate::format::signed;
+use crate::output::{box_table, Format};
+use crate::parse::Data;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+pub struct DiffOptions {
+    pub old: String,
+    pub new: String,
+    pub threshold: u64,
+    pub format: Format,
+    // Of the terminal, long paths are cut to fit
+    pub width: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FileCount {
+    pub path: String,
+    pub language: String,
+    pub code: i64,
+    pub comment: i64,
+    pub blank: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Changed => "changed",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Change {
+    pub status: Status,
+    pub path: String,
+    pub language: String,
+    pub code: i64,
+    pub comment: i64,
+    pub blank: i64,
+}
+
+pub fn run(options: DiffOptions) {
+    let load = |path: &str| {
+        let content = fs::read_to_string(path)
+            .unwrap_or_else(|err| exit!("Cannot read '{}'\n{:#?}", path, err));
+        parse_report(&content).unwrap_or_else(|err| exit!("'{}' {}", path, err))
+    };
+    let old = load(&options.old);
+    let new = load(&options.new);
+
+    let changes = diff(old, new)
+        .into_iter()
+        .filter(|change| change.code.unsigned_abs() >= options.threshold)
+        .collect::<Vec<Change>>();
+
+    let data = match options.format {
+        Format::Markdown => markdown(&changes),
+        Format::Json => json(&changes),
+        _ => table(&changes, options.width),
+    };
+    println!("{}", data.join("\n"));
+}
+
+// What is read of a `-o json` report, anything else is ignored
+#[derive(Deserialize)]
+struct Report {
+    languages: Option<Vec<ReportRow>>,
+    directories: Option<IgnoredAny>,
+    extensions: Option<IgnoredAny>,
+}
+
+// Per-file reports nest a "files" array in every language, aggregate reports
+// have the same shape without it. A `--by-family` row has its languages under
+// "members"
+#[derive(Deserialize)]
+struct ReportRow {
+    members: Option<Vec<ReportRow>>,
+    files: Option<Vec<Data>>,
+}
+
+pub fn parse_report(content: &str) -> Result<Vec<FileCount>, String> {
+    let report: Report =
+        serde_json::from_str(content).map_err(|err| format!("is not a valid report: {}", err))?;
+    let languages = match report {
+        Report {
+            languages: Some(languages),
+            ..
+        } => languages,
+        Report {
+            directories,
+            extensions,
+            ..
+        } if directories.is_some() || extensions.is_some() => {
+            return Err(
+                "is grouped by directory or extension, a per-file report by language is required (`--files -o json`)"
+                    .to_string(),
+            )
+        }
+        _ => return Err("is not a report, missing \"languages\"".to_string()),
+    };
+
+    let mut files = Vec::new();
+    read_rows(languages, &mut files)?;
+    Ok(files)
+}
+
+fn read_rows(rows: Vec<ReportRow>, files: &mut Vec<FileCount>) -> Result<(), String> {
+    for row in rows {
+        if let Some(members) = row.members {
+            read_rows(members, files)?;
+            continue;
+        }
+        let list = row
+            .files
+            .ok_or("is an aggregate report, a per-file report is required (`--files -o json`)")?;
+        // Counts are far below `i64::MAX`, the cast only makes room for a sign
+        files.extend(list.into_iter().map(|file| FileCount {
+            path: normalize(&file.path.to_string_lossy()),
+            language: file.language.to_string(),
+            code: file.code as i64,
+            comment: file.comment as i64,
+            blank: file.blank as i64,
+        }));
+    }
+    Ok(())
+}
+
+// ./src\main.rs => src/main.rs
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut path = path.as_str();
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.to_string()
+}
+
+// Renamed files are not tracked, they show up as removed plus added
+pub fn diff(old: Vec<FileCount>, new: Vec<FileCount>) -> Vec<Change> {
+    let mut old = old
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect::<BTreeMap<String, FileCount>>();
+
+    let mut changes = Vec::new();
+
+    for file in new {
+        match old.remove(&file.path) {
+            Some(prev) => {
+                let change = Change {
+                    status: Status::Changed,
+                    code: file.code - prev.code,
+                    comment: file.comment - prev.comment,
+                    blank: file.blank - prev.blank,
+                    path: file.path,
+                    language: file.language,
+                };
+                if change.code != 0
+                    || change.comment != 0
+                    || change.blank != 0
+                    || prev.language != change.language
+                {
+                    changes.push(change);
+                }
+            }
+            None => changes.push(Change {
+                status: Status::Added,
+                path: file.path,
+                language: file.language,
+                code: file.code,
+                comment: file.comment,
+                blank: file.blank,
+            }),
+        }
+    }
+
+    for (_, file) in old {
+        changes.push(Change {
+            status: Status::Removed,
+            path: file.path,
+            language: file.language,
+            code: -file.code,
+            comment: -file.comment,
+            blank: -file.blank,
+        });
+    }
+
+    changes.sort_by(|a, b| {
+        b.code
+            .unsigned_abs()
+            .cmp(&a.code.unsigned_abs())
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    changes
+}
+
+fn table(changes: &[Change], width: usize) -> Vec<String> {
+    let header = ["Status", "Path", "Language", "Code", "Comment", "Blank"].map(String::from);
+    let rows = changes
+        .iter()
+        .map(|c| {
+            vec![
+                c.status.as_str().to_string(),
+                c.path.clone(),
+                c.language.clone(),
+                signed(c.code, ""),
+                signed(c.comment, ""),
+                signed(c.blank, ""),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+    box_table(&header, &rows, None, 3, 1, width)
+}
+
+fn markdown(changes: &[Change]) -> Vec<String> {
+    let mut data = vec![];
+    data.push("| Status | Path | Language | Code | Comment | Blank |".to_string());
+    data.push("| :----- | :--- | :------- | ---: | ------: | ----: |".to_string());
+    for c in changes {
+        data.push(format!(
+            "| {} | {} | {} | {} | {} | {} |",
+            c.status.as_str(),
+            c.path.replace('|', "\\|"),
+            c.language.replace('|', "\\|"),
+            signed(c.code, ""),
+            signed(c.comment, ""),
+            signed(c.blank, "")
+        ));
+    }
+    data
+}
+
+fn json(changes: &[Change]) -> Vec<String> {
+    #[derive(Serialize)]
+    struct Files<'a> {
+        files: &'a [Change],
+    }
+    vec![serde_json::to_string_pretty(&Files { files: changes }).unwrap()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(path: &str, code: i64) -> FileCount {
+        FileCount {
+            path: path.to_string(),
+            language: "Rust".to_string(),
+            code,
+            comment: 0,
+            blank: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = r#"{
+            "languages": [
+                { "language": "Rust", "code": 3, "files": [
+                    { "path": "./src\\main.rs", "language": "Rust", "code": 3, "comment": 1, "blank": 0, "size": 10 }
+                ] }
+            ],
+            "total": {}
+        }"#;
+        assert_eq!(
+            parse_report(report),
+            Ok(vec![FileCount {
+                path: "src/main.rs".to_string(),
+                language: "Rust".to_string(),
+                code: 3,
+                comment: 1,
+                blank: 0,
+            }])
+        );
+
+        let aggregate = r#"{ "languages": [{ "language": "Rust", "code": 3 }] }"#;
+        assert!(parse_report(aggregate)
+            .unwrap_err()
+            .contains("aggregate report"));
+        // `--by-family` keeps the files under the members of a family
+        let family = r#"{ "languages": [
+            { "language": "JavaScript family", "code": 3, "members": [
+                { "language": "JavaScript", "code": 1, "files": [
+                    { "path": "a.js", "language": "JavaScript", "code": 1, "comment": 0, "blank": 0, "size": 1 }
+                ] },
+                { "language": "TypeScript", "code": 2, "files": [
+                    { "path": "b.ts", "language": "TypeScript", "code": 2, "comment": 0, "blank": 0, "size": 1 }
+                ] }
+            ] },
+            { "language": "Rust", "code": 1, "files": [
+                { "path": "c.rs", "language": "Rust", "code": 1, "comment": 0, "blank": 0, "size": 1 }
+            ] }
+        ] }"#;
+        let files = parse_report(family).unwrap();
+        let names = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.language.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("a.js", "JavaScript"),
+                ("b.ts", "TypeScript"),
+                ("c.rs", "Rust")
+            ]
+        );
+        let family = r#"{ "languages": [{ "language": "JavaScript family", "members": [
+            { "language": "JavaScript", "code": 1 }
+        ] }] }"#;
+        assert!(parse_report(family)
+            .unwrap_err()
+            .contains("aggregate report"));
+
+        let extensions = r#"{ "extensions": [{ "extension": "rs", "files": [] }] }"#;
+        assert!(parse_report(extensions)
+            .unwrap_err()
+            .contains("grouped by directory or extension"));
+        assert!(parse_report("[]").is_err());
+        assert!(parse_report("{").is_err());
+    }
+
+    #[test]
+    fn test_table() {
+        use crate::text::display_width;
+        let changes = diff(vec![], vec![file("src/中文.rs", 12), file("a.rs", 1)]);
+        let data = table(&changes, 80);
+        assert_eq!(
+            data[1],
+            "│ Status  Path         Language  Code  Comment  Blank │"
+        );
+        assert_eq!(
+            data[3],
+            "│ added   src/中文.rs  Rust       +12        0      0 │"
+        );
+        assert!(data
+            .iter()
+            .all(|line| display_width(line) == display_width(&data[0])));
+
+        // The path gives way on a narrow terminal
+        let data = table(&changes, 50);
+        assert!(
+            data.iter().all(|line| display_width(line) == 50),
+            "{:#?}",
+            data
+        );
+        assert!(data[3].contains("│ added   src/…   Rust"), "{:#?}", data);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![file("a.rs", 10), file("b.rs", 5), file("c.rs", 1)];
+        let new = vec![file("a.rs", 12), file("c.rs", 1), file("d.rs", 30)];
+        let changes = diff(old, new);
+
+        let summary = changes
+            .iter()
+            .map(|c| (c.status.as_str(), c.path.as_str(), c.code))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("added", "d.rs", 30),
+                ("removed", "b.rs", -5),
+                ("changed", "a.rs", 2)
+            ]
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&json(&changes).join("\n")).unwrap();
+        assert_eq!(
+            value["files"][1],
+            serde_json::json!({
+                "status": "removed", "path": "b.rs", "language": "Rust",
+                "code": -5, "comment": 0, "blank": 0
+            })
+        );
+    }
+}
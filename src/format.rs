@@ -0,0 +1,628 @@
+// Shared helpers for turning numbers into display strings,
+// every output format goes through these so they can't drift apart
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SizeStyle {
+    // Digits after the decimal point once a unit is applied
+    pub decimals: usize,
+    pub unit: SizeUnit,
+    // Digit grouping of `SizeUnit::Bytes`
+    pub separator: &'static str,
+    // `--no-size`, nothing was measured and every size is `-`
+    pub disabled: bool,
+}
+
+impl Default for SizeStyle {
+    fn default() -> Self {
+        Self {
+            decimals: 2,
+            unit: SizeUnit::default(),
+            separator: NumberFormat::default().separator(),
+            disabled: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SizeUnit {
+    // 1024-based, KiB
+    #[default]
+    Binary,
+    // 1000-based, KB
+    Si,
+    // Raw bytes with thousands separators
+    Bytes,
+}
+
+impl std::str::FromStr for SizeUnit {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "binary" => Ok(SizeUnit::Binary),
+            "si" => Ok(SizeUnit::Si),
+            "bytes" => Ok(SizeUnit::Bytes),
+            _ => Err(()),
+        }
+    }
+}
+
+// Binary: 1023 => "1023 B", 1024 => "1.00 KiB"
+// Si:     999 => "999 B", 1000 => "1.00 KB"
+// Bytes:  1024 => "1,024"
+pub fn human_size(bytes: u64, style: SizeStyle) -> String {
+    if style.disabled {
+        return "-".to_string();
+    }
+    let (base, units) = match style.unit {
+        SizeUnit::Binary => (1024, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+        SizeUnit::Si => (1000, ["KB", "MB", "GB", "TB", "PB", "EB"]),
+        SizeUnit::Bytes => return group_digits(bytes, style.separator),
+    };
+    if bytes < base {
+        return format!("{} B", bytes);
+    }
+    let mut i = 0;
+    let mut unit = base;
+    while i + 1 < units.len() && bytes / unit >= base {
+        unit *= base;
+        i += 1;
+    }
+    format!(
+        "{:.*} {}",
+        style.decimals,
+        bytes as f64 / unit as f64,
+        units[i]
+    )
+}
+
+// The table right-aligns sizes, pad "B" so it lines up with the unit
+pub fn table_size(bytes: u64, style: SizeStyle) -> String {
+    let size = human_size(bytes, style);
+    match style.unit {
+        _ if style.disabled => size,
+        SizeUnit::Binary if bytes < 1024 => format!("{}  ", size),
+        SizeUnit::Si if bytes < 1000 => format!("{} ", size),
+        _ => size,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NumberFormat {
+    #[default]
+    Comma,
+    Dot,
+    Space,
+    None,
+}
+
+impl NumberFormat {
+    pub fn separator(self) -> &'static str {
+        match self {
+            NumberFormat::Comma => ",",
+            NumberFormat::Dot => ".",
+            // Thin space
+            NumberFormat::Space => "\u{2009}",
+            NumberFormat::None => "",
+        }
+    }
+}
+
+impl std::str::FromStr for NumberFormat {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "comma" => Ok(NumberFormat::Comma),
+            "dot" => Ok(NumberFormat::Dot),
+            "space" => Ok(NumberFormat::Space),
+            "none" => Ok(NumberFormat::None),
+            _ => Err(()),
+        }
+    }
+}
+
+// 1234567 => "1,234,567", -1000 => "-1,000"
+pub fn group_digits<T: Display>(n: T, separator: &str) -> String {
+    let num = n.to_string();
+    let (sign, digits) = match num.strip_prefix(['-', '+']) {
+        Some(digits) => (&num[..1], digits),
+        None => ("", num.as_str()),
+    };
+    let mut rst = String::from(sign);
+    for (i, ch) in digits.chars().enumerate() {
+        rst.push(ch);
+        let rest = digits.len() - 1 - i;
+        if rest != 0 && rest % 3 == 0 {
+            rst.push_str(separator);
+        }
+    }
+    rst
+}
+
+// A change, 1234 => "+1,234", -5 => "-5", 0 => "0"
+pub fn signed(n: i64, separator: &str) -> String {
+    match n > 0 {
+        true => format!("+{}", group_digits(n, separator)),
+        false => group_digits(n, separator),
+    }
+}
+
+// A change in size, -2048 => "-2.00 KiB", the unit of the absolute value
+pub fn signed_size(delta: i64, style: SizeStyle) -> String {
+    let size = human_size(delta.unsigned_abs(), style);
+    match delta.signum() {
+        1 => format!("+{}", size),
+        -1 => format!("-{}", size),
+        _ => size,
+    }
+}
+
+// Share of `part` in `whole` out of 100, a zero whole is 0
+pub fn share(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+pub fn percent(part: u64, whole: u64, decimals: usize) -> String {
+    format!("{:.*}%", decimals, share(part, whole))
+}
+
+// 999 => 999, 12345 => 12.3k, 1500000 => 1.5M
+pub fn compact_number(n: u64) -> String {
+    compact(n, 1000, &["", "k", "M", "B", "T", "P", "E"])
+}
+
+// Bytes with binary units, 2048 => 2KB
+pub fn compact_size(n: u64) -> String {
+    let mut size = compact(n, 1024, &["", "K", "M", "G", "T", "P", "E"]);
+    size.push('B');
+    size
+}
+
+fn compact(n: u64, base: u64, units: &[&str]) -> String {
+    let mut unit = 0;
+    let mut value = n as f64;
+    while value >= base as f64 && unit < units.len() - 1 {
+        value /= base as f64;
+        unit += 1;
+    }
+    if unit == 0 {
+        return n.to_string();
+    }
+    // 999.96k would round to 1000.0k
+    if (value * 10.0).round() / 10.0 >= base as f64 && unit < units.len() - 1 {
+        value /= base as f64;
+        unit += 1;
+    }
+    let text = format!("{:.1}", value);
+    let text = text.strip_suffix(".0").unwrap_or(&text);
+    format!("{}{}", text, units[unit])
+}
+
+// Seconds since the epoch => "2026-10-14 08:30:00 UTC"
+pub fn utc_datetime(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // Civil date from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+// "512", "512K", "10M", "1.5G" => bytes, suffixes are binary and case-insensitive
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let error = || {
+        format!(
+            "Cannot parse '{}' as a size, use bytes or a K, M, G, T suffix",
+            value
+        )
+    };
+    let value = value.trim();
+    let lower = value.to_ascii_lowercase();
+    // `10M`, `10MB` and `10MiB` are the same
+    let lower = lower
+        .strip_suffix("ib")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+    let (number, shift) = match lower.char_indices().last() {
+        Some((i, 'k')) => (&lower[..i], 10),
+        Some((i, 'm')) => (&lower[..i], 20),
+        Some((i, 'g')) => (&lower[..i], 30),
+        Some((i, 't')) => (&lower[..i], 40),
+        _ => (lower, 0),
+    };
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return Err(error());
+    }
+    match number.split_once('.') {
+        // Whole numbers stay exact
+        None => number
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(1 << shift))
+            .ok_or_else(error),
+        Some(_) => {
+            let n = number.parse::<f64>().map_err(|_| error())? * (1u64 << shift) as f64;
+            if n.is_finite() && n < u64::MAX as f64 {
+                Ok(n as u64)
+            } else {
+                Err(error())
+            }
+        }
+    }
+}
+
+// Days since the epoch of a civil date, the inverse of `utc_datetime`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// "2026-10-14" => days since the epoch
+fn parse_date(value: &str) -> Option<i64> {
+    let mut parts = value.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+// "08:30:00", "08:30:00.25" => seconds since midnight
+fn parse_clock(value: &str) -> Option<i64> {
+    let value = value.split('.').next()?;
+    let mut parts = value.splitn(3, ':');
+    let (hour, minute, second) = (parts.next()?, parts.next()?, parts.next()?);
+    if hour.len() != 2 || minute.len() != 2 || second.len() != 2 {
+        return None;
+    }
+    let (hour, minute, second) = (number(hour)?, number(minute)?, number(second)?);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(hour * 3600 + minute * 60 + second)
+}
+
+// "+02:00", "Z" => seconds east of UTC
+fn parse_offset(value: &str) -> Option<i64> {
+    if value.eq_ignore_ascii_case("z") {
+        return Some(0);
+    }
+    let sign = match value.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let (hour, minute) = value[1..].split_once(':')?;
+    if hour.len() != 2 || minute.len() != 2 {
+        return None;
+    }
+    let (hour, minute) = (number(hour)?, number(minute)?);
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+    Some(sign * (hour * 3600 + minute * 60))
+}
+
+fn number(value: &str) -> Option<i64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+// Seconds since the epoch for `--modified-since`, `now` resolves relative values
+// 2026-10-14T08:30:00+02:00 RFC3339
+// 2026-10-14                midnight UTC
+// 30d, 12h, 15m, 2w         that long before now
+pub fn parse_since(value: &str, now: u64) -> Result<u64, String> {
+    let value = value.trim();
+    let error = || {
+        format!(
+            "Cannot parse '{}' as a time, use '2026-10-14', '2026-10-14T08:30:00Z' or '30d'",
+            value
+        )
+    };
+
+    let unit = match value.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 7 * 86400,
+        _ => 0,
+    };
+    if let Some(n) = number(&value[..value.len().saturating_sub(1)]).filter(|_| unit > 0) {
+        return Ok(now.saturating_sub((n as u64).saturating_mul(unit)));
+    }
+
+    let secs = if value.len() == 10 {
+        parse_date(value).ok_or_else(error)? * 86400
+    } else {
+        let (date, rest) = value.split_once(['T', 't', ' ']).ok_or_else(error)?;
+        let days = parse_date(date).ok_or_else(error)?;
+        let split = rest.find(['Z', 'z', '+', '-']).ok_or_else(error)?;
+        let clock = parse_clock(&rest[..split]).ok_or_else(error)?;
+        let offset = parse_offset(&rest[split..]).ok_or_else(error)?;
+        days * 86400 + clock - offset
+    };
+    Ok(secs.max(0) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn style(unit: SizeUnit) -> SizeStyle {
+        SizeStyle {
+            unit,
+            ..SizeStyle::default()
+        }
+    }
+
+    #[test]
+    fn test_human_size() {
+        let style = SizeStyle::default();
+        assert_eq!(human_size(0, style), "0 B");
+        assert_eq!(human_size(1, style), "1 B");
+        assert_eq!(human_size(999, style), "999 B");
+        assert_eq!(human_size(1000, style), "1000 B");
+        assert_eq!(human_size(1023, style), "1023 B");
+        assert_eq!(human_size(1024, style), "1.00 KiB");
+        assert_eq!(human_size(1024 * 1024 - 1, style), "1024.00 KiB");
+        assert_eq!(human_size(1024 * 1024, style), "1.00 MiB");
+        assert_eq!(human_size(1024 * 1024 * 1024 * 1024, style), "1.00 TiB");
+        assert_eq!(human_size(u64::MAX, style), "16.00 EiB");
+        let decimals = |decimals| SizeStyle {
+            decimals,
+            ..SizeStyle::default()
+        };
+        assert_eq!(human_size(1536, decimals(0)), "2 KiB");
+        assert_eq!(human_size(1536, decimals(1)), "1.5 KiB");
+    }
+
+    #[test]
+    fn test_human_size_si() {
+        let style = style(SizeUnit::Si);
+        assert_eq!(human_size(999, style), "999 B");
+        assert_eq!(human_size(1000, style), "1.00 KB");
+        assert_eq!(human_size(1023, style), "1.02 KB");
+        assert_eq!(human_size(1024, style), "1.02 KB");
+        assert_eq!(human_size(999_999, style), "1000.00 KB");
+        assert_eq!(human_size(1_000_000, style), "1.00 MB");
+        assert_eq!(human_size(u64::MAX, style), "18.45 EB");
+    }
+
+    #[test]
+    fn test_human_size_bytes() {
+        let style = style(SizeUnit::Bytes);
+        assert_eq!(human_size(0, style), "0");
+        assert_eq!(human_size(999, style), "999");
+        assert_eq!(human_size(1000, style), "1,000");
+        assert_eq!(human_size(1023, style), "1,023");
+        assert_eq!(human_size(1024, style), "1,024");
+    }
+
+    #[test]
+    fn test_table_size() {
+        let binary = SizeStyle::default();
+        assert_eq!(table_size(0, binary), "0 B  ");
+        assert_eq!(table_size(1023, binary), "1023 B  ");
+        assert_eq!(table_size(1024, binary), "1.00 KiB");
+        let si = style(SizeUnit::Si);
+        assert_eq!(table_size(999, si), "999 B ");
+        assert_eq!(table_size(1000, si), "1.00 KB");
+        assert_eq!(table_size(1024, style(SizeUnit::Bytes)), "1,024");
+        let disabled = SizeStyle {
+            disabled: true,
+            ..binary
+        };
+        assert_eq!(table_size(0, disabled), "-");
+        assert_eq!(human_size(4096, disabled), "-");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits(0, ","), "0");
+        assert_eq!(group_digits(1, ","), "1");
+        assert_eq!(group_digits(1000, ","), "1,000");
+        assert_eq!(group_digits(999999, ","), "999,999");
+        assert_eq!(group_digits(1234567, ","), "1,234,567");
+        assert_eq!(group_digits(1234567, "."), "1.234.567");
+        assert_eq!(group_digits(1234567, " "), "1 234 567");
+        assert_eq!(group_digits(1234567, ""), "1234567");
+        assert_eq!(group_digits(-1, ","), "-1");
+        assert_eq!(group_digits(-100, ","), "-100");
+        assert_eq!(group_digits(-1000, ","), "-1,000");
+        assert_eq!(group_digits("+1000", ","), "+1,000");
+        assert_eq!(group_digits(u64::MAX, ","), "18,446,744,073,709,551,615");
+        assert_eq!(group_digits(i32::MIN, ","), "-2,147,483,648");
+        assert_eq!(group_digits("-5", "."), "-5");
+        assert_eq!(group_digits(-123456, ""), "-123456");
+    }
+
+    #[test]
+    fn test_signed() {
+        assert_eq!(signed(0, ","), "0");
+        assert_eq!(signed(3, ","), "+3");
+        assert_eq!(signed(-3, ","), "-3");
+        assert_eq!(signed(1234567, "."), "+1.234.567");
+        assert_eq!(signed(-1000, ""), "-1000");
+        let style = SizeStyle::default();
+        assert_eq!(signed_size(0, style), "0 B");
+        assert_eq!(signed_size(-2048, style), "-2.00 KiB");
+        assert_eq!(signed_size(10, style), "+10 B");
+    }
+
+    #[test]
+    fn test_number_format() {
+        let group = |n: i64, format: &str| {
+            group_digits(n, format.parse::<NumberFormat>().unwrap().separator())
+        };
+        assert_eq!(group(1234567, "comma"), "1,234,567");
+        assert_eq!(group(1234567, "dot"), "1.234.567");
+        assert_eq!(group(1234567, "space"), "1\u{2009}234\u{2009}567");
+        assert_eq!(group(1234567, "none"), "1234567");
+        assert_eq!(group(-1000, "dot"), "-1.000");
+        assert_eq!(group(7, "comma"), "7");
+        assert!("tab".parse::<NumberFormat>().is_err());
+
+        let style = SizeStyle {
+            unit: SizeUnit::Bytes,
+            separator: NumberFormat::Dot.separator(),
+            ..SizeStyle::default()
+        };
+        assert_eq!(human_size(1024, style), "1.024");
+    }
+
+    #[test]
+    fn test_share() {
+        assert_eq!(share(0, 0), 0.0);
+        assert_eq!(share(1, 4), 25.0);
+        assert_eq!(share(4, 4), 100.0);
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(0, 0, 1), "0.0%");
+        assert_eq!(percent(5, 0, 1), "0.0%");
+        assert_eq!(percent(1, 3, 1), "33.3%");
+        assert_eq!(percent(2, 3, 2), "66.67%");
+        assert_eq!(percent(3, 3, 1), "100.0%");
+        assert_eq!(percent(u64::MAX, u64::MAX, 0), "100%");
+    }
+
+    #[test]
+    fn test_utc_datetime() {
+        assert_eq!(utc_datetime(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc_datetime(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(utc_datetime(1_790_000_000), "2026-09-21 14:13:20 UTC");
+        assert_eq!(utc_datetime(4_102_444_799), "2099-12-31 23:59:59 UTC");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("512K"), Ok(524_288));
+        assert_eq!(parse_size("512k"), Ok(524_288));
+        assert_eq!(parse_size("10M"), Ok(10_485_760));
+        assert_eq!(parse_size("10MB"), Ok(10_485_760));
+        assert_eq!(parse_size("10MiB"), Ok(10_485_760));
+        assert_eq!(parse_size("1.5G"), Ok(1_610_612_736));
+        assert_eq!(parse_size("2T"), Ok(2_199_023_255_552));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+
+        // Overflow
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("16777216T").is_err());
+        assert!(parse_size("99999999999T").is_err());
+        assert!(parse_size("16777216.0T").is_err());
+
+        for value in ["", "K", "-1", "1.2.3", "10X", "10 M", "ten"] {
+            assert!(parse_size(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = 1_790_000_000;
+        assert_eq!(parse_since("2026-09-21", now), Ok(1_789_948_800));
+        assert_eq!(parse_since("2000-02-29", now), Ok(951_782_400));
+        assert_eq!(parse_since("2026-09-21T14:13:20Z", now), Ok(1_790_000_000));
+        assert_eq!(
+            parse_since("2026-09-21t14:13:20.5z", now),
+            Ok(1_790_000_000)
+        );
+        assert_eq!(
+            parse_since("2026-09-21T16:13:20+02:00", now),
+            Ok(1_790_000_000)
+        );
+        assert_eq!(
+            parse_since("2026-09-21 10:13:20-02:00", now),
+            Ok(1_789_992_800)
+        );
+        assert_eq!(parse_since("1969-12-31", now), Ok(0));
+
+        assert_eq!(parse_since("30s", now), Ok(now - 30));
+        assert_eq!(parse_since("15m", now), Ok(now - 900));
+        assert_eq!(parse_since("12h", now), Ok(now - 43_200));
+        assert_eq!(parse_since("30d", now), Ok(now - 2_592_000));
+        assert_eq!(parse_since("2w", now), Ok(now - 1_209_600));
+        assert_eq!(parse_since("99999999d", now), Ok(0));
+
+        for value in [
+            "",
+            "d",
+            "-3d",
+            "3y",
+            "3.5d",
+            "2026-9-21",
+            "2026-13-01",
+            "2025-02-29",
+            "2026-09-21T14:13:20",
+            "2026-09-21T25:00:00Z",
+            "2026-09-21T14:13Z",
+            "2026-09-21T14:13:20+2:00",
+            "yesterday",
+        ] {
+            assert!(parse_since(value, now).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_compact_number() {
+        assert_eq!(compact_number(0), "0");
+        assert_eq!(compact_number(999), "999");
+        assert_eq!(compact_number(1000), "1k");
+        assert_eq!(compact_number(12345), "12.3k");
+        assert_eq!(compact_number(999_949), "999.9k");
+        assert_eq!(compact_number(999_999), "1M");
+        assert_eq!(compact_number(1_500_000), "1.5M");
+        assert_eq!(compact_number(u64::MAX), "18.4E");
+    }
+
+    #[test]
+    fn test_compact_size() {
+        assert_eq!(compact_size(512), "512B");
+        assert_eq!(compact_size(2048), "2KB");
+        assert_eq!(compact_size(1024 * 1024 * 3 / 2), "1.5MB");
+    }
+}
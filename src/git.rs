@@ -0,0 +1,270 @@
+// `--git-diff A..B`: the files changed between two revisions, counted from
+// the blobs in git instead of the working tree
+use crate::config::CONFIG;
+use crate::format::{NumberFormat, SizeStyle};
+use crate::parse::{is_binary, parse_str};
+use crate::snapshot::{self, Row};
+use crate::{detect, exit, is_wanted};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct GitDiff {
+    pub range: String,
+    // Inside the repository, only files below it are compared
+    pub dir: PathBuf,
+    pub heuristics: bool,
+    pub languages: Option<Vec<&'static str>>,
+    pub exclude_languages: Option<Vec<&'static str>>,
+    pub json: bool,
+    pub number: NumberFormat,
+    pub size: SizeStyle,
+    pub width: usize,
+}
+
+// One line of `git diff --raw`, a blob is `None` on the side it doesn't exist
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub old: Option<(String, String)>,
+    pub new: Option<(String, String)>,
+}
+
+pub fn run(options: GitDiff) {
+    let (from, to) = split_range(&options.range).unwrap_or_else(|| {
+        exit!(
+            "'{}' isn't a range, expected 'REV1..REV2', e.g. 'v1.2..v1.3'",
+            options.range
+        )
+    });
+    let dir = &options.dir;
+    // `A...B` compares with where B branched off A
+    let from = match options.range.contains("...") {
+        true => text(git(dir, &["merge-base", &from, &to])),
+        false => from,
+    };
+    let raw = git(
+        dir,
+        &[
+            "diff",
+            "--raw",
+            "-z",
+            "-M",
+            "--no-abbrev",
+            &from,
+            &to,
+            "--",
+            ".",
+        ],
+    );
+    let changes = parse_raw(&raw).unwrap_or_else(|err| exit!("Cannot read 'git diff': {}", err));
+
+    let mut old = BTreeMap::new();
+    let mut new = BTreeMap::new();
+    for change in &changes {
+        if let Some((path, blob)) = &change.old {
+            count(dir, path, blob, &options, &mut old);
+        }
+        if let Some((path, blob)) = &change.new {
+            count(dir, path, blob, &options, &mut new);
+        }
+    }
+    let old = old.into_values().collect::<Vec<Row>>();
+    let new = new.into_values().collect::<Vec<Row>>();
+    let deltas = snapshot::diff(&old, &new);
+
+    let data = match options.json {
+        true => {
+            let head = serde_json::json!({ "from": from, "to": to });
+            snapshot::delta_json(("range", head), "Language", &deltas)
+        }
+        false => {
+            let title = format!(
+                "{}..{}, {} files changed",
+                short(&from),
+                short(&to),
+                changes.len()
+            );
+            snapshot::delta_table(
+                &title,
+                "Language",
+                &deltas,
+                options.number,
+                options.size,
+                options.width,
+            )
+        }
+    };
+    println!("{}", data.join("\n"));
+}
+
+// `v1.2..v1.3` and `main...topic`, a side left out is HEAD like in git
+pub fn split_range(range: &str) -> Option<(String, String)> {
+    let (from, to) = range.split_once("...").or_else(|| range.split_once(".."))?;
+    let side = |rev: &str| match rev.is_empty() {
+        true => "HEAD".to_string(),
+        false => rev.to_string(),
+    };
+    Some((side(from), side(to)))
+}
+
+// `:100644 100644 <old> <new> M\0path\0`, renames and copies have two paths.
+// Only regular files are counted, not symlinks or submodules
+pub fn parse_raw(raw: &[u8]) -> Result<Vec<Change>, String> {
+    let text = String::from_utf8_lossy(raw);
+    let mut fields = text.split('\0').filter(|field| !field.is_empty());
+    let mut rst = vec![];
+    while let Some(head) = fields.next() {
+        let parts = head
+            .trim_start_matches(':')
+            .split(' ')
+            .collect::<Vec<&str>>();
+        let (modes, blobs, status) = match parts[..] {
+            [old_mode, new_mode, old, new, status] => ([old_mode, new_mode], [old, new], status),
+            _ => return Err(format!("unexpected line '{}'", head)),
+        };
+        let mut path = || {
+            fields
+                .next()
+                .map(String::from)
+                .ok_or_else(|| format!("no path after '{}'", head))
+        };
+        let old_path = path()?;
+        let new_path = match status.starts_with(['R', 'C']) {
+            true => path()?,
+            false => old_path.clone(),
+        };
+        // 100644 and 100755, a missing side is 000000
+        let side = |mode: &str, blob: &str, path: String| {
+            mode.starts_with("100").then(|| (path, blob.to_string()))
+        };
+        let old = match status.starts_with('C') {
+            // A copy leaves the original as it was
+            true => None,
+            false => side(modes[0], blobs[0], old_path),
+        };
+        rst.push(Change {
+            old,
+            new: side(modes[1], blobs[1], new_path),
+        });
+    }
+    Ok(rst)
+}
+
+// Adds the blob to the row of its language, binary and unknown files are skipped
+fn count(dir: &Path, path: &str, blob: &str, options: &GitDiff, rows: &mut BTreeMap<String, Row>) {
+    let file = PathBuf::from(path);
+    let config = match CONFIG.get_by_path(&file) {
+        Some(config) => config,
+        None => return,
+    };
+    let bytes = git(dir, &["cat-file", "blob", blob]);
+    if is_binary(&bytes) {
+        return;
+    }
+    let content = match String::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    let config = match options.heuristics {
+        true => detect::refine(&file, &content, config),
+        false => config,
+    };
+    if !is_wanted(config.name, &options.languages, &options.exclude_languages) {
+        return;
+    }
+    let data = parse_str(file, &content, content.len() as u64, config);
+    let row = rows
+        .entry(data.language.to_string())
+        .or_insert_with(|| Row::new(data.language));
+    row.code += data.code as i64;
+    row.comment += data.comment as i64;
+    row.blank += data.blank as i64;
+    row.file += 1;
+    row.size += data.size as i64;
+}
+
+fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap_or_else(|err| exit!("Cannot run git\n{:#?}", err));
+    if !output.status.success() {
+        exit!(
+            "'git {}' failed\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    output.stdout
+}
+
+fn text(bytes: Vec<u8>) -> String {
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// A merge base is a hash, the table shows it like git does
+fn short(rev: &str) -> &str {
+    match rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => &rev[..7],
+        false => rev,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_range() {
+        let range = |from: &str, to: &str| Some((from.to_string(), to.to_string()));
+        assert_eq!(split_range("v1.2..v1.3"), range("v1.2", "v1.3"));
+        assert_eq!(split_range("main...topic"), range("main", "topic"));
+        assert_eq!(split_range("v1.2.."), range("v1.2", "HEAD"));
+        assert_eq!(split_range("v1.2"), None);
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let zero = "0".repeat(40);
+        let raw = format!(
+            ":100644 100644 {a} {b} M\0src/main.rs\0\
+             :000000 100644 {zero} {b} A\0new.rs\0\
+             :100644 100644 {a} {b} R087\0old.rs\0moved.rs\0\
+             :100644 100644 {a} {b} C100\0lib.rs\0copy.rs\0\
+             :160000 160000 {a} {b} M\0vendor/sub\0",
+            a = a,
+            b = b,
+            zero = zero
+        );
+        let side = |path: &str, blob: &str| Some((path.to_string(), blob.to_string()));
+        assert_eq!(
+            parse_raw(raw.as_bytes()).unwrap(),
+            [
+                Change {
+                    old: side("src/main.rs", &a),
+                    new: side("src/main.rs", &b),
+                },
+                Change {
+                    old: None,
+                    new: side("new.rs", &b),
+                },
+                Change {
+                    old: side("old.rs", &a),
+                    new: side("moved.rs", &b),
+                },
+                Change {
+                    old: None,
+                    new: side("copy.rs", &b),
+                },
+                Change {
+                    old: None,
+                    new: None,
+                },
+            ]
+        );
+        assert!(parse_raw(b"M\0a.rs\0").is_err());
+    }
+}
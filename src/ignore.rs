@@ -0,0 +1,181 @@
+// Skip what `.gitignore` files list, read per directory as the walk enters it
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `*` stops at `/`, `**` crosses directories
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    // `!pattern` re-includes
+    negate: bool,
+    // `pattern/` only matches directories
+    dir_only: bool,
+    // A `/` anywhere but the end ties the pattern to the .gitignore directory,
+    // otherwise it matches the name at any depth
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // `\#` and `\!` escape the first character
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        // A broken pattern is skipped the way git does
+        let pattern = Pattern::new(line).ok()?;
+        Some(Self {
+            pattern,
+            negate,
+            dir_only,
+            anchored,
+        })
+    }
+
+    // `path` is relative to the directory of the .gitignore
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.pattern.matches_path_with(path, OPTIONS)
+        } else {
+            path.file_name()
+                .map(|name| self.pattern.matches_with(&name.to_string_lossy(), OPTIONS))
+                .unwrap_or(false)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gitignore {
+    files: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl Gitignore {
+    // Read `dir/.gitignore`, call it before checking anything inside `dir`
+    pub fn load(&mut self, dir: &Path) {
+        if let Ok(content) = fs::read_to_string(dir.join(".gitignore")) {
+            self.add(dir, &content);
+        }
+    }
+
+    fn add(&mut self, dir: &Path, content: &str) {
+        let rules = content
+            .lines()
+            .filter_map(Rule::parse)
+            .collect::<Vec<Rule>>();
+        if !rules.is_empty() {
+            self.files.insert(dir.to_path_buf(), rules);
+        }
+    }
+
+    // The last matching line wins, deeper files override outer ones.
+    // Ignored directories are never entered so their contents need no check
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| self.files.contains_key(*dir))
+            .collect::<Vec<&Path>>();
+
+        let mut ignored = false;
+        for dir in dirs.into_iter().rev() {
+            let relative = match path.strip_prefix(dir) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            for rule in &self.files[dir] {
+                if rule.matches(relative, is_dir) {
+                    ignored = !rule.negate;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gitignore(files: &[(&str, &str)]) -> Gitignore {
+        let mut ignore = Gitignore::default();
+        for (dir, content) in files {
+            ignore.add(Path::new(dir), content);
+        }
+        ignore
+    }
+
+    #[test]
+    fn test_rule() {
+        assert!(Rule::parse("").is_none());
+        assert!(Rule::parse("# comment").is_none());
+        let rule = Rule::parse("\\#file").unwrap();
+        assert!(rule.matches(Path::new("#file"), false));
+
+        let rule = Rule::parse("!target/").unwrap();
+        assert!(rule.negate && rule.dir_only && !rule.anchored);
+        let rule = Rule::parse("/build").unwrap();
+        assert!(rule.anchored);
+        assert!(rule.matches(Path::new("build"), true));
+        assert!(!rule.matches(Path::new("src/build"), true));
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let ignore = gitignore(&[
+            (
+                ".",
+                "target/\n*.log\n!keep.log\n/dist\ndocs/*.md\nsrc/**/gen.rs\n",
+            ),
+            ("./src", "local.rs\n"),
+        ]);
+        let ignored = |path: &str, is_dir| ignore.is_ignored(Path::new(path), is_dir);
+
+        assert!(ignored("./target", true));
+        assert!(ignored("./src/target", true));
+        assert!(!ignored("./target", false));
+        assert!(ignored("./a.log", false));
+        assert!(ignored("./src/b.log", false));
+        assert!(!ignored("./keep.log", false));
+        assert!(ignored("./dist", true));
+        assert!(!ignored("./src/dist", true));
+        assert!(ignored("./docs/a.md", false));
+        assert!(!ignored("./docs/api/a.md", false));
+        assert!(ignored("./src/gen.rs", false));
+        assert!(ignored("./src/parse/gen.rs", false));
+        // Nested file only applies below its directory
+        assert!(ignored("./src/local.rs", false));
+        assert!(ignored("./src/parse/local.rs", false));
+        assert!(!ignored("./local.rs", false));
+        assert!(!ignored("./src/main.rs", false));
+    }
+
+    #[test]
+    fn test_nested_negation() {
+        let ignore = gitignore(&[("dir", "*.js\n"), ("dir/web", "!app.js\n")]);
+        assert!(ignore.is_ignored(Path::new("dir/index.js"), false));
+        assert!(ignore.is_ignored(Path::new("dir/web/index.js"), false));
+        assert!(!ignore.is_ignored(Path::new("dir/web/app.js"), false));
+    }
+}
@@ -0,0 +1,378 @@
+// Deflate (RFC 1951) decoding for the archives, `.gz` and the entries of `.zip`.
+// Decodes a symbol a bit at a time, slow next to zlib but source drops are small
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // The input ended in the middle of a block
+    Truncated,
+    Invalid(&'static str),
+    // More output than the limit, a few KiB can expand to GiB
+    TooLarge(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "unexpected end of the compressed data"),
+            Error::Invalid(reason) => write!(f, "invalid compressed data, {}", reason),
+            Error::TooLarge(limit) => write!(f, "decompresses to more than {} bytes", limit),
+        }
+    }
+}
+
+struct Bits<'a> {
+    input: &'a [u8],
+    pos: usize,
+    // Bits not used yet, the lowest first
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, need: u32) -> Result<u32, Error> {
+        while self.count < need {
+            let byte = *self.input.get(self.pos).ok_or(Error::Truncated)?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1u64 << need) - 1) as u32;
+        self.buf = ((self.buf as u64) >> need) as u32;
+        self.count -= need;
+        Ok(value)
+    }
+
+    // A stored block starts at the next byte
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+// Canonical Huffman code, the number of codes of each length and the symbols in order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, Error> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        // More codes of a length than there is room for
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(Error::Invalid("over-subscribed code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, Error> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(Error::Invalid("unknown code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// The order the lengths of the code length code are sent in
+const ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// The decoded data and how many bytes of `input` the stream took, a gzip
+// trailer comes after it. Stops once the data would pass `limit` bytes
+pub fn inflate(input: &[u8], limit: usize) -> Result<(Vec<u8>, usize), Error> {
+    let mut bits = Bits {
+        input,
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity(limit.min(input.len() * 3));
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored(&mut bits, &mut out, limit)?,
+            1 => {
+                let (lengths, distances) = fixed();
+                codes(&mut bits, &mut out, limit, &lengths, &distances)?
+            }
+            2 => {
+                let (lengths, distances) = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, limit, &lengths, &distances)?
+            }
+            _ => return Err(Error::Invalid("unknown block type")),
+        }
+        if last {
+            break;
+        }
+    }
+    Ok((out, bits.pos))
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+    bits.align();
+    let input = bits.input;
+    let header = input.get(bits.pos..bits.pos + 4).ok_or(Error::Truncated)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(Error::Invalid("stored block length doesn't match"));
+    }
+    let start = bits.pos + 4;
+    let data = input
+        .get(start..start + len as usize)
+        .ok_or(Error::Truncated)?;
+    if out.len() + data.len() > limit {
+        return Err(Error::TooLarge(limit));
+    }
+    out.extend_from_slice(data);
+    bits.pos = start + len as usize;
+    Ok(())
+}
+
+fn fixed() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    // Both can't fail, the fixed codes are complete
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), Error> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(Error::Invalid("too many codes"));
+    }
+    let mut lengths = [0u8; 19];
+    for &index in &ORDER[..ncode] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut index = 0;
+    while index < nlen + ndist {
+        let symbol = code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match index {
+                0 => return Err(Error::Invalid("repeat without a length")),
+                _ => (lengths[index - 1], 3 + bits.bits(2)? as usize),
+            },
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if index + repeat > nlen + ndist {
+            return Err(Error::Invalid("too many lengths"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(Error::Invalid("no end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<(), Error> {
+    loop {
+        let symbol = lengths.decode(bits)? as usize;
+        match symbol {
+            0..=255 if out.len() == limit => return Err(Error::TooLarge(limit)),
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(Error::Invalid("unknown length code"));
+                }
+                let len =
+                    LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(Error::Invalid("unknown distance code"));
+                }
+                let dist =
+                    DIST_BASE[index] as usize + bits.bits(DIST_EXTRA[index] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(Error::Invalid("distance too far back"));
+                }
+                // The copy may overlap what it writes
+                let start = out.len() - dist;
+                if out.len() + len > limit {
+                    return Err(Error::TooLarge(limit));
+                }
+                for i in 0..len {
+                    let byte = out[start + i];
+                    out.push(byte);
+                }
+            }
+        }
+    }
+}
+
+// CRC-32 of gzip and zip, to tell a broken entry from a good one
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stored() {
+        // One last stored block of "abc"
+        let data = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data, 3), Ok((b"abc".to_vec(), 8)));
+        assert_eq!(inflate(&data[..6], 3), Err(Error::Truncated));
+        assert_eq!(inflate(&data, 2), Err(Error::TooLarge(2)));
+    }
+
+    #[test]
+    fn test_fixed() {
+        // "hello hello hello\n" from zlib, without a header
+        let data = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00,
+        ];
+        let (out, used) = inflate(&data, 18).unwrap();
+        assert_eq!(out, b"hello hello hello\n");
+        assert_eq!(used, data.len());
+        assert_eq!(crc32(&out), 0xdf8a7c3b);
+        // Cut in a literal, then in a match
+        assert_eq!(inflate(&data, 3), Err(Error::TooLarge(3)));
+        assert_eq!(inflate(&data, 10), Err(Error::TooLarge(10)));
+    }
+
+    // `let x0 = 0;` to `let x11 = 121;` from zlib at level 9, a dynamic block
+    const DYNAMIC: [u8; 79] = [
+        0x3d, 0x8e, 0xb1, 0x0d, 0xc0, 0x30, 0x08, 0x04, 0xfb, 0x4c, 0xc1, 0x08, 0x80, 0x6d, 0x6c,
+        0x2b, 0xca, 0x38, 0xe9, 0x5c, 0xa6, 0xc8, 0xf8, 0x7e, 0x22, 0x48, 0x77, 0xa7, 0x07, 0x9e,
+        0x75, 0x3f, 0xf4, 0x32, 0x5d, 0xc4, 0xe7, 0xb1, 0x9c, 0x05, 0x2c, 0xc1, 0x0a, 0xae, 0xc1,
+        0x05, 0x3c, 0x83, 0xab, 0xcf, 0x58, 0x48, 0x83, 0x68, 0x0b, 0x31, 0x48, 0xc9, 0xa4, 0xfb,
+        0x7a, 0xee, 0x0c, 0x88, 0xe5, 0xb1, 0x09, 0x19, 0xd9, 0x22, 0x5e, 0x2f, 0xfc, 0x3f, 0xf0,
+        0x7d, 0xa0, 0x48, 0x37,
+    ];
+
+    #[test]
+    fn test_dynamic() {
+        let text = (0..12)
+            .map(|i| format!("let x{} = {};\n", i, i * i))
+            .collect::<String>();
+        assert_eq!(DYNAMIC[0] >> 1 & 3, 2);
+        let (out, used) = inflate(&DYNAMIC, 1024).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+        assert_eq!(used, DYNAMIC.len());
+        assert_eq!(crc32(text.as_bytes()), 0xd190da65);
+        assert_eq!(inflate(&DYNAMIC, 100), Err(Error::TooLarge(100)));
+    }
+
+    #[test]
+    fn test_malformed() {
+        // A dynamic block whose code length code has four codes of one bit
+        let header = 1u32 | 2 << 1 | 1 << 17 | 1 << 20 | 1 << 23 | 1 << 26;
+        assert_eq!(
+            inflate(&header.to_le_bytes(), 1024),
+            Err(Error::Invalid("over-subscribed code"))
+        );
+        // Block type 3
+        assert_eq!(
+            inflate(&[0x07], 1024),
+            Err(Error::Invalid("unknown block type"))
+        );
+        // A fixed block that opens with a match, there is nothing to copy yet
+        assert_eq!(
+            inflate(&[0x03, 0x02], 1024),
+            Err(Error::Invalid("distance too far back"))
+        );
+
+        // Cut anywhere or with any bit flipped it fails or decodes, but doesn't panic
+        for len in 0..DYNAMIC.len() {
+            assert!(inflate(&DYNAMIC[..len], 1024).is_err());
+        }
+        for bit in 0..DYNAMIC.len() * 8 {
+            let mut data = DYNAMIC;
+            data[bit / 8] ^= 1 << (bit % 8);
+            if let Ok((out, _)) = inflate(&data, 1024) {
+                assert!(out.len() <= 1024);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+}
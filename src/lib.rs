@@ -0,0 +1,513 @@
+//! Counts the code, comment and blank lines of source files by language, the
+//! library behind the `lok` command.
+//!
+//! ```
+//! use lok::{count, CountOptions};
+//! use std::path::Path;
+//!
+//! let details = count(Path::new("src"), &CountOptions::default()).unwrap();
+//! let rust = details.iter().find(|detail| detail.name == "Rust").unwrap();
+//! assert!(rust.code > 0 && rust.file > 0);
+//! ```
+pub mod archive;
+pub mod badge;
+pub mod cache;
+pub mod config;
+mod count;
+pub mod detect;
+pub mod format;
+mod ignore;
+mod inflate;
+mod mmap;
+pub mod output;
+pub mod parse;
+mod report;
+mod syntax;
+pub mod text;
+
+pub use config::{Config, Language, CONFIG};
+pub use count::{
+    count, normalize_globs, root_of, CountOptions, CountOptionsBuilder, Counter, Error, Progress,
+    Skipped, Summary, VENDORED_DIRS,
+};
+pub use output::{Output, Stats, Total};
+pub use parse::{parse_bytes, parser, parser_mmap, parser_no_size, Data, Value};
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::BTreeMap;
+
+// Fold a file into the row called `name`, `--files` also keeps the file itself
+pub fn fold(list: &mut BTreeMap<String, Detail>, name: &str, data: Data, per_file: bool) {
+    let detail = list
+        .entry(name.to_string())
+        .or_insert_with(|| Detail::new(name));
+    detail.add(&data);
+    if per_file {
+        detail.files.push(data);
+    }
+}
+
+// Roll up languages into their family, languages without a family stay standalone.
+// A family named like a language is still its own row, `true` in the key
+pub fn group_by_family(total: Vec<Detail>) -> Vec<Detail> {
+    let mut result = BTreeMap::new();
+
+    for detail in total {
+        let family = match CONFIG.find(&detail.name).and_then(|l| l.family) {
+            Some(family) => family,
+            None => {
+                result.insert((detail.name.clone(), false), detail);
+                continue;
+            }
+        };
+        result
+            .entry((family.to_string(), true))
+            .or_insert_with(|| Detail::new(family))
+            .merge(detail);
+    }
+
+    result.into_values().collect()
+}
+
+pub fn sort_detail(mut data: Vec<Detail>, sort: &Sort, order: Order) -> Vec<Detail> {
+    for detail in data.iter_mut() {
+        let members = std::mem::take(&mut detail.members);
+        detail.members = sort_detail(members, sort, order);
+        sort_files(&mut detail.files, sort, order);
+    }
+
+    data.sort_by(|a, b| {
+        let key = match sort {
+            Sort::Language => compare_name(&a.name, &b.name),
+            Sort::Code => a.code.cmp(&b.code),
+            Sort::Comment => a.comment.cmp(&b.comment),
+            Sort::Blank => a.blank.cmp(&b.blank),
+            Sort::File => a.file.cmp(&b.file),
+            Sort::Size => a.size.cmp(&b.size),
+            Sort::Lines => a.lines.cmp(&b.lines),
+        };
+        // Ties fall back to the name so every run agrees
+        order
+            .apply(key)
+            .then_with(|| compare_name(&a.name, &b.name))
+    });
+    data
+}
+
+// Files have no file count, `--sort file` lists them by path, in `order` like
+// every other key
+fn sort_files(files: &mut [Data], sort: &Sort, order: Order) {
+    files.sort_by(|a, b| {
+        let lines = |f: &Data| f.code + f.comment + f.blank;
+        let key = match sort {
+            Sort::Language => order.apply(a.path.cmp(&b.path)),
+            Sort::File => order.apply(a.path.cmp(&b.path)),
+            Sort::Code => order.apply(a.code.cmp(&b.code)),
+            Sort::Comment => order.apply(a.comment.cmp(&b.comment)),
+            Sort::Blank => order.apply(a.blank.cmp(&b.blank)),
+            Sort::Size => order.apply(a.size.cmp(&b.size)),
+            Sort::Lines => order.apply(lines(a).cmp(&lines(b))),
+        };
+        key.then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+// Case-insensitive, "C" < "C#" < "C++" < "CoffeeScript" < "CSS",
+// names that only differ in case still get a fixed order
+fn compare_name(a: &str, b: &str) -> cmp::Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+// Passes `--language` and `--exclude-language`
+pub fn is_wanted(
+    name: &str,
+    languages: &Option<Vec<&'static str>>,
+    excluded: &Option<Vec<&'static str>>,
+) -> bool {
+    languages.as_ref().is_none_or(|names| names.contains(&name))
+        && excluded.as_ref().is_none_or(|names| !names.contains(&name))
+}
+
+/// A row of the report, the lines of a language, directory, extension or package.
+/// [`Output`] renders them as a table, json and the other formats
+///
+/// ```
+/// use lok::output::Format;
+/// use lok::{Data, Detail, Output};
+///
+/// let mut rust = Detail::new("Rust");
+/// rust.add(&Data {
+///     path: "src/main.rs".into(),
+///     language: "Rust",
+///     blank: 1,
+///     comment: 0,
+///     code: 3,
+///     size: 40,
+/// });
+/// let output = Output::new(vec![rust]);
+/// assert_eq!(output.total_lines, 4);
+/// assert!(output.render(&Format::Json).contains("\"code\": 3"));
+/// ```
+///
+/// Serialized as `name`, `code`, `comment`, `blank`, `file`, `size` and `lines`,
+/// with `members` and `files` when they aren't empty. These names are kept
+/// between releases
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Detail {
+    // Language, directory or extension, or the package name in the vendored report
+    pub name: String,
+    pub code: u64,
+    pub comment: u64,
+    pub blank: u64,
+    pub file: u64,
+    pub size: u64,
+    // Code, comment and blank lines together
+    pub lines: u64,
+    // Languages rolled up into this row, only used by `--by-family`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Detail>,
+    // Every counted file, only kept by `--files`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<Data>,
+}
+
+impl Detail {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            blank: 0,
+            comment: 0,
+            code: 0,
+            size: 0,
+            file: 0,
+            lines: 0,
+            members: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    // Saturating, a count stuck at the maximum is wrong but not silently small
+    pub fn add(&mut self, data: &Data) {
+        let lines = data
+            .code
+            .saturating_add(data.comment)
+            .saturating_add(data.blank);
+        self.comment = self.comment.saturating_add(data.comment);
+        self.blank = self.blank.saturating_add(data.blank);
+        self.code = self.code.saturating_add(data.code);
+        self.size = self.size.saturating_add(data.size);
+        self.file = self.file.saturating_add(1);
+        self.lines = self.lines.saturating_add(lines);
+    }
+
+    pub fn merge(&mut self, detail: Detail) {
+        self.comment = self.comment.saturating_add(detail.comment);
+        self.blank = self.blank.saturating_add(detail.blank);
+        self.code = self.code.saturating_add(detail.code);
+        self.size = self.size.saturating_add(detail.size);
+        self.file = self.file.saturating_add(detail.file);
+        self.lines = self.lines.saturating_add(detail.lines);
+        self.members.push(detail);
+    }
+}
+
+#[derive(Debug, Default)]
+pub enum Sort {
+    #[default]
+    Language,
+    Code,
+    Comment,
+    Blank,
+    File,
+    Size,
+    Lines,
+}
+
+impl std::str::FromStr for Sort {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "language" => Ok(Sort::Language),
+            "code" => Ok(Sort::Code),
+            "comment" => Ok(Sort::Comment),
+            "blank" => Ok(Sort::Blank),
+            "file" => Ok(Sort::File),
+            "size" => Ok(Sort::Size),
+            "lines" => Ok(Sort::Lines),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Sort {
+    // Names A to Z, numbers biggest first
+    pub fn default_order(&self) -> Order {
+        match self {
+            Sort::Language => Order::Asc,
+            _ => Order::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order {
+    fn apply(self, ordering: cmp::Ordering) -> cmp::Ordering {
+        match self {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse(),
+        }
+    }
+}
+
+impl std::str::FromStr for Order {
+    type Err = ();
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn detail(language: &'static str, code: u64) -> Detail {
+        Detail {
+            code,
+            file: 1,
+            ..Detail::new(language)
+        }
+    }
+
+    #[test]
+    fn test_group_by_family() {
+        let total = vec![
+            detail("TypeScript", 2),
+            detail("Rust", 5),
+            detail("JavaScript", 1),
+        ];
+        let data = group_by_family(total);
+
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0].name, "JavaScript family");
+        assert_eq!(data[0].code, 3);
+        assert_eq!(data[0].file, 2);
+        assert_eq!(data[0].members.len(), 2);
+        assert_eq!(data[1].name, "Rust");
+        assert!(data[1].members.is_empty());
+    }
+
+    #[test]
+    fn test_large_counts() {
+        let near = i32::MAX as u64 - 1;
+        let data = Data {
+            path: PathBuf::from("dump.sql"),
+            language: "SQL",
+            code: near,
+            comment: 1,
+            blank: 1,
+            size: 0,
+        };
+        let mut detail = Detail::new("SQL");
+        detail.add(&data);
+        detail.add(&data);
+        assert_eq!(detail.code, 2 * near);
+        assert_eq!(detail.lines, 2 * (near + 2));
+        assert!(detail.lines > i32::MAX as u64);
+
+        detail.merge(Detail {
+            code: u64::MAX,
+            ..Detail::new("SQL")
+        });
+        assert_eq!(detail.code, u64::MAX);
+        assert_eq!(detail.file, 2);
+    }
+
+    #[test]
+    fn test_fold_threads() {
+        let data = |thread: usize, i: u64, language| Data {
+            path: PathBuf::from(format!("{}/{}", thread, i)),
+            language,
+            blank: 1,
+            comment: i,
+            code: 10 * i,
+            size: 100,
+        };
+        // Each worker sends its files as it counts them, the order varies per run
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let threads = (0..3)
+            .map(|thread| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for i in 1..=4 {
+                        let language = ["Rust", "Go"][(thread + i as usize) % 2];
+                        sender.send(data(thread, i, language)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        let mut total = BTreeMap::new();
+        for data in receiver {
+            fold(&mut total, data.language, data, false);
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let total = total.into_values().collect::<Vec<Detail>>();
+        let expected = |name: &str, code, comment| Detail {
+            code,
+            comment,
+            blank: 6,
+            file: 6,
+            size: 600,
+            lines: code + comment + 6,
+            ..Detail::new(name)
+        };
+        assert_eq!(total, [expected("Go", 140, 14), expected("Rust", 160, 16)]);
+    }
+
+    #[test]
+    fn test_sort_files() {
+        let file = |path: &str, code| Data {
+            path: PathBuf::from(path),
+            language: "Rust",
+            blank: 0,
+            comment: 0,
+            code,
+            size: 0,
+        };
+        let mut total = BTreeMap::new();
+        fold(&mut total, "Rust", file("./b.rs", 1), true);
+        fold(&mut total, "Rust", file("./a.rs", 2), true);
+        fold(&mut total, "Rust", file("./c.rs", 3), true);
+        let total = total.into_values().collect();
+
+        let paths = |data: &[Detail]| {
+            data[0]
+                .files
+                .iter()
+                .map(|f| f.path.to_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let data = sort_detail(total, &Sort::Code, Order::Desc);
+        assert_eq!(data[0].code, 6);
+        assert_eq!(data[0].file, 3);
+        assert_eq!(data[0].lines, 6);
+        assert_eq!(paths(&data), vec!["./c.rs", "./a.rs", "./b.rs"]);
+        let data = sort_detail(data, &Sort::Language, Order::Asc);
+        assert_eq!(paths(&data), vec!["./a.rs", "./b.rs", "./c.rs"]);
+        let data = sort_detail(data, &Sort::Code, Order::Asc);
+        assert_eq!(paths(&data), vec!["./b.rs", "./a.rs", "./c.rs"]);
+        let data = sort_detail(data, &Sort::File, Order::Desc);
+        assert_eq!(paths(&data), vec!["./c.rs", "./b.rs", "./a.rs"]);
+        let data = sort_detail(data, &Sort::File, Order::Asc);
+        assert_eq!(paths(&data), vec!["./a.rs", "./b.rs", "./c.rs"]);
+        let data = sort_detail(data, &Sort::Language, Order::Desc);
+        assert_eq!(paths(&data), vec!["./c.rs", "./b.rs", "./a.rs"]);
+
+        let mut total = BTreeMap::new();
+        fold(&mut total, "Rust", file("./a.rs", 2), false);
+        assert!(total["Rust"].files.is_empty());
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let detail = |name: &str, code| Detail {
+            code,
+            ..Detail::new(name)
+        };
+        let names = |data: Vec<Detail>| {
+            data.into_iter()
+                .map(|detail| detail.name)
+                .collect::<Vec<String>>()
+        };
+        let data = || vec![detail("Go", 2), detail("Rust", 3), detail("CSS", 1)];
+
+        assert_eq!(Sort::Language.default_order(), Order::Asc);
+        assert_eq!(Sort::Code.default_order(), Order::Desc);
+        assert_eq!(
+            names(sort_detail(data(), &Sort::Code, Order::Desc)),
+            vec!["Rust", "Go", "CSS"]
+        );
+        assert_eq!(
+            names(sort_detail(data(), &Sort::Code, Order::Asc)),
+            vec!["CSS", "Go", "Rust"]
+        );
+        assert_eq!(
+            names(sort_detail(data(), &Sort::Language, Order::Asc)),
+            vec!["CSS", "Go", "Rust"]
+        );
+        assert_eq!(
+            names(sort_detail(data(), &Sort::Language, Order::Desc)),
+            vec!["Rust", "Go", "CSS"]
+        );
+    }
+
+    #[test]
+    fn test_sort_names() {
+        let data = ["CSS", "C++", "CoffeeScript", "C", "css", "C#", "Go", "c"]
+            .iter()
+            .map(|name| Detail::new(name))
+            .collect::<Vec<Detail>>();
+        let names = sort_detail(data, &Sort::Language, Order::Asc)
+            .into_iter()
+            .map(|detail| detail.name)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            vec!["C", "c", "C#", "C++", "CoffeeScript", "CSS", "css", "Go"]
+        );
+
+        // Equal counts keep the name order in both directions
+        let data = || {
+            ["Rust", "C", "Go"]
+                .iter()
+                .map(|name| Detail::new(name))
+                .collect::<Vec<Detail>>()
+        };
+        for order in [Order::Asc, Order::Desc] {
+            let names = sort_detail(data(), &Sort::Code, order)
+                .into_iter()
+                .map(|detail| detail.name)
+                .collect::<Vec<String>>();
+            assert_eq!(names, vec!["C", "Go", "Rust"]);
+        }
+    }
+
+    #[test]
+    fn test_detail_serde() {
+        let file = Data {
+            path: PathBuf::from("src/main.rs"),
+            language: "Rust",
+            code: 3,
+            comment: 1,
+            blank: 0,
+            size: 40,
+        };
+        let mut rust = Detail::new("Rust");
+        rust.add(&file);
+        rust.files.push(file);
+        let mut family = Detail::new("Rust family");
+        family.merge(rust);
+
+        let json = serde_json::to_string(&family).unwrap();
+        assert_eq!(serde_json::from_str::<Detail>(&json).unwrap(), family);
+        // Empty lists are left out and read back as empty
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["members"][0].get("members").is_none());
+        assert_eq!(value["members"][0]["files"][0]["language"], "Rust");
+    }
+}
@@ -1,15 +1,34 @@
+mod blame;
+mod check;
 mod cli;
-mod config;
-mod output;
-mod parse;
-
+mod diff;
+mod git;
+mod man;
+mod progress;
+mod settings;
+mod snapshot;
+mod tui;
+
+use blame::Authors;
+use cache::Cache;
 use cli::Options;
 use config::{Language, CONFIG};
-use crossbeam_deque::{Stealer, Worker};
-use output::Output;
-use parse::{parser, Data, Value};
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use lok::VENDORED_DIRS;
+use lok::{archive, badge, cache, config, detect, format, output, parse, text};
+use lok::{
+    fold, group_by_family, is_wanted, root_of, sort_detail, Counter, Detail, Order, Progress, Sort,
+    Summary,
+};
+use output::{AtomicFile, Format, Output, Stats};
+use serde::Serialize;
+use snapshot::Snapshot;
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::ControlFlow;
+use std::path::{Component, Path};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[macro_export]
 macro_rules! exit {
@@ -23,257 +42,633 @@ macro_rules! exit {
     };
 }
 
-macro_rules! err {
-    ($kind: expr, $path: expr) => {{
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {{
         use bright::Colorful;
-        eprintln!("{} {:?} {:?}", "error:".yellow(), $kind, $path);
+        eprint!("{} ", "warning:".yellow().bold());
+        eprintln!($($arg)*);
     }};
 }
 
 fn main() {
     let Options {
-        work_dir,
-        print_error,
-        exclude,
-        include,
-        format,
+        count: mut options,
+        stdin,
+        outputs,
         sort,
-        extension,
+        order,
+        group,
+        by_family,
+        per_file,
+        avg,
+        total: show_total,
+        size,
+        number,
+        width,
+        color,
+        warn_code,
+        warn_ratio,
+        thresholds,
+        allow_empty,
+        cache,
+        save_snapshot,
+        diff_snapshot,
+        snapshot_flags,
+        by_author,
+        tui: use_tui,
+        check,
+        progress: show_progress,
+        stats: show_stats,
     } = cli::parse();
+    let print_error = options.print_error;
+    let vendored_report = options.vendored;
 
-    let worker = Worker::new_fifo();
-    let cpus = num_cpus::get();
-    let mut threads = Vec::with_capacity(cpus);
-
-    // Created thread
-    for _ in 0..cpus {
-        let stealer = worker.stealer().clone();
-        threads.push(std::thread::spawn(move || {
-            let task = Task {
-                stealer,
-                print_error,
-            };
-            task.start()
-        }));
+    if vendored_report {
+        warn!("--vendored-report also scans vendored directories, this may take a long time");
     }
 
-    let files = WalkDir::new(work_dir).into_iter().filter_map(|item| {
-        let entry = match item {
-            Ok(entry) => entry,
-            Err(error) => {
-                if print_error {
-                    if let (Some(err), Some(path)) = (error.io_error(), error.path()) {
-                        err!(err.kind(), path);
-                    }
-                }
-                return None;
-            }
-        };
+    // Piped content is counted as one file
+    if let Some(ext) = &stdin {
+        let config = CONFIG.get(ext).unwrap_or_else(|| {
+            let extensions = CONFIG
+                .all_language()
+                .iter()
+                .flat_map(|language| language.extension.iter())
+                .copied()
+                .collect::<Vec<&str>>();
+            exit!(
+                "'{}' is not a known extension, use one of:\n{}",
+                ext,
+                extensions.join(", ")
+            )
+        });
+        let mut content = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut content) {
+            exit!("Cannot read stdin\n{:#?}", err);
+        }
+        options.stdin = Some((content, config));
+    }
+
+    // jsonl prints every file as soon as a worker finishes it
+    let jsonl = match outputs.first() {
+        Some((Format::Jsonl, path)) if check.is_none() && !use_tui => Some(path.clone()),
+        _ => None,
+    };
 
-        let path = entry.path();
+    let cache = cache.map(|path| Arc::new(Cache::load(path, options.heuristics)));
+    let authors = by_author.map(|pattern| Arc::new(Authors::new(pattern)));
 
-        // Include files
-        if let Some(include) = &include {
-            let any = include.iter().any(|m| m.matches_path(path));
-            if !any {
-                return None;
+    let work_dirs = options.work_dirs.clone();
+    let files = options.files.clone();
+    let archives = options.archives.clone();
+    let mut counter = Counter::new(options);
+    if let Some(cache) = &cache {
+        counter = counter.cache(cache.clone());
+    }
+    if let Some(authors) = &authors {
+        let authors = authors.clone();
+        counter = counter.inspect(move |data| {
+            if let Some(config) = CONFIG.find(data.language) {
+                authors.add(&data.path, config);
             }
-        }
+        });
+    }
+
+    // Lines of jsonl on stdout would be drawn over
+    let mut display = None;
+    if show_progress && !matches!(jsonl, Some(None)) {
+        let progress = Arc::new(Progress::default());
+        counter = counter.progress(progress.clone());
+        display = Some(progress::Display::start(progress));
+    }
 
-        // Exclude files
-        if let Some(exclude) = &exclude {
-            for matcher in exclude {
-                if matcher.matches_path(path) {
-                    return None;
+    // Summary of all data, keyed by name so the threads can finish in any order
+    let mut total = BTreeMap::new();
+    let mut vendored = BTreeMap::new();
+    // Files jsonl wrote, counted or not the output ends early
+    let mut written = 0;
+    let started = Instant::now();
+    let summary = match &jsonl {
+        Some(Some(path)) => AtomicFile::create(path)
+            .and_then(|mut file| {
+                let mut writer = BufWriter::new(&mut file);
+                let (summary, result) = write_jsonl(counter, &mut writer, &mut written);
+                result?;
+                drop(writer);
+                file.commit()?;
+                Ok(summary)
+            })
+            .unwrap_or_else(|err| exit!("Cannot write '{}'\n{:#?}", path.display(), err)),
+        // A closed pipe only ends the output
+        Some(None) => write_jsonl(counter, &mut std::io::stdout().lock(), &mut written).0,
+        None => counter
+            .run_with(|data| {
+                let work_dir = root_of(&work_dirs, &data.path);
+                if vendored_report {
+                    if let Some(package) = vendored_package(work_dir, &data.path) {
+                        fold(&mut vendored, &package, data, per_file);
+                        return ControlFlow::Continue(());
+                    }
                 }
-            }
-        }
 
-        // File with the specified extension
-        let ext = match path.extension() {
-            Some(s) => match s.to_str() {
-                Some(ext) => ext,
-                None => return None,
-            },
-            None => return None,
-        };
+                match &group {
+                    Group::Language => fold(&mut total, data.language, data, per_file),
+                    Group::Dir(depth) => {
+                        let mut dir = dir_group(work_dir, &data.path, *depth);
+                        // Roots can share directory names, keep them apart
+                        if work_dirs.len() > 1 {
+                            dir = match dir.as_str() {
+                                "." => work_dir.display().to_string(),
+                                _ => format!("{}/{}", work_dir.display(), dir),
+                            };
+                        }
+                        fold(&mut total, &dir, data, per_file)
+                    }
+                    Group::Extension => {
+                        // `Makefile` and the like have their file name
+                        let ext = CONFIG
+                            .extension_of(&data.path)
+                            .map(String::from)
+                            .or_else(|| {
+                                data.path
+                                    .file_name()
+                                    .map(|name| name.to_string_lossy().to_string())
+                            })
+                            .unwrap_or_default();
+                        fold(&mut total, &ext, data, per_file)
+                    }
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap_or_else(|err| exit!("{}", err)),
+    };
+    let elapsed = started.elapsed();
+    if let Some(display) = display {
+        display.finish();
+    }
 
-        // This extension is not included in config
-        if let Some(extension) = &extension {
-            if !extension.iter().any(|s| s == ext) {
-                return None;
-            }
+    if print_error && summary.pruned > 0 {
+        warn!(
+            "Skipped {} build and dependency directories, count them with --no-default-excludes",
+            summary.pruned
+        );
+    }
+    if print_error && summary.skipped.oversized > 0 {
+        warn!(
+            "Skipped {} files larger than --max-size",
+            summary.skipped.oversized
+        );
+    }
+
+    if jsonl.is_some() {
+        save_cache(cache, print_error);
+        warn_vanished(summary.vanished);
+        if show_stats {
+            let stats = Stats::new(&summary, written, elapsed);
+            eprintln!("{}", stats.lines(number, size).join("\n"));
         }
+        exit_if_empty(written, allow_empty);
+        return;
+    }
 
-        // Get file path and configuration
-        CONFIG
-            .get(ext)
-            .map(|config| (entry.path().to_path_buf(), config))
-    });
+    let counted = total
+        .values()
+        .chain(vendored.values())
+        .map(|detail| detail.file as usize)
+        .sum::<usize>();
+    let mut total = total.into_values().collect::<Vec<Detail>>();
+    if by_family {
+        total = group_by_family(total);
+    }
+
+    save_cache(cache, print_error);
+    warn_vanished(summary.vanished);
 
-    for (path, config) in files {
-        worker.push(Work::Parse(path, config));
+    if let Some(authors) = authors.and_then(|authors| Arc::try_unwrap(authors).ok()) {
+        exit_if_empty(counted, allow_empty);
+        let rows = authors.rows();
+        let data = match outputs.first() {
+            Some((Format::Json, _)) => blame::json(&rows),
+            _ => blame::table(&rows, number.separator(), width),
+        };
+        println!("{}", data.join("\n"));
+        return;
     }
 
-    for _ in 0..cpus {
-        worker.push(Work::Quit);
+    if let Some(check) = &check {
+        exit_if_empty(counted, allow_empty);
+        std::process::exit(check::run(check, &total));
     }
 
-    // Summary of all data
-    let mut total = Vec::new();
+    if use_tui {
+        exit_if_empty(counted, allow_empty);
+        if tui::run(total, sort, order, number, size) {
+            return;
+        }
+        // Not a terminal after all
+        exit!("Cannot open the terminal for --tui");
+    }
 
-    for thread in threads {
-        let task_data = thread.join().unwrap_or_else(|err| {
-            exit!("Thread exits abnormally\n{:#?}", err);
-        });
+    let mut output = Output::new(sort_detail(total, &sort, order));
+    output.avg = avg;
+    output.total = show_total;
+    output.size = size;
+    output.number = number;
+    output.width = width;
+    output.warn_code = warn_code;
+    output.warn_ratio = warn_ratio;
+    output.dir = work_dirs
+        .iter()
+        .chain(&files)
+        .chain(&archives)
+        .map(|dir| dir.display().to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    match group {
+        Group::Language => {}
+        Group::Dir(_) => output.column = "Directory",
+        Group::Extension => output.column = "Extension",
+    }
+    if vendored_report {
+        let vendored = vendored.into_values().collect();
+        let mut report = Output::new(sort_detail(vendored, &sort, order));
+        report.column = "Package";
+        report.avg = avg;
+        report.total = show_total;
+        report.size = size;
+        report.number = number;
+        report.width = width;
+        report.warn_code = warn_code;
+        report.warn_ratio = warn_ratio;
+        output.vendored = Some(Box::new(report));
+    }
 
-        for data in task_data {
-            let find = total
-                .iter_mut()
-                .find(|item: &&mut Detail| item.language == data.language);
+    output.stats = show_stats.then(|| Stats::new(&summary, counted, elapsed));
 
-            match find {
-                Some(detail) => detail.add(data),
-                None => total.push(data.into_detail()),
-            }
+    // Replaces the outputs, the new count is saved before it's compared
+    if save_snapshot.is_some() || diff_snapshot.is_some() {
+        let paths = work_dirs
+            .iter()
+            .chain(&files)
+            .chain(&archives)
+            .map(|dir| dir.display().to_string())
+            .collect();
+        let snapshot = Snapshot::new(&output.data, output.column, paths, snapshot_flags);
+        if let Some(path) = &save_snapshot {
+            snapshot
+                .save(path)
+                .unwrap_or_else(|err| exit!("Cannot write '{}'\n{:#?}", path.display(), err));
+        }
+        if let Some(path) = &diff_snapshot {
+            let old = Snapshot::load(path).unwrap_or_else(|err| exit!("{}", err));
+            snapshot::warn_mismatches(path, &old, &snapshot);
+            let deltas = snapshot::diff(&old.rows, &snapshot.rows);
+            let data = match outputs.first() {
+                Some((Format::Json, _)) => snapshot::json(&old, &deltas),
+                _ => snapshot::table(&old, &deltas, number, size, width),
+            };
+            println!("{}", data.join("\n"));
         }
     }
 
-    let data = match sort {
-        Sort::Language => bubble_sort(total, |a, b| position(a.language) > position(b.language)),
-        Sort::Code => bubble_sort(total, |a, b| a.code > b.code),
-        Sort::Comment => bubble_sort(total, |a, b| a.comment > b.comment),
-        Sort::Blank => bubble_sort(total, |a, b| a.blank > b.blank),
-        Sort::File => bubble_sort(total, |a, b| a.file > b.file),
-        Sort::Size => bubble_sort(total, |a, b| a.size > b.size),
-    };
+    // The data is counted once and shared by every output
+    for (format, path) in outputs.iter().filter(|_| diff_snapshot.is_none()) {
+        let colored = match path {
+            Some(_) => color == Color::Always,
+            None => color != Color::Never,
+        };
+        output.color = colored;
+        if let Some(report) = &mut output.vendored {
+            report.color = colored;
+        }
+        let content = output.render(format);
+        match path {
+            Some(path) => output::save(path, &content)
+                .unwrap_or_else(|err| exit!("Cannot write '{}'\n{:#?}", path.display(), err)),
+            None => println!("{}", content),
+        }
+    }
+    if let Some(stats) = &output.stats {
+        eprintln!("{}", stats.lines(number, size).join("\n"));
+    }
 
-    Output::new(data).print(format);
+    exit_if_empty(counted, allow_empty);
+
+    // Every failed threshold is reported, after the report itself
+    let failures = check::failures(&thresholds, &output);
+    for failure in &failures {
+        use bright::Colorful;
+        eprintln!("{} {}", "failed:".red().bold(), failure);
+    }
+    if !failures.is_empty() {
+        std::process::exit(1);
+    }
 }
 
-pub fn print_language_list() {
-    let n = CONFIG
-        .all_language()
-        .iter()
-        .map(|language| language.name.len())
-        .fold(0, |a, b| a.max(b));
+// Leading directories of a file, files directly in the work dir are "."
+// ./src/parser/mod.rs, depth 1 => src
+// ./src/parser/mod.rs, depth 2 => src/parser
+fn dir_group(work_dir: &Path, path: &Path, depth: usize) -> String {
+    let path = path.strip_prefix(work_dir).unwrap_or(path);
+    let dirs = path
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(s) => Some(s.to_string_lossy()),
+                    _ => None,
+                })
+                .take(depth)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if dirs.is_empty() {
+        ".".to_string()
+    } else {
+        dirs.join("/")
+    }
+}
 
-    for language in CONFIG.all_language() {
-        let ext = language
-            .extension
-            .iter()
-            .map(|e| format!(".{}", e))
-            .collect::<Vec<String>>()
-            .join(" ");
-        println!("{:name$}    {}", language.name, ext, name = n);
+// Get the top-level dependency a vendored file belongs to
+// node_modules/lodash/index.js              => lodash
+// node_modules/@types/node/index.d.ts       => @types/node
+// node_modules/a/node_modules/b/index.js    => a
+fn vendored_package(work_dir: &Path, path: &Path) -> Option<String> {
+    let path = path.strip_prefix(work_dir).unwrap_or(path);
+    let mut components = path.components().filter_map(|c| match c {
+        Component::Normal(s) => s.to_str(),
+        _ => None,
+    });
+
+    let dir = components.find(|name| VENDORED_DIRS.contains(name))?;
+    let rest = components.collect::<Vec<&str>>();
+
+    // Files placed directly in the vendored directory
+    if rest.len() < 2 {
+        return Some(dir.to_string());
+    }
+    if dir == "node_modules" && rest[0].starts_with('@') && rest.len() > 2 {
+        return Some(format!("{}/{}", rest[0], rest[1]));
     }
+    Some(rest[0].to_string())
 }
 
-fn bubble_sort<T>(mut vec: Vec<T>, call: fn(&T, &T) -> bool) -> Vec<T> {
-    for x in 0..vec.len() {
-        for y in x..vec.len() {
-            if call(&vec[x], &vec[y]) {
-                vec.swap(x, y);
-            }
-        }
+// Written once the workers are done, `--error` tells how much it saved
+fn save_cache(cache: Option<Arc<Cache>>, print_error: bool) {
+    let cache = match cache.and_then(|cache| Arc::try_unwrap(cache).ok()) {
+        Some(cache) => cache,
+        None => return,
+    };
+    if print_error {
+        use bright::Colorful;
+        eprintln!(
+            "{} {} hits, {} misses",
+            "cache:".cyan(),
+            cache.hits.load(Ordering::Relaxed),
+            cache.misses.load(Ordering::Relaxed)
+        );
+    }
+    let path = cache.path().to_path_buf();
+    if let Err(err) = cache.save() {
+        warn!("Cannot write the cache '{}'\n{:#?}", path.display(), err);
     }
-    vec
 }
 
-fn position(s: &str) -> usize {
-    const LETTER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-    let first = s.chars().next().unwrap_or_default();
-    LETTER.chars().position(|d| d == first).unwrap_or(0)
+fn warn_vanished(n: usize) {
+    if n > 0 {
+        warn!(
+            "{} files or directories vanished during the scan and were not counted",
+            n
+        );
+    }
 }
 
-#[derive(Debug)]
-pub struct Detail {
-    language: &'static str,
-    blank: i32,
-    comment: i32,
-    code: i32,
-    size: u64,
-    file: i32,
+fn write_jsonl(
+    counter: Counter,
+    writer: &mut impl Write,
+    files: &mut usize,
+) -> (Summary, io::Result<()>) {
+    let mut result = Ok(());
+    let summary = counter
+        .run_with(|data| {
+            *files += 1;
+            result = writeln!(writer, "{}", output::jsonl(&data));
+            match result.is_ok() {
+                true => ControlFlow::Continue(()),
+                false => ControlFlow::Break(()),
+            }
+        })
+        .unwrap_or_else(|err| exit!("{}", err));
+    let result = result.and_then(|_| writer.flush());
+    (summary, result)
 }
 
-impl Detail {
-    fn add(&mut self, data: Data) {
-        self.comment += data.comment;
-        self.blank += data.blank;
-        self.code += data.code;
-        self.size += data.size;
-        self.file += 1;
+// Nothing counted is most likely a typo in a path or a filter, `--allow-empty` is fine with it
+fn exit_if_empty(files: usize, allow_empty: bool) {
+    if files == 0 && !allow_empty {
+        warn!(
+            "No files were counted. Either none has a known extension or name, see 'lok ls',\n\
+             or --exclude, --include, --extension, --language and .gitignore left them all out.\n\
+             Pass --allow-empty if that's expected"
+        );
+        std::process::exit(2);
     }
 }
 
-#[derive(Debug)]
-pub enum Sort {
-    Language,
-    Code,
-    Comment,
-    Blank,
-    File,
-    Size,
+pub fn print_language_list(languages: &[&Language], aliases: bool, verbose: bool) {
+    for line in language_list(languages, aliases, verbose) {
+        println!("{}", line);
+    }
 }
 
-impl std::str::FromStr for Sort {
-    type Err = ();
-    fn from_str(value: &str) -> Result<Self, Self::Err> {
-        match value {
-            "language" => Ok(Sort::Language),
-            "code" => Ok(Sort::Code),
-            "comment" => Ok(Sort::Comment),
-            "blank" => Ok(Sort::Blank),
-            "file" => Ok(Sort::File),
-            "size" => Ok(Sort::Size),
-            _ => Err(()),
+// Aligned columns: name, extensions, with `verbose` the comment tokens, then aliases
+fn language_list(languages: &[&Language], aliases: bool, verbose: bool) -> Vec<String> {
+    let or_dash = |s: String| if s.is_empty() { "-".to_string() } else { s };
+    let rows = languages
+        .iter()
+        .map(|language| {
+            let mut row = vec![
+                language.name.to_string(),
+                language
+                    .extension
+                    .iter()
+                    .map(|e| format!(".{}", e))
+                    .chain(language.filename.iter().map(|name| name.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ];
+            if verbose {
+                row.push(or_dash(language.single.join(" ")));
+                row.push(or_dash(
+                    language
+                        .multi
+                        .iter()
+                        .map(|(start, end)| format!("{} {}", start, end))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                ));
+            }
+            if aliases && !language.alias.is_empty() {
+                row.push(language.alias.join(", "));
+            }
+            row
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    let mut widths = Vec::<usize>::new();
+    for row in &rows {
+        for (i, column) in row.iter().enumerate() {
+            match widths.get_mut(i) {
+                Some(width) => *width = (*width).max(column.len()),
+                None => widths.push(column.len()),
+            }
         }
     }
+    rows.iter()
+        .map(|row| {
+            let last = row.len() - 1;
+            row.iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    if i == last {
+                        column.to_string()
+                    } else {
+                        format!("{:width$}", column, width = widths[i])
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("    ")
+        })
+        .collect()
 }
 
-impl Default for Sort {
-    fn default() -> Self {
-        Sort::Language
-    }
+// `lok ls -o json`, the fields of a `[[language]]` table
+#[derive(Serialize)]
+struct JsonLanguage<'a> {
+    name: &'a str,
+    extensions: &'a [&'a str],
+    filenames: &'a [&'a str],
+    aliases: &'a [&'a str],
+    single: &'a [&'a str],
+    multi: &'a [(&'a str, &'a str)],
 }
 
-enum Work<'a> {
-    Parse(PathBuf, &'a Language),
-    Quit,
+pub fn print_language_json(languages: &[&Language]) {
+    let items = languages
+        .iter()
+        .map(|language| JsonLanguage {
+            name: language.name,
+            extensions: language.extension,
+            filenames: language.filename,
+            aliases: language.alias,
+            single: language.single,
+            multi: language.multi,
+        })
+        .collect::<Vec<JsonLanguage>>();
+    println!("{}", serde_json::to_string_pretty(&items).unwrap());
 }
 
-struct Task<'a> {
-    stealer: Stealer<Work<'a>>,
-    print_error: bool,
+// What the rows of the report are keyed on
+#[derive(Debug)]
+pub enum Group {
+    Language,
+    // Number of leading directories
+    Dir(usize),
+    // `.css` and `.scss` are separate rows
+    Extension,
 }
 
-impl<'a> Task<'a> {
-    fn start(self) -> Vec<Data> {
-        let mut result = Vec::new();
+// Escape codes in the table, `--color`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    // Stdout is a terminal and NO_COLOR is unset
+    Auto,
+    Always,
+    Never,
+}
 
-        loop {
-            // Receive message
-            let work = match self.stealer.steal().success() {
-                Some(work) => work,
-                None => continue,
-            };
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vendored_package() {
+        let package = |path: &str| vendored_package(Path::new("."), Path::new(path));
+
+        assert_eq!(package("./src/main.rs"), None);
+        assert_eq!(
+            package("./node_modules/lodash/index.js"),
+            Some("lodash".to_string())
+        );
+        assert_eq!(
+            package("./node_modules/@types/node/index.d.ts"),
+            Some("@types/node".to_string())
+        );
+        assert_eq!(
+            package("./node_modules/@babel/core/node_modules/debug/src/index.js"),
+            Some("@babel/core".to_string())
+        );
+        assert_eq!(
+            package("./node_modules/a/node_modules/b/index.js"),
+            Some("a".to_string())
+        );
+        assert_eq!(
+            package("./web/node_modules/react/index.js"),
+            Some("react".to_string())
+        );
+        assert_eq!(
+            package("./vendor/github.com/pkg/errors/errors.go"),
+            Some("github.com".to_string())
+        );
+        assert_eq!(
+            package("./node_modules/.package-lock.json"),
+            Some("node_modules".to_string())
+        );
+        assert_eq!(
+            vendored_package(Path::new("/code"), Path::new("/code/node_modules/a/b.js")),
+            Some("a".to_string())
+        );
+    }
 
-            match work {
-                Work::Parse(path, config) => {
-                    match parser(path, &config) {
-                        Value::Ok(data) => result.push(data),
-                        Value::Err(kind, p) => {
-                            if self.print_error {
-                                err!(kind, p)
-                            }
-                        }
-                        Value::Invalid => continue,
-                    };
-                }
-                Work::Quit => break,
-            }
-        }
+    #[test]
+    fn test_dir_group() {
+        let dir = |path: &str, depth| dir_group(Path::new("."), Path::new(path), depth);
+
+        assert_eq!(dir("./src/main.rs", 1), "src");
+        assert_eq!(dir("./src/parser/mod.rs", 1), "src");
+        assert_eq!(dir("./src/parser/mod.rs", 2), "src/parser");
+        assert_eq!(dir("./src/parser/mod.rs", 3), "src/parser");
+        assert_eq!(dir("./build.rs", 1), ".");
+        assert_eq!(
+            dir_group(Path::new("/code"), Path::new("/code/tests/cli.rs"), 1),
+            "tests"
+        );
+    }
 
-        result
+    #[test]
+    fn test_language_list() {
+        let languages = ["Rust", "Dockerfile", "JavaScript"]
+            .iter()
+            .map(|name| CONFIG.find(name).unwrap())
+            .collect::<Vec<&Language>>();
+        assert_eq!(
+            language_list(&languages, false, false),
+            [
+                "Rust          .rs .rs.in",
+                "Dockerfile    .dockerfile Dockerfile Containerfile",
+                "JavaScript    .js .mjs",
+            ]
+        );
+        assert_eq!(
+            language_list(&languages, true, true),
+            [
+                "Rust          .rs .rs.in                              // ///    /* */    rs",
+                "Dockerfile    .dockerfile Dockerfile Containerfile    #         -        docker",
+                "JavaScript    .js .mjs                                //        /* */    js",
+            ]
+        );
     }
 }
@@ -0,0 +1,228 @@
+// `lok gen-man`: the man page, rendered as roff from the clap definition so it
+// can't drift from the flags
+use clap::{App, AppSettings, ArgSettings};
+
+const EXAMPLES: [(&str, &str); 6] = [
+    ("lok", "Count the current directory"),
+    (
+        "lok crates/foo crates/bar",
+        "Count several directories into one report",
+    ),
+    (
+        "lok -e '**/generated/**' -s code",
+        "Leave out some files, biggest language first",
+    ),
+    (
+        "lok -o table -o json=code.json",
+        "Print the table and save a json report",
+    ),
+    (
+        "lok ls rust --verbose",
+        "Show the extensions and comment tokens of a language",
+    ),
+    (
+        "lok check --max-code 50000",
+        "Exit with 1 when the count goes over a limit",
+    ),
+];
+
+// One documented argument, `-s, --sort <SORT>` and what it does
+struct Entry {
+    spec: String,
+    help: String,
+}
+
+pub fn render(app: &App) -> String {
+    let meta = &app.p.meta;
+    let name = meta.name.as_str();
+    let mut rst = vec![
+        format!(
+            ".TH {} 1 \"\" \"{} {}\"",
+            name.to_uppercase(),
+            name,
+            meta.version.unwrap_or_default()
+        ),
+        ".SH NAME".to_string(),
+        format!("{} \\- {}", name, escape(env!("CARGO_PKG_DESCRIPTION"))),
+        ".SH SYNOPSIS".to_string(),
+        format!(
+            "\\fB{}\\fR [OPTIONS] [directory]... [SUBCOMMAND]",
+            escape(name)
+        ),
+        ".SH DESCRIPTION".to_string(),
+        "Count the code, comment and blank lines of the files in the given directories, \
+         by language. Paths default to the current directory."
+            .to_string(),
+        ".SH OPTIONS".to_string(),
+    ];
+    rst.extend(entries(app));
+
+    rst.push(".SH COMMANDS".to_string());
+    for command in &app.p.subcommands {
+        if command.p.is_set(AppSettings::Hidden) {
+            continue;
+        }
+        rst.push(".TP".to_string());
+        rst.push(format!("\\fB{} {}\\fR", name, escape(&command.p.meta.name)));
+        rst.push(escape(command.p.meta.about.unwrap_or_default()));
+        let args = entries(command);
+        if !args.is_empty() {
+            rst.push(".RS".to_string());
+            rst.extend(args);
+            rst.push(".RE".to_string());
+        }
+    }
+
+    rst.push(".SH EXAMPLES".to_string());
+    for (example, about) in EXAMPLES.iter() {
+        rst.push(".TP".to_string());
+        rst.push(format!("\\fB{}\\fR", escape(example)));
+        rst.push(escape(about));
+    }
+    rst.push(".SH SEE ALSO".to_string());
+    rst.push(format!(
+        "Run \\fB{} ls\\fR for the supported languages. {}",
+        name,
+        escape(env!("CARGO_PKG_HOMEPAGE"))
+    ));
+    rst.push(String::new());
+    rst.join("\n")
+}
+
+// Positionals in order, then the flags and options by long name
+fn entries(app: &App) -> Vec<String> {
+    let mut positionals = app
+        .p
+        .positionals
+        .values()
+        .filter(|arg| !arg.b.is_set(ArgSettings::Hidden))
+        .map(|arg| Entry {
+            spec: format!("\\fI{}\\fR", escape(arg.b.name)),
+            help: details(arg.b.help, arg.v.default_val, &arg.v.possible_vals),
+        })
+        .collect::<Vec<Entry>>();
+
+    let flags = app
+        .p
+        .flags
+        .iter()
+        .filter(|arg| !arg.b.is_set(ArgSettings::Hidden))
+        .map(|arg| {
+            let entry = Entry {
+                spec: switch(arg.s.short, arg.s.long),
+                help: details(arg.b.help, None, &None),
+            };
+            (arg.s.long, entry)
+        });
+    let opts = app
+        .p
+        .opts
+        .iter()
+        .filter(|arg| !arg.b.is_set(ArgSettings::Hidden))
+        .map(|arg| {
+            let value = arg
+                .v
+                .val_names
+                .as_ref()
+                .and_then(|names| names.values().next().copied())
+                .unwrap_or(arg.b.name);
+            let entry = Entry {
+                spec: format!(
+                    "{} \\fI{}\\fR",
+                    switch(arg.s.short, arg.s.long),
+                    escape(value)
+                ),
+                help: details(
+                    arg.b.help,
+                    arg.v
+                        .default_val
+                        .filter(|_| !arg.b.is_set(ArgSettings::HideDefaultValue)),
+                    &arg.v.possible_vals,
+                ),
+            };
+            (arg.s.long, entry)
+        });
+    let mut named = flags.chain(opts).collect::<Vec<(Option<&str>, Entry)>>();
+    named.sort_by_key(|(long, _)| *long);
+    positionals.extend(named.into_iter().map(|(_, entry)| entry));
+
+    positionals
+        .into_iter()
+        .flat_map(|entry| vec![".TP".to_string(), entry.spec, entry.help])
+        .collect()
+}
+
+fn switch(short: Option<char>, long: Option<&str>) -> String {
+    let short = short.map(|c| format!("\\fB\\-{}\\fR", escape(&c.to_string())));
+    let long = long.map(|name| format!("\\fB\\-\\-{}\\fR", escape(name)));
+    short
+        .into_iter()
+        .chain(long)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// The help line with the default and the possible values, as `--help` shows them
+fn details(
+    help: Option<&str>,
+    default: Option<&std::ffi::OsStr>,
+    possible: &Option<Vec<&str>>,
+) -> String {
+    let mut rst = help.unwrap_or_default().to_string();
+    if let Some(default) = default.filter(|value| !value.is_empty()) {
+        rst.push_str(&format!(" [default: {}]", default.to_string_lossy()));
+    }
+    if let Some(values) = possible {
+        rst.push_str(&format!(" [possible values: {}]", values.join(", ")));
+    }
+    escape(&rst)
+}
+
+// Dashes are hyphens in roff, a leading dot or quote starts a request
+fn escape(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    match s.starts_with(['.', '\'']) {
+        true => format!("\\&{}", s),
+        false => s,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli;
+
+    #[test]
+    fn test_render() {
+        let app = cli::app();
+        let page = render(&app);
+        assert!(page.starts_with(".TH LOK 1"));
+        assert!(page.contains("\n.SH OPTIONS\n"));
+        assert!(page.contains("\\fB\\-s\\fR, \\fB\\-\\-sort\\fR \\fISORT\\fR"));
+        assert!(page.contains("[possible values: language, code, comment,"));
+        assert!(page.contains("\\fBlok ls\\fR"));
+        assert!(!page.contains("gen\\-man"));
+
+        // A new flag can't be left out
+        let commands = std::iter::once(&app).chain(&app.p.subcommands);
+        for command in commands.filter(|c| !c.p.is_set(AppSettings::Hidden)) {
+            let flags = command.p.flags.iter().map(|arg| (arg.s.long, &arg.b));
+            let opts = command.p.opts.iter().map(|arg| (arg.s.long, &arg.b));
+            for (long, arg) in flags.chain(opts) {
+                if arg.is_set(ArgSettings::Hidden) {
+                    continue;
+                }
+                let long = long.unwrap();
+                let flag = format!("\\fB\\-\\-{}\\fR", escape(long));
+                assert!(page.contains(&flag), "--{} is missing", long);
+            }
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("--max-depth"), "\\-\\-max\\-depth");
+        assert_eq!(escape(".rots.toml"), "\\&.rots.toml");
+        assert_eq!(escape("a\\tb"), "a\\etb");
+    }
+}
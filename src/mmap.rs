@@ -0,0 +1,83 @@
+// `--mmap`: a read-only map of a file, the pages are read as they are scanned
+// instead of copied into a buffer first
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// Only ever read, and a private map isn't changed by this process
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    // Fails for an empty file and where the filesystem can't map, procfs and the like
+    #[cfg(unix)]
+    pub fn open(file: &File, len: u64) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        let len = usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        if len == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_: &File, _: u64) -> io::Result<Mmap> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_mmap() {
+        let path = std::env::temp_dir().join("lok-mmap-file.txt");
+        fs::write(&path, b"mapped\n").unwrap();
+        let file = File::open(&path).unwrap();
+        if cfg!(unix) {
+            assert_eq!(&Mmap::open(&file, 7).unwrap()[..], b"mapped\n");
+        }
+        assert!(Mmap::open(&file, 0).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,12 +1,33 @@
-use crate::Detail;
+use crate::badge::Badge;
+use crate::format::{
+    group_digits, human_size, percent, share, table_size, NumberFormat, SizeStyle,
+};
+use crate::parse::Data;
+use crate::report;
+use crate::text::{display_width, pad_end, pad_start, truncate_display, truncate_start};
+use crate::{Detail, Skipped, Summary};
+use bright::Colorful;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Format {
     Table,
     Html,
+    HtmlReport,
     Markdown,
+    Csv(char),
+    Json,
+    Yaml,
+    Xml,
+    Jsonl,
+    Badge(Badge),
 }
 
 impl FromStr for Format {
@@ -15,25 +36,233 @@ impl FromStr for Format {
         match value {
             "table" => Ok(Format::Table),
             "html" => Ok(Format::Html),
+            "html-report" => Ok(Format::HtmlReport),
             "markdown" => Ok(Format::Markdown),
+            "csv" => Ok(Format::Csv(',')),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            "xml" => Ok(Format::Xml),
+            "jsonl" => Ok(Format::Jsonl),
+            "svg-badge" => Ok(Format::Badge(Badge::default())),
             _ => Err(()),
         }
     }
 }
 
+// Spaces in front of each number column
+const TABLE_GAP: usize = 2;
+// Narrowest the name column gets on a small terminal
+const TABLE_MIN_NAME: usize = 6;
+
 #[derive(Default)]
 pub struct Output {
     pub data: Vec<Detail>,
-    pub total_code: i32,
-    pub total_comment: i32,
-    pub total_blank: i32,
-    pub total_file: i32,
+    pub total_code: u64,
+    pub total_comment: u64,
+    pub total_blank: u64,
+    pub total_file: u64,
     pub total_size: u64,
+    pub total_lines: u64,
+    // Code lines per file over every file
+    pub total_avg: u64,
+    // Show the average column, `--avg`
+    pub avg: bool,
+    // Show the totals row, off with `--no-total`
+    pub total: bool,
+    pub size: SizeStyle,
+    // Digit grouping of every formatted number
+    pub number: NumberFormat,
+    // Name of the first column
+    pub column: &'static str,
+    // Widest the table may get, `--width` or the terminal
+    pub width: usize,
+    // Escape codes in the table, `--color`
+    pub color: bool,
+    // Rows over these get flagged, `--warn-code` and `--warn-comment-ratio`
+    pub warn_code: Option<u64>,
+    pub warn_ratio: Option<f64>,
+    // Counted directory, shown by the html report
+    pub dir: String,
+    // Separate section printed by `--vendored-report`
+    pub vendored: Option<Box<Output>>,
+    // `--stats`, also in json, yaml and xml
+    pub stats: Option<Stats>,
+}
+
+/// The summed lines of a report or of one of its rows. Serialized as `code`,
+/// `comment`, `blank`, `file`, `size` and `lines`, the `total` of `-o json`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Total {
+    pub code: u64,
+    pub comment: u64,
+    pub blank: u64,
+    pub file: u64,
+    pub size: u64,
+    pub lines: u64,
+}
+
+impl Total {
+    pub fn of(detail: &Detail) -> Self {
+        Self {
+            code: detail.code,
+            comment: detail.comment,
+            blank: detail.blank,
+            file: detail.file,
+            size: detail.size,
+            lines: detail.lines,
+        }
+    }
+}
+
+/// `--stats`, where the time of a run went and which files it left out
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    // Seconds, the walk is part of the whole
+    pub elapsed: f64,
+    pub walk: f64,
+    pub threads: usize,
+    // Handed to the workers
+    pub discovered: usize,
+    pub counted: usize,
+    pub skipped: Skipped,
+    pub vanished: usize,
+    // Size of the counted files
+    pub bytes: u64,
+    pub files_per_sec: u64,
+    pub bytes_per_sec: u64,
+}
+
+impl Stats {
+    pub fn new(summary: &Summary, counted: usize, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let millis = |secs: f64| (secs * 1000.0).round() / 1000.0;
+        Self {
+            elapsed: millis(elapsed.as_secs_f64()),
+            walk: millis(summary.walk_time.as_secs_f64()),
+            threads: summary.threads,
+            discovered: summary.discovered,
+            counted,
+            skipped: summary.skipped.clone(),
+            vanished: summary.vanished,
+            bytes: summary.bytes,
+            files_per_sec: (counted as f64 / secs).round() as u64,
+            bytes_per_sec: (summary.bytes as f64 / secs).round() as u64,
+        }
+    }
+
+    // The block printed to stderr after the report
+    pub fn lines(&self, number: NumberFormat, size: SizeStyle) -> Vec<String> {
+        let separator = number.separator();
+        let skipped = self
+            .fields()
+            .iter()
+            .copied()
+            .chain([("vanished", self.vanished)])
+            .filter(|(_, n)| *n > 0)
+            .map(|(reason, n)| format!("{} {}", group_digits(n, separator), reason))
+            .collect::<Vec<String>>();
+        vec![
+            format!("Elapsed   {:.3}s, walk {:.3}s", self.elapsed, self.walk),
+            format!("Threads   {}", self.threads),
+            format!(
+                "Files     {} found, {} counted",
+                group_digits(self.discovered, separator),
+                group_digits(self.counted, separator)
+            ),
+            match skipped.is_empty() {
+                true => "Skipped   none".to_string(),
+                false => format!("Skipped   {}", skipped.join(", ")),
+            },
+            format!(
+                "Read      {}, {} files/s, {}/s",
+                human_size(self.bytes, size),
+                group_digits(self.files_per_sec, separator),
+                human_size(self.bytes_per_sec, size)
+            ),
+        ]
+    }
+
+    // The skip reasons in the order of `Skipped`
+    fn fields(&self) -> [(&'static str, usize); 10] {
+        let s = &self.skipped;
+        [
+            ("unknown", s.unknown),
+            ("excluded", s.excluded),
+            ("extension", s.extension),
+            ("language", s.language),
+            ("modified", s.modified),
+            ("oversized", s.oversized),
+            ("duplicate", s.duplicate),
+            ("lines", s.lines),
+            ("binary", s.binary),
+            ("error", s.error),
+        ]
+    }
+
+    fn numbers(&self) -> [(&'static str, String); 9] {
+        [
+            ("elapsed", self.elapsed.to_string()),
+            ("walk", self.walk.to_string()),
+            ("threads", self.threads.to_string()),
+            ("discovered", self.discovered.to_string()),
+            ("counted", self.counted.to_string()),
+            ("vanished", self.vanished.to_string()),
+            ("bytes", self.bytes.to_string()),
+            ("files_per_sec", self.files_per_sec.to_string()),
+            ("bytes_per_sec", self.bytes_per_sec.to_string()),
+        ]
+    }
+}
+
+// `-o json`, the rows are named by the first column, `languages` or `directories`
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    #[serde(flatten)]
+    rows: BTreeMap<String, Vec<JsonRow<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<JsonRow<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<JsonWarning<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendored: Option<Box<JsonReport<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a Stats>,
+    // `--no-size`, the sizes are 0 because nothing was measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_disabled: Option<bool>,
+}
+
+// A `Detail` under the key of its column, `{ "language": "Rust", "code": 3 }`
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    // Nothing for the total
+    #[serde(flatten)]
+    name: BTreeMap<String, &'a str>,
+    #[serde(flatten)]
+    total: Total,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percent: Option<f64>,
+    // `--avg`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    members: Vec<JsonRow<'a>>,
+    #[serde(skip_serializing_if = "<[Data]>::is_empty")]
+    files: &'a [Data],
+}
+
+#[derive(Serialize)]
+struct JsonWarning<'a> {
+    #[serde(flatten)]
+    name: BTreeMap<String, &'a str>,
+    limit: &'static str,
+    value: serde_json::Number,
+    max: serde_json::Number,
 }
 
 impl Output {
     pub fn new(data: Vec<Detail>) -> Self {
-        let (total_code, total_comment, total_blank, total_file, total_size) = data
+        let (total_code, total_comment, total_blank, total_file, total_size, total_lines) = data
             .iter()
             .map(|detail| {
                 (
@@ -42,10 +271,18 @@ impl Output {
                     detail.blank,
                     detail.file,
                     detail.size,
+                    detail.lines,
                 )
             })
-            .fold((0, 0, 0, 0, 0), |p, n| {
-                (p.0 + n.0, p.1 + n.1, p.2 + n.2, p.3 + n.3, p.4 + n.4)
+            .fold((0u64, 0u64, 0u64, 0u64, 0u64, 0u64), |p, n| {
+                (
+                    p.0.saturating_add(n.0),
+                    p.1.saturating_add(n.1),
+                    p.2.saturating_add(n.2),
+                    p.3.saturating_add(n.3),
+                    p.4.saturating_add(n.4),
+                    p.5.saturating_add(n.5),
+                )
             });
 
         Self {
@@ -55,69 +292,254 @@ impl Output {
             total_blank,
             total_file,
             total_size,
+            total_lines,
+            total_avg: average(total_code, total_file),
+            avg: false,
+            total: true,
+            size: SizeStyle::default(),
+            number: NumberFormat::default(),
+            column: "Language",
+            width: 80,
+            color: false,
+            warn_code: None,
+            warn_ratio: None,
+            dir: ".".to_string(),
+            vendored: None,
+            stats: None,
         }
     }
 
-    pub fn print(self, format: Format) {
+    pub fn total(&self) -> Total {
+        Total {
+            code: self.total_code,
+            comment: self.total_comment,
+            blank: self.total_blank,
+            file: self.total_file,
+            size: self.total_size,
+            lines: self.total_lines,
+        }
+    }
+
+    pub fn render(&self, format: &Format) -> String {
         let mut data = vec![];
+        self.write(format, &mut data);
+        data.join("\n")
+    }
+
+    fn write(&self, format: &Format, data: &mut Vec<String>) {
         match format {
-            Format::Table => self.table(&mut data),
-            Format::Html => self.html(&mut data),
-            Format::Markdown => self.markdown(&mut data),
+            Format::Table => self.table(data),
+            Format::Html => self.html(data),
+            Format::Markdown => self.markdown(data),
+            Format::Csv(delimiter) => self.csv(data, *delimiter),
+            // Structured formats nest the vendored section themselves
+            Format::Json => return self.json(data),
+            Format::Yaml => return self.yaml(data),
+            Format::Xml => return self.xml(data),
+            // Printed per file while counting, see `jsonl`
+            Format::Jsonl => return,
+            // Wraps the html output, files and vendored section included
+            Format::HtmlReport => return data.push(report::html(self)),
+            Format::Badge(badge) => return data.push(badge.svg(&badge.value(self))),
         };
 
-        println!("{}", data.join("\n"));
+        // `--files` lists every file below the summary
+        let groups = file_groups(&self.data);
+        if !groups.is_empty() {
+            data.push(String::new());
+            match format {
+                Format::Table => table_files(self, data, &groups),
+                Format::Html => html_files(self, data, &groups),
+                Format::Markdown => markdown_files(self, data, &groups),
+                Format::Csv(delimiter) => csv_files(data, &groups, self.column, *delimiter),
+                _ => {}
+            }
+        }
+
+        if let Some(vendored) = &self.vendored {
+            data.push(String::new());
+            vendored.write(format, data);
+        }
     }
 
     fn table(&self, data: &mut Vec<String>) {
-        data.push(format!("╭{:─<78}╮", ""));
-        data.push(format!(
-            "│ {:<14}{:>12}{:>12}{:>12}{:>12}{:>14} │",
-            "Language", "Code", "Comment", "Blank", "File", "Size"
-        ));
-        data.push(format!("├{:─<78}┤", ""));
+        let header = self.header();
+        let (rows, total) = self.rows(table_size);
 
-        for item in &self.data {
-            data.push(format!(
-                "│ {:<14}{:>12}{:>12}{:>12}{:>12}{:>14} │",
-                item.language,
-                item.code,
-                item.comment,
-                item.blank,
-                item.file,
-                format_size(item.size)
-            ));
+        // Every column as wide as its widest cell
+        let mut widths = vec![0; header.len()];
+        for cells in std::iter::once(&header).chain(&rows).chain(&total) {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(display_width(cell));
+            }
         }
+        // Names give way when the numbers don't fit, the borders take 4
+        let numbers = widths[1..].iter().map(|w| w + TABLE_GAP).sum::<usize>();
+        let room = self.width.saturating_sub(numbers + 4);
+        widths[0] = widths[0].min(room.max(TABLE_MIN_NAME));
+        let inner = widths[0] + numbers;
 
-        data.push(format!("├{:─<78}┤", ""));
+        // Rows over one `--warn-*` limit are yellow, over both red,
+        // without color they get a `!` at the end instead
+        let levels = self
+            .data
+            .iter()
+            .map(|item| self.warnings(item).len())
+            .collect::<Vec<usize>>();
+        let marker = !self.color && (self.warn_code.is_some() || self.warn_ratio.is_some());
+        let inner = if marker { inner + 2 } else { inner };
 
-        data.push(format!(
-            "│ {:<14}{:>12}{:>12}{:>12}{:>12}{:>14} │",
-            "Total",
-            format_number(self.total_code),
-            format_number(self.total_comment),
-            format_number(self.total_blank),
-            format_number(self.total_file),
-            format_size(self.total_size)
-        ));
-        data.push(format!("╰{:─<78}╯", ""));
+        // Header and totals are bold, names cyan; cells are painted
+        // before padding, `display_width` skips the escape codes
+        let paint = |text: String, bold: bool, name: bool, level: usize| {
+            if !self.color {
+                text
+            } else if level > 1 {
+                text.red().to_string()
+            } else if level == 1 {
+                text.yellow().to_string()
+            } else if bold {
+                text.bold().to_string()
+            } else if name {
+                text.cyan().to_string()
+            } else {
+                text
+            }
+        };
+        let line = |cells: &[String], bold: bool, level: usize| {
+            let name = paint(truncate_display(&cells[0], widths[0]), bold, true, level);
+            let mut line = format!("│ {}", pad_end(&name, widths[0]));
+            for (cell, width) in cells[1..].iter().zip(&widths[1..]) {
+                line.push_str(&pad_start(
+                    &paint(cell.to_string(), bold, false, level),
+                    width + TABLE_GAP,
+                ));
+            }
+            if marker {
+                line.push_str(if level > 0 { " !" } else { "  " });
+            }
+            line.push_str(" │");
+            line
+        };
+
+        data.push(format!("╭{}╮", "─".repeat(inner + 2)));
+        data.push(line(&header, true, 0));
+        data.push(format!("├{}┤", "─".repeat(inner + 2)));
+        for (cells, level) in rows.iter().zip(levels) {
+            data.push(line(cells, false, level));
+        }
+        if let Some(cells) = &total {
+            data.push(format!("├{}┤", "─".repeat(inner + 2)));
+            data.push(line(cells, true, 0));
+        }
+        data.push(format!("╰{}╯", "─".repeat(inner + 2)));
+    }
+
+    // Limits the row goes over, as (limit, value, maximum)
+    fn warnings(&self, item: &Detail) -> Vec<(&'static str, String, String)> {
+        let mut rst = vec![];
+        if let Some(limit) = self.warn_code {
+            if item.code > limit {
+                rst.push(("code", item.code.to_string(), limit.to_string()));
+            }
+        }
+        // No ratio without code, e.g. Markdown is all comments
+        if let (Some(limit), true) = (self.warn_ratio, item.code > 0) {
+            let ratio = item.comment as f64 / item.code as f64;
+            if ratio > limit {
+                rst.push(("comment_ratio", format!("{:.2}", ratio), limit.to_string()));
+            }
+        }
+        rst
+    }
+
+    // Column names of the table-like formats
+    fn header(&self) -> Vec<String> {
+        let mut header = vec![self.column];
+        header.extend(["Code", "Comment", "Blank", "File", "Size", "Lines", "%"]);
+        if self.avg {
+            header.push("Avg");
+        }
+        header.into_iter().map(|s| s.to_string()).collect()
+    }
+
+    // Formatted cells of every row and of the totals row
+    fn rows(&self, size: fn(u64, SizeStyle) -> String) -> (Vec<Vec<String>>, Option<Vec<String>>) {
+        let row = |name: &str, code, comment, blank, file, bytes, lines| {
+            let mut cells = vec![
+                name.to_string(),
+                self.number(code),
+                self.number(comment),
+                self.number(blank),
+                self.number(file),
+                size(bytes, self.size),
+                self.number(lines),
+                self.percent(code),
+            ];
+            if self.avg {
+                cells.push(self.number(average(code, file)));
+            }
+            cells
+        };
+
+        let rows = self
+            .data
+            .iter()
+            .map(|item| {
+                row(
+                    &item.name,
+                    item.code,
+                    item.comment,
+                    item.blank,
+                    item.file,
+                    item.size,
+                    item.lines,
+                )
+            })
+            .collect();
+        let total = if self.total {
+            Some(row(
+                "Total",
+                self.total_code,
+                self.total_comment,
+                self.total_blank,
+                self.total_file,
+                self.total_size,
+                self.total_lines,
+            ))
+        } else {
+            None
+        };
+        (rows, total)
+    }
+
+    fn number<T: Display>(&self, n: T) -> String {
+        group_digits(n, self.number.separator())
+    }
+
+    // Share of the total code, "12.3%"
+    fn percent(&self, code: u64) -> String {
+        percent(code, self.total_code, 1)
     }
 
     fn html(&self, data: &mut Vec<String>) {
         data.push("<table>".to_string());
-        data.push(
+        data.push(format!(
             "   <thead>
         <tr>
-            <th>Language</th>
+            <th>{}</th>
             <th>Code</th>
             <th>Comment</th>
             <th>Blank</th>
             <th>File</th>
             <th>Size</th>
+            <th>Lines</th>
+            <th>%</th>{}
         </tr>
-    </thead>"
-                .to_string(),
-        );
+    </thead>",
+            xml_escape(self.column),
+            self.html_avg("th", &"Avg")
+        ));
         data.push("    <tbody>".to_string());
 
         for item in &self.data {
@@ -129,17 +551,26 @@ impl Output {
             <td>{}</td>
             <td>{}</td>
             <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>{}
         </tr>",
-                item.language,
-                item.code,
-                item.comment,
-                item.blank,
-                item.file,
-                format_size(item.size)
+                xml_escape(&item.name),
+                self.number(item.code),
+                self.number(item.comment),
+                self.number(item.blank),
+                self.number(item.file),
+                human_size(item.size, self.size),
+                self.number(item.lines),
+                self.percent(item.code),
+                self.html_avg("td", &self.number(average(item.code, item.file)))
             ));
         }
         data.push("    </tbody>".to_string());
 
+        if !self.total {
+            data.push("</table>".to_string());
+            return;
+        }
         data.push(format!(
             "    <tfoot>
         <tr>
@@ -149,97 +580,1515 @@ impl Output {
             <td>{}</td>
             <td>{}</td>
             <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>{}
         </tr>
     </tfoot>",
-            format_number(self.total_code),
-            format_number(self.total_comment),
-            format_number(self.total_blank),
-            format_number(self.total_file),
-            format_size(self.total_size)
+            self.number(self.total_code),
+            self.number(self.total_comment),
+            self.number(self.total_blank),
+            self.number(self.total_file),
+            human_size(self.total_size, self.size),
+            self.number(self.total_lines),
+            self.percent(self.total_code),
+            self.html_avg("td", &self.number(self.total_avg))
         ));
         data.push("</table>".to_string());
     }
 
+    fn html_avg(&self, tag: &str, value: &dyn Display) -> String {
+        if self.avg {
+            format!("\n            <{}>{}</{}>", tag, value, tag)
+        } else {
+            String::new()
+        }
+    }
+
     fn markdown(&self, data: &mut Vec<String>) {
-        data.push(format!(
-            "| {:<14} | {:<12} | {:<12} | {:<12} | {:<12} | {:<14} |",
-            "Language", "Code", "Comment", "Blank", "File", "Size"
-        ));
-        data.push(format!(
-            "| :{:-<13} | {:-<11}: | {:-<11}: | {:-<11}: | {:-<11}: | {:-<13}: |",
-            "", "", "", "", "", ""
+        let (mut rows, mut total) = self.rows(human_size);
+        for cells in rows.iter_mut().chain(&mut total) {
+            cells[0] = markdown_escape(&cells[0]);
+        }
+        rows.extend(total);
+        markdown_table(data, &self.header(), &rows);
+    }
+
+    fn csv(&self, data: &mut Vec<String>, delimiter: char) {
+        let row = |values: &[&str], avg: &dyn Display| {
+            let mut values = values.to_vec();
+            let avg = avg.to_string();
+            if self.avg {
+                values.push(&avg);
+            }
+            csv_row(&values, delimiter)
+        };
+
+        data.push(row(
+            &[
+                &self.column.to_lowercase(),
+                "code",
+                "comment",
+                "blank",
+                "file",
+                "size",
+                "lines",
+                "percent",
+            ],
+            &"avg",
         ));
+
         for item in &self.data {
+            data.push(row(
+                &[
+                    &item.name,
+                    &item.code.to_string(),
+                    &item.comment.to_string(),
+                    &item.blank.to_string(),
+                    &item.file.to_string(),
+                    &item.size.to_string(),
+                    &item.lines.to_string(),
+                    &format!("{:.1}", share(item.code, self.total_code)),
+                ],
+                &average(item.code, item.file),
+            ));
+        }
+
+        if !self.total {
+            return;
+        }
+        data.push(row(
+            &[
+                "Total",
+                &self.total_code.to_string(),
+                &self.total_comment.to_string(),
+                &self.total_blank.to_string(),
+                &self.total_file.to_string(),
+                &self.total_size.to_string(),
+                &self.total_lines.to_string(),
+                &format!("{:.1}", share(self.total_code, self.total_code)),
+            ],
+            &self.total_avg,
+        ));
+    }
+
+    fn json(&self, data: &mut Vec<String>) {
+        // Plain structs and numbers, serializing them can't fail
+        data.push(serde_json::to_string_pretty(&self.json_report()).unwrap());
+    }
+
+    fn json_report(&self) -> JsonReport<'_> {
+        let key = self.column.to_lowercase();
+        let mut rows = BTreeMap::new();
+        rows.insert(plural(&key), json_rows(self, &self.data, &key));
+        let warnings = (self.warn_code.is_some() || self.warn_ratio.is_some()).then(|| {
+            self.data
+                .iter()
+                .flat_map(|item| {
+                    let key = key.clone();
+                    self.warnings(item)
+                        .into_iter()
+                        .map(move |(limit, value, max)| JsonWarning {
+                            name: BTreeMap::from([(key.clone(), item.name.as_str())]),
+                            limit,
+                            // Formatted numbers, an integer or one with decimals
+                            value: value.parse().unwrap(),
+                            max: max.parse().unwrap(),
+                        })
+                })
+                .collect()
+        });
+        JsonReport {
+            rows,
+            total: self.total.then(|| JsonRow {
+                name: BTreeMap::new(),
+                total: self.total(),
+                // 100, or 0 with nothing counted, as in the table
+                percent: Some(share(self.total_code, self.total_code)),
+                avg: self.avg.then_some(self.total_avg),
+                members: Vec::new(),
+                files: &[],
+            }),
+            warnings,
+            vendored: self
+                .vendored
+                .as_ref()
+                .map(|vendored| Box::new(vendored.json_report())),
+            stats: self.stats.as_ref(),
+            size_disabled: self.size.disabled.then_some(true),
+        }
+    }
+
+    fn yaml(&self, data: &mut Vec<String>) {
+        self.yaml_fields(data, "");
+    }
+
+    fn yaml_fields(&self, data: &mut Vec<String>, indent: &str) {
+        let key = self.column.to_lowercase();
+        if self.data.is_empty() {
+            data.push(format!("{}{}: []", indent, plural(&key)));
+        } else {
+            data.push(format!("{}{}:", indent, plural(&key)));
+            yaml_details(self, data, &self.data, &key, indent);
+        }
+        if self.total {
+            data.push(format!("{}total:", indent));
+            data.push(format!("{}  code: {}", indent, self.total_code));
+            data.push(format!("{}  comment: {}", indent, self.total_comment));
+            data.push(format!("{}  blank: {}", indent, self.total_blank));
+            data.push(format!("{}  file: {}", indent, self.total_file));
+            data.push(format!("{}  size: {}", indent, self.total_size));
+            data.push(format!("{}  lines: {}", indent, self.total_lines));
+            data.push(format!(
+                "{}  percent: {:.1}",
+                indent,
+                share(self.total_code, self.total_code)
+            ));
+            if self.avg {
+                data.push(format!("{}  avg: {}", indent, self.total_avg));
+            }
+        }
+        if let Some(vendored) = &self.vendored {
+            data.push(format!("{}vendored:", indent));
+            vendored.yaml_fields(data, &format!("{}  ", indent));
+        }
+        if self.size.disabled {
+            data.push(format!("{}size_disabled: true", indent));
+        }
+        if let Some(stats) = &self.stats {
+            data.push(format!("{}stats:", indent));
+            for (key, value) in stats.numbers() {
+                data.push(format!("{}  {}: {}", indent, key, value));
+            }
+            data.push(format!("{}  skipped:", indent));
+            for (key, value) in stats.fields() {
+                data.push(format!("{}    {}: {}", indent, key, value));
+            }
+        }
+    }
+
+    fn xml(&self, data: &mut Vec<String>) {
+        data.push("<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string());
+        data.push(match self.size.disabled {
+            true => "<rots size_disabled=\"true\">".to_string(),
+            false => "<rots>".to_string(),
+        });
+        self.xml_elements(data, "  ");
+        data.push("</rots>".to_string());
+    }
+
+    fn xml_elements(&self, data: &mut Vec<String>, indent: &str) {
+        let key = self.column.to_lowercase();
+        xml_details(self, data, &self.data, &key, indent);
+        if self.total {
             data.push(format!(
-                "| {:<14} | {:<12} | {:<12} | {:<12} | {:<12} | {:<14} |",
-                item.language,
-                item.code,
-                item.comment,
-                item.blank,
-                item.file,
-                format_size(item.size)
+                "{}<total code=\"{}\" comment=\"{}\" blank=\"{}\" files=\"{}\" size=\"{}\" lines=\"{}\" percent=\"{:.1}\"{}/>",
+                indent,
+                self.total_code,
+                self.total_comment,
+                self.total_blank,
+                self.total_file,
+                self.total_size,
+                self.total_lines,
+                share(self.total_code, self.total_code),
+                self.xml_avg(self.total_avg)
             ));
         }
+        if let Some(vendored) = &self.vendored {
+            data.push(format!("{}<vendored>", indent));
+            vendored.xml_elements(data, &format!("{}  ", indent));
+            data.push(format!("{}</vendored>", indent));
+        }
+        if let Some(stats) = &self.stats {
+            let attributes = |pairs: &[(&str, String)]| {
+                pairs
+                    .iter()
+                    .map(|(key, value)| format!(" {}=\"{}\"", key, value))
+                    .collect::<String>()
+            };
+            let skipped = stats.fields().map(|(key, value)| (key, value.to_string()));
+            data.push(format!("{}<stats{}>", indent, attributes(&stats.numbers())));
+            data.push(format!("{}  <skipped{}/>", indent, attributes(&skipped)));
+            data.push(format!("{}</stats>", indent));
+        }
+    }
+
+    fn xml_avg(&self, avg: u64) -> String {
+        if self.avg {
+            format!(" avg=\"{}\"", avg)
+        } else {
+            String::new()
+        }
+    }
+}
+
+// Code lines per file, rounded, nothing counted is 0
+fn average(code: u64, file: u64) -> u64 {
+    if file == 0 {
+        0
+    } else {
+        (code as f64 / file as f64).round() as u64
+    }
+}
+
+// Rows that carry their files, a family lists them under each member
+fn file_groups(details: &[Detail]) -> Vec<&Detail> {
+    let mut groups = vec![];
+    for item in details {
+        if !item.members.is_empty() {
+            groups.extend(file_groups(&item.members));
+        } else if !item.files.is_empty() {
+            groups.push(item);
+        }
+    }
+    groups
+}
+
+fn table_files(output: &Output, data: &mut Vec<String>, groups: &[&Detail]) {
+    let header = ["File", "Code", "Comment", "Blank", "Size"].map(String::from);
+    let rows = groups
+        .iter()
+        .map(|group| {
+            group
+                .files
+                .iter()
+                .map(|file| {
+                    [
+                        file.path.to_string_lossy().to_string(),
+                        output.number(file.code),
+                        output.number(file.comment),
+                        output.number(file.blank),
+                        table_size(file.size, output.size),
+                    ]
+                })
+                .collect::<Vec<[String; 5]>>()
+        })
+        .collect::<Vec<Vec<[String; 5]>>>();
+
+    // Sized like the summary table, paths are indented 2 under their group
+    let mut widths = header.each_ref().map(|cell| display_width(cell));
+    for group in groups {
+        widths[0] = widths[0].max(display_width(&group.name));
+    }
+    for cells in rows.iter().flatten() {
+        widths[0] = widths[0].max(display_width(&cells[0]) + 2);
+        for (width, cell) in widths[1..].iter_mut().zip(&cells[1..]) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    let numbers = widths[1..].iter().map(|w| w + TABLE_GAP).sum::<usize>();
+    let room = output.width.saturating_sub(numbers + 4);
+    widths[0] = widths[0].min(room.max(TABLE_MIN_NAME));
+    let inner = widths[0] + numbers;
+
+    let line = |name: &str, cells: &[String]| {
+        let mut line = format!("│ {}", pad_end(name, widths[0]));
+        for (cell, width) in cells.iter().zip(&widths[1..]) {
+            line.push_str(&pad_start(cell, width + TABLE_GAP));
+        }
+        line.push_str(" │");
+        line
+    };
+
+    data.push(format!("╭{}╮", "─".repeat(inner + 2)));
+    data.push(line(&header[0], &header[1..]));
+    for (group, files) in groups.iter().zip(&rows) {
+        data.push(format!("├{}┤", "─".repeat(inner + 2)));
+        data.push(format!(
+            "│ {} │",
+            pad_end(&truncate_display(&group.name, inner), inner)
+        ));
+        for cells in files {
+            // The file name matters most, a long path loses its start
+            let path = truncate_start(&cells[0], widths[0].saturating_sub(2));
+            data.push(line(&format!("  {}", path), &cells[1..]));
+        }
+    }
+    data.push(format!("╰{}╯", "─".repeat(inner + 2)));
+}
+
+// The box of the subcommand tables, `diff`, `--snapshot`, `--by-author`. The
+// first `left` columns are text, the rest numbers; column `shrink` gives way
+// when the box is wider than `width`
+pub fn box_table(
+    header: &[String],
+    rows: &[Vec<String>],
+    total: Option<&[String]>,
+    left: usize,
+    shrink: usize,
+    width: usize,
+) -> Vec<String> {
+    let all = || {
+        std::iter::once(header)
+            .chain(rows.iter().map(Vec::as_slice))
+            .chain(total)
+    };
+    let mut widths = vec![0; header.len()];
+    for cells in all() {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    // Columns are `TABLE_GAP` apart, the borders take 4
+    let gaps = TABLE_GAP * (widths.len() - 1);
+    let others = widths.iter().sum::<usize>() - widths[shrink] + gaps;
+    let room = width.saturating_sub(others + 4);
+    widths[shrink] = widths[shrink].min(room.max(TABLE_MIN_NAME));
+    let inner = widths.iter().sum::<usize>() + gaps;
+
+    let line = |cells: &[String]| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, width))| match i < left {
+                true => pad_end(&truncate_display(cell, *width), *width),
+                false => pad_start(cell, *width),
+            })
+            .collect::<Vec<String>>();
+        format!("│ {} │", cells.join(&" ".repeat(TABLE_GAP)))
+    };
+    let rule = |start: &str, end: &str| format!("{}{}{}", start, "─".repeat(inner + 2), end);
+
+    let mut data = vec![rule("╭", "╮"), line(header), rule("├", "┤")];
+    data.extend(rows.iter().map(|cells| line(cells)));
+    if let Some(cells) = total {
+        data.push(rule("├", "┤"));
+        data.push(line(cells));
+    }
+    data.push(rule("╰", "╯"));
+    data
+}
 
+fn html_files(output: &Output, data: &mut Vec<String>, groups: &[&Detail]) {
+    data.push("<table>".to_string());
+    data.push(
+        "   <thead>
+        <tr>
+            <th>File</th>
+            <th>Code</th>
+            <th>Comment</th>
+            <th>Blank</th>
+            <th>Size</th>
+        </tr>
+    </thead>"
+            .to_string(),
+    );
+    for group in groups {
+        data.push("    <tbody>".to_string());
         data.push(format!(
-            "| {:<14} | {:<12} | {:<12} | {:<12} | {:<12} | {:<14} |",
-            "Total",
-            format_number(self.total_code),
-            format_number(self.total_comment),
-            format_number(self.total_blank),
-            format_number(self.total_file),
-            format_size(self.total_size)
+            "        <tr>
+            <th colspan=\"5\">{}</th>
+        </tr>",
+            xml_escape(&group.name)
         ));
+        for file in &group.files {
+            data.push(format!(
+                "        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>",
+                xml_escape(&file.path.to_string_lossy()),
+                output.number(file.code),
+                output.number(file.comment),
+                output.number(file.blank),
+                human_size(file.size, output.size)
+            ));
+        }
+        data.push("    </tbody>".to_string());
+    }
+    data.push("</table>".to_string());
+}
+
+fn markdown_files(output: &Output, data: &mut Vec<String>, groups: &[&Detail]) {
+    let header = ["File", "Code", "Comment", "Blank", "Size"].map(String::from);
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            data.push(String::new());
+        }
+        data.push(format!("### {}", group.name));
+        data.push(String::new());
+        let rows = group
+            .files
+            .iter()
+            .map(|file| {
+                vec![
+                    markdown_escape(&file.path.to_string_lossy()),
+                    output.number(file.code),
+                    output.number(file.comment),
+                    output.number(file.blank),
+                    human_size(file.size, output.size),
+                ]
+            })
+            .collect::<Vec<Vec<String>>>();
+        markdown_table(data, &header, &rows);
+    }
+}
+
+// Columns widen to the longest cell so the raw text lines up,
+// at least 3 wide for the `---:` delimiter
+fn markdown_table(data: &mut Vec<String>, header: &[String], rows: &[Vec<String>]) {
+    let mut widths = vec![3; header.len()];
+    for cells in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)) {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    let line = |cells: &[String]| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| pad_end(cell, *width))
+            .collect::<Vec<String>>();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    data.push(line(header));
+    let delimiter = widths
+        .iter()
+        .enumerate()
+        .map(|(i, width)| match i {
+            0 => format!(":{}", "-".repeat(width - 1)),
+            _ => format!("{}:", "-".repeat(width - 1)),
+        })
+        .collect::<Vec<String>>();
+    data.push(line(&delimiter));
+    for cells in rows {
+        data.push(line(cells));
+    }
+}
+
+// A `|` would end the cell
+fn markdown_escape(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn csv_files(data: &mut Vec<String>, groups: &[&Detail], column: &str, delimiter: char) {
+    data.push(csv_row(
+        &[
+            "path",
+            &column.to_lowercase(),
+            "code",
+            "comment",
+            "blank",
+            "size",
+        ],
+        delimiter,
+    ));
+    for group in groups {
+        for file in &group.files {
+            data.push(csv_row(
+                &[
+                    &file.path.to_string_lossy(),
+                    &group.name,
+                    &file.code.to_string(),
+                    &file.comment.to_string(),
+                    &file.blank.to_string(),
+                    &file.size.to_string(),
+                ],
+                delimiter,
+            ));
+        }
     }
 }
 
-fn format_size(n: u64) -> String {
-    const UNITS: [char; 6] = ['K', 'M', 'G', 'T', 'P', 'E'];
-    if n < 1024 {
-        return format!("{} B ", n);
+fn xml_details(
+    output: &Output,
+    data: &mut Vec<String>,
+    details: &[Detail],
+    key: &str,
+    indent: &str,
+) {
+    for item in details {
+        let element = format!(
+            "{}<{} name=\"{}\" code=\"{}\" comment=\"{}\" blank=\"{}\" files=\"{}\" size=\"{}\" lines=\"{}\" percent=\"{:.1}\"{}",
+            indent,
+            key,
+            xml_escape(&item.name),
+            item.code,
+            item.comment,
+            item.blank,
+            item.file,
+            item.size,
+            item.lines,
+            share(item.code, output.total_code),
+            output.xml_avg(average(item.code, item.file))
+        );
+        if item.members.is_empty() && item.files.is_empty() {
+            data.push(format!("{}/>", element));
+        } else {
+            data.push(format!("{}>", element));
+            xml_details(output, data, &item.members, key, &format!("{}  ", indent));
+            for file in &item.files {
+                data.push(format!(
+                    "{}  <file path=\"{}\" code=\"{}\" comment=\"{}\" blank=\"{}\" size=\"{}\"/>",
+                    indent,
+                    xml_escape(&file.path.to_string_lossy()),
+                    file.code,
+                    file.comment,
+                    file.blank,
+                    file.size
+                ));
+            }
+            data.push(format!("{}</{}>", indent, key));
+        }
     }
-    let bytes = n as f64;
-    let i = (bytes.ln() / 1024_f64.ln()) as i32;
-    format!(
-        "{:.2} {}B",
-        bytes / 1024_f64.powi(i),
-        UNITS[(i - 1) as usize]
-    )
 }
 
-fn format_number<T: Display>(num: T) -> String {
-    let num = format!("{}", num);
+// Also used for text and attribute values of the html outputs
+pub fn xml_escape(value: &str) -> String {
     let mut rst = String::new();
-    for (i, ch) in num.chars().enumerate() {
-        rst.push(ch);
-        if i != num.len() - 1 && (num.len() - 1 - i) % 3 == 0 {
-            rst.push(',');
+    for ch in value.chars() {
+        match ch {
+            '&' => rst.push_str("&amp;"),
+            '<' => rst.push_str("&lt;"),
+            '>' => rst.push_str("&gt;"),
+            '"' => rst.push_str("&quot;"),
+            '\'' => rst.push_str("&apos;"),
+            c => rst.push(c),
+        }
+    }
+    rst
+}
+
+// One complete json document per counted file
+pub fn jsonl(data: &Data) -> String {
+    serde_json::to_string(data).unwrap()
+}
+
+// language => languages, package => packages
+fn plural(key: &str) -> String {
+    match key.strip_suffix('y') {
+        Some(stem) => format!("{}ies", stem),
+        None => format!("{}s", key),
+    }
+}
+
+// Names are double-quoted, JSON strings are valid YAML scalars
+fn yaml_details(
+    output: &Output,
+    data: &mut Vec<String>,
+    details: &[Detail],
+    key: &str,
+    indent: &str,
+) {
+    for item in details {
+        data.push(format!(
+            "{}  - {}: {}",
+            indent,
+            key,
+            json_string(&item.name)
+        ));
+        data.push(format!("{}    code: {}", indent, item.code));
+        data.push(format!("{}    comment: {}", indent, item.comment));
+        data.push(format!("{}    blank: {}", indent, item.blank));
+        data.push(format!("{}    file: {}", indent, item.file));
+        data.push(format!("{}    size: {}", indent, item.size));
+        data.push(format!("{}    lines: {}", indent, item.lines));
+        data.push(format!(
+            "{}    percent: {:.1}",
+            indent,
+            share(item.code, output.total_code)
+        ));
+        if output.avg {
+            data.push(format!(
+                "{}    avg: {}",
+                indent,
+                average(item.code, item.file)
+            ));
+        }
+        if !item.members.is_empty() {
+            data.push(format!("{}    members:", indent));
+            yaml_details(output, data, &item.members, key, &format!("{}    ", indent));
+        }
+        if !item.files.is_empty() {
+            data.push(format!("{}    files:", indent));
+            for file in &item.files {
+                data.push(format!(
+                    "{}      - path: {}",
+                    indent,
+                    json_string(&file.path.to_string_lossy())
+                ));
+                data.push(format!("{}        code: {}", indent, file.code));
+                data.push(format!("{}        comment: {}", indent, file.comment));
+                data.push(format!("{}        blank: {}", indent, file.blank));
+                data.push(format!("{}        size: {}", indent, file.size));
+            }
+        }
+    }
+}
+
+// Nested languages (--by-family) are written under "members",
+// files (--files) under "files"
+fn json_rows<'a>(output: &Output, details: &'a [Detail], key: &str) -> Vec<JsonRow<'a>> {
+    details
+        .iter()
+        .map(|item| JsonRow {
+            name: BTreeMap::from([(key.to_string(), item.name.as_str())]),
+            total: Total::of(item),
+            // One decimal like the table
+            percent: Some((share(item.code, output.total_code) * 10.0).round() / 10.0),
+            avg: output.avg.then(|| average(item.code, item.file)),
+            members: json_rows(output, &item.members, key),
+            files: &item.files,
+        })
+        .collect()
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut rst = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => rst.push_str("\\\""),
+            '\\' => rst.push_str("\\\\"),
+            '\n' => rst.push_str("\\n"),
+            '\r' => rst.push_str("\\r"),
+            '\t' => rst.push_str("\\t"),
+            c if (c as u32) < 0x20 => rst.push_str(&format!("\\u{:04x}", c as u32)),
+            c => rst.push(c),
         }
     }
+    rst.push('"');
     rst
 }
 
+fn csv_row(values: &[&str], delimiter: char) -> String {
+    values
+        .iter()
+        .map(|value| csv_escape(value, delimiter))
+        .collect::<Vec<String>>()
+        .join(&delimiter.to_string())
+}
+
+// Quote the field if it contains a delimiter, quote or line break
+fn csv_escape(value: &str, delimiter: char) -> String {
+    if value.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Write to a temporary file next to the target, then rename it into place
+// so readers never see a half-written report
+pub struct AtomicFile {
+    file: Option<File>,
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        Ok(Self {
+            file: Some(File::create(&temp)?),
+            temp,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+            drop(file);
+            fs::rename(&self.temp, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("file already committed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+pub fn save(path: &Path, content: &str) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.commit()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::text::strip_ansi;
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("Rust", ','), "Rust");
+        assert_eq!(csv_escape("C, C++", ','), "\"C, C++\"");
+        assert_eq!(csv_escape("C, C++", '\t'), "C, C++");
+        assert_eq!(csv_escape("a\tb", '\t'), "\"a\tb\"");
+        assert_eq!(csv_escape("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_csv() {
+        let output = Output::new(vec![Detail {
+            name: "Rust".to_string(),
+            blank: 1,
+            comment: 2,
+            code: 3,
+            size: 2048,
+            file: 1,
+            lines: 6,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        let mut data = vec![];
+        output.csv(&mut data, ',');
+        assert_eq!(
+            data,
+            vec![
+                "language,code,comment,blank,file,size,lines,percent",
+                "Rust,3,2,1,1,2048,6,100.0",
+                "Total,3,2,1,1,2048,6,100.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_total_serde() {
+        let output = Output::new(vec![Detail {
+            name: "Go".to_string(),
+            code: 5,
+            comment: 1,
+            blank: 2,
+            file: 1,
+            size: 64,
+            lines: 8,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        let total = output.total();
+        assert_eq!(total, Total::of(&output.data[0]));
+        let json = serde_json::to_string(&total).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":5,"comment":1,"blank":2,"file":1,"size":64,"lines":8}"#
+        );
+        assert_eq!(serde_json::from_str::<Total>(&json).unwrap(), total);
+    }
 
     #[test]
-    fn test_format_size() {
-        assert_eq!(format_size(0), "0 B ");
-        assert_eq!(format_size(1), "1 B ");
-        assert_eq!(format_size(1023), "1023 B ");
-        assert_eq!(format_size(1024), "1.00 KB");
-        assert_eq!(format_size(1 * 1024 * 1024), "1.00 MB");
-        assert_eq!(format_size(1 * 1024 * 1024 * 1024 * 1024), "1.00 TB");
-        assert_eq!(format_size(u64::max_value()), "16.00 EB");
+    fn test_stats() {
+        let summary = Summary {
+            discovered: 12,
+            skipped: Skipped {
+                unknown: 3,
+                binary: 1,
+                ..Skipped::default()
+            },
+            vanished: 1,
+            bytes: 4 << 20,
+            walk_time: Duration::from_millis(250),
+            threads: 4,
+            ..Summary::default()
+        };
+        let stats = Stats::new(&summary, 10, Duration::from_secs(2));
+        assert_eq!(
+            stats.lines(NumberFormat::Comma, SizeStyle::default()),
+            [
+                "Elapsed   2.000s, walk 0.250s",
+                "Threads   4",
+                "Files     12 found, 10 counted",
+                "Skipped   3 unknown, 1 binary, 1 vanished",
+                "Read      4.00 MiB, 5 files/s, 2.00 MiB/s",
+            ]
+        );
+
+        let mut output = Output::new(vec![]);
+        output.stats = Some(stats);
+        let json: serde_json::Value = serde_json::from_str(&output.render(&Format::Json)).unwrap();
+        assert_eq!(json["stats"]["skipped"]["unknown"], 3);
+        assert_eq!(json["stats"]["files_per_sec"], 5);
+        let yaml = output.render(&Format::Yaml);
+        assert!(yaml.contains("stats:\n  elapsed: 2\n  walk: 0.25\n"));
+        assert!(output
+            .render(&Format::Xml)
+            .contains("<skipped unknown=\"3\""));
     }
 
     #[test]
-    fn test_format_number() {
-        assert_eq!(format_number(0), "0");
-        assert_eq!(format_number(1), "1");
-        assert_eq!(format_number(1000), "1,000");
-        assert_eq!(format_number(999999), "999,999");
-        assert_eq!(format_number(1234567), "1,234,567");
+    fn test_large_totals() {
+        let big = Detail {
+            code: i32::MAX as u64,
+            file: 1,
+            lines: i32::MAX as u64,
+            ..Detail::new("SQL")
+        };
+        let output = Output::new(vec![
+            big.clone(),
+            Detail {
+                name: "C".into(),
+                ..big
+            },
+        ]);
+        assert_eq!(output.total_code, 2 * i32::MAX as u64);
+        let table = output.render(&Format::Table);
+        let widths = table
+            .lines()
+            .map(|line| line.chars().count())
+            .collect::<Vec<_>>();
+        assert!(table.contains("4,294,967,294"));
+        assert!(widths.iter().all(|width| *width == widths[0]));
+        let json: serde_json::Value = serde_json::from_str(&output.render(&Format::Json)).unwrap();
+        assert_eq!(json["total"]["code"], 4_294_967_294u64);
+    }
+
+    #[test]
+    fn test_no_size() {
+        let mut output = Output::new(vec![]);
+        let json = |output: &Output| {
+            serde_json::from_str::<serde_json::Value>(&output.render(&Format::Json)).unwrap()
+        };
+        assert!(json(&output).get("size_disabled").is_none());
+
+        output.size.disabled = true;
+        assert_eq!(json(&output)["size_disabled"], true);
+        assert_eq!(json(&output)["total"]["size"], 0);
+        assert!(output
+            .render(&Format::Yaml)
+            .contains("\nsize_disabled: true"));
+        assert!(output
+            .render(&Format::Xml)
+            .contains("<rots size_disabled=\"true\">"));
+    }
+
+    #[test]
+    fn test_average() {
+        assert_eq!(average(0, 0), 0);
+        assert_eq!(average(10, 0), 0);
+        assert_eq!(average(10, 4), 3);
+        assert_eq!(average(10, 3), 3);
+        assert_eq!(average(11, 2), 6);
+
+        let mut output = Output::new(vec![Detail {
+            name: "Rust".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 7,
+            size: 0,
+            file: 2,
+            lines: 7,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        output.avg = true;
+        let mut data = vec![];
+        output.csv(&mut data, ',');
+        assert_eq!(
+            data[0],
+            "language,code,comment,blank,file,size,lines,percent,avg"
+        );
+        assert_eq!(data[1], "Rust,7,0,0,2,0,7,100.0,4");
+        assert_eq!(data[2], "Total,7,0,0,2,0,7,100.0,4");
+    }
+
+    #[test]
+    fn test_no_total() {
+        let mut output = Output::new(vec![]);
+        output.total = false;
+        output.vendored = Some(Box::new(Output::new(vec![])));
+
+        let json: serde_json::Value = serde_json::from_str(&output.render(&Format::Json)).unwrap();
+        assert!(json.get("total").is_none());
+        assert!(json["vendored"].get("total").is_some());
+
+        let markdown = output.render(&Format::Markdown);
+        assert_eq!(
+            markdown
+                .lines()
+                .filter(|l| l.starts_with("| Total"))
+                .count(),
+            1
+        );
+    }
+
+    // Large counts with separators must keep the columns aligned
+    #[test]
+    fn test_table_large_counts() {
+        let detail = |name: &str, code, comment, blank, file, size| Detail {
+            name: name.to_string(),
+            blank,
+            comment,
+            code,
+            size,
+            file,
+            lines: code + comment + blank,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let output = Output::new(vec![
+            detail("Rust", 1_234_567, 89_012, 345_678, 9_876, 52_428_800),
+            detail("TOML", 12, 0, 3, 1, 300),
+        ]);
+        assert_eq!(
+            output.render(&Format::Table),
+            "\
+╭────────────────────────────────────────────────────────────────────────────╮
+│ Language       Code  Comment    Blank   File       Size      Lines       % │
+├────────────────────────────────────────────────────────────────────────────┤
+│ Rust      1,234,567   89,012  345,678  9,876  50.00 MiB  1,669,257  100.0% │
+│ TOML             12        0        3      1    300 B           15    0.0% │
+├────────────────────────────────────────────────────────────────────────────┤
+│ Total     1,234,579   89,012  345,681  9,877  50.00 MiB  1,669,272  100.0% │
+╰────────────────────────────────────────────────────────────────────────────╯"
+        );
+    }
+
+    // Long names are cut to keep the table inside `width`
+    #[test]
+    fn test_table_width() {
+        let detail = Detail {
+            name: "Visual Basic for Applications".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 12,
+            size: 300,
+            file: 1,
+            lines: 12,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut output = Output::new(vec![detail]);
+        output.width = 200;
+        let wide = output.render(&Format::Table);
+        assert!(wide.contains("│ Visual Basic for Applications  "));
+        assert_eq!(display_width(wide.lines().next().unwrap()), 85);
+
+        output.width = 70;
+        let narrow = output.render(&Format::Table);
+        for line in narrow.lines() {
+            assert_eq!(display_width(line), 70);
+        }
+        assert!(narrow.contains("│ Visual Basic … "));
+
+        // Still the narrowest name column when even the numbers don't fit
+        output.width = 10;
+        let tiny = output.render(&Format::Table);
+        assert!(tiny.contains("│ Total  "));
+        assert!(tiny.contains("│ Visua…  "));
+    }
+
+    // Full-width characters take two columns, combining marks none
+    #[test]
+    fn test_table_wide_names() {
+        let detail = |name: &str| Detail {
+            name: name.to_string(),
+            blank: 0,
+            comment: 0,
+            code: 1,
+            size: 1,
+            file: 1,
+            lines: 1,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut output = Output::new(vec![
+            detail("中文注释"),
+            detail("Cafe\u{301}"),
+            detail("ＦＵＬＬ"),
+            detail("Rust"),
+        ]);
+        output.width = 200;
+        let table = output.render(&Format::Table);
+        let widths = table.lines().map(display_width).collect::<Vec<usize>>();
+        assert!(widths.iter().all(|w| *w == widths[0]), "{}", table);
+        assert!(table.contains("│ 中文注释  "));
+
+        // Cut on a two column character, the gap is filled with a space
+        output.width = 60;
+        let table = output.render(&Format::Table);
+        assert!(
+            table.lines().all(|line| display_width(line) == 60),
+            "{}",
+            table
+        );
+        assert!(table.contains("│ 中文…   "));
+        assert!(table.contains("│ ＦＵ…   "));
+
+        let markdown = output.render(&Format::Markdown);
+        let widths = markdown.lines().map(display_width).collect::<Vec<usize>>();
+        assert!(widths.iter().all(|w| *w == widths[0]), "{}", markdown);
+    }
+
+    #[test]
+    fn test_table_files_width() {
+        let file = |path: &str| Data {
+            path: PathBuf::from(path),
+            language: "Rust",
+            blank: 0,
+            comment: 0,
+            code: 1,
+            size: 1,
+        };
+        let rust = Detail {
+            name: "Rust".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 2,
+            size: 2,
+            file: 2,
+            lines: 2,
+            members: Vec::new(),
+            files: vec![
+                file("./src/main.rs"),
+                file("./a/very/long/path/to/some/deeply/nested/module/lib.rs"),
+            ],
+        };
+        let mut output = Output::new(vec![rust]);
+        output.width = 200;
+        let table = output.render(&Format::Table);
+        let files = table.lines().skip_while(|line| !line.is_empty()).skip(1);
+        let widths = files.map(display_width).collect::<Vec<usize>>();
+        assert!(widths.iter().all(|w| *w == widths[0]), "{}", table);
+        assert!(widths[0] < 200);
+        assert!(table.contains("│   ./src/main.rs  "));
+
+        output.width = 60;
+        let table = output.render(&Format::Table);
+        let files = table.lines().skip_while(|line| !line.is_empty()).skip(1);
+        assert!(
+            files.clone().all(|line| display_width(line) == 60),
+            "{}",
+            table
+        );
+        assert!(table.contains("│   …ply/nested/module/lib.rs  "));
+    }
+
+    #[test]
+    fn test_markdown() {
+        let detail = |name: &str, code| Detail {
+            name: name.to_string(),
+            blank: 0,
+            comment: 0,
+            code,
+            size: 0,
+            file: 1,
+            lines: code,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let output = Output::new(vec![
+            detail("A Very Long Language Name", 1_234_567_890),
+            detail("C | D", 1),
+        ]);
+        let markdown = output.render(&Format::Markdown);
+        let lines = markdown.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            lines[0],
+            "| Language                  | Code          | Comment | Blank | File | Size | Lines         | %      |"
+        );
+        assert_eq!(
+            lines[1],
+            "| :------------------------ | ------------: | ------: | ----: | ---: | ---: | ------------: | -----: |"
+        );
+        assert!(lines[2].starts_with("| A Very Long Language Name | 1,234,567,890 |"));
+        assert!(lines[3].starts_with("| C \\| D                    | 1             |"));
+
+        // Valid GFM: same cell count on every row, all of the same width
+        for line in &lines {
+            assert!(line.starts_with("| ") && line.ends_with(" |"));
+            assert_eq!(line.replace("\\|", "").matches('|').count(), 9, "{}", line);
+            assert_eq!(display_width(line), display_width(lines[0]));
+        }
+    }
+
+    #[test]
+    fn test_markdown_files() {
+        let file = |path: &str, code| Data {
+            path: PathBuf::from(path),
+            language: "Rust",
+            blank: 0,
+            comment: 0,
+            code,
+            size: 1,
+        };
+        let rust = Detail {
+            name: "Rust".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 12_346,
+            size: 2,
+            file: 2,
+            lines: 12_346,
+            members: Vec::new(),
+            files: vec![file("./src/main.rs", 12_345), file("./a|b.rs", 1)],
+        };
+        let markdown = Output::new(vec![rust]).render(&Format::Markdown);
+        let lines = markdown
+            .lines()
+            .skip_while(|line| *line != "### Rust")
+            .skip(2)
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            lines,
+            [
+                "| File          | Code   | Comment | Blank | Size |",
+                "| :------------ | -----: | ------: | ----: | ---: |",
+                "| ./src/main.rs | 12,345 | 0       | 0     | 1 B  |",
+                "| ./a\\|b.rs     | 1      | 0       | 0     | 1 B  |",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_escape() {
+        let file = Data {
+            path: PathBuf::from("./<b>&.rs"),
+            language: "C<T>&Co",
+            blank: 0,
+            comment: 0,
+            code: 1,
+            size: 1,
+        };
+        let output = Output::new(vec![Detail {
+            name: "C<T>&Co".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 1,
+            size: 1,
+            file: 1,
+            lines: 1,
+            members: Vec::new(),
+            files: vec![file],
+        }]);
+        let html = output.render(&Format::Html);
+        assert!(html.contains("<td>C&lt;T&gt;&amp;Co</td>"));
+        assert!(html.contains("<th colspan=\"5\">C&lt;T&gt;&amp;Co</th>"));
+        assert!(html.contains("<td>./&lt;b&gt;&amp;.rs</td>"));
+        assert!(!html.contains("C<T>"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_table_color() {
+        let mut output = Output::new(vec![Detail {
+            name: "中文".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 1,
+            size: 0,
+            file: 1,
+            lines: 1,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        let plain = output.render(&Format::Table);
+        assert!(!plain.contains('\x1b'));
+
+        output.color = true;
+        let colored = output.render(&Format::Table);
+        assert!(colored.contains("\x1b[36m中文\x1b[0m"));
+        assert!(colored.contains("\x1b[1mTotal\x1b[0m"));
+        // Same layout once the escape codes are gone
+        for (a, b) in plain.lines().zip(colored.lines()) {
+            assert_eq!(display_width(a), display_width(b));
+        }
+        assert_eq!(strip_ansi(&colored), plain);
+        assert!(!output.render(&Format::Markdown).contains('\x1b'));
+        assert!(!output.render(&Format::Json).contains('\x1b'));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let detail = |name: &str, code, comment| Detail {
+            name: name.to_string(),
+            blank: 0,
+            comment,
+            code,
+            size: 0,
+            file: 1,
+            lines: code + comment,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut output = Output::new(vec![
+            detail("Rust", 100, 80),
+            detail("TOML", 20, 1),
+            detail("Markdown", 0, 9),
+            detail("Shell", 1, 0),
+        ]);
+        output.warn_code = Some(50);
+        output.warn_ratio = Some(0.5);
+
+        let table = output.render(&Format::Table);
+        let lines = table.lines().collect::<Vec<&str>>();
+        assert!(lines[3].starts_with("│ Rust") && lines[3].ends_with("% ! │"));
+        assert!(lines[4].ends_with("%   │"));
+        // Comment only, there is no ratio to go over
+        assert!(lines[5].starts_with("│ Markdown") && lines[5].ends_with("%   │"));
+        assert!(lines
+            .iter()
+            .all(|l| display_width(l) == display_width(lines[0])));
+
+        output.color = true;
+        let colored = output.render(&Format::Table);
+        assert!(colored.contains("\x1b[31mRust\x1b[0m"));
+        assert!(!colored.contains(" ! "));
+
+        output.warn_code = Some(10);
+        let json: serde_json::Value = serde_json::from_str(&output.render(&Format::Json)).unwrap();
+        let warnings = json["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0]["language"], "Rust");
+        assert_eq!(warnings[0]["limit"], "code");
+        assert_eq!(warnings[1]["limit"], "comment_ratio");
+        assert_eq!(warnings[1]["value"], 0.8);
+        assert_eq!(warnings[2]["language"], "TOML");
+        assert!(output
+            .render(&Format::Table)
+            .contains("\x1b[33mTOML\x1b[0m"));
+
+        output.warn_code = None;
+        output.warn_ratio = None;
+        let json: serde_json::Value = serde_json::from_str(&output.render(&Format::Json)).unwrap();
+        assert!(json.get("warnings").is_none());
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("C#"), "\"C#\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\n\u{1}"), "\"\\n\\u0001\"");
+    }
+
+    #[test]
+    fn test_json_members() {
+        let member = |name: &str, code| Detail {
+            name: name.to_string(),
+            blank: 0,
+            comment: 0,
+            code,
+            size: 0,
+            file: 1,
+            lines: code,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let family = Detail {
+            name: "JavaScript family".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 3,
+            size: 0,
+            file: 2,
+            lines: 3,
+            members: vec![member("JavaScript", 1), member("TypeScript", 2)],
+            files: Vec::new(),
+        };
+        let mut data = vec![];
+        Output::new(vec![family]).json(&mut data);
+        let json: serde_json::Value = serde_json::from_str(&data.join("\n")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "languages": [{
+                    "language": "JavaScript family", "code": 3, "comment": 0, "blank": 0, "file": 2,
+                    "size": 0, "lines": 3, "percent": 100.0,
+                    "members": [
+                        { "language": "JavaScript", "code": 1, "comment": 0, "blank": 0, "file": 1, "size": 0, "lines": 1, "percent": 33.3 },
+                        { "language": "TypeScript", "code": 2, "comment": 0, "blank": 0, "file": 1, "size": 0, "lines": 2, "percent": 66.7 }
+                    ]
+                }],
+                "total": { "code": 3, "comment": 0, "blank": 0, "file": 2, "size": 0, "lines": 3, "percent": 100.0 }
+            })
+        );
+        assert!(data[0].starts_with("{\n  \"languages\": [\n"));
+    }
+
+    #[test]
+    fn test_json_files() {
+        let file = |path: &str, code| Data {
+            path: PathBuf::from(path),
+            language: "Rust",
+            blank: 0,
+            comment: 0,
+            code,
+            size: 1,
+        };
+        let rust = Detail {
+            name: "Rust".to_string(),
+            blank: 0,
+            comment: 0,
+            code: 3,
+            size: 2,
+            file: 2,
+            lines: 3,
+            members: Vec::new(),
+            files: vec![file("./src/main.rs", 2), file("./src/cli.rs", 1)],
+        };
+        let mut data = vec![];
+        Output::new(vec![rust]).json(&mut data);
+        let json: serde_json::Value = serde_json::from_str(&data.join("\n")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "languages": [{
+                    "language": "Rust", "code": 3, "comment": 0, "blank": 0, "file": 2,
+                    "size": 2, "lines": 3, "percent": 100.0,
+                    "files": [
+                        { "path": "./src/main.rs", "language": "Rust", "code": 2, "comment": 0, "blank": 0, "size": 1 },
+                        { "path": "./src/cli.rs", "language": "Rust", "code": 1, "comment": 0, "blank": 0, "size": 1 }
+                    ]
+                }],
+                "total": { "code": 3, "comment": 0, "blank": 0, "file": 2, "size": 2, "lines": 3, "percent": 100.0 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_yaml() {
+        let output = Output::new(vec![Detail {
+            name: "C#".to_string(),
+            blank: 1,
+            comment: 2,
+            code: 3,
+            size: 4,
+            file: 1,
+            lines: 6,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        let mut data = vec![];
+        output.yaml(&mut data);
+        assert_eq!(
+            data.join("\n"),
+            r#"languages:
+  - language: "C#"
+    code: 3
+    comment: 2
+    blank: 1
+    file: 1
+    size: 4
+    lines: 6
+    percent: 100.0
+total:
+  code: 3
+  comment: 2
+  blank: 1
+  file: 1
+  size: 4
+  lines: 6
+  percent: 100.0"#
+        );
+
+        let mut data = vec![];
+        Output::new(vec![]).yaml(&mut data);
+        assert_eq!(data[0], "languages: []");
+    }
+
+    // Read `<name attr="value" .../>` elements, enough for the xml writer
+    fn read_xml(xml: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let unescape = |s: &str| {
+            s.replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&")
+        };
+        xml.split('<')
+            .skip(1)
+            .filter(|tag| !tag.starts_with('?') && !tag.starts_with('/'))
+            .map(|tag| {
+                let tag = tag.trim_end().trim_end_matches('>').trim_end_matches('/');
+                let (name, mut rest) = tag.split_at(tag.find(' ').unwrap_or(tag.len()));
+                let mut attrs = vec![];
+                while let Some(eq) = rest.find("=\"") {
+                    let key = rest[..eq].trim().to_string();
+                    let end = rest[eq + 2..].find('"').unwrap() + eq + 2;
+                    attrs.push((key, unescape(&rest[eq + 2..end])));
+                    rest = &rest[end + 1..];
+                }
+                (name.to_string(), attrs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_xml() {
+        let output = Output::new(vec![Detail {
+            name: "C<T>&\"Co\"".to_string(),
+            blank: 1,
+            comment: 2,
+            code: 3,
+            size: 4,
+            file: 1,
+            lines: 6,
+            members: Vec::new(),
+            files: Vec::new(),
+        }]);
+        let mut data = vec![];
+        output.xml(&mut data);
+        let elements = read_xml(&data.join("\n"));
+
+        let attr = |i: usize, key: &str| {
+            elements[i]
+                .1
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0].0, "rots");
+        assert_eq!(elements[1].0, "language");
+        assert_eq!(attr(1, "name"), "C<T>&\"Co\"");
+        assert_eq!(attr(1, "code"), "3");
+        assert_eq!(attr(1, "files"), "1");
+        assert_eq!(attr(1, "size"), "4");
+        assert_eq!(elements[2].0, "total");
+        assert_eq!(attr(2, "comment"), "2");
+        assert_eq!(attr(2, "blank"), "1");
+        assert_eq!(attr(2, "percent"), "100.0");
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let data = Data {
+            path: std::path::PathBuf::from("./src/\"main\".rs"),
+            language: "Rust",
+            blank: 1,
+            comment: 2,
+            code: 10,
+            size: 321,
+        };
+        assert_eq!(
+            jsonl(&data),
+            r#"{"path":"./src/\"main\".rs","language":"Rust","code":10,"comment":2,"blank":1,"size":321}"#
+        );
     }
 }
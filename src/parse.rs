@@ -1,36 +1,92 @@
-use crate::{config::Language, Detail};
-use std::{fs, io::ErrorKind, path::PathBuf};
+use crate::config::{Language, CONFIG};
+use crate::detect;
+use crate::detect::GUESS_BYTES;
+use crate::mmap::Mmap;
+use crate::syntax::{self, State};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::str;
 
 #[derive(Debug)]
 pub enum Value {
     Ok(Data),
     Err(ErrorKind, PathBuf),
+    // Removed after it was found by the walk
+    Vanished,
+    // A NUL byte near the start, counting lines makes no sense
+    Binary(PathBuf),
     Invalid,
 }
 
-#[derive(Debug)]
+/// A counted file. Serialized as `path`, `language`, `code`, `comment`, `blank`
+/// and `size`, the fields of `-o jsonl` and of `files` in `--files -o json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Data {
+    #[serde(serialize_with = "lossy_path")]
+    pub path: PathBuf,
     pub language: &'static str,
-    pub blank: i32,
-    pub comment: i32,
-    pub code: i32,
+    pub code: u64,
+    pub comment: u64,
+    pub blank: u64,
     pub size: u64,
 }
 
-impl Data {
-    pub fn into_detail(self) -> Detail {
-        Detail {
-            language: self.language,
-            comment: self.comment,
-            blank: self.blank,
-            code: self.code,
-            size: self.size,
-            file: 1,
-        }
+// A path that isn't UTF-8 is still written, like everywhere else in the output
+fn lossy_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+// `Data` as read, its language has to be one of the known ones
+#[derive(Deserialize)]
+struct NamedData {
+    path: PathBuf,
+    language: String,
+    code: u64,
+    comment: u64,
+    blank: u64,
+    size: u64,
+}
+
+impl<'de> Deserialize<'de> for Data {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = NamedData::deserialize(deserializer)?;
+        let language = CONFIG
+            .find(&data.language)
+            .ok_or_else(|| de::Error::custom(format!("unknown language '{}'", data.language)))?;
+        Ok(Data {
+            path: data.path,
+            language: language.name,
+            code: data.code,
+            comment: data.comment,
+            blank: data.blank,
+            size: data.size,
+        })
     }
 }
 
-pub fn parser(path: PathBuf, config: &Language) -> Value {
+// `heuristics` lets `.h` files be counted as C++ or Objective-C
+pub fn parser(path: PathBuf, config: &Language, heuristics: bool) -> Value {
+    parse_path(path, config, heuristics, false)
+}
+
+// `--mmap`, files over `MMAP_SIZE` are mapped instead of read. One that can't be
+// mapped, on a virtual filesystem or not on unix, is read like without it
+pub fn parser_mmap(path: PathBuf, config: &Language, heuristics: bool) -> Value {
+    parse_path(path, config, heuristics, true)
+}
+
+const MMAP_SIZE: u64 = 1024 * 1024;
+
+// `--no-size`, read through a buffer without a stat first, `size` is 0
+pub fn parser_no_size(path: PathBuf, config: &Language, heuristics: bool) -> Value {
+    parse_file(path, 0, config, heuristics, NO_SIZE_CHUNK)
+}
+
+const NO_SIZE_CHUNK: usize = 64 * 1024;
+
+fn parse_path(path: PathBuf, config: &Language, heuristics: bool, mmap: bool) -> Value {
     let size = match path.metadata() {
         Ok(meta) => {
             if !meta.is_file() {
@@ -38,45 +94,280 @@ pub fn parser(path: PathBuf, config: &Language) -> Value {
             }
             meta.len()
         }
+        Err(err) if err.kind() == ErrorKind::NotFound => return Value::Vanished,
         Err(err) => return Value::Err(err.kind(), path),
     };
 
-    let content = match fs::read_to_string(&path) {
+    if mmap && size > MMAP_SIZE {
+        if let Some(value) = parse_mapped(&path, size, config, heuristics) {
+            return value;
+        }
+    }
+    if size > STREAM_SIZE {
+        return parse_file(path, size, config, heuristics, STREAM_SIZE as usize);
+    }
+    let bytes = match fs::read(&path) {
         Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Value::Vanished,
         Err(err) => return Value::Err(err.kind(), path),
     };
+    parse_bytes(path, bytes, size, config, heuristics)
+}
+
+// Files over this are read a buffer at a time, a few large SQL dumps shouldn't
+// take the memory of all of them
+const STREAM_SIZE: u64 = 8 * 1024 * 1024;
 
+// Pages past the end of a file raise SIGBUS, so the map is only as long as the
+// opened file. One changed since the stat is read instead
+fn parse_mapped(path: &Path, size: u64, config: &Language, heuristics: bool) -> Option<Value> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() != size {
+        return None;
+    }
+    let map = Mmap::open(&file, size).ok()?;
+    Some(parse_slice(
+        path.to_path_buf(),
+        &map,
+        size,
+        config,
+        heuristics,
+    ))
+}
+
+// A line at a time through a buffer of `chunk` bytes, only the longest line
+// is held on top of it. Counts the same as `parse_bytes`
+fn parse_file(
+    path: PathBuf,
+    size: u64,
+    config: &Language,
+    heuristics: bool,
+    chunk: usize,
+) -> Value {
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Value::Vanished,
+        Err(err) => return Value::Err(err.kind(), path),
+    };
+    let mut reader = BufReader::with_capacity(chunk, file);
+    // Without a stat a link to a directory only shows here
+    let failed = |err: io::Error, path| match err.kind() {
+        ErrorKind::IsADirectory => Value::Invalid,
+        kind => Value::Err(kind, path),
+    };
+
+    // The binary check and the heuristics only look at the start
+    let mut head = Vec::with_capacity(GUESS_BYTES);
+    if let Err(err) = (&mut reader)
+        .take(GUESS_BYTES as u64)
+        .read_to_end(&mut head)
+    {
+        return failed(err, path);
+    }
+    if is_binary(&head) {
+        return Value::Binary(path);
+    }
+    let config = match heuristics {
+        true => {
+            // The last char may be cut in half
+            let start = match str::from_utf8(&head) {
+                Ok(start) => start,
+                Err(err) if err.error_len().is_none() => {
+                    str::from_utf8(&head[..err.valid_up_to()]).unwrap()
+                }
+                Err(_) => return Value::Err(ErrorKind::InvalidData, path),
+            };
+            detect::refine(&path, start, config)
+        }
+        false => config,
+    };
+
+    let mut reader = Cursor::new(head).chain(reader);
+    let mut lines = Lines::new(config);
+    let (mut blank, mut comment, mut code) = (0, 0, 0);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => return Value::Err(err.kind(), path),
+        }
+        // `\n` is never part of another char, each line is UTF-8 on its own
+        let text = match str::from_utf8(&line) {
+            Ok(text) => text,
+            Err(_) => return Value::Err(ErrorKind::InvalidData, path),
+        };
+        match lines.next(text) {
+            Kind::Blank => blank += 1,
+            Kind::Comment => comment += 1,
+            Kind::Code => code += 1,
+        }
+    }
+
+    Value::Ok(Data {
+        path,
+        language: config.name,
+        code,
+        comment,
+        blank,
+        size,
+    })
+}
+
+// Content that isn't a file on disk, like an archive entry, `path` only names it
+pub fn parse_bytes(
+    path: PathBuf,
+    bytes: Vec<u8>,
+    size: u64,
+    config: &Language,
+    heuristics: bool,
+) -> Value {
+    parse_slice(path, &bytes, size, config, heuristics)
+}
+
+// Bytes read into memory and a mapped file, counted the same way
+fn parse_slice(
+    path: PathBuf,
+    bytes: &[u8],
+    size: u64,
+    config: &Language,
+    heuristics: bool,
+) -> Value {
+    if is_binary(bytes) {
+        return Value::Binary(path);
+    }
+    let content = match str::from_utf8(bytes) {
+        Ok(data) => data,
+        Err(_) => return Value::Err(ErrorKind::InvalidData, path),
+    };
+
+    let config = match heuristics {
+        true => detect::refine(&path, content, config),
+        false => config,
+    };
+    Value::Ok(parse_str(path, content, size, config))
+}
+
+// Same check as git, a NUL byte in the first 8 KiB
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8192).any(|b| *b == 0)
+}
+
+// Count the lines of `content` as if it was read from `path`
+pub fn parse_str(path: PathBuf, content: &str, size: u64, config: &Language) -> Data {
     let mut blank = 0;
     let mut comment = 0;
     let mut code = 0;
-    let mut in_comment = None;
+    classify(content, config, |kind| match kind {
+        Kind::Blank => blank += 1,
+        Kind::Comment => comment += 1,
+        Kind::Code => code += 1,
+    });
+
+    Data {
+        path,
+        language: config.name,
+        blank,
+        comment,
+        code,
+        size,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Blank,
+    Comment,
+    Code,
+}
+
+// What each line of `content` is, in order, `--by-author` needs the lines themselves
+pub fn classify(content: &str, config: &Language, mut f: impl FnMut(Kind)) {
+    let mut lines = Lines::new(config);
+    for line in content.lines() {
+        f(lines.next(line));
+    }
+}
+
+// The kind of one line after another, a multi-line comment carries over
+struct Lines<'a> {
+    config: &'a Language,
+    in_comment: Option<(&'a str, &'a str)>,
+    // Open comments of a `nested` pair
+    depth: usize,
+    // What a `syntax` language carries over, an open string literal
+    state: State,
+}
+
+impl<'a> Lines<'a> {
+    fn new(config: &'a Language) -> Self {
+        Self {
+            config,
+            in_comment: None,
+            depth: 0,
+            state: State::new(config.syntax),
+        }
+    }
 
-    'line: for line in content.lines() {
-        let line = line.trim();
+    fn next(&mut self, raw: &str) -> Kind {
+        let line = raw.trim();
+
+        // Every line of a string is code, even blank or starting with a comment token
+        if let Some(syntax) = self.config.syntax {
+            if let Some(kind) = syntax::before(syntax, raw, &mut self.state) {
+                return kind;
+            }
+        }
 
         // Matching blank line
         if line.is_empty() {
-            blank += 1;
-            continue 'line;
+            return Kind::Blank;
         }
 
         // Match multiple lines of comments
-        for (start, end) in config.multi {
-            if let Some(d) = in_comment {
+        for &(start, end) in self.config.multi {
+            if let Some(d) = self.in_comment {
                 if d != (start, end) {
                     continue;
                 }
             }
 
+            if self.config.line_start {
+                let token = match self.in_comment {
+                    Some(_) => end,
+                    None => start,
+                };
+                if directive(raw, token) {
+                    self.in_comment = match self.in_comment {
+                        Some(_) => None,
+                        None => Some((start, end)),
+                    };
+                    return Kind::Comment;
+                }
+                if self.in_comment.is_some() {
+                    return Kind::Comment;
+                }
+                continue;
+            }
+
+            if self.config.nested.contains(&(start, end)) {
+                if self.in_comment.is_none() && !line.starts_with(start) {
+                    continue;
+                }
+                self.depth = nesting(line, start, end, self.depth);
+                self.in_comment = (self.depth > 0).then_some((start, end));
+                return Kind::Comment;
+            }
+
             // Multi-line comments may also end in a single line
             let mut same_line = false;
 
             if line.starts_with(start) {
-                in_comment = match in_comment {
+                self.in_comment = match self.in_comment {
                     Some(_) => {
-                        comment += 1;
-                        in_comment = None;
-                        continue 'line;
+                        self.in_comment = None;
+                        return Kind::Comment;
                     }
                     None => {
                         same_line = true;
@@ -86,37 +377,483 @@ pub fn parser(path: PathBuf, config: &Language) -> Value {
             }
 
             // This line is in the comment
-            if in_comment.is_some() {
-                comment += 1;
+            if self.in_comment.is_some() {
                 if line.ends_with(end) {
                     if same_line {
                         if line.len() >= (start.len() + end.len()) {
-                            in_comment = None;
+                            self.in_comment = None;
                         }
                     } else {
-                        in_comment = None;
+                        self.in_comment = None;
                     }
                 }
-                continue 'line;
+                return Kind::Comment;
             }
         }
 
         //  Match single line comments
-        for single in config.single {
+        for single in self.config.single {
             if line.starts_with(single) {
-                comment += 1;
-                continue 'line;
+                return Kind::Comment;
             }
         }
 
-        code += 1;
+        if let Some(syntax) = self.config.syntax {
+            syntax::after(syntax, line, &mut self.state);
+        }
+        Kind::Code
     }
+}
 
-    Value::Ok(Data {
-        language: config.name,
-        blank,
-        comment,
-        code,
-        size,
-    })
+// `=begin` at the very start of the line, alone or before a space
+fn directive(line: &str, token: &str) -> bool {
+    line.strip_prefix(token)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+// How many comments are still open after `line`, `depth` were before it. What
+// follows the last close is left to the next line
+fn nesting(line: &str, start: &str, end: &str, mut depth: usize) -> usize {
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix(end) {
+            depth = depth.saturating_sub(1);
+            if depth == 0 {
+                break;
+            }
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix(start) {
+            depth += 1;
+            rest = after;
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::CONFIG;
+
+    fn kinds(ext: &str, content: &str) -> Vec<Kind> {
+        let mut kinds = vec![];
+        classify(content, CONFIG.get(ext).unwrap(), |kind| kinds.push(kind));
+        kinds
+    }
+
+    #[test]
+    fn test_vanished() {
+        let path = std::env::temp_dir().join("lok-vanished-file.rs");
+        let _ = fs::remove_file(&path);
+        assert!(matches!(
+            parser(path, CONFIG.get("rs").unwrap(), true),
+            Value::Vanished
+        ));
+    }
+
+    #[test]
+    fn test_binary() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary(b"fn main() {}\n"));
+        assert!(is_binary(b"var a;\0\x01\x02"));
+        let mut late = vec![b'a'; 8192];
+        late.push(0);
+        assert!(!is_binary(&late));
+
+        let path = std::env::temp_dir().join("lok-binary-file.js");
+        fs::write(&path, b"var a = 1;\n\0\0\0\n").unwrap();
+        let value = parser(path.clone(), CONFIG.get("js").unwrap(), true);
+        assert!(matches!(value, Value::Binary(p) if p == path));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let path = PathBuf::from("release.tar.gz/src/main.rs");
+        let bytes = b"// entry\nfn main() {}\n".to_vec();
+        match parse_bytes(path.clone(), bytes, 22, CONFIG.get("rs").unwrap(), true) {
+            Value::Ok(data) => {
+                assert_eq!((data.comment, data.code, data.size), (1, 1, 22));
+                assert_eq!(data.path, path);
+            }
+            _ => panic!("expected the entry to be counted"),
+        }
+        let value = parse_bytes(path, vec![0xff, 0xfe], 2, CONFIG.get("rs").unwrap(), true);
+        assert!(matches!(value, Value::Err(ErrorKind::InvalidData, _)));
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let counted = |value: Value| match value {
+            Value::Ok(data) => data,
+            other => panic!("expected a count, got {:?}", other),
+        };
+        // Every fixture counts the same a few bytes at a time
+        for entry in fs::read_dir("tests/__code").unwrap() {
+            let path = entry.unwrap().path();
+            let config = CONFIG.get_by_path(&path).unwrap();
+            let size = fs::metadata(&path).unwrap().len();
+            assert_eq!(
+                counted(parse_file(path.clone(), size, config, true, 16)),
+                counted(parser(path.clone(), config, true)),
+                "{}",
+                path.display()
+            );
+        }
+
+        // Comments open in one chunk and closed many chunks later
+        let mut content = String::new();
+        for i in 0..2000 {
+            content.push_str("fn a() {}\r\n\n/* é\n");
+            content.push_str(&"  still a comment\n".repeat(i % 7));
+            content.push_str("*/\n// done\n");
+        }
+        content.push_str("fn last() {}");
+        let path = std::env::temp_dir().join("lok-stream-file.rs");
+        fs::write(&path, &content).unwrap();
+        let config = CONFIG.get("rs").unwrap();
+        let size = content.len() as u64;
+        let chunk = 4096;
+        assert!(size > chunk as u64 * 10);
+        let streamed = counted(parse_file(path.clone(), size, config, true, chunk));
+        let in_memory = counted(parse_bytes(
+            path.clone(),
+            content.into_bytes(),
+            size,
+            config,
+            true,
+        ));
+        assert_eq!(streamed, in_memory);
+        assert_eq!(streamed.code, 2001);
+
+        fs::write(&path, [b'a', b'\n', 0xff, b'\n']).unwrap();
+        let value = parse_file(path.clone(), 4, config, false, 2);
+        assert!(matches!(value, Value::Err(ErrorKind::InvalidData, _)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parser_mmap() {
+        let path = std::env::temp_dir().join("lok-mmap-file.rs");
+        let content = "/* a\n b */\nfn a() {}\n\n".repeat(64 * 1024);
+        assert!(content.len() as u64 > MMAP_SIZE);
+        fs::write(&path, &content).unwrap();
+        let config = CONFIG.get("rs").unwrap();
+        match (
+            parser_mmap(path.clone(), config, true),
+            parser(path.clone(), config, true),
+        ) {
+            (Value::Ok(mapped), Value::Ok(read)) => assert_eq!(mapped, read),
+            other => panic!("expected two counts, got {:?}", other),
+        }
+        // Shorter than the stat said, not mapped
+        let size = content.len() as u64;
+        assert!(parse_mapped(&path, size + 4096, config, true).is_none());
+        assert!(parse_mapped(&path, size, config, true).is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parser_no_size() {
+        let path = std::env::temp_dir().join("lok-no-size-file.rs");
+        fs::write(&path, "/* a\n b */\nfn a() {}\n\n".repeat(1024)).unwrap();
+        let config = CONFIG.get("rs").unwrap();
+        match (
+            parser_no_size(path.clone(), config, true),
+            parser(path.clone(), config, true),
+        ) {
+            (Value::Ok(skipped), Value::Ok(read)) => {
+                assert_eq!(skipped.size, 0);
+                assert_eq!(skipped, Data { size: 0, ..read });
+            }
+            other => panic!("expected two counts, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            parser_no_size(std::env::temp_dir(), config, true),
+            Value::Invalid
+        ));
+    }
+
+    // cargo test --release bench_mmap -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_mmap() {
+        let path = std::env::temp_dir().join("lok-bench-mmap.rs");
+        let block = "// line\nfn a() {\n    /* b\n    c */\n}\n\n".repeat(1024);
+        let mut file = fs::File::create(&path).unwrap();
+        for _ in 0..6000 {
+            std::io::Write::write_all(&mut file, block.as_bytes()).unwrap();
+        }
+        drop(file);
+        let config = CONFIG.get("rs").unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+        let time = |f: &dyn Fn() -> Value| {
+            let start = std::time::Instant::now();
+            let value = f();
+            (start.elapsed(), value)
+        };
+        let (read, a) = time(&|| parser(path.clone(), config, true));
+        let (mapped, b) = time(&|| parser_mmap(path.clone(), config, true));
+        eprintln!("{} MiB: read {:?}, mmap {:?}", size >> 20, read, mapped);
+        assert!(matches!((a, b), (Value::Ok(a), Value::Ok(b)) if a == b));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_str() {
+        let content = "// main\nfn main() {\n\n    /* one\n    two */\n}\n";
+        let data = parse_str(PathBuf::from("-"), content, 42, CONFIG.get("rs").unwrap());
+        assert_eq!(data.language, "Rust");
+        assert_eq!((data.code, data.comment, data.blank), (2, 3, 1));
+        assert_eq!(data.size, 42);
+    }
+
+    #[test]
+    fn test_nested() {
+        use Kind::*;
+        let rust = "/* outer\n   /* inner */\n   still comment\n*/\nfn main() {}\n";
+        assert_eq!(
+            kinds("rs", rust),
+            [Comment, Comment, Comment, Comment, Code]
+        );
+        let rust = "/* a /* b */ c */\nlet a = 1;\n";
+        assert_eq!(kinds("rs", rust), [Comment, Code]);
+
+        let haskell = "{- outer\n{- inner\n-}\nstill -}\nmain = pure ()\n";
+        assert_eq!(
+            kinds("hs", haskell),
+            [Comment, Comment, Comment, Comment, Code]
+        );
+        let nim = "#[ a\n  #[ b ]#\n]#\necho 1\n";
+        assert_eq!(kinds("nim", nim), [Comment, Comment, Comment, Code]);
+        let d = "/+ a\n /+ b +/\n+/\nvoid main() {}\n";
+        assert_eq!(kinds("d", d), [Comment, Comment, Comment, Code]);
+        // Only `/+ +/` nests in D, `/*` inside `/* */` is just text
+        let d = "/* a\n/* b */\nint x;\nvoid main() {}\n";
+        assert_eq!(kinds("d", d), [Comment, Comment, Code, Code]);
+
+        // C comments don't nest, the first `*/` ends it
+        let c = "/* a\n/* b */\nint a;\n";
+        assert_eq!(kinds("c", c), [Comment, Comment, Code]);
+    }
+
+    #[test]
+    fn test_rust_strings() {
+        let content = r###"static URL: &str = r"^https?://[^/]+//?$";
+const QUERY: &str = r#"
+/* the latest release */
+select "name"
+// not a comment either
+
+from releases
+"#;
+let bytes = br##"
+# "# isn't the end
+"##;
+// a real comment
+fn main() {}
+"###;
+        use Kind::*;
+        assert_eq!(
+            kinds("rs", content),
+            [Code, Code, Code, Code, Code, Code, Code, Code, Code, Code, Code, Comment, Code]
+        );
+    }
+
+    #[test]
+    fn test_python_strings() {
+        let content = r#"#!/usr/bin/env python
+'''Release notes.
+
+Reads the releases table.
+'''
+import db
+
+QUERY = f"""
+select name -- the latest
+# not a comment
+
+from releases
+"""
+
+
+class Release:
+    r"""A release."""
+
+    def fetch(self,
+              limit):
+        """
+        The latest `limit` releases.
+        """
+        return db.run(QUERY, limit)
+
+
+def empty(): pass
+"""Not a docstring after `def empty(): pass`."""
+"#;
+        use Kind::*;
+        assert_eq!(
+            kinds("py", content),
+            [
+                Comment, Comment, Comment, Comment, Comment, Code, Blank, Code, Code, Code, Code,
+                Code, Code, Blank, Blank, Code, Comment, Blank, Code, Code, Comment, Comment,
+                Comment, Code, Blank, Blank, Code, Code
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ruby_comments() {
+        let content = "# A release
+def release(version)
+  name = \"v#{version}\"
+  tag =begin_tag(name)
+  =begin not a comment, it is indented
+  notes = notes_for(name)
+  notes == ended
+end
+
+=begin
+The release of one version,
+  tag and notes.
+
+=end
+=beginning isn't a comment either
+puts release(1)
+";
+        let data = parse_str(
+            PathBuf::from("release.rb"),
+            content,
+            0,
+            CONFIG.get("rb").unwrap(),
+        );
+        assert_eq!((data.code, data.comment, data.blank), (9, 5, 2));
+        use Kind::*;
+        assert_eq!(
+            kinds("rb", content)[9..14],
+            [Comment, Comment, Comment, Blank, Comment]
+        );
+    }
+
+    #[test]
+    fn test_perl_pod() {
+        let content = "#!/usr/bin/perl
+use strict;
+my $count = 0;
+my $total=$count + 1;
+
+=head1 NAME
+
+release - tag a release
+
+  =cut is indented, still POD
+=cut
+
+# the work
+print \"$total\\n\";
+ =pod isn't at the start of the line
+";
+        let data = parse_str(
+            PathBuf::from("release.pl"),
+            content,
+            0,
+            CONFIG.get("pl").unwrap(),
+        );
+        assert_eq!((data.code, data.comment, data.blank), (5, 6, 4));
+        use Kind::*;
+        assert_eq!(
+            kinds("pl", content)[5..11],
+            [Comment, Blank, Comment, Blank, Comment, Comment]
+        );
+    }
+
+    #[test]
+    fn test_lua_brackets() {
+        let content = "--[==[ a leveled comment
+]] doesn't end it
+]==]
+local usage = [[
+-- not a comment, a line of the string
+]]
+-- a comment
+local t = { [1] = \"--[[\" }
+print(usage) --[[ to the
+end of this ]]
+";
+        use Kind::*;
+        assert_eq!(
+            kinds("lua", content),
+            [Comment, Comment, Comment, Code, Code, Code, Comment, Code, Code, Comment]
+        );
+    }
+
+    #[test]
+    fn test_heredocs() {
+        let shell = "#!/bin/sh
+# Write the CI config
+cat <<'EOF' > .ci.yml
+# Generated, don't edit
+
+steps:
+  # the tests
+  - run: cargo test
+EOF
+echo done
+";
+        let data = parse_str(PathBuf::from("ci.sh"), shell, 0, CONFIG.get("sh").unwrap());
+        assert_eq!((data.code, data.comment, data.blank), (8, 2, 0));
+
+        let ruby = "sql = <<~SQL
+  -- all of them
+  # select
+  SQL
+# a comment
+";
+        use Kind::*;
+        assert_eq!(kinds("rb", ruby), [Code, Code, Code, Code, Comment]);
+        let php = "<?php\n$doc = <<<EOT\n  # title\n  EOT;\n// done\n";
+        assert_eq!(kinds("php", php), [Code, Code, Code, Code, Comment]);
+        let perl = "print <<\"END\";\n=pod\n\nEND\n=pod\n";
+        assert_eq!(kinds("pl", perl), [Code, Code, Code, Code, Comment]);
+
+        // Shifts and heredocs in comments don't start one
+        let shell = "x=$(( a << b ))\n# one\n# two\necho $((1<<n))\n# three\n";
+        assert_eq!(kinds("sh", shell), [Code, Comment, Comment, Code, Comment]);
+        let php = "<?php\n$a = 1; // see <<<EOT\n// one\n$b = 2; /* <<<EOT */\n# two\n";
+        assert_eq!(kinds("php", php), [Code, Code, Comment, Code, Comment]);
+        // `<<-` strips tabs only, an end indented with spaces is still data
+        let shell = "cat <<-EOF\n\t# data\n  EOF\n\tEOF\n# done\n";
+        assert_eq!(kinds("sh", shell), [Code, Code, Code, Code, Comment]);
+    }
+
+    #[test]
+    fn test_data_serde() {
+        let data = Data {
+            path: PathBuf::from("a/b.py"),
+            language: "Python",
+            code: 10,
+            comment: 2,
+            blank: 1,
+            size: 120,
+        };
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":"a/b.py","language":"Python","code":10,"comment":2,"blank":1,"size":120}"#
+        );
+        let back = serde_json::from_str::<Data>(&json).unwrap();
+        assert_eq!(back, data);
+        // The same static name as the config's
+        assert!(std::ptr::eq(
+            back.language,
+            CONFIG.find("Python").unwrap().name
+        ));
+
+        let unknown = json.replace("Python", "Klingon");
+        let err = serde_json::from_str::<Data>(&unknown).unwrap_err();
+        assert!(err.to_string().contains("unknown language 'Klingon'"));
+    }
 }
@@ -0,0 +1,115 @@
+// Progress on stderr while counting, a spinner with the files found so far, then
+// a bar once the walk is over and the total known. Never on stdout, so
+// `lok -o json > out.json` stays clean
+use lok::Progress;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const BAR: usize = 30;
+// A run done before it shows nothing
+const DELAY: Duration = Duration::from_millis(300);
+const TICK: Duration = Duration::from_millis(100);
+
+pub struct Display {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Display {
+    pub fn start(progress: Arc<Progress>) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                let mut frame = 0;
+                loop {
+                    std::thread::park_timeout(TICK);
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if start.elapsed() < DELAY {
+                        continue;
+                    }
+                    let text = line(&progress, frame, start.elapsed());
+                    eprint!("\r\x1b[2K{}", text);
+                    let _ = io::stderr().flush();
+                    frame += 1;
+                }
+                if frame > 0 {
+                    eprint!("\r\x1b[2K");
+                }
+            })
+        };
+        Self { done, thread }
+    }
+
+    // Clears the line before anything else is printed
+    pub fn finish(self) {
+        self.done.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+    }
+}
+
+fn line(progress: &Progress, frame: usize, elapsed: Duration) -> String {
+    let discovered = progress.discovered();
+    if !progress.walked() {
+        return format!(
+            "{} discovered {} files",
+            FRAMES[frame % FRAMES.len()],
+            discovered
+        );
+    }
+    let done = progress.done().min(discovered);
+    let filled = match discovered {
+        0 => BAR,
+        total => done * BAR / total,
+    };
+    let rate = done as f64 / elapsed.as_secs_f64().max(0.001);
+    format!(
+        "[{}{}] {}/{} files, {:.0} files/s",
+        "█".repeat(filled),
+        "░".repeat(BAR - filled),
+        done,
+        discovered,
+        rate
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lok::{CountOptions, Counter};
+
+    #[test]
+    fn test_line() {
+        let progress = Arc::new(Progress::default());
+        let second = Duration::from_secs(1);
+        assert_eq!(line(&progress, 11, second), "⠙ discovered 0 files");
+
+        let options = CountOptions {
+            work_dirs: vec!["src".into()],
+            ..CountOptions::default()
+        };
+        Counter::new(options)
+            .progress(progress.clone())
+            .run()
+            .unwrap();
+        let total = progress.discovered();
+        assert_eq!(
+            line(&progress, 0, second),
+            format!(
+                "[{}] {}/{} files, {} files/s",
+                "█".repeat(BAR),
+                total,
+                total,
+                total
+            )
+        );
+    }
+}
@@ -0,0 +1,103 @@
+// A complete html document around the html table, for CI artifacts
+use crate::format::{share, utc_datetime};
+use crate::output::{xml_escape, Format, Output};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STYLE: &str = "
+    body { font-family: -apple-system, BlinkMacSystemFont, \"Segoe UI\", Helvetica, Arial, sans-serif; color: #24292f; max-width: 960px; margin: 2em auto; padding: 0 1em; }
+    h1 { font-size: 1.5em; }
+    table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+    th, td { padding: 6px 12px; border-bottom: 1px solid #d0d7de; text-align: right; }
+    th:first-child, td:first-child { text-align: left; }
+    thead th { background: #f6f8fa; }
+    tfoot td { font-weight: bold; }
+    .bar { display: flex; align-items: center; margin: 4px 0; }
+    .bar .name { width: 160px; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+    .bar .track { flex: 1; height: 12px; background: #eaeef2; border-radius: 6px; overflow: hidden; }
+    .bar .fill { display: block; height: 100%; background: #0969da; }
+    .bar .value { width: 64px; text-align: right; font-variant-numeric: tabular-nums; }
+    footer { margin-top: 2em; color: #57606a; font-size: 0.85em; }";
+
+pub fn html(output: &Output) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    document(output, &utc_datetime(now))
+}
+
+fn document(output: &Output, time: &str) -> String {
+    let dir = xml_escape(&output.dir);
+    let mut data = vec![
+        "<!DOCTYPE html>".to_string(),
+        "<html lang=\"en\">".to_string(),
+        "<head>".to_string(),
+        "<meta charset=\"utf-8\">".to_string(),
+        "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">".to_string(),
+        format!("<title>lok - {}</title>", dir),
+        format!("<style>{}\n</style>", STYLE),
+        "</head>".to_string(),
+        "<body>".to_string(),
+        format!("<h1>Code statistics of <code>{}</code></h1>", dir),
+        output.render(&Format::Html),
+        "<section class=\"bars\">".to_string(),
+    ];
+
+    // Share of the code, the widths are plain css
+    for item in &output.data {
+        let percent = share(item.code, output.total_code);
+        data.push(format!(
+            "    <div class=\"bar\"><span class=\"name\">{}</span><span class=\"track\"><span class=\"fill\" style=\"width: {:.1}%\"></span></span><span class=\"value\">{:.1}%</span></div>",
+            xml_escape(&item.name),
+            percent,
+            percent
+        ));
+    }
+
+    data.push("</section>".to_string());
+    data.push(format!(
+        "<footer>Counted <code>{}</code> at {} with lok {}</footer>",
+        dir,
+        time,
+        env!("CARGO_PKG_VERSION")
+    ));
+    data.push("</body>".to_string());
+    data.push("</html>".to_string());
+    data.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Detail;
+
+    #[test]
+    fn test_document() {
+        let detail = |name: &str, code| Detail {
+            name: name.to_string(),
+            blank: 0,
+            comment: 0,
+            code,
+            size: 0,
+            file: 1,
+            lines: code,
+            members: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut output = Output::new(vec![detail("Rust", 3), detail("C<T>", 1)]);
+        output.dir = "./a&b".to_string();
+        let html = document(&output, "2026-10-14 08:30:00 UTC");
+
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"en\">"));
+        assert!(html.ends_with("</body>\n</html>"));
+        assert!(html.contains("<style>"));
+        assert!(!html.contains("<script"));
+        assert!(html.contains("<title>lok - ./a&amp;b</title>"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains(
+            "<span class=\"name\">Rust</span><span class=\"track\"><span class=\"fill\" style=\"width: 75.0%\"></span></span><span class=\"value\">75.0%</span>"
+        ));
+        assert!(html.contains("<span class=\"name\">C&lt;T&gt;</span>"));
+        assert!(html.contains("at 2026-10-14 08:30:00 UTC with lok"));
+    }
+}
@@ -0,0 +1,258 @@
+// Config files: the user's `rots/config.toml` or `--config FILE`, then the
+// project's `.rots.toml`. Command line flags override both
+use crate::check::Limits;
+use crate::cli::{FORMATS, SORTS};
+use crate::config::UserLanguage;
+use crate::output::Format;
+use crate::Sort;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+
+// Looked up in the work dir, then up to the repository root
+const PROJECT_FILES: [&str; 2] = [".rots.toml", "rots.toml"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(default)]
+    pub language: Vec<UserLanguage>,
+    // Defaults for the flags of the same name
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub extension: Option<Vec<String>>,
+    pub sort: Option<Spanned<String>>,
+    pub output: Option<Vec<Spanned<String>>>,
+    // `--cache` on every run
+    pub cache: Option<bool>,
+    // `lok check` limits of the whole count
+    pub max_code: Option<u64>,
+    pub max_files: Option<u64>,
+    pub max_comment_ratio: Option<f64>,
+    // `[limits.NAME]`, limits of one language
+    #[serde(default)]
+    pub limits: BTreeMap<String, Limits>,
+}
+
+impl Settings {
+    // `project` wins, languages of both are kept and the later one wins
+    pub fn merge(mut self, project: Settings) -> Settings {
+        self.language.extend(project.language);
+        for (name, limits) in project.limits {
+            let user = self.limits.remove(&name).unwrap_or_default();
+            self.limits.insert(name, limits.or(user));
+        }
+        Settings {
+            language: self.language,
+            exclude: project.exclude.or(self.exclude),
+            include: project.include.or(self.include),
+            extension: project.extension.or(self.extension),
+            sort: project.sort.or(self.sort),
+            output: project.output.or(self.output),
+            cache: project.cache.or(self.cache),
+            max_code: project.max_code.or(self.max_code),
+            max_files: project.max_files.or(self.max_files),
+            max_comment_ratio: project.max_comment_ratio.or(self.max_comment_ratio),
+            limits: self.limits,
+        }
+    }
+
+    pub fn total_limits(&self) -> Limits {
+        Limits {
+            max_code: self.max_code,
+            max_files: self.max_files,
+            max_comment_ratio: self.max_comment_ratio,
+        }
+    }
+}
+
+// `$XDG_CONFIG_HOME/rots/config.toml`, else `~/.config/rots/config.toml`
+pub fn user_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("rots").join("config.toml"))
+}
+
+// `.rots.toml` or `rots.toml` in `dir`, or in a parent up to the one with `.git`.
+// Outside a repository only `dir` itself is looked at
+pub fn project_path(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    let dirs = match dir.ancestors().position(|dir| dir.join(".git").exists()) {
+        Some(root) => dir.ancestors().take(root + 1).collect::<Vec<&Path>>(),
+        None => vec![dir.as_path()],
+    };
+    dirs.into_iter()
+        .flat_map(|dir| PROJECT_FILES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+pub fn read(path: &Path) -> Result<Settings, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read '{}': {}", path.display(), err))?;
+    parse(&content).map_err(|err| format!("Cannot parse '{}', {}", path.display(), err))
+}
+
+fn parse(content: &str) -> Result<Settings, String> {
+    let settings = toml::from_str::<Settings>(content).map_err(|err| {
+        let message = err.message().trim_end();
+        match err.span() {
+            Some(span) => error_at(content, span.start, message),
+            None => message.to_string(),
+        }
+    })?;
+
+    // Checked here, the line is gone once the value leaves the file
+    if let Some(sort) = &settings.sort {
+        if sort.get_ref().parse::<Sort>().is_err() {
+            let message = format!("expected one of: {}", SORTS.join(", "));
+            return Err(error_at(content, sort.span().start, &message));
+        }
+    }
+    for output in settings.output.iter().flatten() {
+        let format = output.get_ref().split('=').next().unwrap();
+        if format.parse::<Format>().is_err() {
+            let message = format!("expected one of: {}", FORMATS.join(", "));
+            return Err(error_at(content, output.span().start, &message));
+        }
+    }
+    Ok(settings)
+}
+
+fn error_at(content: &str, offset: usize, message: &str) -> String {
+    let (line, key) = key_at(content, offset);
+    format!("line {}, key '{}': {}", line, key, message)
+}
+
+// Line number of `offset` and the key it belongs to, found on that line or
+// above it for multi-line arrays. A table header names the table
+fn key_at(content: &str, offset: usize) -> (usize, &str) {
+    let offset = offset.min(content.len());
+    let start = content[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = content[offset..]
+        .find('\n')
+        .map(|i| offset + i)
+        .unwrap_or(content.len());
+    let number = content[..start].matches('\n').count() + 1;
+    let is_key = |key: &str| {
+        !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_. ".contains(c))
+    };
+    let key = content[..end]
+        .lines()
+        .rev()
+        .find_map(|line| {
+            let line = line.trim();
+            let header = line.trim_start_matches('[');
+            if header.len() < line.len() && is_key(header.trim_end_matches(']')) {
+                return Some(header.trim_end_matches(']'));
+            }
+            line.split_once('=')
+                .map(|(key, _)| key)
+                .filter(|key| is_key(key))
+        })
+        .unwrap_or("");
+    (number, key.trim())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let settings = parse(
+            r#"
+[[language]]
+name = "Zig"
+extensions = ["zig"]
+single = ["//"]
+
+[[language]]
+name = "Rust"
+extensions = ["rsx"]
+multi = [["/*", "*/"]]
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.language.len(), 2);
+        assert_eq!(settings.language[0].name, "Zig");
+        assert_eq!(settings.language[0].single, Some(vec!["//".to_string()]));
+        assert!(settings.language[0].multi.is_none());
+        assert_eq!(
+            settings.language[1].multi,
+            Some(vec![("/*".to_string(), "*/".to_string())])
+        );
+        assert!(parse("").unwrap().language.is_empty());
+
+        let settings = parse(
+            "exclude = [\"vendor/**\"]\nsort = \"code\"\noutput = [\"table\", \"json=a.json\"]\n",
+        )
+        .unwrap();
+        assert_eq!(settings.exclude, Some(vec!["vendor/**".to_string()]));
+        assert_eq!(settings.sort.unwrap().into_inner(), "code");
+        assert_eq!(settings.output.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let user = parse(
+            "sort = \"code\"\ninclude = [\"*.rs\"]\n[[language]]\nname = \"A\"\nextensions = []\n",
+        )
+        .unwrap();
+        let project =
+            parse("sort = \"size\"\n[[language]]\nname = \"B\"\nextensions = []\n").unwrap();
+        let settings = user.merge(project);
+        assert_eq!(settings.sort.unwrap().into_inner(), "size");
+        assert_eq!(settings.include, Some(vec!["*.rs".to_string()]));
+        let names = settings.language.iter().map(|l| l.name.as_str());
+        assert_eq!(names.collect::<Vec<&str>>(), ["A", "B"]);
+
+        let user = parse("cache = true\n").unwrap();
+        assert_eq!(user.merge(parse("").unwrap()).cache, Some(true));
+        let user = parse("cache = true\n").unwrap();
+        assert_eq!(
+            user.merge(parse("cache = false\n").unwrap()).cache,
+            Some(false)
+        );
+
+        let user = parse("max_code = 10\n[limits.Rust]\nmax_code = 5\nmax_files = 2\n").unwrap();
+        let project = parse("max_files = 3\n[limits.Rust]\nmax_code = 8\n").unwrap();
+        let settings = user.merge(project);
+        assert_eq!(
+            settings.total_limits(),
+            Limits {
+                max_code: Some(10),
+                max_files: Some(3),
+                max_comment_ratio: None,
+            }
+        );
+        assert_eq!(settings.limits["Rust"].max_code, Some(8));
+        assert_eq!(settings.limits["Rust"].max_files, Some(2));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = |content| parse(content).unwrap_err();
+        let content = "[[language]]\nname = \"Zig\"\nextentions = [\"zig\"]\n";
+        assert!(error(content).starts_with("line 3, key 'extentions': unknown field `extentions`"));
+        let content = "[[language]]\nname = \"Zig\"\nextensions = 1\n";
+        assert!(error(content).starts_with("line 3, key 'extensions': invalid type"));
+        let content = "\n[[language]]\nextensions = [\"zig\"]\n";
+        assert!(error(content).starts_with("line 2, key 'language': missing field `name`"));
+        let content = "[[language]]\nname = \"Zig\n";
+        assert!(error(content).starts_with("line 2, key 'name'"));
+        let content = "exclude = []\nsort = \"codes\"\n";
+        assert!(error(content).starts_with("line 2, key 'sort': expected one of: language,"));
+        let content = "output = [\n  \"table\",\n  \"pdf=a.pdf\",\n]\n";
+        assert!(error(content).starts_with("line 3, key 'output': expected one of"));
+        let content = "[limits.Markdown]\nmax_file = 100\n";
+        assert!(error(content).starts_with("line 2, key 'max_file': unknown field `max_file`"));
+    }
+}
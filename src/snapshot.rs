@@ -0,0 +1,465 @@
+// `--save-snapshot` and `--diff`: the rows of one run, compared with a later one
+use crate::format::{signed, signed_size, utc_datetime, NumberFormat, SizeStyle};
+use crate::output::{box_table, save};
+use crate::{warn, Detail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bumped when the file changes shape
+const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    // The lok that counted, another one may count differently
+    pub rots: String,
+    // Seconds since the epoch
+    pub created: u64,
+    pub paths: Vec<String>,
+    // Flags of the command line that change what is counted
+    pub flags: Vec<String>,
+    // Language, directory or extension, the first column of the table
+    pub column: String,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    pub name: String,
+    pub code: i64,
+    pub comment: i64,
+    pub blank: i64,
+    pub file: i64,
+    pub size: i64,
+}
+
+impl Row {
+    pub fn new(name: &str) -> Row {
+        Row {
+            name: name.to_string(),
+            code: 0,
+            comment: 0,
+            blank: 0,
+            file: 0,
+            size: 0,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.code == 0 && self.comment == 0 && self.blank == 0 && self.file == 0 && self.size == 0
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Changed => "changed",
+        }
+    }
+}
+
+// New minus old, a removed row is all negative
+#[derive(Debug, PartialEq)]
+pub struct Delta {
+    pub status: Status,
+    pub row: Row,
+}
+
+impl Snapshot {
+    pub fn new(details: &[Detail], column: &str, paths: Vec<String>, flags: Vec<String>) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let rows = details
+            .iter()
+            .map(|detail| Row {
+                name: detail.name.clone(),
+                code: detail.code as i64,
+                comment: detail.comment as i64,
+                blank: detail.blank as i64,
+                file: detail.file as i64,
+                size: detail.size as i64,
+            })
+            .collect();
+        Snapshot {
+            version: VERSION,
+            rots: env!("CARGO_PKG_VERSION").to_string(),
+            created,
+            paths,
+            flags,
+            column: column.to_string(),
+            rows,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let content = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        save(path, &content)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read '{}': {}", path.display(), err))?;
+        let value = serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|err| format!("'{}' is not valid json: {}", path.display(), err))?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(version) if version == VERSION as u64 => {}
+            Some(version) => {
+                return Err(format!(
+                    "'{}' is a version {} snapshot, this lok reads version {}",
+                    path.display(),
+                    version,
+                    VERSION
+                ))
+            }
+            None => return Err(format!("'{}' is not a snapshot", path.display())),
+        }
+        serde_json::from_value(value)
+            .map_err(|err| format!("'{}' is not a snapshot: {}", path.display(), err))
+    }
+
+    // What makes the two counts not the same kind of count
+    pub fn mismatches(&self, new: &Snapshot) -> Vec<String> {
+        let mut rst = vec![];
+        if self.rots != new.rots {
+            rst.push(format!(
+                "was counted by lok {}, this is {}",
+                self.rots, new.rots
+            ));
+        }
+        if self.column != new.column {
+            rst.push(format!(
+                "is by {}, this count is by {}",
+                self.column.to_lowercase(),
+                new.column.to_lowercase()
+            ));
+        }
+        if self.paths != new.paths {
+            rst.push(format!("counted '{}'", self.paths.join("', '")));
+        }
+        if self.flags != new.flags {
+            let flags = match self.flags.is_empty() {
+                true => "no flags".to_string(),
+                false => format!("'{}'", self.flags.join(" ")),
+            };
+            rst.push(format!("was counted with {}", flags));
+        }
+        rst
+    }
+}
+
+// Rows in both by their name, then the added and removed ones, biggest change first
+pub fn diff(old: &[Row], new: &[Row]) -> Vec<Delta> {
+    let mut old = old
+        .iter()
+        .map(|row| (row.name.as_str(), row))
+        .collect::<BTreeMap<&str, &Row>>();
+    let mut rst = vec![];
+    for row in new {
+        let delta = match old.remove(row.name.as_str()) {
+            Some(prev) => Delta {
+                status: Status::Changed,
+                row: Row {
+                    name: row.name.clone(),
+                    code: row.code - prev.code,
+                    comment: row.comment - prev.comment,
+                    blank: row.blank - prev.blank,
+                    file: row.file - prev.file,
+                    size: row.size - prev.size,
+                },
+            },
+            None => Delta {
+                status: Status::Added,
+                row: row.clone(),
+            },
+        };
+        if !delta.row.is_zero() {
+            rst.push(delta);
+        }
+    }
+    for (_, row) in old {
+        rst.push(Delta {
+            status: Status::Removed,
+            row: Row {
+                name: row.name.clone(),
+                code: -row.code,
+                comment: -row.comment,
+                blank: -row.blank,
+                file: -row.file,
+                size: -row.size,
+            },
+        });
+    }
+    rst.sort_by(|a, b| {
+        b.row
+            .code
+            .unsigned_abs()
+            .cmp(&a.row.code.unsigned_abs())
+            .then_with(|| a.row.name.cmp(&b.row.name))
+    });
+    rst
+}
+
+fn total(deltas: &[Delta]) -> Row {
+    let mut total = Row::new("Total");
+    for delta in deltas {
+        total.code += delta.row.code;
+        total.comment += delta.row.comment;
+        total.blank += delta.row.blank;
+        total.file += delta.row.file;
+        total.size += delta.row.size;
+    }
+    total
+}
+
+pub fn table(
+    old: &Snapshot,
+    deltas: &[Delta],
+    number: NumberFormat,
+    size: SizeStyle,
+    width: usize,
+) -> Vec<String> {
+    let title = format!("Since {}, lok {}", utc_datetime(old.created), old.rots);
+    delta_table(&title, &old.column, deltas, number, size, width)
+}
+
+// Signed numbers in columns, `lok --git-diff` prints the same table. Names are
+// cut to fit `width`
+pub fn delta_table(
+    title: &str,
+    column: &str,
+    deltas: &[Delta],
+    number: NumberFormat,
+    size: SizeStyle,
+    width: usize,
+) -> Vec<String> {
+    let separator = number.separator();
+    let cells = |status: &str, row: &Row| {
+        vec![
+            status.to_string(),
+            row.name.clone(),
+            signed(row.code, separator),
+            signed(row.comment, separator),
+            signed(row.blank, separator),
+            signed(row.file, separator),
+            signed_size(row.size, size),
+        ]
+    };
+    let header = ["Status", column, "Code", "Comment", "Blank", "File", "Size"].map(String::from);
+    let rows = deltas
+        .iter()
+        .map(|delta| cells(delta.status.as_str(), &delta.row))
+        .collect::<Vec<Vec<String>>>();
+    let total = cells("", &total(deltas));
+
+    let mut data = vec![title.to_string()];
+    data.extend(box_table(&header, &rows, Some(&total), 2, 1, width));
+    if deltas.is_empty() {
+        data.push("No changes".to_string());
+    }
+    data
+}
+
+// `-o json` of the deltas, the rows are named by the first column
+#[derive(Serialize)]
+struct JsonDeltas<'a> {
+    // What the changes are compared with, `snapshot` or `range`
+    #[serde(flatten)]
+    head: BTreeMap<&'a str, serde_json::Value>,
+    changes: Vec<JsonDelta<'a>>,
+    total: Counts,
+}
+
+#[derive(Serialize)]
+struct JsonDelta<'a> {
+    #[serde(flatten)]
+    name: BTreeMap<String, &'a str>,
+    status: &'a Status,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+// A `Row` without its name
+#[derive(Serialize)]
+struct Counts {
+    code: i64,
+    comment: i64,
+    blank: i64,
+    file: i64,
+    size: i64,
+}
+
+impl From<&Row> for Counts {
+    fn from(row: &Row) -> Self {
+        Counts {
+            code: row.code,
+            comment: row.comment,
+            blank: row.blank,
+            file: row.file,
+            size: row.size,
+        }
+    }
+}
+
+pub fn json(old: &Snapshot, deltas: &[Delta]) -> Vec<String> {
+    let head = serde_json::json!({ "rots": old.rots, "created": old.created });
+    delta_json(("snapshot", head), &old.column, deltas)
+}
+
+// `head` is a field before the changes, what they are compared with
+pub fn delta_json(head: (&str, serde_json::Value), column: &str, deltas: &[Delta]) -> Vec<String> {
+    let key = column.to_lowercase();
+    let report = JsonDeltas {
+        head: BTreeMap::from([head]),
+        changes: deltas
+            .iter()
+            .map(|delta| JsonDelta {
+                name: BTreeMap::from([(key.clone(), delta.row.name.as_str())]),
+                status: &delta.status,
+                counts: Counts::from(&delta.row),
+            })
+            .collect(),
+        total: Counts::from(&total(deltas)),
+    };
+    vec![serde_json::to_string_pretty(&report).unwrap()]
+}
+
+pub fn warn_mismatches(path: &Path, old: &Snapshot, new: &Snapshot) {
+    for mismatch in old.mismatches(new) {
+        warn!("'{}' {}", path.display(), mismatch);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::text::display_width;
+
+    fn row(name: &str, code: i64, size: i64) -> Row {
+        Row {
+            code,
+            file: 1,
+            size,
+            ..Row::new(name)
+        }
+    }
+
+    fn snapshot(rows: Vec<Row>) -> Snapshot {
+        Snapshot {
+            version: VERSION,
+            rots: "0.1.4".to_string(),
+            created: 0,
+            paths: vec![".".to_string()],
+            flags: vec![],
+            column: "Language".to_string(),
+            rows,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = [row("Rust", 10, 100), row("Go", 5, 50), row("C", 1, 10)];
+        let new = [row("Rust", 12, 90), row("C", 1, 10), row("Zig", 30, 300)];
+        let summary = diff(&old, &new)
+            .into_iter()
+            .map(|d| (d.status.as_str(), d.row.name, d.row.code, d.row.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("added", "Zig".to_string(), 30, 300),
+                ("removed", "Go".to_string(), -5, -50),
+                ("changed", "Rust".to_string(), 2, -10),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let old = snapshot(vec![]);
+        let deltas = diff(&[row("Go", 1500, 2048)], &[row("Rust", 3, 10)]);
+        let data = table(&old, &deltas, NumberFormat::Comma, SizeStyle::default(), 80);
+        assert_eq!(data[0], "Since 1970-01-01 00:00:00 UTC, lok 0.1.4");
+        assert_eq!(
+            &data[1..],
+            [
+                "╭────────────────────────────────────────────────────────────╮",
+                "│ Status   Language    Code  Comment  Blank  File       Size │",
+                "├────────────────────────────────────────────────────────────┤",
+                "│ removed  Go        -1,500        0      0    -1  -2.00 KiB │",
+                "│ added    Rust          +3        0      0    +1      +10 B │",
+                "├────────────────────────────────────────────────────────────┤",
+                "│          Total     -1,497        0      0     0  -1.99 KiB │",
+                "╰────────────────────────────────────────────────────────────╯",
+            ]
+        );
+        assert_eq!(
+            table(&old, &[], NumberFormat::Comma, SizeStyle::default(), 80)
+                .last()
+                .unwrap(),
+            "No changes"
+        );
+
+        // Two columns a character, the name gives way on a narrow terminal
+        let deltas = diff(&[], &[row("中文注释语言名", 3, 10)]);
+        let widths = |data: &[String]| {
+            let widths = data[1..].iter().map(|line| display_width(line));
+            widths.collect::<Vec<usize>>()
+        };
+        let data = table(&old, &deltas, NumberFormat::Comma, SizeStyle::default(), 80);
+        assert!(widths(&data).iter().all(|w| *w == 61), "{:#?}", data);
+        assert!(
+            data[4].starts_with("│ added   中文注释语言名  "),
+            "{:#?}",
+            data
+        );
+        let data = table(&old, &deltas, NumberFormat::Comma, SizeStyle::default(), 40);
+        assert!(widths(&data).iter().all(|w| *w == 53), "{:#?}", data);
+        assert!(data[4].starts_with("│ added   中文…   "), "{:#?}", data);
+    }
+
+    #[test]
+    fn test_json() {
+        let old = snapshot(vec![]);
+        let deltas = diff(&[row("Go", 5, 50)], &[row("Rust", 3, 10)]);
+        let value: serde_json::Value =
+            serde_json::from_str(&json(&old, &deltas).join("\n")).unwrap();
+        assert_eq!(value["changes"][0]["language"], "Go");
+        assert_eq!(value["changes"][0]["status"], "removed");
+        assert_eq!(value["changes"][0]["code"], -5);
+        assert_eq!(value["total"]["size"], -40);
+        assert_eq!(value["snapshot"]["rots"], old.rots);
+        let value: serde_json::Value = serde_json::from_str(&json(&old, &[]).join("\n")).unwrap();
+        assert_eq!(value["changes"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let old = snapshot(vec![]);
+        let mut new = snapshot(vec![]);
+        assert!(old.mismatches(&new).is_empty());
+        new.rots = "0.2.0".to_string();
+        new.flags = vec!["--hidden".to_string()];
+        assert_eq!(
+            old.mismatches(&new),
+            [
+                "was counted by lok 0.1.4, this is 0.2.0",
+                "was counted with no flags"
+            ]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+// What the generic single and multi tokens can't tell: string literals that hide
+// comment tokens and run over several lines, docstrings, POD, long brackets, heredocs
+use crate::config::Syntax;
+use crate::parse::Kind;
+
+// A literal still open at the end of a line, its next lines are code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Open {
+    // `"...`, ends at a `"` that isn't escaped
+    Quoted,
+    // `r##"...`, ends at `"##` and knows no escapes
+    Raw(usize),
+    // Python `'''` or `"""`, its lines are comment when it is a docstring
+    Triple { quote: u8, doc: bool },
+    // Perl documentation, up to a `=cut` line
+    Pod,
+    // Lua `[==[`, a comment after `--`, ends at `]==]` with as many `=`
+    Long { level: usize, comment: bool },
+}
+
+// Carried from one line to the next
+#[derive(Debug, Default)]
+pub struct State {
+    open: Option<Open>,
+    // Python, the next statement starts a module, class or function body
+    body: bool,
+    // Python, a `def` or `class` whose `:` hasn't come yet
+    header: bool,
+    // Heredocs of the last code line, read one after another
+    heredocs: Vec<Heredoc>,
+}
+
+// `<<EOF`, its lines are code up to the one that is `EOF`
+#[derive(Debug, PartialEq)]
+struct Heredoc {
+    word: String,
+    // `<<-EOF` or `<<~EOF`, the end may be indented
+    indented: bool,
+}
+
+impl State {
+    pub fn new(syntax: Option<Syntax>) -> Self {
+        Self {
+            // The module docstring
+            body: syntax == Some(Syntax::Python),
+            ..Self::default()
+        }
+    }
+}
+
+// The kind of a line the tokens shouldn't decide, inside a literal or a docstring.
+// `raw` isn't trimmed, POD only starts unindented
+pub fn before(syntax: Syntax, raw: &str, state: &mut State) -> Option<Kind> {
+    let line = raw.trim();
+    // Data, even a line starting with `#` or a blank one
+    if let Some(heredoc) = state.heredocs.first() {
+        let text = match (heredoc.indented, syntax) {
+            // `<<-` only strips tabs
+            (true, Syntax::Shell) => raw.trim_start_matches('\t'),
+            (true, _) => raw.trim_start(),
+            (false, _) => raw,
+        };
+        let ends = match syntax {
+            // `EOF;` or `EOF)`, the end of the statement may follow
+            Syntax::Php => text
+                .strip_prefix(heredoc.word.as_str())
+                .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')),
+            _ => text.trim_end() == heredoc.word,
+        };
+        if ends {
+            state.heredocs.remove(0);
+        }
+        return Some(Kind::Code);
+    }
+    match (syntax, state.open) {
+        (Syntax::Rust, Some(open)) => {
+            state.open = rust(line, Some(open));
+            Some(Kind::Code)
+        }
+        (Syntax::Python, Some(Open::Triple { quote, doc })) => {
+            if let Some(end) = closing(line.as_bytes(), quote) {
+                state.open = match doc {
+                    true => None,
+                    false => python(&line[end..]),
+                };
+            }
+            Some(if doc { Kind::Comment } else { Kind::Code })
+        }
+        (Syntax::Python, _) => {
+            let (quote, start) = triple_start(line)?;
+            if !state.body {
+                return None;
+            }
+            state.body = false;
+            if closing(&line.as_bytes()[start..], quote).is_none() {
+                state.open = Some(Open::Triple { quote, doc: true });
+            }
+            Some(Kind::Comment)
+        }
+        (Syntax::Perl, Some(_)) => {
+            if pod_command(raw) == Some("cut") {
+                state.open = None;
+            }
+            Some(if line.is_empty() {
+                Kind::Blank
+            } else {
+                Kind::Comment
+            })
+        }
+        (Syntax::Perl, None) => {
+            if pod_command(raw)? != "cut" {
+                state.open = Some(Open::Pod);
+            }
+            Some(Kind::Comment)
+        }
+        (Syntax::Lua, Some(Open::Long { level, comment })) => {
+            if let Some(end) = long_end(line.as_bytes(), level) {
+                state.open = match comment {
+                    true => None,
+                    false => lua(&line[end..]),
+                };
+            }
+            Some(if comment { Kind::Comment } else { Kind::Code })
+        }
+        (Syntax::Lua, _) => {
+            long_start(line.strip_prefix("--")?.as_bytes())?;
+            // `--[[ a ]] b()` is counted as a comment, like `/* a */ b()`
+            state.open = lua(line);
+            Some(Kind::Comment)
+        }
+        _ => None,
+    }
+}
+
+// What a code line leaves open
+pub fn after(syntax: Syntax, line: &str, state: &mut State) {
+    state.open = match syntax {
+        Syntax::Rust => rust(line, None),
+        Syntax::Python => {
+            let statement = line.trim_start_matches("async ");
+            state.header |= statement.starts_with("def ") || statement.starts_with("class ");
+            let open = python(line);
+            let end = code_end(line);
+            state.body = open.is_none() && state.header && end.ends_with(':');
+            // `def f(a,` goes on, `def f(): pass` has no docstring
+            state.header = !state.body && state.header && end.ends_with(['(', '[', ',', '\\']);
+            open
+        }
+        Syntax::Lua => lua(line),
+        Syntax::Shell | Syntax::Ruby | Syntax::Perl | Syntax::Php => {
+            state.heredocs = heredocs(syntax, line);
+            None
+        }
+    }
+}
+
+fn rust(line: &str, mut open: Option<Open>) -> Option<Open> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match open {
+            Some(Open::Raw(hashes)) => {
+                if bytes[i] == b'"' && closes(&bytes[i + 1..], hashes) {
+                    open = None;
+                    i += 1 + hashes;
+                } else {
+                    i += 1;
+                }
+            }
+            Some(_) => match bytes[i] {
+                b'\\' => i += 2,
+                b'"' => {
+                    open = None;
+                    i += 1;
+                }
+                _ => i += 1,
+            },
+            None => {
+                let rest = &bytes[i..];
+                // What follows is a comment, or a block comment ends on this line
+                if rest.starts_with(b"//") {
+                    break;
+                }
+                if rest.starts_with(b"/*") {
+                    match line[i + 2..].find("*/") {
+                        Some(end) => i += 2 + end + 2,
+                        None => break,
+                    }
+                    continue;
+                }
+                // Not the end of an identifier like `bar"`, which isn't Rust anyway
+                let word = i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_');
+                match rest {
+                    [b'"', ..] => {
+                        open = Some(Open::Quoted);
+                        i += 1;
+                    }
+                    [b'b' | b'c', b'r', ..] | [b'r', ..] if !word => {
+                        let prefix = if rest[0] == b'r' { 1 } else { 2 };
+                        let hashes = rest[prefix..].iter().take_while(|b| **b == b'#').count();
+                        if rest.get(prefix + hashes) == Some(&b'"') {
+                            open = Some(Open::Raw(hashes));
+                            i += prefix + hashes + 1;
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    [b'\'', ..] => i += char_literal(rest),
+                    _ => i += 1,
+                }
+            }
+        }
+    }
+    open
+}
+
+fn closes(after: &[u8], hashes: usize) -> bool {
+    after.len() >= hashes && after[..hashes].iter().all(|b| *b == b'#')
+}
+
+// `'"'` and `'\''` are skipped whole, a lifetime `'a` only by its quote
+fn char_literal(rest: &[u8]) -> usize {
+    match rest {
+        [b'\'', b'\\', ..] => rest[2..]
+            .iter()
+            .position(|b| *b == b'\'')
+            .map_or(1, |end| end + 3),
+        [b'\'', _, b'\'', ..] => 3,
+        _ => {
+            // A char that is more than one byte in UTF-8
+            let text = String::from_utf8_lossy(&rest[1..]);
+            match text.chars().next() {
+                Some(c) if rest[1..].get(c.len_utf8()) == Some(&b'\'') => 2 + c.len_utf8(),
+                _ => 1,
+            }
+        }
+    }
+}
+
+// A line that is a string statement, `"""`, `r'''` or `f"""`, and where its
+// text starts
+fn triple_start(line: &str) -> Option<(u8, usize)> {
+    let prefix = line.bytes().take_while(|b| b"rRuUfFbB".contains(b)).count();
+    let rest = &line.as_bytes()[prefix..];
+    match rest {
+        [quote @ (b'"' | b'\''), ..] if prefix <= 2 && rest.starts_with(&[*quote; 3]) => {
+            Some((*quote, prefix + 3))
+        }
+        _ => None,
+    }
+}
+
+// Past the three quotes that end a triple-quoted string
+fn closing(bytes: &[u8], quote: u8) -> Option<usize> {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i..].starts_with(&[quote; 3]) {
+            return Some(i + 3);
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+// The triple-quoted string a code line leaves open, `'...'` ends on its line
+fn python(line: &str) -> Option<Open> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => break,
+            quote @ (b'"' | b'\'') => {
+                if bytes[i..].starts_with(&[quote; 3]) {
+                    match closing(&bytes[i + 3..], quote) {
+                        Some(end) => i += 3 + end,
+                        None => return Some(Open::Triple { quote, doc: false }),
+                    }
+                } else {
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != quote {
+                        i += if bytes[i] == b'\\' { 2 } else { 1 };
+                    }
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// A line without its trailing `# comment`, strings aside
+fn code_end(line: &str) -> &str {
+    match line.find('#') {
+        Some(at) if !line[..at].contains(['"', '\'']) => line[..at].trim_end(),
+        _ => line,
+    }
+}
+
+// The `=` of `[==[`, if `bytes` starts with one
+fn long_start(bytes: &[u8]) -> Option<usize> {
+    let level = bytes.get(1..)?.iter().take_while(|b| **b == b'=').count();
+    match (bytes.first(), bytes.get(1 + level)) {
+        (Some(b'['), Some(b'[')) => Some(level),
+        _ => None,
+    }
+}
+
+// Past the `]==]` of `level`
+fn long_end(bytes: &[u8], level: usize) -> Option<usize> {
+    let mut close = vec![b'='; level + 2];
+    close[0] = b']';
+    close[level + 1] = b']';
+    bytes
+        .windows(close.len())
+        .position(|window| window == close)
+        .map(|at| at + close.len())
+}
+
+// The long string or comment a Lua line leaves open
+fn lua(line: &str) -> Option<Open> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let comment = bytes[i..].starts_with(b"--");
+        let at = if comment { i + 2 } else { i };
+        if let Some(level) = long_start(&bytes[at..]) {
+            match long_end(&bytes[at + level + 2..], level) {
+                Some(end) => i = at + level + 2 + end,
+                None => return Some(Open::Long { level, comment }),
+            }
+            continue;
+        }
+        match bytes[i] {
+            _ if comment => break,
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// `<<EOF`, `<<-'EOF'` or `<<~"EOF"` in Shell, Ruby and Perl, `<<<EOF` in PHP.
+// Quoted strings, Shell arithmetic and a trailing comment are passed over
+fn heredocs(syntax: Syntax, line: &str) -> Vec<Heredoc> {
+    let bytes = line.as_bytes();
+    let mut rst = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' if bytes[i..].starts_with(b"<<") => {
+                let (heredoc, used) = heredoc(syntax, &line[i..]);
+                rst.extend(heredoc);
+                i += used;
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            // `$(( a << b ))` shifts
+            b'(' if syntax == Syntax::Shell && bytes[i..].starts_with(b"((") => {
+                match line[i + 2..].find("))") {
+                    Some(end) => i += end + 4,
+                    None => break,
+                }
+            }
+            // Not `$#array` or `${#name}`
+            b'#' if i == 0 || bytes[i - 1].is_ascii_whitespace() => break,
+            b'/' if syntax == Syntax::Php && bytes[i..].starts_with(b"//") => break,
+            b'/' if syntax == Syntax::Php && bytes[i..].starts_with(b"/*") => {
+                match line[i + 2..].find("*/") {
+                    Some(end) => i += end + 4,
+                    None => break,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    rst
+}
+
+// The heredoc `rest` starts with, if any, and how much of it was read
+fn heredoc(syntax: Syntax, rest: &str) -> (Option<Heredoc>, usize) {
+    let (arrows, flags, spaces): (&str, &[char], bool) = match syntax {
+        Syntax::Php => ("<<<", &[], true),
+        Syntax::Shell => ("<<", &['-'], true),
+        Syntax::Ruby => ("<<", &['-', '~'], false),
+        _ => ("<<", &['~'], false),
+    };
+    let text = match rest.strip_prefix(arrows) {
+        // `<<<` is a here-string in Bash
+        Some(text) if !text.starts_with('<') => text,
+        _ => return (None, rest.len().min(arrows.len())),
+    };
+    let flag = text.starts_with(flags);
+    let text = if flag { &text[1..] } else { text };
+    let quoted = text.trim_start().starts_with(['"', '\'']);
+    // `1 << 2`, only a quoted word in Perl and Ruby may follow a space
+    let text = match spaces || quoted {
+        true => text.trim_start(),
+        false => text,
+    };
+    let quote = text.starts_with(['"', '\'']).then(|| &text[..1]);
+    let word_at = quote.map_or(0, str::len);
+    let len = text[word_at..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len() - word_at);
+    let word = &text[word_at..word_at + len];
+    let closed = quote.is_none_or(|quote| text[word_at + len..].starts_with(quote));
+    // `a <<b` in Ruby is a call, its heredocs go by capitals
+    let starts = match syntax {
+        Syntax::Ruby if quote.is_none() => word.starts_with(|c: char| c.is_ascii_uppercase()),
+        _ => word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'),
+    };
+    let used = rest.len() - text.len() + word_at + len + quote.map_or(0, str::len);
+    match starts && closed {
+        true => (
+            Some(Heredoc {
+                word: word.to_string(),
+                indented: flag || syntax == Syntax::Php,
+            }),
+            used,
+        ),
+        false => (None, arrows.len()),
+    }
+}
+
+// `=head1 NAME` starts POD, `=cut` ends it, `= 1` is neither
+fn pod_command(raw: &str) -> Option<&str> {
+    let rest = raw.strip_prefix('=')?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    match rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => Some(&rest[..end]),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rust() {
+        let scan = |line| rust(line, None);
+        assert_eq!(scan(r#"let a = "text";"#), None);
+        assert_eq!(scan(r#"let a = "a \" b"#), Some(Open::Quoted));
+        assert_eq!(scan(r##"let re = r#"// not "a" comment"#;"##), None);
+        assert_eq!(scan(r###"let sql = br##"select"###), Some(Open::Raw(2)));
+        assert_eq!(rust(r###"end"# "##;"###, Some(Open::Raw(2))), None);
+        assert_eq!(rust(r#"end"#, Some(Open::Raw(1))), Some(Open::Raw(1)));
+        // Quotes in comments and char literals don't open a string
+        assert_eq!(scan(r#"let q = '"'; // "quoted"#), None);
+        assert_eq!(scan(r#"f('\'', "x"); /* " */ g()"#), None);
+        assert_eq!(scan(r#"fn a<'a>(s: &'a str) -> &'a str { "#), None);
+        assert_eq!(scan(r#"let bar = for"x"#), Some(Open::Quoted));
+    }
+
+    #[test]
+    fn test_python() {
+        assert_eq!(triple_start(r#""""Doc""#), Some((b'"', 3)));
+        assert_eq!(triple_start("rb'''x"), Some((b'\'', 5)));
+        assert_eq!(triple_start("'a'"), None);
+        assert_eq!(triple_start("query = '''"), None);
+        assert_eq!(closing(br#"a \""" b""" c"#, b'"'), Some(11));
+
+        assert_eq!(python("x = 1  # '''"), None);
+        let open = Open::Triple {
+            quote: b'"',
+            doc: false,
+        };
+        assert_eq!(python(r#"a = "'''" + f""""#), Some(open));
+        assert_eq!(python("s = '''one''' + 'two'"), None);
+        assert_eq!(code_end("def f():  # note"), "def f():");
+    }
+
+    #[test]
+    fn test_lua() {
+        assert_eq!(long_start(b"[==[ a"), Some(2));
+        assert_eq!(long_start(b"[ [a]"), None);
+        assert_eq!(long_end(b"a ]] ]==] b", 2), Some(9));
+        assert_eq!(
+            lua("local s = [[ -- not a comment ]] -- a comment [["),
+            None
+        );
+        assert_eq!(
+            lua("x = 1 --[=[ to the next lines"),
+            Some(Open::Long {
+                level: 1,
+                comment: true
+            })
+        );
+        assert_eq!(
+            lua("local s = '[[' .. [==["),
+            Some(Open::Long {
+                level: 2,
+                comment: false
+            })
+        );
+        assert_eq!(lua("t[a[1]] = 2"), None);
+    }
+
+    #[test]
+    fn test_heredocs() {
+        let words = |syntax, line| {
+            heredocs(syntax, line)
+                .into_iter()
+                .map(|heredoc| (heredoc.word, heredoc.indented))
+                .collect::<Vec<_>>()
+        };
+        let word = |word: &str, indented| vec![(word.to_string(), indented)];
+        assert_eq!(
+            words(Syntax::Shell, "cat <<EOF > a.yml"),
+            word("EOF", false)
+        );
+        assert_eq!(words(Syntax::Shell, "cat <<-'END' | sh"), word("END", true));
+        assert_eq!(words(Syntax::Shell, "cat << \"EOF\""), word("EOF", false));
+        assert_eq!(words(Syntax::Shell, "grep a <<< \"$b\""), []);
+        assert_eq!(words(Syntax::Shell, "echo '<<EOF' # <<END"), []);
+        assert_eq!(words(Syntax::Shell, "f <<A <<B").len(), 2);
+        assert_eq!(words(Syntax::Ruby, "sql = <<~SQL.strip"), word("SQL", true));
+        assert_eq!(words(Syntax::Ruby, "list <<item"), []);
+        assert_eq!(words(Syntax::Perl, "print <<\"EOF\";"), word("EOF", false));
+        assert_eq!(words(Syntax::Perl, "my $x = 1 << 2;"), []);
+        assert_eq!(words(Syntax::Php, "$a = <<<'EOT'"), word("EOT", true));
+        assert_eq!(words(Syntax::Php, "$a = $b << 2;"), []);
+    }
+
+    #[test]
+    fn test_pod_command() {
+        assert_eq!(pod_command("=head1 NAME"), Some("head1"));
+        assert_eq!(pod_command("=cut"), Some("cut"));
+        assert_eq!(pod_command("= 1;"), None);
+        assert_eq!(pod_command("=1"), None);
+        assert_eq!(pod_command(" =pod"), None);
+    }
+}
@@ -0,0 +1,192 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const ELLIPSIS: &str = "…";
+
+// Number of terminal columns, CJK and emoji take two, escape codes none
+pub fn display_width(s: &str) -> usize {
+    if s.contains('\x1b') {
+        UnicodeWidthStr::width(strip_ansi(s).as_str())
+    } else {
+        UnicodeWidthStr::width(s)
+    }
+}
+
+// Drop `ESC [ ... m` style sequences
+pub fn strip_ansi(s: &str) -> String {
+    let mut rst = String::new();
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            rst.push(ch);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            // Parameters up to the final byte
+            for ch in chars.by_ref() {
+                if ('@'..='~').contains(&ch) {
+                    break;
+                }
+            }
+        }
+    }
+    rst
+}
+
+// Fit into `width` columns, cut on grapheme boundaries and end with an ellipsis
+pub fn truncate_display(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let limit = width - display_width(ELLIPSIS);
+    let mut used = 0;
+    let mut rst = String::new();
+    for grapheme in s.graphemes(true) {
+        let w = display_width(grapheme);
+        if used + w > limit {
+            break;
+        }
+        used += w;
+        rst.push_str(grapheme);
+    }
+    rst.push_str(ELLIPSIS);
+    rst
+}
+
+// Same as `truncate_display` but keep the end, for paths the file name matters most
+pub fn truncate_start(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let limit = width - display_width(ELLIPSIS);
+    let mut used = 0;
+    let mut graphemes = Vec::new();
+    for grapheme in s.graphemes(true).rev() {
+        let w = display_width(grapheme);
+        if used + w > limit {
+            break;
+        }
+        used += w;
+        graphemes.push(grapheme);
+    }
+    graphemes.push(ELLIPSIS);
+    graphemes.into_iter().rev().collect()
+}
+
+// Left-align in `width` columns, longer strings are left as is
+pub fn pad_end(s: &str, width: usize) -> String {
+    let fill = width.saturating_sub(display_width(s));
+    format!("{}{}", s, " ".repeat(fill))
+}
+
+// Right-align in `width` columns
+pub fn pad_start(s: &str, width: usize) -> String {
+    let fill = width.saturating_sub(display_width(s));
+    format!("{}{}", " ".repeat(fill), s)
+}
+
+// Levenshtein distance over chars, for "did you mean" hints
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut prev = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            row.push((prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("Rust"), 4);
+        assert_eq!(display_width("中文"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\x1b[1;36m中文\x1b[0m"), 4);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("Rust"), "Rust");
+        assert_eq!(strip_ansi("\x1b[1mTotal\x1b[0m"), "Total");
+        assert_eq!(strip_ansi("\x1b[38;2;1;2;3mR\x1b[0must"), "Rust");
+    }
+
+    #[test]
+    fn test_truncate_display() {
+        // Exact fit and shorter strings are untouched
+        assert_eq!(truncate_display("Rust", 4), "Rust");
+        assert_eq!(truncate_display("Rust", 10), "Rust");
+        assert_eq!(truncate_display("", 0), "");
+
+        assert_eq!(truncate_display("TypeScript JSX", 8), "TypeScr…");
+        assert_eq!(truncate_display("Rust", 0), "");
+        assert_eq!(truncate_display("Rust", 1), "…");
+        assert_eq!(truncate_display("Rust", 2), "R…");
+    }
+
+    #[test]
+    fn test_truncate_display_wide() {
+        // A wide character never gets split, padding is left to the caller
+        assert_eq!(truncate_display("中文注释", 5), "中文…");
+        assert_eq!(truncate_display("中文注释", 4), "中…");
+        assert_eq!(truncate_display("中文注释", 2), "…");
+        assert_eq!(display_width(&truncate_display("中文注释", 6)), 5);
+    }
+
+    #[test]
+    fn test_truncate_display_graphemes() {
+        // Combining marks stay with their base character
+        assert_eq!(truncate_display("e\u{301}e\u{301}e\u{301}", 2), "e\u{301}…");
+        // Emoji are two columns wide
+        assert_eq!(truncate_display("👍👍👍", 5), "👍👍…");
+        assert_eq!(truncate_display("👍👍👍", 4), "👍…");
+        // Family emoji joined with ZWJ is a single grapheme
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(truncate_display(&format!("{}abc", family), 2), "…");
+    }
+
+    #[test]
+    fn test_truncate_start() {
+        assert_eq!(truncate_start("src/main.rs", 11), "src/main.rs");
+        assert_eq!(truncate_start("./src/parse/mod.rs", 11), "…rse/mod.rs");
+        assert_eq!(truncate_start("./src/中文.rs", 8), "…中文.rs");
+        assert_eq!(truncate_start("./src/中文.rs", 7), "…文.rs");
+        assert_eq!(truncate_start("main.rs", 1), "…");
+        assert_eq!(truncate_start("main.rs", 0), "");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("rust", "rust"), 0);
+        assert_eq!(edit_distance("rust", ""), 4);
+        assert_eq!(edit_distance("rust", "ruts"), 2);
+        assert_eq!(edit_distance("pyton", "python"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_pad() {
+        assert_eq!(pad_end("Rust", 6), "Rust  ");
+        assert_eq!(pad_start("Rust", 6), "  Rust");
+        assert_eq!(pad_end("Rust", 2), "Rust");
+        assert_eq!(pad_start("1\u{2009}234", 6), " 1\u{2009}234");
+    }
+}
@@ -0,0 +1,452 @@
+// `--tui`: browse the languages and their files in the terminal, with the same
+// counting as the batch mode. Only on unix, elsewhere the table is printed
+use crate::format::{self, NumberFormat, SizeStyle};
+use crate::parse::Data;
+use crate::text::{pad_end, pad_start, truncate_display, truncate_start};
+use crate::{sort_detail, Detail, Order, Sort};
+
+const COLUMNS: [&str; 6] = ["Code", "Comment", "Blank", "Files", "Size", "Lines"];
+const HELP: &str = "↑↓ move  enter open  esc back  s sort  r reverse  1-6 columns  q quit";
+// Title, column names and the help line
+const CHROME: usize = 3;
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Back,
+    Sort,
+    Reverse,
+    Toggle(usize),
+    Quit,
+    Other,
+}
+
+// What one read from the terminal means, arrow keys come as `ESC [ A`
+fn key(bytes: &[u8]) -> Key {
+    match bytes {
+        b"\x1b[A" | b"\x1bOA" | b"k" => Key::Up,
+        b"\x1b[B" | b"\x1bOB" | b"j" => Key::Down,
+        b"\x1b[5~" => Key::PageUp,
+        b"\x1b[6~" | b" " => Key::PageDown,
+        b"\x1b[H" | b"\x1b[1~" | b"g" => Key::Home,
+        b"\x1b[F" | b"\x1b[4~" | b"G" => Key::End,
+        b"\r" | b"\n" | b"\x1b[C" | b"l" => Key::Enter,
+        b"\x1b" | b"\x7f" | b"\x1b[D" | b"h" => Key::Back,
+        b"s" => Key::Sort,
+        b"r" => Key::Reverse,
+        [n @ b'1'..=b'6'] => Key::Toggle((n - b'1') as usize),
+        b"q" | b"\x03" => Key::Quit,
+        _ => Key::Other,
+    }
+}
+
+fn next_sort(sort: &Sort) -> Sort {
+    match sort {
+        Sort::Language => Sort::Code,
+        Sort::Code => Sort::Comment,
+        Sort::Comment => Sort::Blank,
+        Sort::Blank => Sort::File,
+        Sort::File => Sort::Size,
+        Sort::Size => Sort::Lines,
+        Sort::Lines => Sort::Language,
+    }
+}
+
+// The files of a language have a path instead of a name and no file count
+fn sort_name(sort: &Sort, files: bool) -> &'static str {
+    match sort {
+        Sort::Language | Sort::File if files => "path",
+        Sort::Language => "language",
+        Sort::Code => "code",
+        Sort::Comment => "comment",
+        Sort::Blank => "blank",
+        Sort::File => "files",
+        Sort::Size => "size",
+        Sort::Lines => "lines",
+    }
+}
+
+struct State {
+    details: Vec<Detail>,
+    sort: Sort,
+    order: Order,
+    // The language whose files are shown
+    open: Option<usize>,
+    selected: usize,
+    // First visible row
+    scroll: usize,
+    hidden: [bool; COLUMNS.len()],
+    number: NumberFormat,
+    size: SizeStyle,
+}
+
+impl State {
+    fn new(details: Vec<Detail>, sort: Sort, order: Order) -> Self {
+        Self {
+            details: sort_detail(details, &sort, order),
+            sort,
+            order,
+            open: None,
+            selected: 0,
+            scroll: 0,
+            hidden: [false; COLUMNS.len()],
+            number: NumberFormat::default(),
+            size: SizeStyle::default(),
+        }
+    }
+
+    fn rows(&self) -> usize {
+        match self.open {
+            Some(i) => self.details[i].files.len(),
+            None => self.details.len(),
+        }
+    }
+
+    // Applies a key, false once it's time to quit
+    fn handle(&mut self, key: Key, height: usize) -> bool {
+        let page = height.saturating_sub(CHROME).max(1);
+        let last = self.rows().saturating_sub(1);
+        match key {
+            Key::Quit => return false,
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected = (self.selected + 1).min(last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(page),
+            Key::PageDown => self.selected = (self.selected + page).min(last),
+            Key::Home => self.selected = 0,
+            Key::End => self.selected = last,
+            Key::Enter if self.open.is_none() && !self.details.is_empty() => {
+                self.open = Some(self.selected);
+                self.selected = 0;
+                self.scroll = 0;
+            }
+            Key::Back => {
+                if let Some(i) = self.open.take() {
+                    self.selected = i;
+                }
+            }
+            Key::Sort => {
+                let sort = next_sort(&self.sort);
+                self.order = sort.default_order();
+                self.resort(sort);
+            }
+            Key::Reverse => {
+                self.order = match self.order {
+                    Order::Asc => Order::Desc,
+                    Order::Desc => Order::Asc,
+                };
+                let sort = std::mem::take(&mut self.sort);
+                self.resort(sort);
+            }
+            Key::Toggle(i) => self.hidden[i] = !self.hidden[i],
+            _ => {}
+        }
+        true
+    }
+
+    // The open language stays open, the selection starts over
+    fn resort(&mut self, sort: Sort) {
+        let open = self.open.map(|i| self.details[i].name.clone());
+        self.details = sort_detail(std::mem::take(&mut self.details), &sort, self.order);
+        self.sort = sort;
+        self.open = open.and_then(|name| self.details.iter().position(|d| d.name == name));
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+        let files = self.open.is_some();
+        // No file count for a single file
+        let columns = (0..COLUMNS.len())
+            .filter(|i| !(self.hidden[*i] || files && COLUMNS[*i] == "Files"))
+            .collect::<Vec<usize>>();
+        let cell = |i: usize| if COLUMNS[i] == "Size" { 12 } else { 9 };
+        let numbers = columns.iter().map(|i| cell(*i) + 2).sum::<usize>();
+        let name_width = width.saturating_sub(numbers + 1).max(8);
+
+        let arrow = match self.order {
+            Order::Asc => "↑",
+            Order::Desc => "↓",
+        };
+        let title = match self.open {
+            Some(i) => format!(
+                " {}, {} files",
+                self.details[i].name,
+                format::group_digits(self.details[i].file, self.number.separator())
+            ),
+            None => format!(" {} languages", self.details.len()),
+        };
+        let title = format!(
+            "{}  sorted by {} {}",
+            title,
+            sort_name(&self.sort, files),
+            arrow
+        );
+        let mut header = pad_end(if files { " Path" } else { " Language" }, name_width);
+        for i in &columns {
+            header.push_str(&pad_start(COLUMNS[*i], cell(*i) + 2));
+        }
+
+        let values = |code: u64, comment: u64, blank: u64, file: u64, size: u64| {
+            let separator = self.number.separator();
+            columns
+                .iter()
+                .map(|i| {
+                    let value = match COLUMNS[*i] {
+                        "Code" => format::group_digits(code, separator),
+                        "Comment" => format::group_digits(comment, separator),
+                        "Blank" => format::group_digits(blank, separator),
+                        "Files" => format::group_digits(file, separator),
+                        "Size" => format::table_size(size, self.size),
+                        _ => format::group_digits(code + comment + blank, separator),
+                    };
+                    pad_start(&value, cell(*i) + 2)
+                })
+                .collect::<String>()
+        };
+        let rows = match self.open {
+            Some(i) => self.details[i]
+                .files
+                .iter()
+                .map(|f: &Data| {
+                    let path = truncate_start(&f.path.to_string_lossy(), name_width - 1);
+                    let name = pad_end(&format!(" {}", path), name_width);
+                    name + &values(f.code, f.comment, f.blank, 1, f.size)
+                })
+                .collect::<Vec<String>>(),
+            None => self
+                .details
+                .iter()
+                .map(|d| {
+                    let name = truncate_display(&d.name, name_width - 1);
+                    let name = pad_end(&format!(" {}", name), name_width);
+                    name + &values(d.code, d.comment, d.blank, d.file, d.size)
+                })
+                .collect::<Vec<String>>(),
+        };
+
+        // Keep the selection on screen
+        let page = height.saturating_sub(CHROME).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + page {
+            self.scroll = self.selected + 1 - page;
+        }
+
+        let mut rst = vec![
+            format!("\x1b[1m{}\x1b[0m", truncate_display(&title, width)),
+            format!("\x1b[1;4m{}\x1b[0m", truncate_display(&header, width)),
+        ];
+        for (i, row) in rows.iter().enumerate().skip(self.scroll).take(page) {
+            let row = truncate_display(row, width);
+            match i == self.selected {
+                true => rst.push(format!("\x1b[7m{}\x1b[0m", pad_end(&row, width))),
+                false => rst.push(row),
+            }
+        }
+        while rst.len() < height.saturating_sub(1) {
+            rst.push(String::new());
+        }
+        rst.push(format!("\x1b[2m{}\x1b[0m", truncate_display(HELP, width)));
+        rst
+    }
+}
+
+// Takes over the terminal until `q`, false if it isn't a terminal after all
+#[cfg(unix)]
+pub fn run(
+    details: Vec<Detail>,
+    sort: Sort,
+    order: Order,
+    number: NumberFormat,
+    size: SizeStyle,
+) -> bool {
+    use std::io::{Read, Write};
+
+    let terminal = match Terminal::enable() {
+        Some(terminal) => terminal,
+        None => return false,
+    };
+    let mut state = State::new(details, sort, order);
+    state.number = number;
+    state.size = size;
+    let mut stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut buf = [0; 16];
+    loop {
+        let (width, height) = terminal.size();
+        let screen = state.render(width, height);
+        let _ = write!(stdout, "\x1b[H{}\x1b[J", screen.join("\x1b[K\r\n"));
+        let _ = stdout.flush();
+        let n = match stdin.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if !state.handle(key(&buf[..n]), height) {
+            break;
+        }
+    }
+    true
+}
+
+#[cfg(not(unix))]
+pub fn run(_: Vec<Detail>, _: Sort, _: Order, _: NumberFormat, _: SizeStyle) -> bool {
+    false
+}
+
+// Raw input on the alternate screen, both are undone when dropped
+#[cfg(unix)]
+struct Terminal {
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    fn enable() -> Option<Self> {
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            print!("\x1b[?1049h\x1b[?25l");
+            Some(Self { saved })
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0
+            {
+                return (size.ws_col as usize, size.ws_row as usize);
+            }
+        }
+        (80, 24)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        use std::io::Write;
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::text::strip_ansi;
+    use std::path::PathBuf;
+
+    fn state() -> State {
+        let file = |path: &str, code| Data {
+            path: PathBuf::from(path),
+            language: "Rust",
+            blank: 0,
+            comment: 1,
+            code,
+            size: 10,
+        };
+        let mut rust = Detail::new("Rust");
+        let mut go = Detail::new("Go");
+        for data in [file("a.rs", 5), file("b.rs", 20)] {
+            rust.add(&data);
+            rust.files.push(data);
+        }
+        go.add(&file("main.go", 10));
+        State::new(vec![go, rust], Sort::Code, Order::Desc)
+    }
+
+    fn names(state: &mut State) -> Vec<String> {
+        state
+            .render(60, 10)
+            .iter()
+            .skip(2)
+            .map(|line| strip_ansi(line))
+            .filter(|line| !line.is_empty() && !line.starts_with('↑'))
+            .map(|line| line.split_whitespace().next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(key(b"\x1b[A"), Key::Up);
+        assert_eq!(key(b"j"), Key::Down);
+        assert_eq!(key(b"\r"), Key::Enter);
+        assert_eq!(key(b"\x1b"), Key::Back);
+        assert_eq!(key(b"3"), Key::Toggle(2));
+        assert_eq!(key(b"q"), Key::Quit);
+        assert_eq!(key(b"x"), Key::Other);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut state = state();
+        assert_eq!(names(&mut state), ["Rust", "Go"]);
+
+        // Into the files of Rust, ranked by code
+        assert!(state.handle(Key::Enter, 10));
+        assert_eq!(names(&mut state), ["b.rs", "a.rs"]);
+        assert!(state.render(60, 10)[0].contains("Rust, 2 files"));
+        state.handle(Key::Reverse, 10);
+        assert_eq!(names(&mut state), ["a.rs", "b.rs"]);
+
+        // Back on Rust, last now that the order is flipped
+        state.handle(Key::Back, 10);
+        assert_eq!(state.open, None);
+        assert_eq!(names(&mut state), ["Go", "Rust"]);
+        assert_eq!(state.selected, 1);
+        state.handle(Key::Down, 10);
+        assert_eq!(state.selected, 1);
+        state.handle(Key::Up, 10);
+        assert_eq!(state.selected, 0);
+        assert!(!state.handle(Key::Quit, 10));
+    }
+
+    #[test]
+    fn test_sort_and_columns() {
+        let mut state = state();
+        // code => comment, Rust has two comments
+        state.handle(Key::Sort, 10);
+        assert_eq!(sort_name(&state.sort, false), "comment");
+        assert_eq!(names(&mut state), ["Rust", "Go"]);
+        for _ in 0..5 {
+            state.handle(Key::Sort, 10);
+        }
+        assert_eq!(sort_name(&state.sort, false), "language");
+        assert_eq!(names(&mut state), ["Go", "Rust"]);
+
+        let header = strip_ansi(&state.render(80, 10)[1]);
+        assert!(header.contains("Comment"));
+        state.handle(Key::Toggle(1), 10);
+        let header = strip_ansi(&state.render(80, 10)[1]);
+        assert!(!header.contains("Comment"));
+        assert!(header.contains("Blank"));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut state = state();
+        state.handle(Key::Enter, 10);
+        // One row fits between the title, the header and the help line
+        state.handle(Key::Down, 4);
+        let screen = state.render(60, 4);
+        assert_eq!(screen.len(), 4);
+        assert!(strip_ansi(&screen[2]).trim_start().starts_with("a.rs"));
+    }
+}
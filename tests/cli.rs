@@ -0,0 +1,1975 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// Keep the user's own `rots/config.toml` out of the tests
+fn no_user_config() -> PathBuf {
+    std::env::temp_dir().join("lok-test-no-config")
+}
+
+fn lok(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", no_user_config())
+        .args(args)
+        .output()
+        .expect("failed to run lok")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+fn lok_with_stdin(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", no_user_config())
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run lok");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// An empty directory under the system temp dir, unique per test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lok-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn multiple_outputs() {
+    let dir = temp_dir("multiple_outputs");
+    let json = dir.join("report.json");
+    let csv = dir.join("report.csv");
+
+    let output = lok(&[
+        "tests/__code",
+        "-o",
+        "table",
+        "-o",
+        &format!("json={}", json.display()),
+        "-o",
+        &format!("csv={}", csv.display()),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Language"));
+
+    let json = fs::read_to_string(json).unwrap();
+    assert!(json.starts_with("{\n  \"languages\""));
+    assert!(json.ends_with("}\n"));
+    let csv = fs::read_to_string(csv).unwrap();
+    assert!(csv.starts_with("language,code,comment,blank,file,size,lines,percent\n"));
+
+    // No temporary files are left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[test]
+fn conflicting_outputs() {
+    let output = lok(&["tests/__code", "-o", "table", "-o", "json"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("stdout"));
+
+    let output = lok(&["tests/__code", "-o", "json=a.json", "-o", "yaml=a.json"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("more than one output"));
+
+    let output = lok(&["tests/__code", "-o", "unknown"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn files_removed_during_scan() {
+    let dir = temp_dir("files_removed_during_scan");
+    let mut files = vec![];
+    for d in 0..40 {
+        let sub = dir.join(format!("d{}", d));
+        fs::create_dir(&sub).unwrap();
+        for f in 0..50 {
+            let file = sub.join(format!("f{}.rs", f));
+            fs::write(&file, "fn main() {}\n// comment\n").unwrap();
+            files.push(file);
+        }
+    }
+
+    let child = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .arg(&dir)
+        .args(["--error", "-o", "json"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let remover = std::thread::spawn(move || {
+        for file in files.iter().step_by(2) {
+            let _ = fs::remove_file(file);
+        }
+        for d in (0..40).step_by(4) {
+            let _ =
+                fs::remove_dir_all(files[0].parent().unwrap().with_file_name(format!("d{}", d)));
+        }
+    });
+    let output = child.wait_with_output().unwrap();
+    remover.join().unwrap();
+
+    // Whatever the timing, the scan finishes cleanly with sane totals
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("error:"), "{}", stderr(&output));
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let total = &json["total"];
+    let file = total["file"].as_u64().unwrap();
+    assert!(file <= 2000);
+    assert_eq!(total["code"].as_u64().unwrap(), file);
+    assert_eq!(total["comment"].as_u64().unwrap(), file);
+}
+
+#[test]
+fn files_report_diff() {
+    let dir = temp_dir("files_report_diff");
+    let report = dir.join("report.json");
+
+    let output = lok(&[
+        "tests/__code",
+        "--files",
+        "-o",
+        &format!("json={}", report.display()),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let json = fs::read_to_string(&report).unwrap();
+    assert!(json.contains("\"path\": \"tests/__code/main.rs\""));
+
+    // Identical per-file reports have nothing to compare
+    let report = report.to_str().unwrap();
+    let output = lok(&["diff", "--files", report, report, "-o", "json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\n  \"files\": []\n}\n");
+
+    // A `--by-family` report holds the same files under its families
+    let family = dir.join("family.json");
+    let family = family.to_str().unwrap();
+    let output = lok(&[
+        "tests/__code",
+        "--files",
+        "--by-family",
+        "-o",
+        &format!("json={}", family),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = lok(&["diff", "--files", report, family, "-o", "json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "{\n  \"files\": []\n}\n");
+
+    let extension = dir.join("extension.json");
+    let extension = extension.to_str().unwrap();
+    let output = lok(&[
+        "tests/__code",
+        "--files",
+        "--group-by",
+        "extension",
+        "-o",
+        &format!("json={}", extension),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = lok(&["diff", "--files", report, extension]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("is grouped by directory or extension"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn group_by_extension() {
+    let output = lok(&["tests/__code", "--group-by", "extension", "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let csv = stdout(&output);
+    assert!(csv.starts_with("extension,code,comment,blank,file,size,lines,percent\n"));
+    assert!(csv.contains("\ncss,4,4,3,1,107,11,4.4\n"));
+    assert!(csv.contains("\nscss,3,5,3,1,102,11,3.3\n"));
+}
+
+#[test]
+fn output_path() {
+    let dir = temp_dir("output_path");
+    let report = dir.join("reports").join("code.md");
+
+    let output = lok(&[
+        "tests/__code",
+        "-o",
+        "markdown",
+        "--output-path",
+        report.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+
+    let markdown = fs::read_to_string(&report).unwrap();
+    assert!(markdown.starts_with("| Language "));
+    assert!(markdown.contains("| Rust "));
+    assert!(markdown.ends_with("|\n"));
+}
+
+#[test]
+fn table_width() {
+    let output = lok(&["tests/__code", "--width", "64"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let table = stdout(&output);
+    for line in table.lines() {
+        assert_eq!(line.chars().count(), 64, "{}", line);
+    }
+    assert!(table.contains("│ TypeSc…  "));
+
+    let output = lok(&["tests/__code", "--width", "0"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'--width' must be at least 1"));
+}
+
+#[test]
+fn html_report() {
+    let dir = temp_dir("html_report");
+    let path = dir.join("report.html");
+
+    let output = lok(&[
+        "tests/__code",
+        "-o",
+        "html-report",
+        "--output-path",
+        path.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let html = fs::read_to_string(&path).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>Code statistics of <code>tests/__code</code></h1>"));
+    assert!(html.contains("<span class=\"name\">CSS</span>"));
+    assert!(html.contains("<footer>Counted <code>tests/__code</code> at "));
+    assert!(html.trim_end().ends_with("</html>"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn table_color() {
+    // Not a terminal, so auto leaves the table plain
+    let output = lok(&["tests/__code"]);
+    assert!(!stdout(&output).contains('\x1b'));
+
+    let dir = temp_dir("table_color");
+    let json = dir.join("report.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .args(["tests/__code", "--color", "always", "-o", "table"])
+        .args(["-o", &format!("json={}", json.display())])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(stdout(&output).contains("\x1b[1mTotal\x1b[0m"));
+    assert!(!fs::read_to_string(&json).unwrap().contains('\x1b'));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn deterministic_output() {
+    let dir = temp_dir("deterministic_output");
+    // Many files with equal counts so every sort has ties to break
+    for i in 0..200 {
+        let sub = dir.join(format!("d{}", i % 7));
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join(format!("f{}.rs", i)), "// a\nfn main() {}\n").unwrap();
+        fs::write(sub.join(format!("f{}.go", i)), "// a\nfunc main() {}\n").unwrap();
+        fs::write(sub.join(format!("f{}.py", i)), "# a\nprint()\n").unwrap();
+    }
+    let dir = dir.to_str().unwrap();
+
+    for args in [
+        vec![dir, "--files", "-o", "json"],
+        vec![dir, "--files", "-s", "code"],
+        vec![dir, "--group-by", "dir", "-s", "file", "-o", "csv"],
+    ] {
+        let first = lok(&args);
+        assert!(first.status.success(), "{}", stderr(&first));
+        let second = lok(&args);
+        assert_eq!(first.stdout, second.stdout, "{:?}", args);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// Paths of the counted files, read from the csv `--files` listing, none is fine
+fn counted_files(args: &[&str]) -> Vec<String> {
+    let output = lok(&[args, &["--files", "-o", "csv", "--allow-empty"]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    let mut paths = text
+        .lines()
+        .skip_while(|line| !line.starts_with("path,"))
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().to_string())
+        .collect::<Vec<String>>();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn gitignore() {
+    let dir = temp_dir("gitignore");
+    for path in [
+        "src/main.rs",
+        "src/gen.rs",
+        "src/app.js",
+        "src/vendor.js",
+        "target/debug/build.rs",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    fs::write(dir.join(".gitignore"), "target/\n*.js\n!app.js\n").unwrap();
+    fs::write(dir.join("src/.gitignore"), "gen.rs\n").unwrap();
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root]),
+        vec![path("src/app.js"), path("src/main.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--no-ignore", "--no-default-excludes"]).len(),
+        5
+    );
+    // An explicit include wins over .gitignore
+    assert_eq!(
+        counted_files(&[root, "-i", &path("src/gen.rs")]),
+        vec![path("src/gen.rs")]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exclude_files() {
+    let dir = temp_dir("exclude_files");
+    for path in ["src/main.rs", "src/gen.rs", "src/app.js", "lib.go"] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    fs::write(dir.join(".rotsignore"), "# generated\n\nsrc/gen.rs\n").unwrap();
+    fs::write(dir.join("excludes"), "**/*.js\n").unwrap();
+    fs::write(dir.join("broken"), "*.go\n[\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lok"))
+            .current_dir(&dir)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[".", "--files", "-o", "csv"]);
+    let text = stdout(&output);
+    assert!(!text.contains("./src/gen.rs"));
+    assert!(text.contains("./src/app.js"));
+
+    let output = run(&[
+        ".",
+        "--exclude-from",
+        "excludes",
+        "-e",
+        "*.go",
+        "--files",
+        "-o",
+        "csv",
+    ]);
+    let text = stdout(&output);
+    assert!(text.contains("./src/main.rs"));
+    assert!(!text.contains("./src/gen.rs"));
+    assert!(!text.contains("./src/app.js"));
+    assert!(!text.contains("./lib.go"));
+
+    let output = run(&[".", "--exclude-from", "broken"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("broken:2: Cannot parse '[' to glob matcher"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn hidden_files() {
+    let dir = temp_dir("hidden_files");
+    for path in [
+        ".git/config",
+        ".git/index.json",
+        ".hidden/src.rs",
+        "src/main.rs",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "{}\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+            .current_dir(&dir)
+            .args(args)
+            .args(["--files", "-o", "csv"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output)
+    };
+
+    let text = run(&["."]);
+    assert!(text.contains("./src/main.rs"));
+    assert!(!text.contains("./.hidden/src.rs"));
+    assert!(!text.contains("./.git/index.json"));
+
+    let text = run(&[".", "--hidden"]);
+    assert!(text.contains("./.hidden/src.rs"));
+    assert!(text.contains("./.git/index.json"));
+
+    // Only a glob that names the dot reaches a hidden path
+    let text = run(&[".", "-i", "./**/*.rs"]);
+    assert!(text.contains("./src/main.rs"));
+    assert!(!text.contains("./.hidden/src.rs"));
+    let text = run(&[".", "-i", "./.hidden/*.rs"]);
+    assert!(text.contains("./.hidden/src.rs"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn default_excludes() {
+    let dir = temp_dir("default_excludes");
+    for path in [
+        "src/main.rs",
+        "src/build.rs",
+        "target/debug/out.rs",
+        "node_modules/lodash/index.js",
+        "web/dist/app.js",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+            .current_dir(&dir)
+            .args(args)
+            .args(["--files", "-o", "csv", "--allow-empty"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        output
+    };
+
+    let output = run(&[".", "--error"]);
+    let text = stdout(&output);
+    assert!(text.contains("./src/main.rs"));
+    assert!(text.contains("./src/build.rs"));
+    assert!(!text.contains("./target/debug/out.rs"));
+    assert!(!text.contains("./web/dist/app.js"));
+    assert!(stderr(&output).contains("Skipped 3 build and dependency directories"));
+
+    let text = stdout(&run(&[".", "--no-default-excludes"]));
+    assert!(text.contains("./target/debug/out.rs"));
+    assert!(text.contains("./node_modules/lodash/index.js"));
+
+    // Only a glob that names the directory reaches into it
+    let text = stdout(&run(&[".", "-i", "./**/*.js"]));
+    assert!(!text.contains("./node_modules/lodash/index.js"));
+    assert!(!text.contains("./web/dist/app.js"));
+    let text = stdout(&run(&[".", "-i", "./node_modules/**/*.js"]));
+    assert!(text.contains("./node_modules/lodash/index.js"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn max_depth() {
+    let dir = temp_dir("max_depth");
+    for path in [
+        "main.rs",
+        "src/lib.rs",
+        "src/parse/mod.rs",
+        "src/parse/a/b.rs",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root, "--max-depth", "1"]),
+        vec![path("main.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--max-depth", "2"]),
+        vec![path("main.rs"), path("src/lib.rs")]
+    );
+    assert_eq!(counted_files(&[root]).len(), 4);
+    // A glob below the cutoff matches nothing
+    assert!(counted_files(&[root, "--max-depth", "2", "-i", &path("src/parse/*.rs")]).is_empty());
+
+    let output = lok(&[root, "--max-depth", "0"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'--max-depth' must be at least 1"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stats() {
+    let output = lok(&["tests/__code", "--stats", "-o", "json", "--threads", "2"]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(json["stats"]["threads"], 2);
+    assert_eq!(json["stats"]["counted"], json["total"]["file"]);
+    let report = stderr(&output);
+    assert!(report.contains("Elapsed"), "{}", report);
+    assert!(report.contains("Threads   2"), "{}", report);
+
+    // Nothing extra without the flag
+    let output = lok(&["tests/__code", "-o", "json"]);
+    assert!(!stdout(&output).contains("\"stats\""));
+    assert!(stderr(&output).is_empty());
+}
+
+#[test]
+fn threads() {
+    let counts = |args: &[&str]| {
+        let output = lok(&[&["tests/__code", "-o", "json"], args].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output)
+    };
+    let one = counts(&["--threads", "1"]);
+    assert_eq!(one, counts(&["--threads", "8"]));
+    assert_eq!(one, counts(&[]));
+
+    let output = lok(&["tests/__code", "--threads", "0"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'--threads' must be at least 1"));
+    let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", no_user_config())
+        .env("ROTS_THREADS", "none")
+        .arg("tests/__code")
+        .output()
+        .unwrap();
+    assert!(stderr(&output).contains("'none' isn't a valid value for 'ROTS_THREADS'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_links() {
+    use std::os::unix::fs::symlink;
+
+    let dir = temp_dir("follow_links");
+    for path in ["src/main.rs", "packages/lib/lib.rs"] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let linked = symlink(dir.join("packages/lib"), dir.join("src/lib"))
+        .and_then(|_| symlink(dir.join("src"), dir.join("src/cycle")));
+    if linked.is_err() {
+        eprintln!("skipped, cannot create symlinks");
+        return;
+    }
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root]),
+        vec![path("packages/lib/lib.rs"), path("src/main.rs")]
+    );
+    // The linked file is counted once, the cycle ends the walk
+    let files = counted_files(&[root, "--follow"]);
+    assert_eq!(files.len(), 2);
+    assert!(files.contains(&path("src/main.rs")));
+
+    let output = lok(&[root, "--follow", "--error"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("links back to"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dedup_hardlinks() {
+    let dir = temp_dir("dedup_hardlinks");
+    fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+    if fs::hard_link(dir.join("main.rs"), dir.join("copy.rs")).is_err() {
+        eprintln!("skipped, cannot create hardlinks");
+        return;
+    }
+    let root = dir.to_str().unwrap();
+    let count = |args: &[&str]| {
+        let output = lok(&[&[root, "-o", "csv"], args].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        stdout(&output).lines().nth(1).unwrap().to_string()
+    };
+
+    // language,code,comment,blank,file,size
+    assert!(count(&[]).starts_with("Rust,2,0,0,2,26,"));
+    assert!(count(&["--dedup"]).starts_with("Rust,1,0,0,1,13,"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn multiple_directories() {
+    let dir = temp_dir("multiple_directories");
+    for path in ["foo/src/main.rs", "foo/src/gen.rs", "bar/src/lib.rs"] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lok"))
+            .current_dir(&dir)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let bar = dir.join("bar");
+    let bar = bar.to_str().unwrap();
+
+    // A relative and an absolute root, `-e` applies to both
+    let output = run(&["./foo", bar, "-e", "**/gen.rs", "--files", "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.starts_with("language,code,comment,blank,file,size,lines,percent\nRust,2,"));
+    assert!(text.contains("./foo/src/main.rs"));
+    assert!(text.contains(&format!("{}/src/lib.rs", bar)));
+    assert!(!text.contains("gen.rs"));
+
+    let output = run(&["./foo", "bar", "--group-by", "dir", "-o", "csv"]);
+    let text = stdout(&output);
+    assert!(text.contains("\n./foo/src,"));
+    assert!(text.contains("\nbar/src,"));
+
+    // A missing root is a typo, even next to one that exists
+    let output = run(&["./foo", "missing", "-o", "csv"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'missing' does not exist"));
+    assert!(stdout(&output).is_empty());
+    let output = run(&["missing"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_arguments() {
+    let dir = temp_dir("file_arguments");
+    for path in [
+        "src/main.rs",
+        "src/lib.rs",
+        "src/parse/mod.rs",
+        "README.md",
+        "LICENSE.txt",
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lok"))
+            .current_dir(&dir)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let files = |args: &[&str]| {
+        let output = run(&[args, &["--files", "-o", "csv"]].concat());
+        assert!(output.status.success(), "{}", stderr(&output));
+        let mut paths = stdout(&output)
+            .lines()
+            .skip_while(|line| !line.starts_with("path,"))
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect::<Vec<String>>();
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(
+        files(&["src/main.rs", "src/lib.rs"]),
+        vec!["src/lib.rs", "src/main.rs"]
+    );
+    // Filters are for the walk, a named file is always counted
+    assert_eq!(files(&["src/lib.rs", "-e", "**/*.rs"]), vec!["src/lib.rs"]);
+    // A quoted glob is expanded, files without a language are left out
+    assert_eq!(
+        files(&["src/**/*"]),
+        vec!["src/lib.rs", "src/main.rs", "src/parse/mod.rs"]
+    );
+
+    let output = run(&["LICENSE.txt"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'LICENSE.txt' is not a known language"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_from_stdin() {
+    let dir = temp_dir("files_from_stdin");
+    for path in ["src/main.rs", "src/lib.rs", "src/app.js", "lib.go"] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "fn main() {}\n").unwrap();
+    }
+    let run = |args: &[&str], input: &str| {
+        let output = lok_with_stdin(&dir, &[args, &["--files", "-o", "csv"]].concat(), input);
+        assert!(output.status.success(), "{}", stderr(&output));
+        output
+    };
+
+    let output = run(
+        &["--files-from", "-", "--error"],
+        "src/main.rs\nsrc/app.js\nmissing.rs\n",
+    );
+    let text = stdout(&output);
+    assert!(text.contains("./src/main.rs"));
+    assert!(text.contains("./src/app.js"));
+    assert!(!text.contains("./src/lib.rs"));
+    assert!(!text.contains("./lib.go"));
+    assert!(stderr(&output).contains("'./missing.rs' does not exist"));
+
+    // The filters still apply
+    let text = stdout(&run(
+        &["--files-from", "-", "-e", "**/*.js"],
+        "src/main.rs\nsrc/app.js\n",
+    ));
+    assert!(text.contains("./src/main.rs"));
+    assert!(!text.contains("./src/app.js"));
+
+    // Names with spaces and newlines survive NUL separation
+    fs::write(dir.join("my file.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.join("line\nbreak.rs"), "fn main() {}\n").unwrap();
+    let text = stdout(&run(
+        &["--files-from", "-", "-0"],
+        "my file.rs\0line\nbreak.rs\0",
+    ));
+    assert!(text.contains("\nRust,2,0,0,2,"), "{}", text);
+    assert!(text.contains("./my file.rs"));
+
+    let output = lok(&["-0"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn count_stdin() {
+    let dir = temp_dir("count_stdin");
+    let input = "// main\nfn main() {}\n\n";
+    let output = lok_with_stdin(&dir, &["--stdin", "rs", "-o", "csv"], input);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // language,code,comment,blank,file,size
+    assert!(stdout(&output).contains("\nRust,1,1,1,1,22,"));
+
+    let output = lok_with_stdin(&dir, &["--stdin", "nope"], input);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'nope' is not a known extension"));
+    assert!(stderr(&output).contains(", rs,"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn modified_since() {
+    let dir = temp_dir("modified_since");
+    fs::write(dir.join("new.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.join("old.rs"), "fn main() {}\n").unwrap();
+    let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(946_684_800);
+    fs::File::options()
+        .write(true)
+        .open(dir.join("old.rs"))
+        .and_then(|file| file.set_modified(old))
+        .unwrap();
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root, "--modified-since", "30d"]),
+        vec![path("new.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--modified-since", "1999-12-31"]).len(),
+        2
+    );
+    assert_eq!(
+        counted_files(&[root, "--modified-since", "2000-01-01T00:00:01Z"]),
+        vec![path("new.rs")]
+    );
+
+    let output = lok(&[root, "--modified-since", "last week"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot parse 'last week' as a time"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn max_size() {
+    let dir = temp_dir("max_size");
+    fs::write(dir.join("small.rs"), "fn main() {}\n").unwrap();
+    fs::write(dir.join("dump.json"), "[1]\n".repeat(1024)).unwrap();
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root, "--max-size", "1K"]),
+        vec![path("small.rs")]
+    );
+    assert_eq!(counted_files(&[root, "--max-size", "4K"]).len(), 2);
+
+    let output = lok(&[root, "--max-size", "1K", "--error"]);
+    let text = stderr(&output);
+    assert!(text.contains("skipped:"));
+    assert!(text.contains("dump.json\" is larger than --max-size"));
+    assert!(text.contains("Skipped 1 files larger than --max-size"));
+    assert!(!text.contains("error:"));
+
+    let output = lok(&[root, "--max-size", "10X"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot parse '10X' as a size"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn no_size() {
+    let output = lok(&["tests/__code", "--no-size", "-o", "json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(json["size_disabled"], true);
+    assert_eq!(json["total"]["size"], 0);
+    assert!(json["total"]["code"].as_u64().unwrap() > 0);
+
+    let output = lok(&["tests/__code", "--no-size", "--sort", "size"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'--sort size' doesn't work with '--no-size'"));
+    let output = lok(&["tests/__code", "--no-size", "--max-size", "1K"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn line_limits() {
+    let dir = temp_dir("line_limits");
+    fs::write(dir.join("one.rs"), "fn a() {}\n").unwrap();
+    fs::write(dir.join("three.rs"), "fn a() {}\n\n// b\n").unwrap();
+    fs::write(dir.join("five.rs"), "fn a() {}\n\n// b\n\nfn c() {}\n").unwrap();
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root, "--min-lines", "3"]),
+        vec![path("five.rs"), path("three.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--max-lines", "3"]),
+        vec![path("one.rs"), path("three.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--min-lines", "3", "--max-lines", "3"]),
+        vec![path("three.rs")]
+    );
+
+    // Totals only add up the files that are left
+    let output = lok(&[root, "--min-lines", "3", "-o", "csv"]);
+    // language,code,comment,blank,file,size
+    assert!(stdout(&output).contains("\nRust,3,2,3,2,43,"));
+    let output = lok(&[root, "--max-lines", "1", "-o", "jsonl"]);
+    assert_eq!(stdout(&output).lines().count(), 1);
+
+    let output = lok(&[root, "--min-lines", "5", "--max-lines", "3"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn binary_files() {
+    let dir = temp_dir("binary_files");
+    fs::write(dir.join("app.js"), "var a = 1;\n").unwrap();
+    fs::write(dir.join("blob.js"), b"var a = 1;\n\0\x01\x02\nvar b;\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let output = lok(&[root, "-o", "csv", "--error"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // language,code,comment,blank,file
+    assert!(stdout(&output).contains("\nJavaScript,1,0,0,1,"));
+    assert!(stderr(&output).contains("blob.js\" is binary"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn language_filter() {
+    let dir = temp_dir("language_filter");
+    for path in ["main.rs", "app.ts", "view.tsx", "index.js"] {
+        fs::write(dir.join(path), "let a = 1;\n").unwrap();
+    }
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(
+        counted_files(&[root, "--language", "rust", "--language", "typescript jsx"]),
+        vec![path("main.rs"), path("view.tsx")]
+    );
+    // Both filters have to agree
+    assert_eq!(
+        counted_files(&[root, "--language", "RUST", "--extension", "rs", "ts"]),
+        vec![path("main.rs")]
+    );
+    assert_eq!(
+        counted_files(&[root, "--language", "rust", "-e", &path("*.rs")]),
+        Vec::<String>::new()
+    );
+
+    // Aliases work too
+    assert_eq!(
+        counted_files(&[root, "--language", "ts", "--language", "TSX"]),
+        vec![path("app.ts"), path("view.tsx")]
+    );
+
+    let output = lok(&[root, "--language", "rsut"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown language 'rsut', did you mean 'Rust'?"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exclude_language() {
+    let dir = temp_dir("exclude_language");
+    for path in ["main.rs", "README.md", "data.json", "Cargo.toml"] {
+        fs::write(dir.join(path), "a = 1\n").unwrap();
+    }
+    let root = dir.to_str().unwrap();
+    let run = |args: &[&str]| lok(&[&[root, "-o", "csv"], args].concat());
+
+    let output = run(&[
+        "--exclude-language",
+        "markdown",
+        "--exclude-language",
+        "JSON",
+        "--exclude-language",
+        "toml",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.contains("\nRust,"));
+    assert!(!text.contains("Markdown"));
+    assert!(!text.contains("JSON"));
+    assert!(!text.contains("TOML"));
+
+    let output = run(&["--exclude-language", "jsno"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("did you mean 'JSON'"));
+
+    let output = run(&["--language", "rust", "--exclude-language", "Rust"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("'Rust' is given to both"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disable_language() {
+    let dir = temp_dir("disable_language");
+    fs::write(dir.join("plot.m"), "x = 1;\n").unwrap();
+    fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+    let root = dir.to_str().unwrap();
+    let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+
+    assert_eq!(counted_files(&[root]).len(), 2);
+    // `.m` is left to MATLAB
+    let text = stdout(&lok(&[root, "--disable-language", "objc", "-o", "csv"]));
+    assert!(text.contains("\nMATLAB,1,"), "{}", text);
+    let both = ["--disable-language", "objc", "--disable-language", "matlab"];
+    assert_eq!(
+        counted_files(&[&[root][..], &both].concat()),
+        vec![path("main.rs")]
+    );
+
+    // A named file has no language left
+    let output = lok(&[&[path("plot.m").as_str()][..], &both].concat());
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("is not a known language"));
+
+    // Unless it is mapped again
+    let output = lok(&[&[root][..], &both, &["--map", "m=Octave", "-o", "csv"]].concat());
+    assert!(stdout(&output).contains("\nOctave,1,"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn json_with_comments() {
+    let dir = temp_dir("json_with_comments");
+    let settings = "// editor\n{\n  /* two\n     lines */\n  \"tabs\": false\n}\n";
+    fs::create_dir_all(dir.join(".vscode")).unwrap();
+    fs::write(dir.join("settings.jsonc"), settings).unwrap();
+    fs::write(dir.join("tsconfig.json"), "{\n  // strict\n}\n").unwrap();
+    fs::write(dir.join(".vscode/launch.json"), "{\n  // run\n}\n").unwrap();
+    fs::write(dir.join("data.json"), "{\"url\": \"http://a\"}\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let output = lok(&[root, "--hidden", "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    // language,code,comment,blank,file
+    assert!(text.contains("\nJSON with Comments,7,5,0,3,"), "{}", text);
+    assert!(text.contains("\nJSON,1,0,0,1,"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn map_extensions() {
+    let dir = temp_dir("map_extensions");
+    fs::write(dir.join("plot.m"), "% plot\nx = 1;\n").unwrap();
+    fs::write(dir.join("header.inc"), "<?php\n// a\n").unwrap();
+    let root = dir.to_str().unwrap();
+    let run = |args: &[&str]| lok(&[&[root, "-o", "csv"], args].concat());
+
+    // A new language gets `#` comments, `--map-comment` changes them
+    let output = run(&["--map", "m=Octave", "--map", ".inc=php"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    // language,code,comment
+    assert!(text.contains("\nOctave,2,0,"));
+    assert!(text.contains("\nPHP,1,1,"));
+    assert!(!text.contains("ObjectiveC") && !text.contains("MATLAB"));
+    let text = stdout(&run(&["--map", "m=Octave", "--map-comment", "Octave=%"]));
+    assert!(text.contains("\nOctave,1,1,"));
+
+    let output = lok(&["--map", "inc=PHP", "ls"]);
+    assert!(stdout(&output).contains(".php .inc"));
+
+    for (value, error) in [
+        ("m", "Cannot parse '--map m', use EXT=LANGUAGE"),
+        ("=MATLAB", "'--map =MATLAB' has no extension before '='"),
+        ("m=", "'--map m=' has no language after '='"),
+    ] {
+        let output = run(&["--map", value]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains(error), "{}", stderr(&output));
+    }
+    let output = run(&["--map-comment", "Rust=%"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("only applies to a new language"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_file() {
+    let dir = temp_dir("config_file");
+    let code = dir.join("code");
+    fs::create_dir(&code).unwrap();
+    fs::write(code.join("main.zig"), "// main\nconst x = 1;\n").unwrap();
+    fs::write(code.join("lib.ex"), "# lib\n@doc \"\"\"\nx\n\"\"\"\n").unwrap();
+    fs::write(code.join("main.rs.in"), "(* a *)\nfn main() {}\n").unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        r##"
+[[language]]
+name = "Zig"
+extensions = ["zig"]
+single = ["//"]
+family = "Systems"
+
+[[language]]
+name = "Elixir"
+extensions = [".ex"]
+single = ["#"]
+multi = [['@doc """', '"""']]
+
+[[language]]
+name = "rust"
+extensions = ["in"]
+multi = [["(*", "*)"]]
+family = "Systems"
+"##,
+    )
+    .unwrap();
+    let root = code.to_str().unwrap();
+
+    let output = lok(&[root, "-o", "csv", "--config", config.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    // language,code,comment
+    assert!(text.contains("\nZig,1,1,"), "{}", text);
+    assert!(text.contains("\nElixir,0,4,"), "{}", text);
+    assert!(text.contains("\nRust,1,1,"), "{}", text);
+
+    // New and built-in languages roll up into the config's family
+    let args = [root, "--by-family", "-o", "csv"];
+    let output = lok(&[&args[..], &["--config", config.to_str().unwrap()]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.contains("\nSystems,2,2,"), "{}", text);
+    assert!(text.contains("\nElixir,0,4,"), "{}", text);
+
+    // Found in the user's config directory without `--config`
+    let user = dir.join("xdg");
+    fs::create_dir_all(user.join("rots")).unwrap();
+    fs::copy(&config, user.join("rots").join("config.toml")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", &user)
+        .args(["ls"])
+        .output()
+        .unwrap();
+    assert!(stdout(&output).contains("Zig"));
+
+    fs::write(
+        &config,
+        "[[language]]\nname = \"Zig\"\nextentions = [\"zig\"]\n",
+    )
+    .unwrap();
+    let output = lok(&[root, "--config", config.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let error = stderr(&output);
+    assert!(
+        error.contains(&format!("'{}'", config.display())),
+        "{}",
+        error
+    );
+    assert!(error.contains("line 3, key 'extentions'"), "{}", error);
+
+    let output = lok(&[root, "--config", dir.join("none.toml").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Cannot read"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn project_config() {
+    let dir = temp_dir("project_config");
+    let src = dir.join("repo").join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir(dir.join("repo").join(".git")).unwrap();
+    fs::write(src.join("main.py"), "a = 1\nb = 2\nc = 3\n").unwrap();
+    fs::write(src.join("lib.rs"), "fn a() {}\n").unwrap();
+    fs::write(src.join("gen.rs"), "fn b() {}\n".repeat(5)).unwrap();
+    fs::write(src.join("main.zig"), "// a\nconst a = 1;\n").unwrap();
+    fs::write(
+        dir.join("repo").join(".rots.toml"),
+        "exclude = [\"**/gen.rs\"]\nsort = \"code\"\noutput = [\"csv\"]\n",
+    )
+    .unwrap();
+    let user = dir.join("xdg").join("rots");
+    fs::create_dir_all(&user).unwrap();
+    fs::write(
+        user.join("config.toml"),
+        "sort = \"file\"\noutput = [\"json\"]\n[[language]]\nname = \"Zig\"\nextensions = [\"zig\"]\nsingle = [\"//\"]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lok"))
+            .env("XDG_CONFIG_HOME", dir.join("xdg"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let root = src.to_str().unwrap();
+
+    // The project config is found above the work dir, up to the repository root,
+    // it wins over the user config, which still adds Zig
+    let text = stdout(&run(&[root]));
+    assert!(text.starts_with("language,code,"), "{}", text);
+    let position = |name: &str| text.find(&format!("\n{},", name)).unwrap();
+    assert!(position("Python") < position("Rust"));
+    assert!(text.contains("\nRust,1,"), "{}", text);
+    assert!(text.contains("\nZig,1,1,"), "{}", text);
+
+    // The command line wins over both
+    let text = stdout(&run(&[root, "-s", "language", "-e", "**/lib.rs"]));
+    assert!(text.starts_with("language,code,"));
+    let rows = text
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap());
+    assert_eq!(
+        rows.collect::<Vec<&str>>(),
+        ["Python", "Rust", "Zig", "Total"]
+    );
+    assert!(text.contains("\nRust,5,"), "{}", text);
+
+    // Without the project config the user config is next in line
+    fs::remove_file(dir.join("repo").join(".rots.toml")).unwrap();
+    let text = stdout(&run(&[root]));
+    assert!(text.starts_with("{"), "{}", text);
+
+    // `--no-config` reads neither
+    let text = stdout(&run(&[root, "--no-config"]));
+    assert!(text.contains("Language"), "{}", text);
+    assert!(!text.contains("Zig"));
+
+    fs::write(dir.join("repo").join("rots.toml"), "sort = \"codes\"\n").unwrap();
+    let output = run(&[root]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("rots.toml', line 1, key 'sort'"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn env_defaults() {
+    let run = |vars: &[(&str, &str)], args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lok"))
+            .env("XDG_CONFIG_HOME", no_user_config())
+            .envs(vars.iter().copied())
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = run(&[("ROTS_OUTPUT", "csv")], &["tests/__code"]);
+    assert!(stdout(&output).starts_with("language,"));
+    let output = run(&[("ROTS_OUTPUT", "csv")], &["tests/__code", "-o", "json"]);
+    assert!(stdout(&output).starts_with("{"));
+
+    for (name, value, error) in [
+        (
+            "ROTS_SORT",
+            "codes",
+            "'codes' isn't a valid value for 'ROTS_SORT'",
+        ),
+        (
+            "ROTS_OUTPUT",
+            "pdf",
+            "Invalid value for 'ROTS_OUTPUT': Expected one of:",
+        ),
+        (
+            "ROTS_ERROR",
+            "maybe",
+            "'maybe' isn't a valid value for 'ROTS_ERROR'",
+        ),
+    ] {
+        let output = run(&[(name, value)], &["tests/__code"]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains(error), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn filename_languages() {
+    let dir = temp_dir("filename_languages");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("Makefile"), "# build\nall:\n\tcargo build\n").unwrap();
+    fs::write(dir.join("sub").join("makefile"), "all:\n").unwrap();
+    fs::write(dir.join("Dockerfile"), "# image\nFROM rust\n\nRUN make\n").unwrap();
+    fs::write(dir.join("CMakeLists.txt"), "project(a)\n").unwrap();
+    fs::write(dir.join("Justfile"), "# a\nbuild:\n").unwrap();
+    fs::write(dir.join("README"), "text\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let output = lok(&[root, "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    // language,code,comment,blank,file
+    assert!(text.contains("\nMakefile,3,1,0,2,"), "{}", text);
+    assert!(text.contains("\nDockerfile,2,1,1,1,"), "{}", text);
+    assert!(text.contains("\nCMake,1,0,0,1,"), "{}", text);
+    assert!(!text.contains("Just"));
+
+    let text = stdout(&lok(&[root, "-o", "csv", "--group-by", "extension"]));
+    assert!(text.contains("\nMakefile,2,1,"), "{}", text);
+    assert!(text.contains("\nmakefile,1,0,"), "{}", text);
+
+    // Config file languages can name files too
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        "[[language]]\nname = \"Just\"\nfilenames = [\"Justfile\"]\nsingle = [\"#\"]\n",
+    )
+    .unwrap();
+    let text = stdout(&lok(&[
+        root,
+        "-o",
+        "csv",
+        "--config",
+        config.to_str().unwrap(),
+    ]));
+    assert!(text.contains("\nJust,1,1,"), "{}", text);
+
+    let text = stdout(&lok(&["ls"]));
+    assert!(
+        text.contains(".mk .mak Makefile makefile GNUmakefile"),
+        "{}",
+        text
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn detect_modeline() {
+    let dir = temp_dir("detect_modeline");
+    fs::write(dir.join("app.conf"), "# vim: ft=yaml\nkey: 1\n").unwrap();
+    fs::write(dir.join("Brewfile"), "# -*- mode: ruby -*-\nbrew 'lok'\n").unwrap();
+    fs::write(dir.join("other.conf"), "# vim: ft=bogus\nkey = 1\n").unwrap();
+    fs::write(dir.join("plain.conf"), "# vim: set ts=4:\nkey = 1\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    // Unknown files are left alone without `--detect`
+    let text = stdout(&lok(&[root, "-o", "csv"]));
+    assert!(!text.contains("YAML") && !text.contains("Ruby"), "{}", text);
+
+    let text = stdout(&lok(&[root, "-o", "csv", "--detect", "modeline"]));
+    // language,code,comment,blank,file
+    assert!(text.contains("\nYAML,1,1,0,1,"), "{}", text);
+    assert!(text.contains("\nRuby,1,1,0,1,"), "{}", text);
+    assert!(text.contains("\nTotal,2,2,0,2,"), "{}", text);
+
+    // A file an include glob names is looked at anyway
+    let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", no_user_config())
+        .current_dir(&dir)
+        .args(["-o", "csv", "-i", "./*.conf"])
+        .output()
+        .unwrap();
+    let text = stdout(&output);
+    assert!(text.contains("\nYAML,1,1,0,1,"), "{}", text);
+    assert!(!text.contains("Ruby"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn header_heuristics() {
+    let dir = temp_dir("header_heuristics");
+    fs::write(dir.join("a.h"), "#pragma once\nint a(void);\n").unwrap();
+    fs::write(dir.join("b.h"), "namespace b {\nclass B;\n}\n").unwrap();
+    fs::write(dir.join("c.h"), "#import <Foundation/Foundation.h>\n").unwrap();
+    fs::write(dir.join("d.hpp"), "// d\nvoid d();\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let text = stdout(&lok(&[root, "-o", "csv"]));
+    // language,code,comment,blank,file
+    assert!(text.contains("\nC,2,0,0,1,"), "{}", text);
+    assert!(text.contains("\nC++,4,1,0,2,"), "{}", text);
+    assert!(text.contains("\nObjectiveC,1,0,0,1,"), "{}", text);
+
+    // The guess counts for `--language` too
+    let text = stdout(&lok(&[root, "-o", "csv", "--language", "cpp"]));
+    assert!(text.contains("\nC++,4,1,0,2,"), "{}", text);
+    assert!(
+        !text.contains("\nC,") && !text.contains("ObjectiveC"),
+        "{}",
+        text
+    );
+
+    let text = stdout(&lok(&[root, "-o", "csv", "--no-heuristics"]));
+    assert!(text.contains("\nC,6,0,0,3,"), "{}", text);
+    assert!(text.contains("\nC++,1,1,0,1,"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compound_extensions() {
+    let dir = temp_dir("compound_extensions");
+    fs::write(dir.join("index.d.ts"), "// types\nexport type A = 1;\n").unwrap();
+    fs::write(dir.join("index.ts"), "let a: A = 1;\n").unwrap();
+    fs::write(dir.join("jquery.min.js"), "var $;\n").unwrap();
+    fs::write(dir.join("app.spec.js"), "test();\n").unwrap();
+    fs::write(dir.join("gen.rs.in"), "fn gen() {}\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let text = stdout(&lok(&[root, "-o", "csv"]));
+    // language,code,comment,blank,file
+    assert!(
+        text.contains("\nTypeScript Declarations,1,1,0,1,"),
+        "{}",
+        text
+    );
+    assert!(text.contains("\nTypeScript,1,0,0,1,"), "{}", text);
+    assert!(text.contains("\nJavaScript,2,0,0,2,"), "{}", text);
+    assert!(text.contains("\nRust,1,0,0,1,"), "{}", text);
+
+    let text = stdout(&lok(&[root, "-o", "csv", "--group-by", "extension"]));
+    assert!(text.contains("\nd.ts,1,1,"), "{}", text);
+    assert!(text.contains("\njs,2,0,"), "{}", text);
+    assert!(text.contains("\nrs.in,1,0,"), "{}", text);
+
+    // Custom languages register compound extensions the same way
+    let text = stdout(&lok(&[root, "-o", "csv", "--map", "spec.js=Spec"]));
+    assert!(text.contains("\nSpec,1,0,0,1,"), "{}", text);
+    assert!(text.contains("\nJavaScript,1,0,0,1,"), "{}", text);
+    let text = stdout(&lok(&[root, "-o", "csv", "--extension", "d.ts"]));
+    assert!(text.contains("\nTotal,1,1,0,1,"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shared_extensions() {
+    let dir = temp_dir("shared_extensions");
+    fs::write(dir.join("plot.m"), "% plot\nx = 1:10;\n").unwrap();
+    fs::write(
+        dir.join("App.m"),
+        "#import \"App.h\"\n@implementation App\n@end\n",
+    )
+    .unwrap();
+    fs::write(dir.join("other.m"), "x = 1;\n").unwrap();
+    fs::write(
+        dir.join("family.pl"),
+        "parent(tom, bob).\nancestor(X, Y) :- parent(X, Y).\n",
+    )
+    .unwrap();
+    fs::write(dir.join("script.pl"), "use strict;\nprint 1;\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let text = stdout(&lok(&[root, "-o", "csv"]));
+    // language,code,comment,blank,file
+    assert!(text.contains("\nMATLAB,1,1,0,1,"), "{}", text);
+    // Nothing stands out in other.m, it stays Objective-C
+    assert!(text.contains("\nObjectiveC,4,0,0,2,"), "{}", text);
+    assert!(text.contains("\nProlog,2,0,0,1,"), "{}", text);
+    assert!(text.contains("\nPerl,2,0,0,1,"), "{}", text);
+
+    let text = stdout(&lok(&[root, "-o", "csv", "--language", "matlab"]));
+    assert!(text.contains("\nTotal,1,1,0,1,"), "{}", text);
+    let text = stdout(&lok(&[root, "-o", "csv", "--no-heuristics"]));
+    assert!(text.contains("\nObjectiveC,6,0,0,3,"), "{}", text);
+    let text = stdout(&lok(&[root, "-o", "csv", "--map", "m=MATLAB"]));
+    assert!(text.contains("\nMATLAB,5,1,0,3,"), "{}", text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn doctor() {
+    let output = lok(&["doctor"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("No problems found"));
+
+    let dir = temp_dir("doctor");
+    fs::write(dir.join("a.x"), "x\n").unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        "[[language]]\nname = \"X\"\nextensions = [\"x\"]\nsingle = [\"＃\"]\nmulti = [[\"<<\", \"\"]]\n",
+    )
+    .unwrap();
+    let config = config.to_str().unwrap();
+
+    let output = lok(&["--config", config, "doctor"]);
+    assert!(!output.status.success());
+    let text = stdout(&output);
+    assert!(
+        text.contains("Language 'X': A comment token is empty"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("Language 'X': The comment token '＃' isn't ASCII"),
+        "{}",
+        text
+    );
+
+    // A fatal problem stops a count before it goes wrong
+    let output = lok(&[dir.to_str().unwrap(), "--config", config]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Language 'X': A comment token is empty, see 'lok doctor'"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn list_languages() {
+    let text = stdout(&lok(&["ls", "RUST"]));
+    assert_eq!(text.lines().collect::<Vec<&str>>(), ["Rust    .rs .rs.in"]);
+    let text = stdout(&lok(&["ls", ".tsx"]));
+    assert!(text.starts_with("TypeScript JSX"), "{}", text);
+
+    let output = lok(&["ls", "nothing-like-this"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("No language matches 'nothing-like-this'"));
+
+    // User languages are listed with the built-in ones
+    let dir = temp_dir("list_languages");
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        "[[language]]\nname = \"Zig\"\nextensions = [\"zig\"]\nsingle = [\"//\"]\n",
+    )
+    .unwrap();
+    let config = config.to_str().unwrap();
+    let output = lok(&["--config", config, "ls", "zig", "--output", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([{
+            "name": "Zig",
+            "extensions": ["zig"],
+            "filenames": [],
+            "aliases": [],
+            "single": ["//"],
+            "multi": []
+        }])
+    );
+
+    let output = lok(&["ls", "-o", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let rust = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|language| language["name"] == "Rust")
+        .unwrap();
+    assert_eq!(rust["multi"], serde_json::json!([["/*", "*/"]]));
+    assert_eq!(stdout(&lok(&["ls", "zzz", "-o", "json"])), "[]\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn check_limits() {
+    let dir = temp_dir("check_limits");
+    fs::write(dir.join("main.rs"), "// a\nfn a() {}\nfn b() {}\n").unwrap();
+    fs::write(dir.join("a.md"), "# A\n").unwrap();
+    fs::write(dir.join("b.md"), "# B\n").unwrap();
+    fs::write(
+        dir.join(".rots.toml"),
+        "max_code = 100\n[limits.markdown]\nmax_files = 1\n",
+    )
+    .unwrap();
+    let root = dir.to_str().unwrap();
+
+    let output = lok(&[root, "check"]);
+    assert_eq!(output.status.code(), Some(1));
+    let text = stdout(&output);
+    assert!(text.contains("Markdown    files    2 > 1"), "{}", text);
+    assert!(text.contains("1 of 2 limits exceeded"), "{}", text);
+
+    // Flags win over the config
+    let output = lok(&[root, "check", "--max-code", "1", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let json: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let violations = json["violations"].as_array().unwrap();
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0]["language"], serde_json::Value::Null);
+    assert_eq!(violations[0]["limit"], "code");
+    assert_eq!(violations[0]["value"], 4);
+    assert_eq!(violations[0]["max"], 1);
+
+    fs::write(dir.join(".rots.toml"), "max_comment_ratio = 1.0\n").unwrap();
+    let output = lok(&[root, "check"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("No limit exceeded, 1 checked"));
+
+    fs::write(dir.join(".rots.toml"), "[limits.Rusty]\nmax_code = 1\n").unwrap();
+    let output = lok(&[root, "check"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown language 'Rusty'"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fail_thresholds() {
+    let dir = temp_dir("fail_thresholds");
+    fs::write(dir.join("main.rs"), "// a\nfn a() {}\nfn b() {}\n\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let output = lok(&[
+        root,
+        "-o",
+        "csv",
+        "--fail-over",
+        "code=2",
+        "--fail-under",
+        "blank=1",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Every failure is reported and the report is still printed
+    let output = lok(&[
+        root,
+        "-o",
+        "csv",
+        "--fail-over",
+        "code=1",
+        "--fail-under",
+        "comment=2",
+        "--fail-over",
+        "size=1K",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stdout(&output).contains("\nRust,2,1,"),
+        "{}",
+        stdout(&output)
+    );
+    let error = stderr(&output);
+    assert!(
+        error.contains("code is 2, over --fail-over code=1"),
+        "{}",
+        error
+    );
+    assert!(
+        error.contains("comment is 1, under --fail-under comment=2"),
+        "{}",
+        error
+    );
+    assert!(!error.contains("size"), "{}", error);
+
+    let output = lok(&[root, "--fail-over", "lines=1"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("has an unknown metric, use one of: code, comment"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn nothing_counted() {
+    let dir = temp_dir("nothing_counted");
+    let root = dir.to_str().unwrap();
+
+    // An empty directory
+    let output = lok(&[root, "-o", "csv"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("No files were counted"));
+    assert!(stdout(&output).contains("Total,0,"));
+
+    // Everything excluded, with jsonl too
+    fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+    let output = lok(&[root, "-e", "**/*.rs"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = lok(&[root, "-e", "**/*.rs", "-o", "jsonl"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(lok(&[root, "-o", "jsonl"]).status.success());
+
+    let output = lok(&[root, "-e", "**/*.rs", "--allow-empty", "-o", "csv"]);
+    assert!(output.status.success());
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn completions() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = lok(&["completions", shell]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        let text = stdout(&output);
+        assert!(text.contains("max-depth"), "{}", shell);
+        // Values of `--sort`, powershell only completes the flags
+        if shell != "powershell" {
+            assert!(text.contains("language code comment blank"), "{}", shell);
+        }
+    }
+    let output = lok(&["completions", "tcsh"]);
+    assert!(!output.status.success());
+
+    // Nothing is counted or read, a broken config doesn't matter
+    let dir = temp_dir("completions");
+    let config = dir.join("config.toml");
+    fs::write(&config, "nonsense").unwrap();
+    let output = lok(&["--config", config.to_str().unwrap(), "completions", "zsh"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("#compdef lok"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cache() {
+    let dir = temp_dir("cache");
+    let src = dir.join("src");
+    fs::create_dir_all(&src).unwrap();
+    let source = src.join("main.rs");
+    fs::write(&source, "// main\nfn main() {}\n").unwrap();
+    // Just written files aren't cached, their mtime may not change on the next write
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+    let set_old = |path: &Path| {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(old).unwrap();
+    };
+    set_old(&source);
+    let cache = dir.join("cache.json");
+    let flag = format!("--cache={}", cache.display());
+    let root = src.to_str().unwrap();
+
+    let output = lok(&[root, &flag, "--error", "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("0 hits, 1 misses"));
+    assert!(stdout(&output).contains("Rust,1,1,0,1,"));
+    let stored: serde_json::Value = serde_json::from_slice(&fs::read(&cache).unwrap()).unwrap();
+    assert_eq!(stored["version"], 1);
+
+    let output = lok(&[root, &flag, "--error", "-o", "csv"]);
+    assert!(stderr(&output).contains("1 hits, 0 misses"));
+    assert!(stdout(&output).contains("Rust,1,1,0,1,"));
+
+    // Same size and mtime, the cache wins
+    fs::write(&source, "fn main() {}\n// main\n").unwrap();
+    set_old(&source);
+    let output = lok(&[root, &flag, "-o", "csv"]);
+    assert!(stdout(&output).contains("Rust,1,1,0,1,"));
+
+    // Another size is read again
+    fs::write(&source, "fn main() {}\n").unwrap();
+    set_old(&source);
+    let output = lok(&[root, &flag, "--error", "-o", "csv"]);
+    assert!(stderr(&output).contains("0 hits, 1 misses"));
+    assert!(stdout(&output).contains("Rust,1,0,0,1,"));
+
+    let output = lok(&[root, &flag, "--no-cache", "--error", "-o", "csv"]);
+    assert!(!stderr(&output).contains("hits"));
+    let output = lok(&[root, &flag, "--cache-clear", "--no-cache"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!cache.exists());
+
+    // Without a path the cache dir is used
+    let output = Command::new(env!("CARGO_BIN_EXE_lok"))
+        .env("XDG_CONFIG_HOME", no_user_config())
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .args(["--cache", root])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_dir(dir.join("cache").join("rots"))
+            .unwrap()
+            .count(),
+        1
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn snapshot_diff() {
+    let dir = temp_dir("snapshot_diff");
+    let src = dir.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(src.join("main.go"), "package main\n").unwrap();
+    let root = src.to_str().unwrap();
+    let snapshot = dir.join("snapshot.json");
+    let file = snapshot.to_str().unwrap();
+
+    // Saved next to the usual report
+    let output = lok(&[root, "--save-snapshot", file, "-o", "csv"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Rust,1,"));
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(&snapshot).unwrap()).unwrap();
+    assert_eq!(saved["version"], 1);
+    assert_eq!(saved["rows"][0]["name"], "Go");
+
+    fs::write(src.join("main.rs"), "fn main() {}\nfn a() {}\n").unwrap();
+    fs::remove_file(src.join("main.go")).unwrap();
+    fs::write(src.join("a.py"), "a = 1\n").unwrap();
+    let output = lok(&[root, "--diff", file]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    assert!(text.contains("changed  Rust"), "{}", text);
+    assert!(text.contains("removed  Go"), "{}", text);
+    assert!(text.contains("added    Python"), "{}", text);
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+
+    let output = lok(&[root, "--diff", file, "-o", "json", "--hidden"]);
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let changes = value["changes"].as_array().unwrap();
+    let rust = changes.iter().find(|c| c["language"] == "Rust").unwrap();
+    assert_eq!(rust["status"], "changed");
+    assert_eq!(rust["code"], 1);
+    assert_eq!(value["total"]["file"], 0);
+    // Counted another way than the snapshot
+    assert!(stderr(&output).contains("was counted with no flags"));
+
+    let output = lok(&[root, "--diff", root]);
+    assert!(!output.status.success());
+    let output = lok(&[root, "--diff", file, "-o", "jsonl"]);
+    assert!(stderr(&output).contains("don't work with '--output jsonl'"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn git_diff() {
+    let dir = temp_dir("git_diff");
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(&dir)
+            .args(["-c", "user.name=lok", "-c", "user.email=lok@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+    };
+    git(&["init", "-q"]);
+    let body = (0..20)
+        .map(|i| format!("fn f{}() {{}}\n", i))
+        .collect::<String>();
+    fs::write(dir.join("old.rs"), &body).unwrap();
+    fs::write(dir.join("gone.go"), "package main\n// gone\n").unwrap();
+    fs::write(dir.join("logo.c"), b"int\0\x01").unwrap();
+    git(&["add", "-A"]);
+    git(&["commit", "-q", "-m", "one"]);
+    git(&["tag", "v1"]);
+
+    // Moved and one more line, a new file, a deleted one
+    fs::remove_file(dir.join("old.rs")).unwrap();
+    fs::write(dir.join("new.rs"), format!("{}fn g() {{}}\n", body)).unwrap();
+    fs::remove_file(dir.join("gone.go")).unwrap();
+    fs::write(dir.join("a.py"), "a = 1\n\n").unwrap();
+    fs::write(dir.join("logo.c"), b"int\0\x02").unwrap();
+    git(&["add", "-A"]);
+    git(&["commit", "-q", "-m", "two"]);
+    // Only what is committed counts
+    fs::write(dir.join("new.rs"), "").unwrap();
+
+    let root = dir.to_str().unwrap();
+    let output = lok(&[root, "--git-diff", "v1..HEAD", "-o", "json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let changes = value["changes"].as_array().unwrap();
+    let change = |name: &str| changes.iter().find(|c| c["language"] == name).unwrap();
+    assert_eq!(change("Rust")["code"], 1);
+    assert_eq!(change("Rust")["file"], 0);
+    assert_eq!(change("Rust")["status"], "changed");
+    assert_eq!(change("Go")["status"], "removed");
+    assert_eq!(change("Go")["comment"], -1);
+    assert_eq!(change("Python")["blank"], 1);
+    // A binary blob isn't counted
+    assert!(changes.iter().all(|c| c["language"] != "C"));
+    assert_eq!(value["range"]["from"], "v1");
+
+    let output = lok(&[root, "--git-diff", "v1..HEAD"]);
+    let text = stdout(&output);
+    assert!(text.starts_with("v1..HEAD, 4 files changed\n"), "{}", text);
+    assert!(text.contains("removed  Go"), "{}", text);
+
+    let output = lok(&[root, "--git-diff", "v1"]);
+    assert!(stderr(&output).contains("isn't a range"));
+    let output = lok(&[root, "--git-diff", "v1..nope"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn by_author() {
+    let dir = temp_dir("by_author");
+    let git = |email: &str, args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(&dir)
+            .args(["-c", "user.name=lok", "-c"])
+            .arg(format!("user.email={}", email))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+    };
+    git("ann@example.com", &["init", "-q"]);
+    fs::write(dir.join("main.rs"), "// main\nfn main() {\n}\n").unwrap();
+    git("ann@example.com", &["add", "-A"]);
+    git("ann@example.com", &["commit", "-q", "-m", "one"]);
+    fs::write(dir.join("main.rs"), "// main\nfn main() {\n    a();\n}\n").unwrap();
+    git("bob@test.org", &["commit", "-q", "-am", "two"]);
+    // Changed since, and never committed
+    fs::write(
+        dir.join("main.rs"),
+        "// main\nfn main() {\n    a();\n    b();\n}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("lib.py"), "a = 1\n").unwrap();
+
+    let root = dir.to_str().unwrap();
+    let output = lok(&[root, "--by-author", "-o", "json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let rows = value["authors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| {
+            (
+                row["author"].as_str().unwrap().to_string(),
+                row["language"].as_str().unwrap().to_string(),
+                row["code"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let row = |author: &str, language: &str, code| (author.to_string(), language.to_string(), code);
+    assert_eq!(
+        rows,
+        [
+            row("ann@example.com", "Rust", 2),
+            row("(uncommitted)", "Python", 1),
+            row("(uncommitted)", "Rust", 1),
+            row("bob@test.org", "Rust", 1),
+        ]
+    );
+    assert_eq!(value["total"]["code"], 5);
+
+    let output = lok(&[root, "--by-author", "--author", "*@example.com"]);
+    let text = stdout(&output);
+    assert!(text.contains("│ ann@example.com  Rust"), "{}", text);
+    assert!(!text.contains("bob"), "{}", text);
+    let output = lok(&[root, "--author", "ann"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn archives() {
+    let dir = temp_dir("archives");
+    let src = dir.join("src");
+    fs::create_dir_all(src.join("node_modules/x")).unwrap();
+    fs::create_dir_all(src.join(".github")).unwrap();
+    let body = (0..100)
+        .map(|i| format!("fn f{}() {{}}\n", i))
+        .collect::<String>();
+    fs::write(src.join("main.rs"), format!("// main\n{}", body)).unwrap();
+    fs::write(src.join("tool.py"), "a = 1\n\n").unwrap();
+    fs::write(src.join("node_modules/x/a.js"), "var a;\n").unwrap();
+    fs::write(src.join(".github/b.py"), "b = 1\n").unwrap();
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(&src)
+            .args(["-c", "user.name=lok", "-c", "user.email=lok@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+    };
+    git(&["init", "-q"]);
+    git(&["add", "-A", "-f"]);
+    git(&["commit", "-q", "-m", "one"]);
+    for name in ["release.tar", "release.tar.gz", "release.zip"] {
+        git(&[
+            "archive",
+            "--prefix=release/",
+            "-o",
+            &format!("../{}", name),
+            "HEAD",
+        ]);
+    }
+
+    for name in ["release.tar", "release.tar.gz", "release.zip"] {
+        let archive = dir.join(name);
+        let archive = archive.to_str().unwrap();
+        assert_eq!(
+            counted_files(&[archive]),
+            [
+                format!("{}/release/main.rs", archive),
+                format!("{}/release/tool.py", archive),
+            ]
+        );
+        let output = lok(&[archive, "-o", "json"]);
+        let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let languages = value["languages"].as_array().unwrap();
+        let rust = languages.iter().find(|l| l["language"] == "Rust").unwrap();
+        assert_eq!(
+            (rust["code"].as_i64(), rust["comment"].as_i64()),
+            (Some(100), Some(1))
+        );
+        // The size of the entry, not of its compressed bytes
+        assert_eq!(rust["size"].as_u64(), Some(8 + body.len() as u64));
+    }
+
+    let archive = dir.join("release.tar.gz");
+    let archive = archive.to_str().unwrap();
+    assert_eq!(
+        counted_files(&[archive, "-e", "**/*.rs", "--hidden"]),
+        [
+            format!("{}/release/.github/b.py", archive),
+            format!("{}/release/tool.py", archive),
+        ]
+    );
+    assert_eq!(counted_files(&[archive, "--max-size", "100"]).len(), 1);
+
+    // A broken archive fails as a whole
+    let bytes = fs::read(archive).unwrap();
+    let cut = dir.join("cut.tgz");
+    fs::write(&cut, &bytes[..bytes.len() / 2]).unwrap();
+    let output = lok(&[cut.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cut.tgz"), "{}", stderr(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}